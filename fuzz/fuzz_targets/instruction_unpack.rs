@@ -0,0 +1,12 @@
+#![no_main]
+
+use amm::instruction::AmmInstruction;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // any input decodes or fails with a ProgramError, and decoding is stable
+    if let Ok(instruction) = AmmInstruction::unpack(data) {
+        let packed = instruction.pack();
+        assert_eq!(AmmInstruction::unpack(&packed), Ok(instruction));
+    }
+});
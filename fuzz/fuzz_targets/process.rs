@@ -0,0 +1,1059 @@
+#![no_main]
+
+use amm::{
+    instruction::{
+        find_farm_address, find_order_escrow_address, find_stake_address, find_twamm_address,
+        find_vault_address, AmmInstruction, Deadline, Direction, FARM_REWARD_SEED, FEE_VAULT_SEED,
+        TWAMM_ESCROW_A_SEED, TWAMM_ESCROW_B_SEED, VAULT_A_SEED, VAULT_B_SEED,
+    },
+    processor::Processor,
+    state::{AmmPool, Farm, FeeMode, LongTermOrder, Order, PoolStatus, Stake, Twamm},
+};
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{ProgramResult, SUCCESS},
+    instruction::Instruction,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::{IsInitialized, Pack},
+    program_stubs::{self, SyscallStubs},
+    program_utils::limited_deserialize,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::SystemInstruction,
+    system_program,
+};
+use spl_token::state::{Account, AccountState, Mint};
+use std::{cell::RefCell, collections::HashMap, sync::Once};
+
+const PROGRAM_ID: Pubkey = Pubkey::new_from_array([7; 32]);
+
+// candidate accounts, instructions pick them by index
+const POOL: usize = 0;
+const OWNER: usize = 1;
+const MINT_A: usize = 2;
+const MINT_B: usize = 3;
+const VAULT_A: usize = 4;
+const VAULT_B: usize = 5;
+const FEE_VAULT: usize = 6;
+const PDA: usize = 7;
+const OWNER_TOKEN_A: usize = 8;
+const OWNER_TOKEN_B: usize = 9;
+const USER: usize = 10;
+const USER_TOKEN_A: usize = 11;
+const USER_TOKEN_B: usize = 12;
+const TOKEN_PROGRAM: usize = 13;
+// addresses of a new pool and its vaults, for Initialize to create
+const NEW_POOL: usize = 14;
+const NEW_VAULT_A: usize = 15;
+const NEW_VAULT_B: usize = 16;
+const NEW_FEE_VAULT: usize = 17;
+const NEW_PDA: usize = 18;
+const FAKE_PROGRAM: usize = 19;
+const SYSTEM_PROGRAM: usize = 20;
+// accounts place order creates
+const NEW_ORDER: usize = 21;
+const NEW_ESCROW: usize = 22;
+// accounts the first long-term order creates
+const TWAMM: usize = 23;
+const TWAMM_ESCROW_A: usize = 24;
+const TWAMM_ESCROW_B: usize = 25;
+const NEW_LONG_TERM_ORDER: usize = 26;
+// accounts the first funding and the first stake of the user create
+const FARM: usize = 27;
+const FARM_REWARD_VAULT: usize = 28;
+const STAKE: usize = 29;
+const REWARD_MINT: usize = 30;
+const OWNER_TOKEN_REWARD: usize = 31;
+const USER_TOKEN_REWARD: usize = 32;
+const CANDIDATES: usize = 33;
+
+#[derive(Arbitrary, Debug)]
+struct FuzzData {
+    slot: u64,
+    unix_timestamp: i64,
+    status: u8,
+    lock_time: i64,
+    open_orders: bool,
+    fee: u64,
+    tolerance: u64,
+    referral_share: u64,
+    fee_mode: u8,
+    min_fee: u64,
+    max_fee: u64,
+    volatility: u64,
+    volatility_slot: u64,
+    decimals_a: u8,
+    decimals_b: u8,
+    // token balances, shifted down so the supply of a mint fits in u64
+    vault_a: u64,
+    vault_b: u64,
+    fee_vault: u64,
+    owner_a: u64,
+    owner_b: u64,
+    user_a: u64,
+    user_b: u64,
+    owner_reward: u64,
+    new_pool_lamports: u32,
+    instructions: Vec<FuzzInstruction>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum FuzzInstruction {
+    Initialize {
+        fee: u64,
+        amount_a: u64,
+        amount_b: u64,
+        tolerance: u64,
+        deadline: Option<FuzzDeadline>,
+    },
+    UpdateStatus {
+        status: u8,
+    },
+    UpdateTolerance {
+        tolerance: u64,
+    },
+    UpdateReferralShare {
+        share: u64,
+    },
+    UpdateDynamicFee {
+        mode: u8,
+        min_fee: u64,
+        max_fee: u64,
+    },
+    Swap {
+        amount: u64,
+        direction: u8,
+        by_owner: bool,
+        fake_token_program: bool,
+        twamm: bool,
+        referrer: bool,
+        deadline: Option<FuzzDeadline>,
+    },
+    WithdrawalFee,
+    Terminate {
+        twamm: bool,
+        farm: bool,
+        deadline: Option<FuzzDeadline>,
+    },
+    PlaceOrder {
+        amount_in: u64,
+        direction: u8,
+        min_amount_out: u64,
+        reward: u32,
+    },
+    CancelOrder {
+        owner_signs: bool,
+    },
+    FillOrders {
+        by_owner: bool,
+        twamm: bool,
+    },
+    PlaceLongTermOrder {
+        amount_in: u64,
+        direction: u8,
+        slots: u64,
+    },
+    CancelLongTermOrder {
+        owner_signs: bool,
+    },
+    ExecuteVirtualOrders,
+    FundFarm {
+        amount: u64,
+        slots: u64,
+    },
+    Stake {
+        amount_a: u64,
+        twamm: bool,
+        deadline: Option<FuzzDeadline>,
+    },
+    Unstake {
+        shares: u64,
+        owner_signs: bool,
+        twamm: bool,
+        deadline: Option<FuzzDeadline>,
+    },
+    ClaimRewards,
+    /// advance the clock, for the virtual orders to run
+    Warp {
+        slots: u16,
+    },
+    Migrate,
+    /// arbitrary bytes and (candidate, is_signer) accounts
+    Raw {
+        data: Vec<u8>,
+        accounts: Vec<(u8, bool)>,
+    },
+}
+
+#[derive(Arbitrary, Debug)]
+enum FuzzDeadline {
+    UnixTimestamp(i64),
+    Slot(u64),
+}
+
+impl From<&FuzzDeadline> for Deadline {
+    fn from(deadline: &FuzzDeadline) -> Self {
+        match *deadline {
+            FuzzDeadline::UnixTimestamp(timestamp) => Deadline::UnixTimestamp(timestamp),
+            FuzzDeadline::Slot(slot) => Deadline::Slot(slot),
+        }
+    }
+}
+
+thread_local! {
+    static CLOCK: RefCell<Clock> = RefCell::new(Clock::default());
+}
+
+/// Runs spl-token and the system program natively for CPIs made by the program.
+struct TestSyscallStubs;
+
+impl SyscallStubs for TestSyscallStubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_log_data(&self, _fields: &[&[u8]]) {}
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        CLOCK.with(|clock| unsafe { *(var_addr as *mut Clock) = clock.borrow().clone() });
+        SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        SUCCESS
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &PROGRAM_ID))
+            .collect::<Result<Vec<_>, _>>()?;
+        let program = account_infos
+            .iter()
+            .find(|info| *info.key == instruction.program_id)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if !program.executable {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut infos = Vec::with_capacity(instruction.accounts.len());
+        for meta in instruction.accounts.iter() {
+            let mut info = account_infos
+                .iter()
+                .find(|info| *info.key == meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+            // the runtime rejects privilege escalation
+            if meta.is_signer && !info.is_signer && !signers.contains(info.key) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if meta.is_writable && !info.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+            info.is_signer = meta.is_signer;
+            info.is_writable = meta.is_writable;
+            infos.push(info);
+        }
+        if instruction.program_id == system_program::id() {
+            process_system(&infos, &instruction.data)
+        } else if instruction.program_id == spl_token::id() {
+            spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &infos,
+                &instruction.data,
+            )
+        } else {
+            Err(ProgramError::IncorrectProgramId)
+        }
+    }
+}
+
+/// The system instructions the program makes. Account data can not grow outside the runtime,
+/// so the harness sizes new accounts beforehand and allocate only checks the size.
+fn process_system(infos: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let instruction: SystemInstruction =
+        limited_deserialize(data, 1232).map_err(|_| ProgramError::InvalidInstructionData)?;
+    let account = |index: usize| infos.get(index).ok_or(ProgramError::NotEnoughAccountKeys);
+    match instruction {
+        SystemInstruction::CreateAccount {
+            lamports,
+            space,
+            owner,
+        } => {
+            if account(1)?.lamports() != 0 {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            system_transfer(account(0)?, account(1)?, lamports)?;
+            system_allocate(account(1)?, space)?;
+            system_assign(account(1)?, &owner)
+        }
+        SystemInstruction::Transfer { lamports } => {
+            system_transfer(account(0)?, account(1)?, lamports)
+        }
+        SystemInstruction::Allocate { space } => system_allocate(account(0)?, space),
+        SystemInstruction::Assign { owner } => system_assign(account(0)?, &owner),
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+fn check_system_account(info: &AccountInfo) -> ProgramResult {
+    if !info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if *info.owner != system_program::id() {
+        return Err(ProgramError::IllegalOwner);
+    }
+    Ok(())
+}
+
+fn system_transfer(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+    check_system_account(from)?;
+    if !from.data_is_empty() {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let balance = from.lamports();
+    if balance < lamports {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    **from.lamports.borrow_mut() = balance - lamports;
+    **to.lamports.borrow_mut() += lamports;
+    Ok(())
+}
+
+fn system_allocate(info: &AccountInfo, space: u64) -> ProgramResult {
+    check_system_account(info)?;
+    if info.data_len() as u64 != space || info.data.borrow().iter().any(|byte| *byte != 0) {
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
+fn system_assign(info: &AccountInfo, owner: &Pubkey) -> ProgramResult {
+    check_system_account(info)?;
+    info.assign(owner);
+    Ok(())
+}
+
+struct TestAccount {
+    key: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    data: Vec<u8>,
+    executable: bool,
+}
+
+impl TestAccount {
+    fn new(owner: Pubkey, data: Vec<u8>) -> Self {
+        Self {
+            key: Pubkey::new_unique(),
+            owner,
+            lamports: 1_000_000_000,
+            data,
+            executable: false,
+        }
+    }
+
+    /// A system account at `key`, sized for what initialize creates there
+    fn empty(key: Pubkey, lamports: u64, len: usize) -> Self {
+        Self {
+            key,
+            owner: system_program::id(),
+            lamports,
+            data: vec![0; len],
+            executable: false,
+        }
+    }
+
+    fn program(key: Pubkey) -> Self {
+        Self {
+            key,
+            owner: Pubkey::new_unique(),
+            lamports: 1,
+            data: vec![],
+            executable: true,
+        }
+    }
+
+    fn mint(decimals: u8, supply: u64) -> Self {
+        let mut data = vec![0; Mint::LEN];
+        Mint {
+            mint_authority: COption::None,
+            supply,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut data);
+        Self::new(spl_token::id(), data)
+    }
+
+    fn token(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Self {
+        let mut data = vec![0; Account::LEN];
+        Account {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: AccountState::Initialized,
+            ..Account::default()
+        }
+        .pack_into_slice(&mut data);
+        Self::new(spl_token::id(), data)
+    }
+
+    fn info(&mut self) -> AccountInfo<'_> {
+        AccountInfo::new(
+            &self.key,
+            false,
+            !self.executable,
+            &mut self.lamports,
+            &mut self.data,
+            &self.owner,
+            self.executable,
+            0,
+        )
+    }
+}
+
+fn setup(data: &FuzzData) -> (Vec<TestAccount>, u8) {
+    let pool = Pubkey::new_unique();
+    let (pda, nonce) = Pubkey::find_program_address(&[pool.as_ref()], &PROGRAM_ID);
+    let new_pool = Pubkey::new_unique();
+    let (new_pda, new_nonce) = Pubkey::find_program_address(&[new_pool.as_ref()], &PROGRAM_ID);
+    let new_vault = |seed| {
+        let (key, _) = find_vault_address(&PROGRAM_ID, &new_pool, seed);
+        TestAccount::empty(key, 0, Account::LEN)
+    };
+    let new_order = Pubkey::new_unique();
+    let (new_escrow, _) = find_order_escrow_address(&PROGRAM_ID, &new_order);
+    let (twamm, _) = find_twamm_address(&PROGRAM_ID, &pool);
+    let twamm_escrow = |seed| {
+        let (key, _) = find_vault_address(&PROGRAM_ID, &pool, seed);
+        TestAccount::empty(key, 0, Account::LEN)
+    };
+    let (farm, _) = find_farm_address(&PROGRAM_ID, &pool);
+    let (farm_reward_vault, _) = find_vault_address(&PROGRAM_ID, &pool, FARM_REWARD_SEED);
+    let (vault_a, vault_b, fee_vault) = (data.vault_a >> 3, data.vault_b >> 3, data.fee_vault >> 3);
+    let (owner_a, owner_b, user_a, user_b) = (
+        data.owner_a >> 3,
+        data.owner_b >> 3,
+        data.user_a >> 3,
+        data.user_b >> 3,
+    );
+    let mint_a = TestAccount::mint(data.decimals_a % 20, vault_a + owner_a + user_a);
+    let mint_b = TestAccount::mint(data.decimals_b % 20, vault_b + fee_vault + owner_b + user_b);
+    let reward_mint = TestAccount::mint(6, data.owner_reward);
+    let (mint_a_key, mint_b_key, reward_mint_key) = (mint_a.key, mint_b.key, reward_mint.key);
+    let owner = TestAccount::new(system_program::id(), vec![]);
+    let user = TestAccount::new(system_program::id(), vec![]);
+    let (owner_key, user_key) = (owner.key, user.key);
+    let (stake, _) = find_stake_address(&PROGRAM_ID, &pool, &user_key);
+    let mut accounts = vec![
+        TestAccount::new(PROGRAM_ID, vec![0; AmmPool::LEN]),
+        owner,
+        mint_a,
+        mint_b,
+        TestAccount::token(&mint_a_key, &pda, vault_a),
+        TestAccount::token(&mint_b_key, &pda, vault_b),
+        TestAccount::token(&mint_b_key, &pda, fee_vault),
+        TestAccount::new(system_program::id(), vec![]),
+        TestAccount::token(&mint_a_key, &owner_key, owner_a),
+        TestAccount::token(&mint_b_key, &owner_key, owner_b),
+        user,
+        TestAccount::token(&mint_a_key, &user_key, user_a),
+        TestAccount::token(&mint_b_key, &user_key, user_b),
+        TestAccount::program(spl_token::id()),
+        // lamports sent to the new pool beforehand
+        TestAccount::empty(new_pool, data.new_pool_lamports as u64, AmmPool::LEN),
+        new_vault(VAULT_A_SEED),
+        new_vault(VAULT_B_SEED),
+        new_vault(FEE_VAULT_SEED),
+        TestAccount::new(system_program::id(), vec![]),
+        TestAccount::program(Pubkey::new_unique()),
+        TestAccount::program(system_program::id()),
+        TestAccount::empty(new_order, 0, Order::LEN),
+        TestAccount::empty(new_escrow, 0, Account::LEN),
+        TestAccount::empty(twamm, 0, Twamm::LEN),
+        twamm_escrow(TWAMM_ESCROW_A_SEED),
+        twamm_escrow(TWAMM_ESCROW_B_SEED),
+        TestAccount::empty(Pubkey::new_unique(), 0, LongTermOrder::LEN),
+        TestAccount::empty(farm, 0, Farm::LEN),
+        TestAccount::empty(farm_reward_vault, 0, Account::LEN),
+        TestAccount::empty(stake, 0, Stake::LEN),
+        reward_mint,
+        TestAccount::token(&reward_mint_key, &owner_key, data.owner_reward),
+        TestAccount::token(&reward_mint_key, &user_key, 0),
+    ];
+    accounts[POOL].key = pool;
+    accounts[PDA].key = pda;
+    accounts[NEW_PDA].key = new_pda;
+    AmmPool {
+        status: PoolStatus::try_from(data.status % 4).unwrap(),
+        nonce,
+        ka: vault_a,
+        kb: vault_b,
+        tolerance: data.tolerance,
+        fee: data.fee,
+        owner: owner_key,
+        mint_a: mint_a_key,
+        mint_b: mint_b_key,
+        vault_a: accounts[VAULT_A].key,
+        vault_b: accounts[VAULT_B].key,
+        fee_vault: accounts[FEE_VAULT].key,
+        decimals_a: data.decimals_a % 20,
+        decimals_b: data.decimals_b % 20,
+        lock_time: data.lock_time,
+        open_orders: data.open_orders as u64,
+        long_term_orders: 0,
+        referral_share: data.referral_share,
+        stakes: 0,
+        fee_mode: FeeMode::try_from(data.fee_mode % 3).unwrap(),
+        min_fee: data.min_fee,
+        max_fee: data.max_fee,
+        volatility: data.volatility,
+        volatility_slot: data.volatility_slot,
+        twamm: false,
+        farm: false,
+    }
+    .pack_into_slice(&mut accounts[POOL].data);
+    assert_eq!(accounts.len(), CANDIDATES);
+    (accounts, new_nonce)
+}
+
+/// instruction data and (candidate, is_signer) accounts, `nonce` is the one of the new pool
+fn build(instruction: &FuzzInstruction, nonce: u8) -> (Vec<u8>, Vec<(usize, bool)>) {
+    let signer = |index: usize| {
+        let is_signer = matches!(
+            index,
+            OWNER | USER | NEW_POOL | NEW_ORDER | NEW_LONG_TERM_ORDER
+        );
+        (index, is_signer)
+    };
+    match instruction {
+        FuzzInstruction::Initialize {
+            fee,
+            amount_a,
+            amount_b,
+            tolerance,
+            deadline,
+        } => {
+            let data = AmmInstruction::Initialize {
+                nonce,
+                fee: *fee,
+                amount_a: *amount_a,
+                amount_b: *amount_b,
+                tolerance: *tolerance,
+                deadline: deadline.as_ref().map(Deadline::from),
+            }
+            .pack();
+            let accounts = [
+                NEW_POOL,
+                OWNER,
+                MINT_A,
+                MINT_B,
+                NEW_VAULT_A,
+                NEW_VAULT_B,
+                NEW_FEE_VAULT,
+                NEW_PDA,
+                OWNER_TOKEN_A,
+                OWNER_TOKEN_B,
+                TOKEN_PROGRAM,
+                SYSTEM_PROGRAM,
+            ];
+            (data, accounts.iter().map(|i| signer(*i)).collect())
+        }
+        FuzzInstruction::UpdateStatus { status } => (
+            AmmInstruction::UpdateStatus { status: *status }.pack(),
+            vec![signer(POOL), signer(OWNER)],
+        ),
+        FuzzInstruction::UpdateTolerance { tolerance } => (
+            AmmInstruction::UpdateTolerance {
+                tolerance: *tolerance,
+            }
+            .pack(),
+            vec![signer(POOL), signer(OWNER)],
+        ),
+        FuzzInstruction::UpdateReferralShare { share } => (
+            AmmInstruction::UpdateReferralShare { share: *share }.pack(),
+            vec![signer(POOL), signer(OWNER)],
+        ),
+        FuzzInstruction::UpdateDynamicFee {
+            mode,
+            min_fee,
+            max_fee,
+        } => (
+            AmmInstruction::UpdateDynamicFee {
+                mode: *mode,
+                min_fee: *min_fee,
+                max_fee: *max_fee,
+            }
+            .pack(),
+            vec![signer(POOL), signer(OWNER)],
+        ),
+        FuzzInstruction::Swap {
+            amount,
+            direction,
+            by_owner,
+            fake_token_program,
+            twamm,
+            referrer,
+            deadline,
+        } => {
+            let mut data = AmmInstruction::Swap {
+                amount: *amount,
+                direction: Direction::A2B,
+                deadline: deadline.as_ref().map(Deadline::from),
+            }
+            .pack();
+            // any direction byte, so the decoder sees invalid ones too
+            data[1 + 8] = *direction;
+            let (wallet, token_a, token_b, other_token_b) = if *by_owner {
+                (OWNER, OWNER_TOKEN_A, OWNER_TOKEN_B, USER_TOKEN_B)
+            } else {
+                (USER, USER_TOKEN_A, USER_TOKEN_B, OWNER_TOKEN_B)
+            };
+            let token_program = if *fake_token_program {
+                FAKE_PROGRAM
+            } else {
+                TOKEN_PROGRAM
+            };
+            let mut accounts = vec![
+                POOL,
+                VAULT_A,
+                VAULT_B,
+                FEE_VAULT,
+                PDA,
+                wallet,
+                token_a,
+                token_b,
+                token_program,
+                MINT_A,
+                MINT_B,
+            ];
+            if *twamm {
+                accounts.extend([TWAMM, TWAMM_ESCROW_A, TWAMM_ESCROW_B]);
+            }
+            if *referrer {
+                accounts.push(other_token_b);
+            }
+            (data, accounts.iter().map(|i| signer(*i)).collect())
+        }
+        FuzzInstruction::WithdrawalFee => {
+            let accounts = [
+                POOL,
+                OWNER,
+                FEE_VAULT,
+                OWNER_TOKEN_B,
+                PDA,
+                TOKEN_PROGRAM,
+                MINT_B,
+            ];
+            (
+                AmmInstruction::WithdrawalFee {}.pack(),
+                accounts.iter().map(|i| signer(*i)).collect(),
+            )
+        }
+        FuzzInstruction::Terminate {
+            twamm,
+            farm,
+            deadline,
+        } => {
+            let mut accounts = vec![
+                POOL,
+                OWNER,
+                VAULT_A,
+                VAULT_B,
+                FEE_VAULT,
+                PDA,
+                OWNER_TOKEN_A,
+                OWNER_TOKEN_B,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+            ];
+            // the user places the long-term orders and pays their rent
+            if *twamm {
+                accounts.extend([TWAMM, TWAMM_ESCROW_A, TWAMM_ESCROW_B, USER]);
+            }
+            if *farm {
+                accounts.extend([FARM, FARM_REWARD_VAULT, OWNER_TOKEN_REWARD, REWARD_MINT]);
+            }
+            (
+                AmmInstruction::Terminate {
+                    deadline: deadline.as_ref().map(Deadline::from),
+                }
+                .pack(),
+                accounts.iter().map(|i| signer(*i)).collect(),
+            )
+        }
+        FuzzInstruction::PlaceOrder {
+            amount_in,
+            direction,
+            min_amount_out,
+            reward,
+        } => {
+            let mut data = AmmInstruction::PlaceOrder {
+                amount_in: *amount_in,
+                direction: Direction::A2B,
+                min_amount_out: *min_amount_out,
+                reward: *reward as u64,
+            }
+            .pack();
+            data[1 + 8] = *direction;
+            // the user sells a for b, and b for a
+            let (token_in, token_out) = if *direction == 2 {
+                (USER_TOKEN_B, USER_TOKEN_A)
+            } else {
+                (USER_TOKEN_A, USER_TOKEN_B)
+            };
+            let accounts = [
+                POOL,
+                NEW_ORDER,
+                USER,
+                NEW_ESCROW,
+                PDA,
+                token_in,
+                token_out,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+                SYSTEM_PROGRAM,
+            ];
+            (data, accounts.iter().map(|i| signer(*i)).collect())
+        }
+        FuzzInstruction::CancelOrder { owner_signs } => {
+            // an order selling b recorded the other refund account, and is rejected
+            let accounts = [
+                POOL,
+                NEW_ORDER,
+                USER,
+                NEW_ESCROW,
+                PDA,
+                USER_TOKEN_A,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+            ];
+            let mut metas: Vec<(usize, bool)> = accounts.iter().map(|i| signer(*i)).collect();
+            metas[2].1 = *owner_signs;
+            (AmmInstruction::CancelOrder {}.pack(), metas)
+        }
+        FuzzInstruction::FillOrders { by_owner, twamm } => {
+            let keeper = if *by_owner { OWNER } else { USER };
+            let mut accounts = vec![
+                POOL,
+                VAULT_A,
+                VAULT_B,
+                FEE_VAULT,
+                PDA,
+                keeper,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+            ];
+            if *twamm {
+                accounts.extend([TWAMM, TWAMM_ESCROW_A, TWAMM_ESCROW_B]);
+            }
+            accounts.extend([NEW_ORDER, NEW_ESCROW, USER, USER_TOKEN_B]);
+            (
+                AmmInstruction::FillOrders {}.pack(),
+                accounts.iter().map(|i| signer(*i)).collect(),
+            )
+        }
+        FuzzInstruction::PlaceLongTermOrder {
+            amount_in,
+            direction,
+            slots,
+        } => {
+            let mut data = AmmInstruction::PlaceLongTermOrder {
+                amount_in: *amount_in,
+                direction: Direction::A2B,
+                slots: *slots,
+            }
+            .pack();
+            data[1 + 8] = *direction;
+            let (token_in, token_out) = if *direction == 2 {
+                (USER_TOKEN_B, USER_TOKEN_A)
+            } else {
+                (USER_TOKEN_A, USER_TOKEN_B)
+            };
+            let accounts = [
+                POOL,
+                NEW_LONG_TERM_ORDER,
+                USER,
+                TWAMM,
+                TWAMM_ESCROW_A,
+                TWAMM_ESCROW_B,
+                VAULT_A,
+                VAULT_B,
+                FEE_VAULT,
+                PDA,
+                token_in,
+                token_out,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+                SYSTEM_PROGRAM,
+            ];
+            (data, accounts.iter().map(|i| signer(*i)).collect())
+        }
+        FuzzInstruction::CancelLongTermOrder { owner_signs } => {
+            // an order selling b recorded the other accounts, and is rejected
+            let accounts = [
+                POOL,
+                NEW_LONG_TERM_ORDER,
+                USER,
+                TWAMM,
+                TWAMM_ESCROW_A,
+                TWAMM_ESCROW_B,
+                VAULT_A,
+                VAULT_B,
+                FEE_VAULT,
+                PDA,
+                USER_TOKEN_A,
+                USER_TOKEN_B,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+            ];
+            let mut metas: Vec<(usize, bool)> = accounts.iter().map(|i| signer(*i)).collect();
+            metas[2].1 = *owner_signs;
+            (AmmInstruction::CancelLongTermOrder {}.pack(), metas)
+        }
+        FuzzInstruction::ExecuteVirtualOrders => {
+            let accounts = [
+                POOL,
+                TWAMM,
+                TWAMM_ESCROW_A,
+                TWAMM_ESCROW_B,
+                VAULT_A,
+                VAULT_B,
+                FEE_VAULT,
+                PDA,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+            ];
+            (
+                AmmInstruction::ExecuteVirtualOrders {}.pack(),
+                accounts.iter().map(|i| signer(*i)).collect(),
+            )
+        }
+        FuzzInstruction::FundFarm { amount, slots } => {
+            let accounts = [
+                POOL,
+                OWNER,
+                FARM,
+                FARM_REWARD_VAULT,
+                VAULT_A,
+                PDA,
+                OWNER_TOKEN_REWARD,
+                TOKEN_PROGRAM,
+                REWARD_MINT,
+                SYSTEM_PROGRAM,
+            ];
+            (
+                AmmInstruction::FundFarm {
+                    amount: *amount,
+                    slots: *slots,
+                }
+                .pack(),
+                accounts.iter().map(|i| signer(*i)).collect(),
+            )
+        }
+        FuzzInstruction::Stake {
+            amount_a,
+            twamm,
+            deadline,
+        } => {
+            let mut accounts = vec![
+                POOL,
+                FARM,
+                STAKE,
+                USER,
+                VAULT_A,
+                VAULT_B,
+                FEE_VAULT,
+                PDA,
+                USER_TOKEN_A,
+                USER_TOKEN_B,
+                USER_TOKEN_REWARD,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+                SYSTEM_PROGRAM,
+            ];
+            if *twamm {
+                accounts.extend([TWAMM, TWAMM_ESCROW_A, TWAMM_ESCROW_B]);
+            }
+            (
+                AmmInstruction::Stake {
+                    amount_a: *amount_a,
+                    deadline: deadline.as_ref().map(Deadline::from),
+                }
+                .pack(),
+                accounts.iter().map(|i| signer(*i)).collect(),
+            )
+        }
+        FuzzInstruction::Unstake {
+            shares,
+            owner_signs,
+            twamm,
+            deadline,
+        } => {
+            let mut accounts = vec![
+                POOL,
+                FARM,
+                STAKE,
+                USER,
+                VAULT_A,
+                VAULT_B,
+                FEE_VAULT,
+                PDA,
+                USER_TOKEN_A,
+                USER_TOKEN_B,
+                FARM_REWARD_VAULT,
+                USER_TOKEN_REWARD,
+                TOKEN_PROGRAM,
+                MINT_A,
+                MINT_B,
+                REWARD_MINT,
+            ];
+            if *twamm {
+                accounts.extend([TWAMM, TWAMM_ESCROW_A, TWAMM_ESCROW_B]);
+            }
+            let mut metas: Vec<(usize, bool)> = accounts.iter().map(|i| signer(*i)).collect();
+            metas[3].1 = *owner_signs;
+            (
+                AmmInstruction::Unstake {
+                    shares: *shares,
+                    deadline: deadline.as_ref().map(Deadline::from),
+                }
+                .pack(),
+                metas,
+            )
+        }
+        FuzzInstruction::ClaimRewards => {
+            let accounts = [
+                POOL,
+                FARM,
+                STAKE,
+                USER,
+                FARM_REWARD_VAULT,
+                PDA,
+                USER_TOKEN_REWARD,
+                TOKEN_PROGRAM,
+                REWARD_MINT,
+            ];
+            (
+                AmmInstruction::ClaimRewards {}.pack(),
+                accounts.iter().map(|i| signer(*i)).collect(),
+            )
+        }
+        FuzzInstruction::Migrate => {
+            let accounts = [POOL, OWNER, MINT_A, MINT_B, SYSTEM_PROGRAM];
+            (
+                AmmInstruction::Migrate {}.pack(),
+                accounts.iter().map(|i| signer(*i)).collect(),
+            )
+        }
+        FuzzInstruction::Warp { .. } => unreachable!(),
+        FuzzInstruction::Raw { data, accounts } => (
+            data.clone(),
+            accounts
+                .iter()
+                .map(|(i, is_signer)| (*i as usize % CANDIDATES, *is_signer))
+                .collect(),
+        ),
+    }
+}
+
+/// total lamports and token supply held by accounts, per mint
+fn totals(infos: &[AccountInfo]) -> (u128, HashMap<Pubkey, u128>) {
+    let mut lamports = 0;
+    let mut tokens = HashMap::new();
+    for info in infos.iter() {
+        lamports += info.lamports() as u128;
+        if *info.owner == spl_token::id() && info.data_len() == Account::LEN {
+            let account = Account::unpack_unchecked(&info.data.borrow()).unwrap();
+            if account.is_initialized() {
+                *tokens.entry(account.mint).or_insert(0) += account.amount as u128;
+            }
+        }
+    }
+    (lamports, tokens)
+}
+
+fuzz_target!(|data: FuzzData| {
+    static STUBS: Once = Once::new();
+    STUBS.call_once(|| {
+        program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs));
+    });
+
+    CLOCK.with(|clock| {
+        *clock.borrow_mut() = Clock {
+            slot: data.slot,
+            unix_timestamp: data.unix_timestamp,
+            ..Clock::default()
+        }
+    });
+    let (mut accounts, new_nonce) = setup(&data);
+    let candidates: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+    let (lamports, tokens) = totals(&candidates);
+
+    for instruction in data.instructions.iter() {
+        if let FuzzInstruction::Warp { slots } = instruction {
+            CLOCK.with(|clock| {
+                let mut clock = clock.borrow_mut();
+                clock.slot = clock.slot.saturating_add(*slots as u64);
+            });
+            continue;
+        }
+        let (input, metas) = build(instruction, new_nonce);
+        let infos: Vec<AccountInfo> = metas
+            .iter()
+            .map(|(index, is_signer)| {
+                let mut info = candidates[*index].clone();
+                info.is_signer = *is_signer;
+                info
+            })
+            .collect();
+        let snapshot: Vec<(u64, Vec<u8>, Pubkey)> = candidates
+            .iter()
+            .map(|info| (info.lamports(), info.data.borrow().to_vec(), *info.owner))
+            .collect();
+
+        // Ok or a ProgramError, a panic is a crash
+        let result = Processor::process(&PROGRAM_ID, &infos, &input);
+
+        if result.is_err() {
+            // a failed transaction leaves no trace
+            for (info, (lamports, data, owner)) in candidates.iter().zip(snapshot.into_iter()) {
+                **info.lamports.borrow_mut() = lamports;
+                info.data.borrow_mut().copy_from_slice(&data);
+                info.assign(&owner);
+            }
+        }
+        // nothing is created, whether the instruction succeeded or not
+        let (lamports_after, tokens_after) = totals(&candidates);
+        assert_eq!(
+            lamports, lamports_after,
+            "lamports created by {:?}",
+            instruction
+        );
+        for (mint, amount) in tokens_after.iter() {
+            assert!(
+                *amount <= tokens.get(mint).copied().unwrap_or(0),
+                "tokens of {} created by {:?}",
+                mint,
+                instruction
+            );
+        }
+    }
+});
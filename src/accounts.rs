@@ -0,0 +1,1663 @@
+//! Account contexts of each instruction
+//!
+//! Every `process_*` function loads its accounts into one of these contexts, and
+//! `validate` runs all the checks the instruction relies on before a token moves.
+use {
+    crate::{
+        error::AmmError,
+        instruction::{
+            find_farm_address, find_order_escrow_address, find_stake_address, find_twamm_address,
+            find_vault_address, Direction, FARM_REWARD_SEED, FEE_VAULT_SEED, TWAMM_ESCROW_A_SEED,
+            TWAMM_ESCROW_B_SEED, VAULT_A_SEED, VAULT_B_SEED,
+        },
+        state::{
+            AmmPool, AmmPoolView, Farm, LongTermOrder, Order, OrderStatus, PoolStatus, Stake, Twamm,
+        },
+    },
+    arrayref::array_ref,
+    solana_program::{
+        account_info::AccountInfo, msg, program_error::ProgramError, program_pack::Pack,
+        pubkey::Pubkey, system_program,
+    },
+    spl_token_2022::{
+        extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+        state::{Account, Mint},
+    },
+    std::cell::{Ref, RefMut},
+};
+
+/// Accounts of `Initialize`
+pub struct InitializeAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+    pub vault_a: &'a AccountInfo<'b>,
+    pub vault_b: &'a AccountInfo<'b>,
+    pub fee_vault: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub owner_token_a: &'a AccountInfo<'b>,
+    pub owner_token_b: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub system_program: &'a AccountInfo<'b>,
+    // trailing accounts to wrap native sol
+    pub native: &'a [AccountInfo<'b>],
+}
+
+impl<'a, 'b> InitializeAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let native = accounts.get(12..).unwrap_or_default();
+        let [pool, owner, mint_a, mint_b, vault_a, vault_b, fee_vault, pool_pda, owner_token_a, owner_token_b, token_program, system_program] =
+            array_ref![check_len(accounts, 12)?, 0, 12];
+        Ok(Self {
+            pool,
+            owner,
+            mint_a,
+            mint_b,
+            vault_a,
+            vault_b,
+            fee_vault,
+            pool_pda,
+            owner_token_a,
+            owner_token_b,
+            token_program,
+            system_program,
+            native,
+        })
+    }
+
+    /// Check the accounts of a new pool, returns both mints and the bumps of the vaults
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+        nonce: u8,
+    ) -> Result<(Mint, Mint, [u8; 3]), ProgramError> {
+        check_signer(self.owner)?;
+        check_signer(self.pool)?;
+        // only initialize gives the program an account of this size
+        if self.pool.owner == program_id {
+            return Err(match AmmPoolView::load_unchecked(self.pool)?.status()? {
+                PoolStatus::Closed => AmmError::PoolClosed,
+                _ => AmmError::PoolExist,
+            }
+            .into());
+        }
+        // check mint
+        let mint_a = unpack_mint(self.mint_a)?;
+        let mint_b = unpack_mint(self.mint_b)?;
+        if self.mint_a.owner != self.mint_b.owner {
+            msg!(
+                "mints of different token programs {} {}",
+                self.mint_a.owner,
+                self.mint_b.owner
+            );
+            return Err(AmmError::InvalidTokenProgramId.into());
+        }
+        check_token_program(self.token_program, self.mint_a)?;
+        check_mint_extensions(self.mint_a)?;
+        check_mint_extensions(self.mint_b)?;
+        // check the addresses of the vaults to create
+        let vault_address = |seed| find_vault_address(program_id, self.pool.key, seed);
+        let bumps = [
+            check_vault_address(vault_address(VAULT_A_SEED), self.vault_a)?,
+            check_vault_address(vault_address(VAULT_B_SEED), self.vault_b)?,
+            check_vault_address(vault_address(FEE_VAULT_SEED), self.fee_vault)?,
+        ];
+        check_pda(program_id, self.pool, nonce, self.pool_pda)?;
+        check_system_program(self.system_program)?;
+        Ok((mint_a, mint_b, bumps))
+    }
+}
+
+/// Accounts of the owner instructions `UpdateStatus` and `UpdateTolerance`
+pub struct OwnerAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> OwnerAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, owner] = array_ref![check_len(accounts, 2)?, 0, 2];
+        Ok(Self { pool, owner })
+    }
+
+    /// Check the owner signs for an initialized pool, returns the pool
+    pub fn validate(&self, program_id: &Pubkey) -> Result<RefMut<'a, AmmPoolView>, ProgramError> {
+        let pool = AmmPoolView::load_mut(self.pool)?;
+        check_pool_owner(program_id, self.pool, &pool, self.owner)?;
+        Ok(pool)
+    }
+}
+
+/// Accounts of `Terminate`
+pub struct TerminateAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub vault_a: &'a AccountInfo<'b>,
+    pub vault_b: &'a AccountInfo<'b>,
+    pub fee_vault: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub owner_token_a: &'a AccountInfo<'b>,
+    pub owner_token_b: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+    // trailing accounts: the virtual orders and the wallet that paid their rent while
+    // the pool has them, then the farm, reward vault, owner reward account and reward mint
+    pub trailing: &'a [AccountInfo<'b>],
+}
+
+impl<'a, 'b> TerminateAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let trailing = accounts.get(11..).unwrap_or_default();
+        let [pool, owner, vault_a, vault_b, fee_vault, pool_pda, owner_token_a, owner_token_b, token_program, mint_a, mint_b] =
+            array_ref![check_len(accounts, 11)?, 0, 11];
+        Ok(Self {
+            pool,
+            owner,
+            vault_a,
+            vault_b,
+            fee_vault,
+            pool_pda,
+            owner_token_a,
+            owner_token_b,
+            token_program,
+            mint_a,
+            mint_b,
+            trailing,
+        })
+    }
+
+    /// Check the owner and every pool account, returns the pool and its vaults
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(Ref<'a, AmmPoolView>, Account, Account, Account), ProgramError> {
+        let pool = AmmPoolView::load(self.pool)?;
+        check_pool_owner(program_id, self.pool, &pool, self.owner)?;
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_a)?;
+        check_pool_vault(self.vault_a, &pool.vault_a)?;
+        check_pool_vault(self.vault_b, &pool.vault_b)?;
+        check_pool_vault(self.fee_vault, &pool.fee_vault)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        let vault_a = unpack_vault(self.vault_a, self.pool_pda, self.token_program)?;
+        let vault_b = unpack_vault(self.vault_b, self.pool_pda, self.token_program)?;
+        let fee_vault = unpack_vault(self.fee_vault, self.pool_pda, self.token_program)?;
+        Ok((pool, vault_a, vault_b, fee_vault))
+    }
+
+    /// Check the trailing farm accounts, they are required once the pool created its
+    /// farm and follow those of the virtual orders. Returns them with the reward vault,
+    /// `None` for a pool that never had a farm
+    #[allow(clippy::type_complexity)]
+    pub fn validate_rewards(
+        &self,
+        program_id: &Pubkey,
+        pool: &AmmPoolView,
+    ) -> Result<Option<(&'a [AccountInfo<'b>; 4], Account)>, ProgramError> {
+        if !pool.farm() {
+            return Ok(None);
+        }
+        let skip = if pool.twamm() {
+            TwammAccounts::LEN + 1
+        } else {
+            0
+        };
+        let rewards = self.trailing.get(skip..).unwrap_or_default();
+        let rewards = array_ref![check_len(rewards, 4)?, 0, 4];
+        let [farm, reward_vault, owner_token_reward, reward_mint] = rewards;
+        let farm = unpack_farm(program_id, self.pool, farm)?;
+        check_farm_reward(
+            &farm,
+            reward_vault,
+            reward_mint,
+            self.pool_pda,
+            self.token_program,
+        )?;
+        check_token_mint(owner_token_reward, reward_mint.key)?;
+        let reward_vault = unpack_vault(reward_vault, self.pool_pda, self.token_program)?;
+        Ok(Some((rewards, reward_vault)))
+    }
+
+    /// Check the trailing virtual orders accounts and the wallet that paid their rent,
+    /// they are required once the pool created them. Returns them with both escrows,
+    /// `None` for a pool that never had long-term orders
+    #[allow(clippy::type_complexity)]
+    pub fn validate_twamm(
+        &self,
+        program_id: &Pubkey,
+        pool: &AmmPoolView,
+    ) -> Result<Option<(TwammAccounts<'a, 'b>, &'a AccountInfo<'b>, Account, Account)>, ProgramError>
+    {
+        if !pool.twamm() {
+            return Ok(None);
+        }
+        let accounts = check_len(self.trailing, TwammAccounts::LEN + 1)?;
+        let twamm_accounts = TwammAccounts::load(accounts)?;
+        let payer = &accounts[TwammAccounts::LEN];
+        let twamm =
+            twamm_accounts.validate(program_id, self.pool, self.pool_pda, self.token_program)?;
+        if twamm.payer != *payer.key {
+            msg!("twamm payer not match {} {}", twamm.payer, *payer.key);
+            return Err(AmmError::InvalidOwner.into());
+        }
+        let escrow_a = unpack_token_account(twamm_accounts.escrow_a)?;
+        let escrow_b = unpack_token_account(twamm_accounts.escrow_b)?;
+        Ok(Some((twamm_accounts, payer, escrow_a, escrow_b)))
+    }
+}
+
+/// trailing accounts to wrap native sol: the wsol account, the native mint and the system program
+pub(crate) const NATIVE_LEN: usize = 3;
+
+/// Accounts of `Swap`
+pub struct SwapAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub vault_a: &'a AccountInfo<'b>,
+    pub vault_b: &'a AccountInfo<'b>,
+    pub fee_vault: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub user_wallet: &'a AccountInfo<'b>,
+    pub user_token_a: &'a AccountInfo<'b>,
+    pub user_token_b: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+    // trailing accounts: virtual orders, to wrap native sol, then the referrer
+    pub native: &'a [AccountInfo<'b>],
+}
+
+impl<'a, 'b> SwapAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let native = accounts.get(11..).unwrap_or_default();
+        let [pool, vault_a, vault_b, fee_vault, pool_pda, user_wallet, user_token_a, user_token_b, token_program, mint_a, mint_b] =
+            array_ref![check_len(accounts, 11)?, 0, 11];
+        Ok(Self {
+            pool,
+            vault_a,
+            vault_b,
+            fee_vault,
+            pool_pda,
+            user_wallet,
+            user_token_a,
+            user_token_b,
+            token_program,
+            mint_a,
+            mint_b,
+            native,
+        })
+    }
+
+    /// Check the user signs and every pool account, returns the pool and its vaults
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(RefMut<'a, AmmPoolView>, Account, Account), ProgramError> {
+        let pool = AmmPoolView::load_mut(self.pool)?;
+        check_signer(self.user_wallet)?;
+        check_account_owner(self.pool, program_id)?;
+        check_pool_vault(self.vault_a, &pool.vault_a)?;
+        check_pool_vault(self.vault_b, &pool.vault_b)?;
+        check_pool_vault(self.fee_vault, &pool.fee_vault)?;
+        let status = pool.status()?;
+        if status != PoolStatus::Nomal {
+            msg!("pool status:{}", status);
+            return Err(AmmError::PoolLock.into());
+        }
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_a)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        let vault_a = unpack_vault(self.vault_a, self.pool_pda, self.token_program)?;
+        let vault_b = unpack_vault(self.vault_b, self.pool_pda, self.token_program)?;
+        unpack_vault(self.fee_vault, self.pool_pda, self.token_program)?;
+        Ok((pool, vault_a, vault_b))
+    }
+}
+
+/// Accounts of `WithdrawalFee`
+pub struct WithdrawalFeeAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub fee_vault: &'a AccountInfo<'b>,
+    pub fee_receiver: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> WithdrawalFeeAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, owner, fee_vault, fee_receiver, pool_pda, token_program, mint_b] =
+            array_ref![check_len(accounts, 7)?, 0, 7];
+        Ok(Self {
+            pool,
+            owner,
+            fee_vault,
+            fee_receiver,
+            pool_pda,
+            token_program,
+            mint_b,
+        })
+    }
+
+    /// Check the owner and the fee vault, returns the pool and its fee vault
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(Ref<'a, AmmPoolView>, Account), ProgramError> {
+        let pool = AmmPoolView::load(self.pool)?;
+        check_pool_owner(program_id, self.pool, &pool, self.owner)?;
+        check_pool_vault(self.fee_vault, &pool.fee_vault)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_b)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        let fee_vault = unpack_vault(self.fee_vault, self.pool_pda, self.token_program)?;
+        Ok((pool, fee_vault))
+    }
+}
+
+/// Accounts of `PlaceOrder`
+pub struct PlaceOrderAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub order: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub owner_token_in: &'a AccountInfo<'b>,
+    pub owner_token_out: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+    pub system_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> PlaceOrderAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, order, owner, escrow, pool_pda, owner_token_in, owner_token_out, token_program, mint_a, mint_b, system_program] =
+            array_ref![check_len(accounts, 11)?, 0, 11];
+        Ok(Self {
+            pool,
+            order,
+            owner,
+            escrow,
+            pool_pda,
+            owner_token_in,
+            owner_token_out,
+            token_program,
+            mint_a,
+            mint_b,
+            system_program,
+        })
+    }
+
+    /// Check the accounts of a new order, returns the pool and the bump of the escrow
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+        direction: Direction,
+    ) -> Result<(RefMut<'a, AmmPoolView>, u8), ProgramError> {
+        let pool = AmmPoolView::load_mut(self.pool)?;
+        check_signer(self.owner)?;
+        check_signer(self.order)?;
+        check_account_owner(self.pool, program_id)?;
+        check_open_pool(&pool)?;
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_a)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        let bump = check_vault_address(
+            find_order_escrow_address(program_id, self.order.key),
+            self.escrow,
+        )?;
+        let (mint_in, mint_out) = match direction {
+            Direction::A2B => (&pool.mint_a, &pool.mint_b),
+            Direction::B2A => (&pool.mint_b, &pool.mint_a),
+        };
+        check_token_mint(self.owner_token_in, mint_in)?;
+        check_token_mint(self.owner_token_out, mint_out)?;
+        check_system_program(self.system_program)?;
+        Ok((pool, bump))
+    }
+}
+
+/// Accounts of `CancelOrder`
+pub struct CancelOrderAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub order: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub owner_token_in: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> CancelOrderAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, order, owner, escrow, pool_pda, owner_token_in, token_program, mint_a, mint_b] =
+            array_ref![check_len(accounts, 9)?, 0, 9];
+        Ok(Self {
+            pool,
+            order,
+            owner,
+            escrow,
+            pool_pda,
+            owner_token_in,
+            token_program,
+            mint_a,
+            mint_b,
+        })
+    }
+
+    /// Check the order is open and every account matches it, returns the pool, the order
+    /// and its escrow. Whether the owner must sign depends on the pool status, the
+    /// processor checks it.
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(RefMut<'a, AmmPoolView>, Order, Account), ProgramError> {
+        let pool = AmmPoolView::load_mut(self.pool)?;
+        check_account_owner(self.pool, program_id)?;
+        let order = unpack_order(program_id, self.pool, self.order)?.ok_or_else(|| {
+            msg!("order {} is not open", self.order.key);
+            AmmError::InvalidOrder
+        })?;
+        check_order_account(self.owner, &order.owner)?;
+        check_order_account(self.escrow, &order.escrow)?;
+        check_order_account(self.owner_token_in, &order.token_in)?;
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_a)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        let escrow = unpack_vault(self.escrow, self.pool_pda, self.token_program)?;
+        Ok((pool, order, escrow))
+    }
+}
+
+/// Accounts of `FillOrders`
+pub struct FillOrdersAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub vault_a: &'a AccountInfo<'b>,
+    pub vault_b: &'a AccountInfo<'b>,
+    pub fee_vault: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub keeper: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+    // trailing accounts: virtual orders, then groups of `OrderAccounts`
+    pub orders: &'a [AccountInfo<'b>],
+}
+
+impl<'a, 'b> FillOrdersAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let orders = accounts.get(9..).unwrap_or_default();
+        let [pool, vault_a, vault_b, fee_vault, pool_pda, keeper, token_program, mint_a, mint_b] =
+            array_ref![check_len(accounts, 9)?, 0, 9];
+        Ok(Self {
+            pool,
+            vault_a,
+            vault_b,
+            fee_vault,
+            pool_pda,
+            keeper,
+            token_program,
+            mint_a,
+            mint_b,
+            orders,
+        })
+    }
+
+    /// Check the keeper signs and every pool account, returns the pool
+    pub fn validate(&self, program_id: &Pubkey) -> Result<RefMut<'a, AmmPoolView>, ProgramError> {
+        let pool = AmmPoolView::load_mut(self.pool)?;
+        check_signer(self.keeper)?;
+        check_account_owner(self.pool, program_id)?;
+        check_pool_vault(self.vault_a, &pool.vault_a)?;
+        check_pool_vault(self.vault_b, &pool.vault_b)?;
+        check_pool_vault(self.fee_vault, &pool.fee_vault)?;
+        check_open_pool(&pool)?;
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_a)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        unpack_vault(self.vault_a, self.pool_pda, self.token_program)?;
+        unpack_vault(self.vault_b, self.pool_pda, self.token_program)?;
+        Ok(pool)
+    }
+
+    /// Split the virtual orders off the trailing accounts, they come first while the pool
+    /// has long-term orders. Returns them and the order groups after them.
+    pub fn split(
+        &self,
+        pool: &AmmPoolView,
+    ) -> Result<
+        (
+            Option<TwammAccounts<'a, 'b>>,
+            impl Iterator<Item = OrderAccounts<'a, 'b>>,
+        ),
+        ProgramError,
+    > {
+        let (twamm, orders) = TwammAccounts::split(pool, self.orders)?;
+        if orders.len() % OrderAccounts::LEN != 0 {
+            msg!(
+                "order accounts come in groups of {}, received {}",
+                OrderAccounts::LEN,
+                orders.len()
+            );
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let orders = orders.chunks(OrderAccounts::LEN).map(|group| {
+            let [order, escrow, owner, token_out] = array_ref![group, 0, OrderAccounts::LEN];
+            OrderAccounts {
+                order,
+                escrow,
+                owner,
+                token_out,
+            }
+        });
+        Ok((twamm, orders))
+    }
+}
+
+/// Accounts of one order filled by `FillOrders`
+pub struct OrderAccounts<'a, 'b> {
+    pub order: &'a AccountInfo<'b>,
+    pub escrow: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub token_out: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> OrderAccounts<'a, 'b> {
+    pub const LEN: usize = 4;
+
+    /// Check the accounts match the order, returns the order and its escrow.
+    /// `None` if the order is no longer open, a keeper racing another one skips it.
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+        pool_acc: &AccountInfo,
+        pool_pda: &AccountInfo,
+        token_program_acc: &AccountInfo,
+    ) -> Result<Option<(Order, Account)>, ProgramError> {
+        let order = match unpack_order(program_id, pool_acc, self.order)? {
+            Some(order) => order,
+            None => return Ok(None),
+        };
+        check_order_account(self.escrow, &order.escrow)?;
+        check_order_account(self.owner, &order.owner)?;
+        check_order_account(self.token_out, &order.token_out)?;
+        let escrow = unpack_vault(self.escrow, pool_pda, token_program_acc)?;
+        Ok(Some((order, escrow)))
+    }
+}
+
+/// Accounts of the virtual orders of a pool
+#[derive(Clone, Copy)]
+pub struct TwammAccounts<'a, 'b> {
+    pub twamm: &'a AccountInfo<'b>,
+    pub escrow_a: &'a AccountInfo<'b>,
+    pub escrow_b: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> TwammAccounts<'a, 'b> {
+    pub const LEN: usize = 3;
+
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [twamm, escrow_a, escrow_b] = array_ref![check_len(accounts, Self::LEN)?, 0, 3];
+        Ok(Self {
+            twamm,
+            escrow_a,
+            escrow_b,
+        })
+    }
+
+    /// Split the accounts of the virtual orders off the trailing accounts of `Swap`,
+    /// they come first while the pool has long-term orders
+    pub fn split(
+        pool: &AmmPoolView,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(Option<Self>, &'a [AccountInfo<'b>]), ProgramError> {
+        if pool.long_term_orders() == 0 {
+            return Ok((None, accounts));
+        }
+        let twamm = Self::load(accounts)?;
+        Ok((Some(twamm), &accounts[Self::LEN..]))
+    }
+
+    /// The first long-term order of a pool creates the accounts
+    pub fn is_new(&self, program_id: &Pubkey) -> bool {
+        self.twamm.owner != program_id
+    }
+
+    /// Check the accounts to create are at the derived addresses, returns the bumps of
+    /// the virtual orders account and both escrows
+    pub fn validate_new(
+        &self,
+        program_id: &Pubkey,
+        pool_acc: &AccountInfo,
+    ) -> Result<[u8; 3], ProgramError> {
+        let (twamm, bump) = find_twamm_address(program_id, pool_acc.key);
+        if twamm != *self.twamm.key {
+            msg!("twamm not match {} {}", twamm, *self.twamm.key);
+            return Err(AmmError::InvalidPDA.into());
+        }
+        let escrow_address = |seed| find_vault_address(program_id, pool_acc.key, seed);
+        Ok([
+            bump,
+            check_vault_address(escrow_address(TWAMM_ESCROW_A_SEED), self.escrow_a)?,
+            check_vault_address(escrow_address(TWAMM_ESCROW_B_SEED), self.escrow_b)?,
+        ])
+    }
+
+    /// Check the virtual orders belong to the pool, returns them
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+        pool_acc: &AccountInfo,
+        pool_pda: &AccountInfo,
+        token_program_acc: &AccountInfo,
+    ) -> Result<Twamm, ProgramError> {
+        check_account_owner(self.twamm, program_id)?;
+        let data = self.twamm.try_borrow_data()?;
+        if data.len() != Twamm::LEN {
+            msg!("twamm {} has {} bytes", self.twamm.key, data.len());
+            return Err(AmmError::InvalidPDA.into());
+        }
+        let twamm = Twamm::unpack_from_slice(&data)?;
+        if twamm.pool != *pool_acc.key {
+            msg!("twamm pool not match {} {}", twamm.pool, *pool_acc.key);
+            return Err(AmmError::InvalidPDA.into());
+        }
+        check_pool_vault(self.escrow_a, &twamm.escrow_a)?;
+        check_pool_vault(self.escrow_b, &twamm.escrow_b)?;
+        unpack_vault(self.escrow_a, pool_pda, token_program_acc)?;
+        unpack_vault(self.escrow_b, pool_pda, token_program_acc)?;
+        Ok(twamm)
+    }
+}
+
+/// Accounts of `ExecuteVirtualOrders`, the instructions running the virtual orders
+/// before they trade build one too
+#[derive(Clone, Copy)]
+pub struct VirtualOrdersAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub twamm: TwammAccounts<'a, 'b>,
+    pub vault_a: &'a AccountInfo<'b>,
+    pub vault_b: &'a AccountInfo<'b>,
+    pub fee_vault: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> VirtualOrdersAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, twamm, escrow_a, escrow_b, vault_a, vault_b, fee_vault, pool_pda, token_program, mint_a, mint_b] =
+            array_ref![check_len(accounts, 11)?, 0, 11];
+        Ok(Self {
+            pool,
+            twamm: TwammAccounts {
+                twamm,
+                escrow_a,
+                escrow_b,
+            },
+            vault_a,
+            vault_b,
+            fee_vault,
+            pool_pda,
+            token_program,
+            mint_a,
+            mint_b,
+        })
+    }
+
+    /// Check every pool account and the virtual orders, returns the pool and them
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(Ref<'a, AmmPoolView>, Twamm), ProgramError> {
+        let pool = AmmPoolView::load(self.pool)?;
+        check_account_owner(self.pool, program_id)?;
+        check_open_pool(&pool)?;
+        self.check_pool(program_id, &pool)?;
+        let twamm =
+            self.twamm
+                .validate(program_id, self.pool, self.pool_pda, self.token_program)?;
+        Ok((pool, twamm))
+    }
+
+    /// Check the vaults, mints and pda of the pool
+    fn check_pool(&self, program_id: &Pubkey, pool: &AmmPoolView) -> Result<(), ProgramError> {
+        check_pool_vault(self.vault_a, &pool.vault_a)?;
+        check_pool_vault(self.vault_b, &pool.vault_b)?;
+        check_pool_vault(self.fee_vault, &pool.fee_vault)?;
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_a)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        unpack_vault(self.vault_a, self.pool_pda, self.token_program)?;
+        unpack_vault(self.vault_b, self.pool_pda, self.token_program)?;
+        Ok(())
+    }
+}
+
+/// Accounts of `PlaceLongTermOrder`
+pub struct PlaceLongTermOrderAccounts<'a, 'b> {
+    pub order: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub virtual_orders: VirtualOrdersAccounts<'a, 'b>,
+    pub owner_token_in: &'a AccountInfo<'b>,
+    pub owner_token_out: &'a AccountInfo<'b>,
+    pub system_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> PlaceLongTermOrderAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, order, owner, twamm, escrow_a, escrow_b, vault_a, vault_b, fee_vault, pool_pda, owner_token_in, owner_token_out, token_program, mint_a, mint_b, system_program] =
+            array_ref![check_len(accounts, 16)?, 0, 16];
+        Ok(Self {
+            order,
+            owner,
+            virtual_orders: VirtualOrdersAccounts {
+                pool,
+                twamm: TwammAccounts {
+                    twamm,
+                    escrow_a,
+                    escrow_b,
+                },
+                vault_a,
+                vault_b,
+                fee_vault,
+                pool_pda,
+                token_program,
+                mint_a,
+                mint_b,
+            },
+            owner_token_in,
+            owner_token_out,
+            system_program,
+        })
+    }
+
+    /// Check the accounts of a new long-term order, returns the pool and its virtual
+    /// orders, `None` if this order creates them
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+        direction: Direction,
+    ) -> Result<(RefMut<'a, AmmPoolView>, Option<Twamm>), ProgramError> {
+        let accounts = &self.virtual_orders;
+        let pool = AmmPoolView::load_mut(accounts.pool)?;
+        check_signer(self.owner)?;
+        check_signer(self.order)?;
+        check_account_owner(accounts.pool, program_id)?;
+        check_open_pool(&pool)?;
+        accounts.check_pool(program_id, &pool)?;
+        let (mint_in, mint_out) = match direction {
+            Direction::A2B => (&pool.mint_a, &pool.mint_b),
+            Direction::B2A => (&pool.mint_b, &pool.mint_a),
+        };
+        check_token_mint(self.owner_token_in, mint_in)?;
+        check_token_mint(self.owner_token_out, mint_out)?;
+        check_system_program(self.system_program)?;
+        let twamm = if accounts.twamm.is_new(program_id) {
+            None
+        } else {
+            Some(accounts.twamm.validate(
+                program_id,
+                accounts.pool,
+                accounts.pool_pda,
+                accounts.token_program,
+            )?)
+        };
+        Ok((pool, twamm))
+    }
+}
+
+/// Accounts of `CancelLongTermOrder`
+pub struct CancelLongTermOrderAccounts<'a, 'b> {
+    pub order: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub virtual_orders: VirtualOrdersAccounts<'a, 'b>,
+    pub owner_token_in: &'a AccountInfo<'b>,
+    pub owner_token_out: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> CancelLongTermOrderAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, order, owner, twamm, escrow_a, escrow_b, vault_a, vault_b, fee_vault, pool_pda, owner_token_in, owner_token_out, token_program, mint_a, mint_b] =
+            array_ref![check_len(accounts, 15)?, 0, 15];
+        Ok(Self {
+            order,
+            owner,
+            virtual_orders: VirtualOrdersAccounts {
+                pool,
+                twamm: TwammAccounts {
+                    twamm,
+                    escrow_a,
+                    escrow_b,
+                },
+                vault_a,
+                vault_b,
+                fee_vault,
+                pool_pda,
+                token_program,
+                mint_a,
+                mint_b,
+            },
+            owner_token_in,
+            owner_token_out,
+        })
+    }
+
+    /// Check the order is open and every account matches it, returns the pool, the order
+    /// and the virtual orders. Whether the owner must sign depends on the pool status,
+    /// the processor checks it.
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(RefMut<'a, AmmPoolView>, LongTermOrder, Twamm), ProgramError> {
+        let accounts = &self.virtual_orders;
+        let pool = AmmPoolView::load_mut(accounts.pool)?;
+        check_account_owner(accounts.pool, program_id)?;
+        let order = unpack_long_term_order(program_id, accounts.pool, self.order)?;
+        check_order_account(self.owner, &order.owner)?;
+        check_order_account(self.owner_token_in, &order.token_in)?;
+        check_order_account(self.owner_token_out, &order.token_out)?;
+        accounts.check_pool(program_id, &pool)?;
+        let twamm = accounts.twamm.validate(
+            program_id,
+            accounts.pool,
+            accounts.pool_pda,
+            accounts.token_program,
+        )?;
+        Ok((pool, order, twamm))
+    }
+}
+
+/// Accounts of `FundFarm`
+pub struct FundFarmAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub farm: &'a AccountInfo<'b>,
+    pub reward_vault: &'a AccountInfo<'b>,
+    pub vault_a: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub owner_token_reward: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub reward_mint: &'a AccountInfo<'b>,
+    pub system_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> FundFarmAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, owner, farm, reward_vault, vault_a, pool_pda, owner_token_reward, token_program, reward_mint, system_program] =
+            array_ref![check_len(accounts, 10)?, 0, 10];
+        Ok(Self {
+            pool,
+            owner,
+            farm,
+            reward_vault,
+            vault_a,
+            pool_pda,
+            owner_token_reward,
+            token_program,
+            reward_mint,
+            system_program,
+        })
+    }
+
+    /// Check the owner funds the farm of the pool, returns the pool, vault a and the
+    /// farm, `None` if this instruction creates it
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(RefMut<'a, AmmPoolView>, Account, Option<Farm>), ProgramError> {
+        let pool = AmmPoolView::load_mut(self.pool)?;
+        check_pool_owner(program_id, self.pool, &pool, self.owner)?;
+        check_pool_vault(self.vault_a, &pool.vault_a)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        // the rewards move with the token program of the pool
+        let vault_a = unpack_vault(self.vault_a, self.pool_pda, self.token_program)?;
+        check_token_program(self.token_program, self.reward_mint)?;
+        check_token_mint(self.owner_token_reward, self.reward_mint.key)?;
+        check_system_program(self.system_program)?;
+        let farm = if self.farm.owner != program_id {
+            None
+        } else {
+            let farm = unpack_farm(program_id, self.pool, self.farm)?;
+            check_farm_reward(
+                &farm,
+                self.reward_vault,
+                self.reward_mint,
+                self.pool_pda,
+                self.token_program,
+            )?;
+            Some(farm)
+        };
+        Ok((pool, vault_a, farm))
+    }
+
+    /// Check the farm and reward vault to create are at the derived addresses, returns
+    /// their bumps
+    pub fn validate_new(&self, program_id: &Pubkey) -> Result<[u8; 2], ProgramError> {
+        let (farm, bump) = find_farm_address(program_id, self.pool.key);
+        if farm != *self.farm.key {
+            msg!("farm not match {} {}", farm, *self.farm.key);
+            return Err(AmmError::InvalidPDA.into());
+        }
+        let reward_vault = find_vault_address(program_id, self.pool.key, FARM_REWARD_SEED);
+        Ok([bump, check_vault_address(reward_vault, self.reward_vault)?])
+    }
+}
+
+/// Accounts of `Stake`
+pub struct StakeAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub farm: &'a AccountInfo<'b>,
+    pub stake: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub vault_a: &'a AccountInfo<'b>,
+    pub vault_b: &'a AccountInfo<'b>,
+    pub fee_vault: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub owner_token_a: &'a AccountInfo<'b>,
+    pub owner_token_b: &'a AccountInfo<'b>,
+    pub owner_token_reward: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+    pub system_program: &'a AccountInfo<'b>,
+    // trailing accounts of the virtual orders
+    pub twamm: &'a [AccountInfo<'b>],
+}
+
+impl<'a, 'b> StakeAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let twamm = accounts.get(15..).unwrap_or_default();
+        let [pool, farm, stake, owner, vault_a, vault_b, fee_vault, pool_pda, owner_token_a, owner_token_b, owner_token_reward, token_program, mint_a, mint_b, system_program] =
+            array_ref![check_len(accounts, 15)?, 0, 15];
+        Ok(Self {
+            pool,
+            farm,
+            stake,
+            owner,
+            vault_a,
+            vault_b,
+            fee_vault,
+            pool_pda,
+            owner_token_a,
+            owner_token_b,
+            owner_token_reward,
+            token_program,
+            mint_a,
+            mint_b,
+            system_program,
+            twamm,
+        })
+    }
+
+    /// Check the owner stakes in the farm of an open pool, returns the pool, the farm,
+    /// the stake, `None` if this instruction creates it, and the bump of its address
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(RefMut<'a, AmmPoolView>, Farm, Option<Stake>, u8), ProgramError> {
+        let pool = AmmPoolView::load_mut(self.pool)?;
+        check_signer(self.owner)?;
+        check_account_owner(self.pool, program_id)?;
+        check_open_pool(&pool)?;
+        check_pool_vault(self.vault_a, &pool.vault_a)?;
+        check_pool_vault(self.vault_b, &pool.vault_b)?;
+        check_pool_vault(self.fee_vault, &pool.fee_vault)?;
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_a)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        unpack_vault(self.vault_a, self.pool_pda, self.token_program)?;
+        unpack_vault(self.vault_b, self.pool_pda, self.token_program)?;
+        check_system_program(self.system_program)?;
+        let farm = unpack_farm(program_id, self.pool, self.farm)?;
+        let (address, bump) = find_stake_address(program_id, self.pool.key, self.owner.key);
+        if address != *self.stake.key {
+            msg!("stake not match {} {}", address, *self.stake.key);
+            return Err(AmmError::InvalidPDA.into());
+        }
+        let stake = if self.stake.owner != program_id {
+            check_token_mint(self.owner_token_a, &pool.mint_a)?;
+            check_token_mint(self.owner_token_b, &pool.mint_b)?;
+            check_token_mint(self.owner_token_reward, &farm.reward_mint)?;
+            None
+        } else {
+            let stake = unpack_stake(program_id, self.pool, self.stake)?;
+            check_stake_account(self.owner_token_a, &stake.token_a)?;
+            check_stake_account(self.owner_token_b, &stake.token_b)?;
+            check_stake_account(self.owner_token_reward, &stake.token_reward)?;
+            Some(stake)
+        };
+        Ok((pool, farm, stake, bump))
+    }
+
+    /// The accounts of the virtual orders, while the pool has long-term orders
+    pub fn virtual_orders(
+        &self,
+        pool: &AmmPoolView,
+    ) -> Result<Option<VirtualOrdersAccounts<'a, 'b>>, ProgramError> {
+        let (twamm, _) = TwammAccounts::split(pool, self.twamm)?;
+        Ok(twamm.map(|twamm| VirtualOrdersAccounts {
+            pool: self.pool,
+            twamm,
+            vault_a: self.vault_a,
+            vault_b: self.vault_b,
+            fee_vault: self.fee_vault,
+            pool_pda: self.pool_pda,
+            token_program: self.token_program,
+            mint_a: self.mint_a,
+            mint_b: self.mint_b,
+        }))
+    }
+}
+
+/// Accounts of `Unstake`
+pub struct UnstakeAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub farm: &'a AccountInfo<'b>,
+    pub stake: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub vault_a: &'a AccountInfo<'b>,
+    pub vault_b: &'a AccountInfo<'b>,
+    pub fee_vault: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub owner_token_a: &'a AccountInfo<'b>,
+    pub owner_token_b: &'a AccountInfo<'b>,
+    pub reward_vault: &'a AccountInfo<'b>,
+    pub owner_token_reward: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+    pub reward_mint: &'a AccountInfo<'b>,
+    // trailing accounts of the virtual orders
+    pub twamm: &'a [AccountInfo<'b>],
+}
+
+impl<'a, 'b> UnstakeAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let twamm = accounts.get(16..).unwrap_or_default();
+        let [pool, farm, stake, owner, vault_a, vault_b, fee_vault, pool_pda, owner_token_a, owner_token_b, reward_vault, owner_token_reward, token_program, mint_a, mint_b, reward_mint] =
+            array_ref![check_len(accounts, 16)?, 0, 16];
+        Ok(Self {
+            pool,
+            farm,
+            stake,
+            owner,
+            vault_a,
+            vault_b,
+            fee_vault,
+            pool_pda,
+            owner_token_a,
+            owner_token_b,
+            reward_vault,
+            owner_token_reward,
+            token_program,
+            mint_a,
+            mint_b,
+            reward_mint,
+            twamm,
+        })
+    }
+
+    /// Check the stake and every account matches it, returns the pool, the farm and the
+    /// stake. Whether the owner must sign depends on the pool status, the processor
+    /// checks it.
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(RefMut<'a, AmmPoolView>, Farm, Stake), ProgramError> {
+        let pool = AmmPoolView::load_mut(self.pool)?;
+        check_account_owner(self.pool, program_id)?;
+        check_pool_vault(self.vault_a, &pool.vault_a)?;
+        check_pool_vault(self.vault_b, &pool.vault_b)?;
+        check_pool_vault(self.fee_vault, &pool.fee_vault)?;
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        check_token_program(self.token_program, self.mint_a)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        unpack_vault(self.vault_a, self.pool_pda, self.token_program)?;
+        unpack_vault(self.vault_b, self.pool_pda, self.token_program)?;
+        let farm = unpack_farm(program_id, self.pool, self.farm)?;
+        check_farm_reward(
+            &farm,
+            self.reward_vault,
+            self.reward_mint,
+            self.pool_pda,
+            self.token_program,
+        )?;
+        let stake = unpack_stake(program_id, self.pool, self.stake)?;
+        check_stake_account(self.owner, &stake.owner)?;
+        check_stake_account(self.owner_token_a, &stake.token_a)?;
+        check_stake_account(self.owner_token_b, &stake.token_b)?;
+        check_stake_account(self.owner_token_reward, &stake.token_reward)?;
+        Ok((pool, farm, stake))
+    }
+
+    /// The accounts of the virtual orders, while the pool has long-term orders
+    pub fn virtual_orders(
+        &self,
+        pool: &AmmPoolView,
+    ) -> Result<Option<VirtualOrdersAccounts<'a, 'b>>, ProgramError> {
+        let (twamm, _) = TwammAccounts::split(pool, self.twamm)?;
+        Ok(twamm.map(|twamm| VirtualOrdersAccounts {
+            pool: self.pool,
+            twamm,
+            vault_a: self.vault_a,
+            vault_b: self.vault_b,
+            fee_vault: self.fee_vault,
+            pool_pda: self.pool_pda,
+            token_program: self.token_program,
+            mint_a: self.mint_a,
+            mint_b: self.mint_b,
+        }))
+    }
+}
+
+/// Accounts of `ClaimRewards`
+pub struct ClaimRewardsAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub farm: &'a AccountInfo<'b>,
+    pub stake: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub reward_vault: &'a AccountInfo<'b>,
+    pub pool_pda: &'a AccountInfo<'b>,
+    pub owner_token_reward: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub reward_mint: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> ClaimRewardsAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, farm, stake, owner, reward_vault, pool_pda, owner_token_reward, token_program, reward_mint] =
+            array_ref![check_len(accounts, 9)?, 0, 9];
+        Ok(Self {
+            pool,
+            farm,
+            stake,
+            owner,
+            reward_vault,
+            pool_pda,
+            owner_token_reward,
+            token_program,
+            reward_mint,
+        })
+    }
+
+    /// Check the owner signs for its stake, returns the pool, the farm and the stake
+    pub fn validate(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<(Ref<'a, AmmPoolView>, Farm, Stake), ProgramError> {
+        let pool = AmmPoolView::load(self.pool)?;
+        check_signer(self.owner)?;
+        check_account_owner(self.pool, program_id)?;
+        check_pda(program_id, self.pool, pool.nonce, self.pool_pda)?;
+        let farm = unpack_farm(program_id, self.pool, self.farm)?;
+        check_farm_reward(
+            &farm,
+            self.reward_vault,
+            self.reward_mint,
+            self.pool_pda,
+            self.token_program,
+        )?;
+        let stake = unpack_stake(program_id, self.pool, self.stake)?;
+        check_stake_account(self.owner, &stake.owner)?;
+        check_stake_account(self.owner_token_reward, &stake.token_reward)?;
+        Ok((pool, farm, stake))
+    }
+}
+
+/// Accounts of `Migrate`
+pub struct MigrateAccounts<'a, 'b> {
+    pub pool: &'a AccountInfo<'b>,
+    pub owner: &'a AccountInfo<'b>,
+    pub mint_a: &'a AccountInfo<'b>,
+    pub mint_b: &'a AccountInfo<'b>,
+    pub system_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> MigrateAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let [pool, owner, mint_a, mint_b, system_program] =
+            array_ref![check_len(accounts, 5)?, 0, 5];
+        Ok(Self {
+            pool,
+            owner,
+            mint_a,
+            mint_b,
+            system_program,
+        })
+    }
+
+    /// Check the owner signs for a pool of an earlier layout, returns the pool and both mints
+    pub fn validate(&self, program_id: &Pubkey) -> Result<(AmmPool, Mint, Mint), ProgramError> {
+        check_signer(self.owner)?;
+        check_account_owner(self.pool, program_id)?;
+        // every earlier layout is a prefix of the current one
+        let len = self.pool.data_len();
+        if !(AmmPool::LEGACY_LEN..AmmPool::LEN).contains(&len) {
+            msg!("pool of {} bytes can't be migrated", len);
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let pool = AmmPool::unpack_any(&self.pool.data.borrow())?;
+        match pool.status {
+            PoolStatus::NotInit => return Err(ProgramError::UninitializedAccount),
+            PoolStatus::Closed => return Err(AmmError::PoolClosed.into()),
+            _ => {}
+        }
+        if pool.owner != *self.owner.key {
+            msg!("owner not match {} {}", pool.owner, *self.owner.key);
+            return Err(AmmError::InvalidOwner.into());
+        }
+        check_pool_mint(self.mint_a, &pool.mint_a)?;
+        check_pool_mint(self.mint_b, &pool.mint_b)?;
+        let mint_a = unpack_mint(self.mint_a)?;
+        let mint_b = unpack_mint(self.mint_b)?;
+        check_system_program(self.system_program)?;
+        Ok((pool, mint_a, mint_b))
+    }
+}
+
+/// check account count before `array_ref!`
+pub(crate) fn check_len<'a, 'b>(
+    accounts: &'a [AccountInfo<'b>],
+    len: usize,
+) -> Result<&'a [AccountInfo<'b>], ProgramError> {
+    if accounts.len() < len {
+        msg!("expected {} accounts, received {}", len, accounts.len());
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    Ok(&accounts[..len])
+}
+
+/// Check the account signed the transaction
+fn check_signer(account_info: &AccountInfo) -> Result<(), AmmError> {
+    if !account_info.is_signer {
+        msg!("{} must sign", account_info.key);
+        return Err(AmmError::InvalidSignAccount);
+    }
+    Ok(())
+}
+
+/// Check the account is the pool owner
+fn check_owner(pool: &AmmPoolView, owner_acc: &AccountInfo) -> Result<(), AmmError> {
+    if pool.owner != *owner_acc.key {
+        msg!("owner not match {} {}", pool.owner, *owner_acc.key);
+        return Err(AmmError::InvalidOwner);
+    }
+    Ok(())
+}
+
+/// Check the owner signs for a pool of the program
+fn check_pool_owner(
+    program_id: &Pubkey,
+    pool_acc: &AccountInfo,
+    pool: &AmmPoolView,
+    owner_acc: &AccountInfo,
+) -> Result<(), ProgramError> {
+    check_signer(owner_acc)?;
+    check_account_owner(pool_acc, program_id)?;
+    check_owner(pool, owner_acc)?;
+    Ok(())
+}
+
+/// Check account owner is the given program
+pub(crate) fn check_account_owner(
+    account_info: &AccountInfo,
+    program_id: &Pubkey,
+) -> Result<(), AmmError> {
+    if *program_id != *account_info.owner {
+        msg!(
+            "Expected account to be owned by program {}, received {}",
+            program_id,
+            account_info.owner
+        );
+        Err(AmmError::InvalidProgramAddress)
+    } else {
+        Ok(())
+    }
+}
+
+/// Check the token program is the one that owns the pool mints
+fn check_token_program(
+    token_program_acc: &AccountInfo,
+    mint_acc: &AccountInfo,
+) -> Result<(), AmmError> {
+    if !is_token_program(token_program_acc.key) || token_program_acc.key != mint_acc.owner {
+        msg!(
+            "token program not match {} {}",
+            mint_acc.owner,
+            *token_program_acc.key
+        );
+        return Err(AmmError::InvalidTokenProgramId);
+    }
+    Ok(())
+}
+
+/// Check the vault is the one the pool recorded
+fn check_pool_vault(account_info: &AccountInfo, vault: &Pubkey) -> Result<(), AmmError> {
+    if *account_info.key != *vault {
+        msg!("vault not match {} {}", vault, *account_info.key);
+        return Err(AmmError::InvalidVault);
+    }
+    Ok(())
+}
+
+/// Check a vault to create is at the derived address, returns its bump
+fn check_vault_address(
+    (vault, bump): (Pubkey, u8),
+    account_info: &AccountInfo,
+) -> Result<u8, AmmError> {
+    if vault != *account_info.key {
+        msg!("vault not match {} {}", vault, *account_info.key);
+        return Err(AmmError::InvalidVault);
+    }
+    Ok(bump)
+}
+
+/// Check the pool trades, a locked pool takes no new orders and fills none
+fn check_open_pool(pool: &AmmPoolView) -> Result<(), ProgramError> {
+    let status = pool.status()?;
+    if status != PoolStatus::Nomal {
+        msg!("pool status:{}", status);
+        return Err(AmmError::PoolLock.into());
+    }
+    Ok(())
+}
+
+/// Unpacks an order of the pool, `None` once it was filled or cancelled
+fn unpack_order(
+    program_id: &Pubkey,
+    pool_acc: &AccountInfo,
+    order_acc: &AccountInfo,
+) -> Result<Option<Order>, ProgramError> {
+    // a closed order without lamports left may already belong to the system program
+    if order_acc.lamports() == 0 {
+        return Ok(None);
+    }
+    check_account_owner(order_acc, program_id)?;
+    let data = order_acc.try_borrow_data()?;
+    if data.len() != Order::LEN {
+        msg!("order {} has {} bytes", order_acc.key, data.len());
+        return Err(AmmError::InvalidOrder.into());
+    }
+    let order = Order::unpack_from_slice(&data)?;
+    if order.pool != *pool_acc.key {
+        msg!("order pool not match {} {}", order.pool, *pool_acc.key);
+        return Err(AmmError::InvalidOrder.into());
+    }
+    Ok(match order.status {
+        OrderStatus::Open => Some(order),
+        _ => None,
+    })
+}
+
+/// Unpacks an open long-term order of the pool
+fn unpack_long_term_order(
+    program_id: &Pubkey,
+    pool_acc: &AccountInfo,
+    order_acc: &AccountInfo,
+) -> Result<LongTermOrder, ProgramError> {
+    check_account_owner(order_acc, program_id)?;
+    let data = order_acc.try_borrow_data()?;
+    if data.len() != LongTermOrder::LEN {
+        msg!("order {} has {} bytes", order_acc.key, data.len());
+        return Err(AmmError::InvalidOrder.into());
+    }
+    let order = LongTermOrder::unpack_from_slice(&data)?;
+    if order.pool != *pool_acc.key {
+        msg!("order pool not match {} {}", order.pool, *pool_acc.key);
+        return Err(AmmError::InvalidOrder.into());
+    }
+    if order.status != OrderStatus::Open {
+        msg!("order {} is not open", order_acc.key);
+        return Err(AmmError::InvalidOrder.into());
+    }
+    Ok(order)
+}
+
+/// Check the account is the one the order recorded
+fn check_order_account(account_info: &AccountInfo, key: &Pubkey) -> Result<(), AmmError> {
+    if *account_info.key != *key {
+        msg!("order account not match {} {}", key, *account_info.key);
+        return Err(AmmError::InvalidOrder);
+    }
+    Ok(())
+}
+
+/// Unpacks the farm of the pool
+fn unpack_farm(
+    program_id: &Pubkey,
+    pool_acc: &AccountInfo,
+    farm_acc: &AccountInfo,
+) -> Result<Farm, ProgramError> {
+    check_account_owner(farm_acc, program_id)?;
+    let data = farm_acc.try_borrow_data()?;
+    if data.len() != Farm::LEN {
+        msg!("farm {} has {} bytes", farm_acc.key, data.len());
+        return Err(AmmError::InvalidPDA.into());
+    }
+    let farm = Farm::unpack_from_slice(&data)?;
+    if farm.pool != *pool_acc.key {
+        msg!("farm pool not match {} {}", farm.pool, *pool_acc.key);
+        return Err(AmmError::InvalidPDA.into());
+    }
+    Ok(farm)
+}
+
+/// Check the reward vault and mint are the ones of the farm
+fn check_farm_reward(
+    farm: &Farm,
+    reward_vault_acc: &AccountInfo,
+    reward_mint_acc: &AccountInfo,
+    pool_pda: &AccountInfo,
+    token_program_acc: &AccountInfo,
+) -> Result<(), ProgramError> {
+    check_pool_vault(reward_vault_acc, &farm.reward_vault)?;
+    if *reward_mint_acc.key != farm.reward_mint {
+        msg!(
+            "reward mint not match {} {}",
+            farm.reward_mint,
+            *reward_mint_acc.key
+        );
+        return Err(AmmError::InvalidMint.into());
+    }
+    check_token_program(token_program_acc, reward_mint_acc)?;
+    unpack_vault(reward_vault_acc, pool_pda, token_program_acc)?;
+    Ok(())
+}
+
+/// Unpacks a stake of the pool
+fn unpack_stake(
+    program_id: &Pubkey,
+    pool_acc: &AccountInfo,
+    stake_acc: &AccountInfo,
+) -> Result<Stake, ProgramError> {
+    check_account_owner(stake_acc, program_id)?;
+    let data = stake_acc.try_borrow_data()?;
+    if data.len() != Stake::LEN {
+        msg!("stake {} has {} bytes", stake_acc.key, data.len());
+        return Err(AmmError::InvalidStake.into());
+    }
+    let stake = Stake::unpack_from_slice(&data)?;
+    if stake.pool != *pool_acc.key {
+        msg!("stake pool not match {} {}", stake.pool, *pool_acc.key);
+        return Err(AmmError::InvalidStake.into());
+    }
+    Ok(stake)
+}
+
+/// Check the account is the one the stake recorded
+fn check_stake_account(account_info: &AccountInfo, key: &Pubkey) -> Result<(), AmmError> {
+    if *account_info.key != *key {
+        msg!("stake account not match {} {}", key, *account_info.key);
+        return Err(AmmError::InvalidStake);
+    }
+    Ok(())
+}
+
+/// Check the token account holds the mint
+fn check_token_mint(account_info: &AccountInfo, mint: &Pubkey) -> Result<(), AmmError> {
+    let account = unpack_token_account(account_info)?;
+    if account.mint != *mint {
+        msg!("token account mint not match {} {}", mint, account.mint);
+        return Err(AmmError::InvalidMint);
+    }
+    Ok(())
+}
+
+/// Check the account is the system program
+pub(crate) fn check_system_program(account_info: &AccountInfo) -> Result<(), AmmError> {
+    if *account_info.key != system_program::id() {
+        msg!("system program not match {}", *account_info.key);
+        return Err(AmmError::InvalidProgramAddress);
+    }
+    Ok(())
+}
+
+/// Check the pda is derived from the pool and nonce
+fn check_pda(
+    program_id: &Pubkey,
+    pool_acc: &AccountInfo,
+    nonce: u8,
+    pool_pda: &AccountInfo,
+) -> Result<(), ProgramError> {
+    let pda = Pubkey::create_program_address(&[pool_acc.key.as_ref(), &[nonce]], program_id)?;
+    if pda != *pool_pda.key {
+        msg!("pda not match {} {}", pda, *pool_pda.key);
+        return Err(AmmError::InvalidPDA.into());
+    }
+    Ok(())
+}
+
+/// Unpacks a vault, the pool pda must hold it under the pool token program
+fn unpack_vault(
+    account_info: &AccountInfo,
+    pool_pda: &AccountInfo,
+    token_program_acc: &AccountInfo,
+) -> Result<Account, AmmError> {
+    if account_info.owner != token_program_acc.key {
+        msg!(
+            "vault not owned by token program {} {}",
+            account_info.owner,
+            *token_program_acc.key
+        );
+        return Err(AmmError::InvalidTokenProgramId);
+    }
+    let vault = unpack_token_account(account_info)?;
+    if vault.owner != *pool_pda.key {
+        msg!("vault owner not match {} {}", vault.owner, *pool_pda.key);
+        return Err(AmmError::InvalidOwner);
+    }
+    Ok(vault)
+}
+
+/// Check the id is spl_token or token-2022
+pub(crate) fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == spl_token::ID || *program_id == spl_token_2022::ID
+}
+
+/// Unpacks a spl_token or token-2022 `Account`.
+pub(crate) fn unpack_token_account(account_info: &AccountInfo) -> Result<Account, AmmError> {
+    if !is_token_program(account_info.owner) {
+        Err(AmmError::InvalidTokenProgramId)
+    } else {
+        StateWithExtensions::<Account>::unpack(&account_info.data.borrow())
+            .map(|account| account.base)
+            .map_err(|_| AmmError::ExpectedAccount)
+    }
+}
+
+/// Unpacks a spl_token or token-2022 `Mint`.
+pub(crate) fn unpack_mint(account_info: &AccountInfo) -> Result<Mint, AmmError> {
+    if !is_token_program(account_info.owner) {
+        Err(AmmError::InvalidTokenProgramId)
+    } else {
+        StateWithExtensions::<Mint>::unpack(&account_info.data.borrow())
+            .map(|mint| mint.base)
+            .map_err(|_| AmmError::ExpectedMint)
+    }
+}
+
+/// Check the mint is the one the pool recorded, initialize already unpacked it
+fn check_pool_mint(account_info: &AccountInfo, mint: &Pubkey) -> Result<(), AmmError> {
+    if *account_info.key != *mint {
+        msg!("mint not match {} {}", mint, *account_info.key);
+        return Err(AmmError::InvalidMint);
+    }
+    Ok(())
+}
+
+/// Accept only token-2022 mints whose extensions the pool is known to handle
+fn check_mint_extensions(account_info: &AccountInfo) -> Result<(), AmmError> {
+    let data = account_info.data.borrow();
+    let mint = StateWithExtensions::<Mint>::unpack(&data).map_err(|_| AmmError::ExpectedMint)?;
+    // the base mint unpacked, extensions this token-2022 version can't read are unknown
+    let extensions = mint.get_extension_types().map_err(|_| {
+        msg!("unknown mint extension");
+        AmmError::UnsupportedMint
+    })?;
+    for extension in extensions {
+        match extension {
+            // transfer fees are priced in, interest only changes the ui amount
+            ExtensionType::TransferFeeConfig | ExtensionType::InterestBearingConfig => {}
+            _ => {
+                msg!("unsupported mint extension {:?}", extension);
+                return Err(AmmError::UnsupportedMint);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check the referrer of a swap is a token account of mint b, the fee mint, that the
+/// user wallet doesn't own. Another wallet of the same user is not detected.
+pub(crate) fn check_referrer(
+    account_info: &AccountInfo,
+    pool: &AmmPoolView,
+    user: &Pubkey,
+) -> Result<(), AmmError> {
+    let account = unpack_token_account(account_info)?;
+    if account.mint != pool.mint_b {
+        msg!(
+            "token account mint not match {} {}",
+            pool.mint_b,
+            account.mint
+        );
+        return Err(AmmError::InvalidMint);
+    }
+    if account.owner == *user {
+        msg!("referrer {} is owned by the user", account_info.key);
+        return Err(AmmError::SelfReferral);
+    }
+    Ok(())
+}
+
+/// Check if the wallet is passed in place of its native sol token account
+pub(crate) fn is_native(
+    token_acc: &AccountInfo,
+    wallet_acc: &AccountInfo,
+    mint: &Pubkey,
+) -> Result<bool, AmmError> {
+    if token_acc.key != wallet_acc.key {
+        return Ok(false);
+    }
+    if *mint != spl_token::native_mint::id() {
+        msg!("wallet passed as token account of mint {}", mint);
+        return Err(AmmError::InvalidMint);
+    }
+    Ok(true)
+}
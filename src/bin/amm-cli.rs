@@ -0,0 +1,1220 @@
+//! Command-line tool for operating amm pools
+use amm::{
+    instruction::{self, Deadline, Direction},
+    math,
+    state::{
+        AmmPool, AmmPoolView, Farm, FeeMode, LongTermOrder, Order, PoolStatus, Stake, Twamm,
+        CLOSE_GRACE_PERIOD,
+    },
+};
+use clap::{crate_name, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use solana_clap_utils::{
+    input_parsers::{pubkey_of, value_of},
+    input_validators::{is_amount, is_parsable, is_url, is_valid_pubkey},
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signer},
+    transaction::Transaction,
+};
+use std::{process::exit, str::FromStr};
+
+/// program id used by js/index.js
+const DEFAULT_PROGRAM_ID: &str = "aAmLZ9yP1adeZyRC9qMskX9e1Ma2gR4ktpyrDCWPkdm";
+const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+
+type Error = Box<dyn std::error::Error>;
+type CommandResult = Result<(), Error>;
+
+struct Config {
+    rpc_client: RpcClient,
+    payer: Keypair,
+    program_id: Pubkey,
+}
+
+fn main() {
+    let matches = App::new(crate_name!())
+        .about("Operate amm pools")
+        .version(crate_version!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .short("u")
+                .value_name("URL")
+                .takes_value(true)
+                .global(true)
+                .default_value("http://localhost:8899")
+                .validator(is_url)
+                .help("JSON RPC URL for the cluster"),
+        )
+        .arg(
+            Arg::with_name("keypair")
+                .long("keypair")
+                .short("k")
+                .value_name("KEYPAIR")
+                .takes_value(true)
+                .global(true)
+                .help("Payer and pool owner keypair file [default: ~/.config/solana/id.json]"),
+        )
+        .arg(
+            Arg::with_name("program_id")
+                .long("program-id")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .global(true)
+                .default_value(DEFAULT_PROGRAM_ID)
+                .validator(is_valid_pubkey)
+                .help("Amm program id"),
+        )
+        .subcommand(
+            SubCommand::with_name("create-pool")
+                .about("Create a pool and deposit both tokens")
+                .arg(pubkey_arg("mint_a", "mint-a", true, "Mint of token a"))
+                .arg(pubkey_arg(
+                    "mint_b",
+                    "mint-b",
+                    true,
+                    "Mint of token b, fee is paid in it",
+                ))
+                .arg(amount_arg(
+                    "amount_a",
+                    "amount-a",
+                    true,
+                    "Amount of token a, in base units",
+                ))
+                .arg(amount_arg(
+                    "amount_b",
+                    "amount-b",
+                    true,
+                    "Amount of token b, in base units",
+                ))
+                .arg(amount_arg("fee", "fee", true, "Fee rate, 1000000 is 100%"))
+                .arg(
+                    amount_arg("tolerance", "tolerance", false, "Price impact tolerance in basis points, 0 disables it")
+                        .default_value("0"),
+                )
+                .arg(pubkey_arg(
+                    "source_a",
+                    "source-a",
+                    false,
+                    "Token a account to deposit from [default: associated token account, the wallet for native sol]",
+                ))
+                .arg(pubkey_arg(
+                    "source_b",
+                    "source-b",
+                    false,
+                    "Token b account to deposit from [default: associated token account, the wallet for native sol]",
+                ))
+                .args(&deadline_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("swap")
+                .about("Swap tokens with a pool")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(amount_arg(
+                    "amount",
+                    "amount",
+                    true,
+                    "Amount of token a to pay for a2b, or to receive for b2a",
+                ))
+                .arg(
+                    Arg::with_name("direction")
+                        .long("direction")
+                        .value_name("DIRECTION")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["a2b", "b2a"])
+                        .help("Swap direction"),
+                )
+                .arg(pubkey_arg(
+                    "token_a",
+                    "token-a",
+                    false,
+                    "Token a account [default: associated token account, the wallet for native sol]",
+                ))
+                .arg(pubkey_arg(
+                    "token_b",
+                    "token-b",
+                    false,
+                    "Token b account [default: associated token account, the wallet for native sol]",
+                ))
+                .arg(pubkey_arg(
+                    "referrer",
+                    "referrer",
+                    false,
+                    "Token b account of the referrer receiving a share of the fee",
+                ))
+                .args(&deadline_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("update-status")
+                .about("Update pool status")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(
+                    Arg::with_name("status")
+                        .long("status")
+                        .value_name("STATUS")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["normal", "lock"])
+                        .help("New status"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("update-tolerance")
+                .about("Update pool tolerance")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(amount_arg("tolerance", "tolerance", true, "New tolerance in basis points")),
+        )
+        .subcommand(
+            SubCommand::with_name("update-referral-share")
+                .about("Update the share of the swap fee paid to referrers")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(amount_arg("share", "share", true, "New share in basis points of the fee")),
+        )
+        .subcommand(
+            SubCommand::with_name("update-dynamic-fee")
+                .about("Update how the swap fee follows the market")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(
+                    Arg::with_name("mode")
+                        .long("mode")
+                        .value_name("MODE")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["static", "volatility", "imbalance"])
+                        .help("static keeps the fee, volatility and imbalance add to it"),
+                )
+                .arg(
+                    amount_arg("min_fee", "min-fee", false, "Lowest fee rate of a dynamic mode")
+                        .default_value("0"),
+                )
+                .arg(amount_arg("max_fee", "max-fee", true, "Highest fee rate of a dynamic mode")),
+        )
+        .subcommand(
+            SubCommand::with_name("withdraw-fee")
+                .about("Withdraw all fee of a pool")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(pubkey_arg(
+                    "receiver",
+                    "receiver",
+                    false,
+                    "Token b account receiving fee [default: associated token account]",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("terminate")
+                .about("Terminate a pool locked for the grace period, withdraw all tokens and close all accounts")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(pubkey_arg(
+                    "token_a",
+                    "token-a",
+                    false,
+                    "Token a account receiving vault a [default: associated token account, the wallet for native sol]",
+                ))
+                .arg(pubkey_arg(
+                    "token_b",
+                    "token-b",
+                    false,
+                    "Token b account receiving vault b and fee [default: associated token account, the wallet for native sol]",
+                ))
+                .arg(pubkey_arg(
+                    "token_reward",
+                    "token-reward",
+                    false,
+                    "Token account receiving the rewards left in the farm [default: associated token account]",
+                ))
+                .args(&deadline_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("place-order")
+                .about("Escrow tokens until the pool pays at least a minimum for them")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(amount_arg("amount", "amount", true, "Amount of token to sell"))
+                .arg(
+                    Arg::with_name("direction")
+                        .long("direction")
+                        .value_name("DIRECTION")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["a2b", "b2a"])
+                        .help("a2b sells token a, b2a sells token b"),
+                )
+                .arg(amount_arg(
+                    "min_amount_out",
+                    "min-amount-out",
+                    true,
+                    "Minimum amount of token to buy, after the pool fee",
+                ))
+                .arg(
+                    amount_arg("reward", "reward", false, "Lamports paid to the keeper filling the order")
+                        .default_value("10000"),
+                )
+                .arg(pubkey_arg(
+                    "token_in",
+                    "token-in",
+                    false,
+                    "Token account to sell from, refunded on cancel [default: associated token account]",
+                ))
+                .arg(pubkey_arg(
+                    "token_out",
+                    "token-out",
+                    false,
+                    "Token account to receive the bought token [default: associated token account]",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("cancel-order")
+                .about("Cancel an order and refund the escrow, anyone may cancel once the pool is locked for the grace period")
+                .arg(pubkey_arg("order", "order", true, "Order address")),
+        )
+        .subcommand(
+            SubCommand::with_name("fill-orders")
+                .about("Fill the orders that cross the pool price, earning their keeper reward")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(
+                    pubkey_arg("order", "order", true, "Order address")
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("place-long-term-order")
+                .about("Sell tokens evenly over a number of slots, run as virtual orders against the pool")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(amount_arg("amount", "amount", true, "Amount of token to sell"))
+                .arg(
+                    Arg::with_name("direction")
+                        .long("direction")
+                        .value_name("DIRECTION")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["a2b", "b2a"])
+                        .help("a2b sells token a, b2a sells token b"),
+                )
+                .arg(
+                    Arg::with_name("slots")
+                        .long("slots")
+                        .value_name("SLOTS")
+                        .takes_value(true)
+                        .required(true)
+                        .validator(is_parsable::<u64>)
+                        .help("Number of slots to sell over, rounded up to the expiry interval"),
+                )
+                .arg(pubkey_arg(
+                    "token_in",
+                    "token-in",
+                    false,
+                    "Token account to sell from, refunded on cancel [default: associated token account]",
+                ))
+                .arg(pubkey_arg(
+                    "token_out",
+                    "token-out",
+                    false,
+                    "Token account to receive the bought token [default: associated token account]",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("cancel-long-term-order")
+                .about("Close a long-term order, paying out the proceeds and refunding the unsold tokens")
+                .arg(pubkey_arg("order", "order", true, "Long-term order address")),
+        )
+        .subcommand(
+            SubCommand::with_name("execute-virtual-orders")
+                .about("Run the long-term orders of a pool up to the current slot")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address")),
+        )
+        .subcommand(
+            SubCommand::with_name("fund-farm")
+                .about("Fund the liquidity mining of a pool, paid out over a number of slots from now")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(pubkey_arg(
+                    "reward_mint",
+                    "reward-mint",
+                    false,
+                    "Mint of the rewards, required by the first funding",
+                ))
+                .arg(amount_arg("amount", "amount", true, "Amount of rewards to add"))
+                .arg(
+                    Arg::with_name("slots")
+                        .long("slots")
+                        .value_name("SLOTS")
+                        .takes_value(true)
+                        .required(true)
+                        .validator(is_parsable::<u64>)
+                        .help("Number of slots to pay the added and the remaining rewards over"),
+                )
+                .arg(pubkey_arg(
+                    "source",
+                    "source",
+                    false,
+                    "Token account paying the rewards [default: associated token account]",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("stake")
+                .about("Add liquidity to a pool at its price and stake it in the farm")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(amount_arg(
+                    "amount",
+                    "amount",
+                    true,
+                    "Amount of token a to add, token b follows at the pool price",
+                ))
+                .arg(pubkey_arg(
+                    "token_a",
+                    "token-a",
+                    false,
+                    "Token a account, fixed by the first stake [default: associated token account]",
+                ))
+                .arg(pubkey_arg(
+                    "token_b",
+                    "token-b",
+                    false,
+                    "Token b account, fixed by the first stake [default: associated token account]",
+                ))
+                .arg(pubkey_arg(
+                    "token_reward",
+                    "token-reward",
+                    false,
+                    "Token account receiving the rewards, fixed by the first stake [default: associated token account]",
+                ))
+                .args(&deadline_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("unstake")
+                .about("Withdraw staked liquidity with the unclaimed rewards")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address"))
+                .arg(amount_arg(
+                    "shares",
+                    "shares",
+                    false,
+                    "Farm shares to unstake [default: all]",
+                ))
+                .arg(pubkey_arg(
+                    "owner",
+                    "owner",
+                    false,
+                    "Owner of the stake, others may unstake once the pool is locked for the grace period [default: payer]",
+                ))
+                .args(&deadline_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("claim-rewards")
+                .about("Claim the farm rewards of a stake")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address")),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Grow a pool of an earlier version to the current layout")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address")),
+        )
+        .subcommand(
+            SubCommand::with_name("show-pool")
+                .about("Show pool state")
+                .arg(pubkey_arg("pool", "pool", true, "Pool address")),
+        )
+        .get_matches();
+
+    let config = {
+        let keypair_path = matches
+            .value_of("keypair")
+            .map(|path| path.to_string())
+            .unwrap_or_else(default_keypair_path);
+        let payer = read_keypair_file(&keypair_path).unwrap_or_else(|e| {
+            eprintln!("error: failed to read keypair {}: {}", keypair_path, e);
+            exit(1);
+        });
+        Config {
+            rpc_client: RpcClient::new_with_commitment(
+                matches.value_of("url").unwrap().to_string(),
+                CommitmentConfig::confirmed(),
+            ),
+            payer,
+            program_id: pubkey_of(&matches, "program_id").unwrap(),
+        }
+    };
+
+    let result = match matches.subcommand() {
+        ("create-pool", Some(arg_matches)) => command_create_pool(&config, arg_matches),
+        ("swap", Some(arg_matches)) => command_swap(&config, arg_matches),
+        ("update-status", Some(arg_matches)) => command_update_status(&config, arg_matches),
+        ("update-tolerance", Some(arg_matches)) => command_update_tolerance(&config, arg_matches),
+        ("update-referral-share", Some(arg_matches)) => {
+            command_update_referral_share(&config, arg_matches)
+        }
+        ("update-dynamic-fee", Some(arg_matches)) => {
+            command_update_dynamic_fee(&config, arg_matches)
+        }
+        ("withdraw-fee", Some(arg_matches)) => command_withdraw_fee(&config, arg_matches),
+        ("terminate", Some(arg_matches)) => command_terminate(&config, arg_matches),
+        ("place-order", Some(arg_matches)) => command_place_order(&config, arg_matches),
+        ("cancel-order", Some(arg_matches)) => command_cancel_order(&config, arg_matches),
+        ("fill-orders", Some(arg_matches)) => command_fill_orders(&config, arg_matches),
+        ("place-long-term-order", Some(arg_matches)) => {
+            command_place_long_term_order(&config, arg_matches)
+        }
+        ("cancel-long-term-order", Some(arg_matches)) => {
+            command_cancel_long_term_order(&config, arg_matches)
+        }
+        ("execute-virtual-orders", Some(arg_matches)) => {
+            command_execute_virtual_orders(&config, arg_matches)
+        }
+        ("fund-farm", Some(arg_matches)) => command_fund_farm(&config, arg_matches),
+        ("stake", Some(arg_matches)) => command_stake(&config, arg_matches),
+        ("unstake", Some(arg_matches)) => command_unstake(&config, arg_matches),
+        ("claim-rewards", Some(arg_matches)) => command_claim_rewards(&config, arg_matches),
+        ("migrate", Some(arg_matches)) => command_migrate(&config, arg_matches),
+        ("show-pool", Some(arg_matches)) => command_show_pool(&config, arg_matches),
+        _ => unreachable!(),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
+
+fn pubkey_arg<'a, 'b>(name: &'a str, long: &'a str, required: bool, help: &'a str) -> Arg<'a, 'b> {
+    Arg::with_name(name)
+        .long(long)
+        .value_name("ADDRESS")
+        .takes_value(true)
+        .required(required)
+        .validator(is_valid_pubkey)
+        .help(help)
+}
+
+fn amount_arg<'a, 'b>(name: &'a str, long: &'a str, required: bool, help: &'a str) -> Arg<'a, 'b> {
+    Arg::with_name(name)
+        .long(long)
+        .value_name("AMOUNT")
+        .takes_value(true)
+        .required(required)
+        .validator(is_amount)
+        .help(help)
+}
+
+fn deadline_args<'a, 'b>() -> [Arg<'a, 'b>; 2] {
+    [
+        Arg::with_name("deadline")
+            .long("deadline")
+            .value_name("UNIX_TIMESTAMP")
+            .takes_value(true)
+            .validator(is_parsable::<i64>)
+            .conflicts_with("deadline_slot")
+            .help("Fail if the transaction lands after this unix timestamp"),
+        Arg::with_name("deadline_slot")
+            .long("deadline-slot")
+            .value_name("SLOT")
+            .takes_value(true)
+            .validator(is_parsable::<u64>)
+            .help("Fail if the transaction lands after this slot"),
+    ]
+}
+
+fn deadline_of(arg_matches: &ArgMatches) -> Option<Deadline> {
+    value_of(arg_matches, "deadline")
+        .map(Deadline::UnixTimestamp)
+        .or_else(|| value_of(arg_matches, "deadline_slot").map(Deadline::Slot))
+}
+
+fn default_keypair_path() -> String {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    format!("{}/.config/solana/id.json", home)
+}
+
+fn associated_token_address(wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    let program_id = Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID).unwrap();
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &program_id,
+    )
+    .0
+}
+
+/// the wallet itself for native sol, which the program wraps on the fly
+fn default_token_account(wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    if *mint == spl_token::native_mint::id() {
+        *wallet
+    } else {
+        associated_token_address(wallet, mint, token_program)
+    }
+}
+
+/// token program owning the mint, spl_token or token-2022
+fn token_program_of(config: &Config, mint: &Pubkey) -> Result<Pubkey, Error> {
+    Ok(config.rpc_client.get_account(mint)?.owner)
+}
+
+fn get_pool(config: &Config, pool: &Pubkey) -> Result<AmmPool, Error> {
+    let data = config.rpc_client.get_account_data(pool)?;
+    // pools of earlier versions read too, `migrate` needs them
+    let state = AmmPool::unpack_any(&data)?;
+    if !state.is_initialized() {
+        return Err(format!("invalid pool {}: not initialized", pool).into());
+    }
+    Ok(state)
+}
+
+fn get_order(config: &Config, order: &Pubkey) -> Result<Order, Error> {
+    let data = config.rpc_client.get_account_data(order)?;
+    Order::unpack(&data).map_err(|e| format!("invalid order {}: {}", order, e).into())
+}
+
+fn get_long_term_order(config: &Config, order: &Pubkey) -> Result<LongTermOrder, Error> {
+    let data = config.rpc_client.get_account_data(order)?;
+    LongTermOrder::unpack(&data)
+        .map_err(|e| format!("invalid long-term order {}: {}", order, e).into())
+}
+
+fn get_farm(config: &Config, pool: &Pubkey) -> Result<Farm, Error> {
+    let farm = instruction::find_farm_address(&config.program_id, pool).0;
+    let data = config.rpc_client.get_account_data(&farm)?;
+    Farm::unpack(&data).map_err(|e| format!("invalid farm {}: {}", farm, e).into())
+}
+
+fn get_twamm(config: &Config, pool: &Pubkey) -> Result<Twamm, Error> {
+    let twamm = instruction::find_twamm_address(&config.program_id, pool).0;
+    let data = config.rpc_client.get_account_data(&twamm)?;
+    Twamm::unpack(&data).map_err(|e| format!("invalid twamm {}: {}", twamm, e).into())
+}
+
+fn get_stake(config: &Config, pool: &Pubkey, owner: &Pubkey) -> Result<Stake, Error> {
+    let stake = instruction::find_stake_address(&config.program_id, pool, owner).0;
+    let data = config.rpc_client.get_account_data(&stake)?;
+    Stake::unpack(&data).map_err(|e| format!("invalid stake {}: {}", stake, e).into())
+}
+
+fn pool_pda(config: &Config, pool_key: &Pubkey, pool: &AmmPool) -> Result<Pubkey, Error> {
+    Ok(Pubkey::create_program_address(
+        &[pool_key.as_ref(), &[pool.nonce]],
+        &config.program_id,
+    )?)
+}
+
+fn send(config: &Config, instructions: &[Instruction], signers: &[&Keypair]) -> CommandResult {
+    let blockhash = config.rpc_client.get_latest_blockhash()?;
+    let mut all_signers = vec![&config.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&config.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    let signature = config
+        .rpc_client
+        .send_and_confirm_transaction(&transaction)?;
+    println!("Signature: {}", signature);
+    Ok(())
+}
+
+fn command_create_pool(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let owner = config.payer.pubkey();
+    let mint_a = pubkey_of(arg_matches, "mint_a").unwrap();
+    let mint_b = pubkey_of(arg_matches, "mint_b").unwrap();
+    let amount_a: u64 = value_of(arg_matches, "amount_a").unwrap();
+    let amount_b: u64 = value_of(arg_matches, "amount_b").unwrap();
+    let fee: u64 = value_of(arg_matches, "fee").unwrap();
+    let tolerance: u64 = value_of(arg_matches, "tolerance").unwrap();
+    let token_program = token_program_of(config, &mint_a)?;
+    let source_a = pubkey_of(arg_matches, "source_a")
+        .unwrap_or_else(|| default_token_account(&owner, &mint_a, &token_program));
+    let source_b = pubkey_of(arg_matches, "source_b")
+        .unwrap_or_else(|| default_token_account(&owner, &mint_b, &token_program));
+
+    // initialize creates the pool and its vaults, paid by the owner
+    let pool = Keypair::new();
+    let (pool_pda, nonce) =
+        Pubkey::find_program_address(&[pool.pubkey().as_ref()], &config.program_id);
+    let instruction = instruction::initialize(
+        &config.program_id,
+        &pool.pubkey(),
+        &owner,
+        &mint_a,
+        &mint_b,
+        &pool_pda,
+        &source_a,
+        &source_b,
+        &token_program,
+        nonce,
+        fee,
+        amount_a,
+        amount_b,
+        tolerance,
+        deadline_of(arg_matches),
+    )?;
+    send(config, &[instruction], &[&pool])?;
+    println!("Pool: {}", pool.pubkey());
+    Ok(())
+}
+
+fn command_swap(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let user = config.payer.pubkey();
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let amount: u64 = value_of(arg_matches, "amount").unwrap();
+    let direction = match arg_matches.value_of("direction").unwrap() {
+        "a2b" => Direction::A2B,
+        _ => Direction::B2A,
+    };
+    let pool = get_pool(config, &pool_key)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let token_a = pubkey_of(arg_matches, "token_a")
+        .unwrap_or_else(|| default_token_account(&user, &pool.mint_a, &token_program));
+    let token_b = pubkey_of(arg_matches, "token_b")
+        .unwrap_or_else(|| default_token_account(&user, &pool.mint_b, &token_program));
+    let instruction = instruction::swap(
+        &config.program_id,
+        &pool_key,
+        &pool.vault_a,
+        &pool.vault_b,
+        &pool.fee_vault,
+        &pool_pda(config, &pool_key, &pool)?,
+        &user,
+        &token_a,
+        &token_b,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        amount,
+        direction,
+        deadline_of(arg_matches),
+        pool.long_term_orders > 0,
+        pubkey_of(arg_matches, "referrer").as_ref(),
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_update_status(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let status = match arg_matches.value_of("status").unwrap() {
+        "normal" => PoolStatus::Nomal,
+        _ => PoolStatus::Lock,
+    };
+    get_pool(config, &pool_key)?;
+    let instruction = instruction::update_status(
+        &config.program_id,
+        &pool_key,
+        &config.payer.pubkey(),
+        status.into(),
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_update_tolerance(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let tolerance: u64 = value_of(arg_matches, "tolerance").unwrap();
+    get_pool(config, &pool_key)?;
+    let instruction = instruction::update_tolerance(
+        &config.program_id,
+        &pool_key,
+        &config.payer.pubkey(),
+        tolerance,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_update_referral_share(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let share: u64 = value_of(arg_matches, "share").unwrap();
+    get_pool(config, &pool_key)?;
+    let instruction = instruction::update_referral_share(
+        &config.program_id,
+        &pool_key,
+        &config.payer.pubkey(),
+        share,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_update_dynamic_fee(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let mode = match arg_matches.value_of("mode").unwrap() {
+        "volatility" => FeeMode::Volatility,
+        "imbalance" => FeeMode::Imbalance,
+        _ => FeeMode::Static,
+    };
+    let min_fee: u64 = value_of(arg_matches, "min_fee").unwrap();
+    let max_fee: u64 = value_of(arg_matches, "max_fee").unwrap();
+    get_pool(config, &pool_key)?;
+    let instruction = instruction::update_dynamic_fee(
+        &config.program_id,
+        &pool_key,
+        &config.payer.pubkey(),
+        mode.into(),
+        min_fee,
+        max_fee,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_withdraw_fee(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let owner = config.payer.pubkey();
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let token_program = token_program_of(config, &pool.mint_b)?;
+    let receiver = pubkey_of(arg_matches, "receiver")
+        .unwrap_or_else(|| associated_token_address(&owner, &pool.mint_b, &token_program));
+    let instruction = instruction::withdrawal_fee(
+        &config.program_id,
+        &pool_key,
+        &owner,
+        &pool.fee_vault,
+        &receiver,
+        &pool_pda(config, &pool_key, &pool)?,
+        &token_program,
+        &pool.mint_b,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_terminate(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let owner = config.payer.pubkey();
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let token_a = pubkey_of(arg_matches, "token_a")
+        .unwrap_or_else(|| default_token_account(&owner, &pool.mint_a, &token_program));
+    let token_b = pubkey_of(arg_matches, "token_b")
+        .unwrap_or_else(|| default_token_account(&owner, &pool.mint_b, &token_program));
+    // the rewards left in the farm go back with the pool
+    let rewards = if pool.farm {
+        let farm = get_farm(config, &pool_key)?;
+        Some(instruction::RewardKeys {
+            reward_mint: farm.reward_mint,
+            owner_token_reward: pubkey_of(arg_matches, "token_reward").unwrap_or_else(|| {
+                associated_token_address(&owner, &farm.reward_mint, &token_program)
+            }),
+        })
+    } else {
+        None
+    };
+    // so do the escrows of the long-term orders, their rent goes back to the wallet
+    // that placed the first one
+    let twamm_payer = if pool.twamm {
+        Some(get_twamm(config, &pool_key)?.payer)
+    } else {
+        None
+    };
+    let instruction = instruction::terminate(
+        &config.program_id,
+        &pool_key,
+        &owner,
+        &pool.vault_a,
+        &pool.vault_b,
+        &pool.fee_vault,
+        &pool_pda(config, &pool_key, &pool)?,
+        &token_a,
+        &token_b,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        deadline_of(arg_matches),
+        twamm_payer.as_ref(),
+        rewards.as_ref(),
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_place_order(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let owner = config.payer.pubkey();
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let amount: u64 = value_of(arg_matches, "amount").unwrap();
+    let min_amount_out: u64 = value_of(arg_matches, "min_amount_out").unwrap();
+    let reward: u64 = value_of(arg_matches, "reward").unwrap();
+    let direction = match arg_matches.value_of("direction").unwrap() {
+        "a2b" => Direction::A2B,
+        _ => Direction::B2A,
+    };
+    let pool = get_pool(config, &pool_key)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let (mint_in, mint_out) = match direction {
+        Direction::A2B => (pool.mint_a, pool.mint_b),
+        Direction::B2A => (pool.mint_b, pool.mint_a),
+    };
+    let token_in = pubkey_of(arg_matches, "token_in")
+        .unwrap_or_else(|| associated_token_address(&owner, &mint_in, &token_program));
+    let token_out = pubkey_of(arg_matches, "token_out")
+        .unwrap_or_else(|| associated_token_address(&owner, &mint_out, &token_program));
+    // place order creates the order and its escrow, paid by the owner
+    let order = Keypair::new();
+    let instruction = instruction::place_order(
+        &config.program_id,
+        &pool_key,
+        &order.pubkey(),
+        &owner,
+        &pool_pda(config, &pool_key, &pool)?,
+        &token_in,
+        &token_out,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        amount,
+        direction,
+        min_amount_out,
+        reward,
+    )?;
+    send(config, &[instruction], &[&order])?;
+    println!("Order: {}", order.pubkey());
+    Ok(())
+}
+
+fn command_cancel_order(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let order_key = pubkey_of(arg_matches, "order").unwrap();
+    let order = get_order(config, &order_key)?;
+    let pool = get_pool(config, &order.pool)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let instruction = instruction::cancel_order(
+        &config.program_id,
+        &order.pool,
+        &order_key,
+        &order.owner,
+        &pool_pda(config, &order.pool, &pool)?,
+        &order.token_in,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        order.owner == config.payer.pubkey(),
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_fill_orders(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let mut orders = vec![];
+    for value in arg_matches.values_of("order").unwrap() {
+        let order_key = Pubkey::from_str(value)?;
+        let order = get_order(config, &order_key)?;
+        orders.push(instruction::OrderKeys {
+            order: order_key,
+            owner: order.owner,
+            token_out: order.token_out,
+        });
+    }
+    let instruction = instruction::fill_orders(
+        &config.program_id,
+        &pool_key,
+        &pool.vault_a,
+        &pool.vault_b,
+        &pool.fee_vault,
+        &pool_pda(config, &pool_key, &pool)?,
+        &config.payer.pubkey(),
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        &orders,
+        pool.long_term_orders > 0,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_place_long_term_order(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let owner = config.payer.pubkey();
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let amount: u64 = value_of(arg_matches, "amount").unwrap();
+    let slots: u64 = value_of(arg_matches, "slots").unwrap();
+    let direction = match arg_matches.value_of("direction").unwrap() {
+        "a2b" => Direction::A2B,
+        _ => Direction::B2A,
+    };
+    let pool = get_pool(config, &pool_key)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let (mint_in, mint_out) = match direction {
+        Direction::A2B => (pool.mint_a, pool.mint_b),
+        Direction::B2A => (pool.mint_b, pool.mint_a),
+    };
+    let token_in = pubkey_of(arg_matches, "token_in")
+        .unwrap_or_else(|| associated_token_address(&owner, &mint_in, &token_program));
+    let token_out = pubkey_of(arg_matches, "token_out")
+        .unwrap_or_else(|| associated_token_address(&owner, &mint_out, &token_program));
+    let order = Keypair::new();
+    let instruction = instruction::place_long_term_order(
+        &config.program_id,
+        &pool_key,
+        &order.pubkey(),
+        &owner,
+        &pool.vault_a,
+        &pool.vault_b,
+        &pool.fee_vault,
+        &pool_pda(config, &pool_key, &pool)?,
+        &token_in,
+        &token_out,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        amount,
+        direction,
+        slots,
+    )?;
+    send(config, &[instruction], &[&order])?;
+    println!("Long-term order: {}", order.pubkey());
+    Ok(())
+}
+
+fn command_cancel_long_term_order(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let order_key = pubkey_of(arg_matches, "order").unwrap();
+    let order = get_long_term_order(config, &order_key)?;
+    let pool = get_pool(config, &order.pool)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let instruction = instruction::cancel_long_term_order(
+        &config.program_id,
+        &order.pool,
+        &order_key,
+        &order.owner,
+        &pool.vault_a,
+        &pool.vault_b,
+        &pool.fee_vault,
+        &pool_pda(config, &order.pool, &pool)?,
+        &order.token_in,
+        &order.token_out,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        order.owner == config.payer.pubkey(),
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_execute_virtual_orders(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let instruction = instruction::execute_virtual_orders(
+        &config.program_id,
+        &pool_key,
+        &pool.vault_a,
+        &pool.vault_b,
+        &pool.fee_vault,
+        &pool_pda(config, &pool_key, &pool)?,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_fund_farm(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let owner = config.payer.pubkey();
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let amount: u64 = value_of(arg_matches, "amount").unwrap();
+    let slots: u64 = value_of(arg_matches, "slots").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let reward_mint = match pubkey_of(arg_matches, "reward_mint") {
+        Some(reward_mint) => reward_mint,
+        None => get_farm(config, &pool_key)?.reward_mint,
+    };
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let source = pubkey_of(arg_matches, "source")
+        .unwrap_or_else(|| associated_token_address(&owner, &reward_mint, &token_program));
+    let instruction = instruction::fund_farm(
+        &config.program_id,
+        &pool_key,
+        &owner,
+        &pool.vault_a,
+        &pool_pda(config, &pool_key, &pool)?,
+        &source,
+        &token_program,
+        &reward_mint,
+        amount,
+        slots,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_stake(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let owner = config.payer.pubkey();
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let amount: u64 = value_of(arg_matches, "amount").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let farm = get_farm(config, &pool_key)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    // a stake keeps the token accounts of its first stake
+    let (token_a, token_b, token_reward) = match get_stake(config, &pool_key, &owner) {
+        Ok(stake) => (stake.token_a, stake.token_b, stake.token_reward),
+        Err(_) => (
+            pubkey_of(arg_matches, "token_a")
+                .unwrap_or_else(|| associated_token_address(&owner, &pool.mint_a, &token_program)),
+            pubkey_of(arg_matches, "token_b")
+                .unwrap_or_else(|| associated_token_address(&owner, &pool.mint_b, &token_program)),
+            pubkey_of(arg_matches, "token_reward").unwrap_or_else(|| {
+                associated_token_address(&owner, &farm.reward_mint, &token_program)
+            }),
+        ),
+    };
+    let instruction = instruction::stake(
+        &config.program_id,
+        &pool_key,
+        &owner,
+        &pool.vault_a,
+        &pool.vault_b,
+        &pool.fee_vault,
+        &pool_pda(config, &pool_key, &pool)?,
+        &token_a,
+        &token_b,
+        &token_reward,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        amount,
+        deadline_of(arg_matches),
+        pool.long_term_orders > 0,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_unstake(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let owner = pubkey_of(arg_matches, "owner").unwrap_or_else(|| config.payer.pubkey());
+    let pool = get_pool(config, &pool_key)?;
+    let farm = get_farm(config, &pool_key)?;
+    let stake = get_stake(config, &pool_key, &owner)?;
+    let shares: u64 = value_of(arg_matches, "shares").unwrap_or(stake.shares);
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let instruction = instruction::unstake(
+        &config.program_id,
+        &pool_key,
+        &owner,
+        &pool.vault_a,
+        &pool.vault_b,
+        &pool.fee_vault,
+        &pool_pda(config, &pool_key, &pool)?,
+        &stake.token_a,
+        &stake.token_b,
+        &stake.token_reward,
+        &token_program,
+        &pool.mint_a,
+        &pool.mint_b,
+        &farm.reward_mint,
+        shares,
+        deadline_of(arg_matches),
+        pool.long_term_orders > 0,
+        owner == config.payer.pubkey(),
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_claim_rewards(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let owner = config.payer.pubkey();
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let farm = get_farm(config, &pool_key)?;
+    let stake = get_stake(config, &pool_key, &owner)?;
+    let token_program = token_program_of(config, &pool.mint_a)?;
+    let instruction = instruction::claim_rewards(
+        &config.program_id,
+        &pool_key,
+        &owner,
+        &pool_pda(config, &pool_key, &pool)?,
+        &stake.token_reward,
+        &token_program,
+        &farm.reward_mint,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_migrate(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let instruction = instruction::migrate(
+        &config.program_id,
+        &pool_key,
+        &config.payer.pubkey(),
+        &pool.mint_a,
+        &pool.mint_b,
+    )?;
+    send(config, &[instruction], &[])
+}
+
+fn command_show_pool(config: &Config, arg_matches: &ArgMatches) -> CommandResult {
+    let pool_key = pubkey_of(arg_matches, "pool").unwrap();
+    let pool = get_pool(config, &pool_key)?;
+    let balance = |key: &Pubkey| {
+        config
+            .rpc_client
+            .get_token_account_balance(key)
+            .map(|balance| balance.amount)
+            .unwrap_or_else(|_| "-".to_string())
+    };
+    println!("Pool: {}", pool_key);
+    println!("Status: {}", pool.status);
+    if pool.status == PoolStatus::Lock {
+        println!(
+            "Terminate allowed from: {} (unix timestamp)",
+            pool.lock_time.saturating_add(CLOSE_GRACE_PERIOD)
+        );
+    }
+    println!("Nonce: {}", pool.nonce);
+    println!("PDA: {}", pool_pda(config, &pool_key, &pool)?);
+    println!("Owner: {}", pool.owner);
+    println!("k: {} * {}", pool.ka, pool.kb);
+    println!("Fee: {}", pool.fee);
+    println!("Fee mode: {}", pool.fee_mode);
+    if pool.fee_mode != FeeMode::Static {
+        println!("Fee bounds: {} to {}", pool.min_fee, pool.max_fee);
+    }
+    if pool.fee_mode == FeeMode::Volatility {
+        println!(
+            "Volatility: {} at slot {}",
+            pool.volatility, pool.volatility_slot
+        );
+    }
+    println!("Tolerance: {}", pool.tolerance);
+    println!("Referral share: {}", pool.referral_share);
+    println!("Open orders: {}", pool.open_orders);
+    println!("Long-term orders: {}", pool.long_term_orders);
+    println!("Stakes: {}", pool.stakes);
+    println!("Mint a: {} ({} decimals)", pool.mint_a, pool.decimals_a);
+    println!("Mint b: {} ({} decimals)", pool.mint_b, pool.decimals_b);
+    // price of the live reserves
+    let reserve = |key: &Pubkey| balance(key).parse::<u64>().ok();
+    if let (Some(reserve_a), Some(reserve_b)) = (reserve(&pool.vault_a), reserve(&pool.vault_b)) {
+        if let Ok(price) =
+            math::calculate_price(reserve_a, reserve_b, pool.decimals_a, pool.decimals_b)
+        {
+            println!("Price: {} b per a", price as f64 / math::PRICE_MUL as f64);
+        }
+        // the fee the next swap pays, the same way the program takes it
+        if pool.fee_mode != FeeMode::Static {
+            let data = config.rpc_client.get_account_data(&pool_key)?;
+            let view: &AmmPoolView = bytemuck::from_bytes(&data);
+            let slot = config.rpc_client.get_slot()?;
+            let fee = |direction| view.effective_fee(slot, reserve_a, reserve_b, Some(direction));
+            println!(
+                "Effective fee: {} a2b, {} b2a",
+                fee(Direction::A2B)?,
+                fee(Direction::B2A)?
+            );
+        }
+    }
+    println!("Vault a: {} ({})", pool.vault_a, balance(&pool.vault_a));
+    println!("Vault b: {} ({})", pool.vault_b, balance(&pool.vault_b));
+    println!(
+        "Fee vault: {} ({})",
+        pool.fee_vault,
+        balance(&pool.fee_vault)
+    );
+    if let Ok(farm) = get_farm(config, &pool_key) {
+        println!("Farm reward mint: {}", farm.reward_mint);
+        println!(
+            "Farm reward vault: {} ({})",
+            farm.reward_vault,
+            balance(&farm.reward_vault)
+        );
+        println!(
+            "Farm shares: {} staked of {}",
+            farm.staked_shares, farm.total_shares
+        );
+        println!(
+            "Farm rewards: {} per slot until slot {}",
+            farm.reward_rate, farm.end_slot
+        );
+    }
+    Ok(())
+}
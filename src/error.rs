@@ -31,7 +31,7 @@ pub enum AmmError {
     /// mint not match
     #[error("InvalidMint")]
     InvalidMint,
-    /// mint decimals not in order
+    /// mint decimals not in order, no longer returned, kept so the codes after it don't shift
     #[error("InvalidMintDecimals")]
     InvalidMintDecimals,
     /// The pool status is invalid.
@@ -61,6 +61,39 @@ pub enum AmmError {
     NoughtTransfer,
     #[error("InvalidPDA")]
     InvalidPDA,
+    /// The deadline of the instruction has passed.
+    #[error("Expired")]
+    Expired,
+    /// The mint has a token-2022 extension the pool does not support.
+    #[error("UnsupportedMint")]
+    UnsupportedMint,
+    /// The pool account has the layout of an earlier version, `Migrate` it first.
+    #[error("LegacyPool")]
+    LegacyPool,
+    /// The pool has not been locked for the whole grace period.
+    #[error("GracePeriod")]
+    GracePeriod,
+    /// The pool was terminated.
+    #[error("PoolClosed")]
+    PoolClosed,
+    /// The pool still escrows open limit orders.
+    #[error("OpenOrders")]
+    OpenOrders,
+    /// The order account is not an open order of the pool, or an account does not match it.
+    #[error("InvalidOrder")]
+    InvalidOrder,
+    /// Every expiry of the pool is taken, the order must end with one of them.
+    #[error("TooManyExpiries")]
+    TooManyExpiries,
+    /// The referrer of a swap is a token account of the user swapping.
+    #[error("SelfReferral")]
+    SelfReferral,
+    /// The farm of the pool still holds staked liquidity.
+    #[error("OpenStakes")]
+    OpenStakes,
+    /// The stake account is not a stake of the pool, or an account does not match it.
+    #[error("InvalidStake")]
+    InvalidStake,
 }
 impl From<AmmError> for ProgramError {
     fn from(e: AmmError) -> Self {
@@ -0,0 +1,894 @@
+//! Program events
+//!
+//! Every state-changing instruction emits one event through `sol_log_data`.
+//! The runtime prints it as `Program data: <base64>`, use
+//! [AmmEvent::from_log](enum.AmmEvent.html#method.from_log) to decode it.
+//!
+//! Layout is `version:u8 | tag:u8 | body`, all integers are little endian.
+use crate::{
+    error::AmmError,
+    instruction::Direction,
+    state::{FeeMode, PoolStatus},
+};
+use arrayref::{array_ref, array_refs};
+use solana_program::{log::sol_log_data, program_error::ProgramError, pubkey::Pubkey};
+use std::mem::size_of;
+
+/// current event layout version
+pub const EVENT_VERSION: u8 = 1;
+
+/// prefix of the log line printed by `sol_log_data`
+pub const EVENT_LOG_PREFIX: &str = "Program data: ";
+
+/// Events emitted by the amm program.
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AmmEvent {
+    /// pool created, reserves are the initial deposits
+    Initialize {
+        pool: Pubkey,
+        owner: Pubkey,
+        fee_rate: u64,
+        tolerance: u64,
+        reserve_a: u64,
+        reserve_b: u64,
+    },
+    /// swap executed, reserves are post-trade
+    Swap {
+        pool: Pubkey,
+        user: Pubkey,
+        direction: Direction,
+        amount_in: u64,
+        amount_out: u64,
+        fee: u64,
+        reserve_a: u64,
+        reserve_b: u64,
+    },
+    /// fee moved out of fee vault
+    WithdrawalFee {
+        pool: Pubkey,
+        owner: Pubkey,
+        amount: u64,
+    },
+    /// pool status changed
+    UpdateStatus {
+        pool: Pubkey,
+        owner: Pubkey,
+        status: PoolStatus,
+    },
+    /// pool tolerance changed
+    UpdateTolerance {
+        pool: Pubkey,
+        owner: Pubkey,
+        tolerance: u64,
+    },
+    /// pool closed, amounts are paid out to owner
+    Terminate {
+        pool: Pubkey,
+        owner: Pubkey,
+        amount_a: u64,
+        amount_b: u64,
+        fee: u64,
+    },
+    /// limit order escrowed, amount in is what the escrow received
+    PlaceOrder {
+        pool: Pubkey,
+        order: Pubkey,
+        owner: Pubkey,
+        direction: Direction,
+        amount_in: u64,
+        min_amount_out: u64,
+        reward: u64,
+    },
+    /// limit order cancelled, amount is refunded to owner
+    CancelOrder {
+        pool: Pubkey,
+        order: Pubkey,
+        owner: Pubkey,
+        amount: u64,
+    },
+    /// limit order filled against the pool, reserves are post-trade
+    FillOrder {
+        pool: Pubkey,
+        order: Pubkey,
+        keeper: Pubkey,
+        direction: Direction,
+        amount_in: u64,
+        amount_out: u64,
+        fee: u64,
+        reserve_a: u64,
+        reserve_b: u64,
+    },
+    /// long-term order placed, sells sell rate per slot until end slot
+    PlaceLongTermOrder {
+        pool: Pubkey,
+        order: Pubkey,
+        owner: Pubkey,
+        direction: Direction,
+        amount_in: u64,
+        sell_rate: u64,
+        end_slot: u64,
+    },
+    /// long-term order cancelled or withdrawn after it ended
+    CancelLongTermOrder {
+        pool: Pubkey,
+        order: Pubkey,
+        owner: Pubkey,
+        unsold: u64,
+        proceeds: u64,
+    },
+    /// virtual orders ran up to slot, amounts are in and out of the vaults, reserves are
+    /// post-trade
+    VirtualOrders {
+        pool: Pubkey,
+        slot: u64,
+        amount_a_in: u64,
+        amount_a_out: u64,
+        amount_b_in: u64,
+        amount_b_out: u64,
+        fee: u64,
+        reserve_a: u64,
+        reserve_b: u64,
+    },
+    /// pool referral share changed
+    UpdateReferralShare {
+        pool: Pubkey,
+        owner: Pubkey,
+        share: u64,
+    },
+    /// part of the swap fee paid to the referrer instead of the fee vault, logged
+    /// before the swap event
+    Referral {
+        pool: Pubkey,
+        user: Pubkey,
+        referrer: Pubkey,
+        amount: u64,
+    },
+    /// farm funded, pays reward rate per slot until end slot
+    FundFarm {
+        pool: Pubkey,
+        owner: Pubkey,
+        amount: u64,
+        reward_rate: u64,
+        end_slot: u64,
+    },
+    /// liquidity staked, reserves are post-deposit
+    Stake {
+        pool: Pubkey,
+        owner: Pubkey,
+        amount_a: u64,
+        amount_b: u64,
+        shares: u64,
+        reserve_a: u64,
+        reserve_b: u64,
+    },
+    /// liquidity unstaked with the rewards paid, reserves are post-withdrawal
+    Unstake {
+        pool: Pubkey,
+        owner: Pubkey,
+        amount_a: u64,
+        amount_b: u64,
+        shares: u64,
+        rewards: u64,
+        reserve_a: u64,
+        reserve_b: u64,
+    },
+    /// farm rewards paid to a stake
+    ClaimRewards {
+        pool: Pubkey,
+        owner: Pubkey,
+        amount: u64,
+    },
+    /// fee mode or its bounds changed, the volatility starts over
+    UpdateDynamicFee {
+        pool: Pubkey,
+        owner: Pubkey,
+        mode: FeeMode,
+        min_fee: u64,
+        max_fee: u64,
+    },
+    /// legacy pool grown to the current layout, with the decimals of its mints
+    Migrate {
+        pool: Pubkey,
+        owner: Pubkey,
+        decimals_a: u8,
+        decimals_b: u8,
+    },
+}
+
+impl AmmEvent {
+    /// Log the event through `sol_log_data`.
+    pub fn emit(&self) {
+        sol_log_data(&[&self.pack()]);
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let (version, rest) = input.split_first().ok_or(AmmError::InvalidInput)?;
+        if *version != EVENT_VERSION {
+            return Err(AmmError::InvalidInput.into());
+        }
+        let (tag, rest) = rest.split_first().ok_or(AmmError::InvalidInput)?;
+        Ok(match tag {
+            0 => {
+                let data = Self::body(rest, 32 * 2 + 8 * 4)?;
+                let data = array_ref![data, 0, 32 * 2 + 8 * 4];
+                let (
+                    pool_buf,
+                    owner_buf,
+                    fee_rate_buf,
+                    tolerance_buf,
+                    reserve_a_buf,
+                    reserve_b_buf,
+                ) = array_refs![data, 32, 32, 8, 8, 8, 8];
+                Self::Initialize {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    fee_rate: u64::from_le_bytes(*fee_rate_buf),
+                    tolerance: u64::from_le_bytes(*tolerance_buf),
+                    reserve_a: u64::from_le_bytes(*reserve_a_buf),
+                    reserve_b: u64::from_le_bytes(*reserve_b_buf),
+                }
+            }
+            1 => {
+                let data = Self::body(rest, 32 * 2 + 1 + 8 * 5)?;
+                let data = array_ref![data, 0, 32 * 2 + 1 + 8 * 5];
+                let (
+                    pool_buf,
+                    user_buf,
+                    direction_buf,
+                    amount_in_buf,
+                    amount_out_buf,
+                    fee_buf,
+                    reserve_a_buf,
+                    reserve_b_buf,
+                ) = array_refs![data, 32, 32, 1, 8, 8, 8, 8, 8];
+                Self::Swap {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    user: Pubkey::new_from_array(*user_buf),
+                    direction: Direction::try_from(u8::from_le_bytes(*direction_buf))?,
+                    amount_in: u64::from_le_bytes(*amount_in_buf),
+                    amount_out: u64::from_le_bytes(*amount_out_buf),
+                    fee: u64::from_le_bytes(*fee_buf),
+                    reserve_a: u64::from_le_bytes(*reserve_a_buf),
+                    reserve_b: u64::from_le_bytes(*reserve_b_buf),
+                }
+            }
+            2 => {
+                let data = Self::body(rest, 32 * 2 + 8)?;
+                let data = array_ref![data, 0, 32 * 2 + 8];
+                let (pool_buf, owner_buf, amount_buf) = array_refs![data, 32, 32, 8];
+                Self::WithdrawalFee {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    amount: u64::from_le_bytes(*amount_buf),
+                }
+            }
+            3 => {
+                let data = Self::body(rest, 32 * 2 + 1)?;
+                let data = array_ref![data, 0, 32 * 2 + 1];
+                let (pool_buf, owner_buf, status_buf) = array_refs![data, 32, 32, 1];
+                Self::UpdateStatus {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    status: PoolStatus::try_from(u8::from_le_bytes(*status_buf))?,
+                }
+            }
+            4 => {
+                let data = Self::body(rest, 32 * 2 + 8)?;
+                let data = array_ref![data, 0, 32 * 2 + 8];
+                let (pool_buf, owner_buf, tolerance_buf) = array_refs![data, 32, 32, 8];
+                Self::UpdateTolerance {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    tolerance: u64::from_le_bytes(*tolerance_buf),
+                }
+            }
+            5 => {
+                let data = Self::body(rest, 32 * 2 + 8 * 3)?;
+                let data = array_ref![data, 0, 32 * 2 + 8 * 3];
+                let (pool_buf, owner_buf, amount_a_buf, amount_b_buf, fee_buf) =
+                    array_refs![data, 32, 32, 8, 8, 8];
+                Self::Terminate {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    amount_a: u64::from_le_bytes(*amount_a_buf),
+                    amount_b: u64::from_le_bytes(*amount_b_buf),
+                    fee: u64::from_le_bytes(*fee_buf),
+                }
+            }
+            6 => {
+                let data = Self::body(rest, 32 * 3 + 1 + 8 * 3)?;
+                let data = array_ref![data, 0, 32 * 3 + 1 + 8 * 3];
+                let (
+                    pool_buf,
+                    order_buf,
+                    owner_buf,
+                    direction_buf,
+                    amount_in_buf,
+                    min_amount_out_buf,
+                    reward_buf,
+                ) = array_refs![data, 32, 32, 32, 1, 8, 8, 8];
+                Self::PlaceOrder {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    order: Pubkey::new_from_array(*order_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    direction: Direction::try_from(u8::from_le_bytes(*direction_buf))?,
+                    amount_in: u64::from_le_bytes(*amount_in_buf),
+                    min_amount_out: u64::from_le_bytes(*min_amount_out_buf),
+                    reward: u64::from_le_bytes(*reward_buf),
+                }
+            }
+            7 => {
+                let data = Self::body(rest, 32 * 3 + 8)?;
+                let data = array_ref![data, 0, 32 * 3 + 8];
+                let (pool_buf, order_buf, owner_buf, amount_buf) = array_refs![data, 32, 32, 32, 8];
+                Self::CancelOrder {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    order: Pubkey::new_from_array(*order_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    amount: u64::from_le_bytes(*amount_buf),
+                }
+            }
+            8 => {
+                let data = Self::body(rest, 32 * 3 + 1 + 8 * 5)?;
+                let data = array_ref![data, 0, 32 * 3 + 1 + 8 * 5];
+                let (
+                    pool_buf,
+                    order_buf,
+                    keeper_buf,
+                    direction_buf,
+                    amount_in_buf,
+                    amount_out_buf,
+                    fee_buf,
+                    reserve_a_buf,
+                    reserve_b_buf,
+                ) = array_refs![data, 32, 32, 32, 1, 8, 8, 8, 8, 8];
+                Self::FillOrder {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    order: Pubkey::new_from_array(*order_buf),
+                    keeper: Pubkey::new_from_array(*keeper_buf),
+                    direction: Direction::try_from(u8::from_le_bytes(*direction_buf))?,
+                    amount_in: u64::from_le_bytes(*amount_in_buf),
+                    amount_out: u64::from_le_bytes(*amount_out_buf),
+                    fee: u64::from_le_bytes(*fee_buf),
+                    reserve_a: u64::from_le_bytes(*reserve_a_buf),
+                    reserve_b: u64::from_le_bytes(*reserve_b_buf),
+                }
+            }
+            9 => {
+                let data = Self::body(rest, 32 * 3 + 1 + 8 * 3)?;
+                let data = array_ref![data, 0, 32 * 3 + 1 + 8 * 3];
+                let (
+                    pool_buf,
+                    order_buf,
+                    owner_buf,
+                    direction_buf,
+                    amount_in_buf,
+                    sell_rate_buf,
+                    end_slot_buf,
+                ) = array_refs![data, 32, 32, 32, 1, 8, 8, 8];
+                Self::PlaceLongTermOrder {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    order: Pubkey::new_from_array(*order_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    direction: Direction::try_from(u8::from_le_bytes(*direction_buf))?,
+                    amount_in: u64::from_le_bytes(*amount_in_buf),
+                    sell_rate: u64::from_le_bytes(*sell_rate_buf),
+                    end_slot: u64::from_le_bytes(*end_slot_buf),
+                }
+            }
+            10 => {
+                let data = Self::body(rest, 32 * 3 + 8 * 2)?;
+                let data = array_ref![data, 0, 32 * 3 + 8 * 2];
+                let (pool_buf, order_buf, owner_buf, unsold_buf, proceeds_buf) =
+                    array_refs![data, 32, 32, 32, 8, 8];
+                Self::CancelLongTermOrder {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    order: Pubkey::new_from_array(*order_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    unsold: u64::from_le_bytes(*unsold_buf),
+                    proceeds: u64::from_le_bytes(*proceeds_buf),
+                }
+            }
+            11 => {
+                let data = Self::body(rest, 32 + 8 * 8)?;
+                let data = array_ref![data, 0, 32 + 8 * 8];
+                let (
+                    pool_buf,
+                    slot_buf,
+                    amount_a_in_buf,
+                    amount_a_out_buf,
+                    amount_b_in_buf,
+                    amount_b_out_buf,
+                    fee_buf,
+                    reserve_a_buf,
+                    reserve_b_buf,
+                ) = array_refs![data, 32, 8, 8, 8, 8, 8, 8, 8, 8];
+                Self::VirtualOrders {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    slot: u64::from_le_bytes(*slot_buf),
+                    amount_a_in: u64::from_le_bytes(*amount_a_in_buf),
+                    amount_a_out: u64::from_le_bytes(*amount_a_out_buf),
+                    amount_b_in: u64::from_le_bytes(*amount_b_in_buf),
+                    amount_b_out: u64::from_le_bytes(*amount_b_out_buf),
+                    fee: u64::from_le_bytes(*fee_buf),
+                    reserve_a: u64::from_le_bytes(*reserve_a_buf),
+                    reserve_b: u64::from_le_bytes(*reserve_b_buf),
+                }
+            }
+            12 => {
+                let data = Self::body(rest, 32 * 2 + 8)?;
+                let data = array_ref![data, 0, 32 * 2 + 8];
+                let (pool_buf, owner_buf, share_buf) = array_refs![data, 32, 32, 8];
+                Self::UpdateReferralShare {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    share: u64::from_le_bytes(*share_buf),
+                }
+            }
+            13 => {
+                let data = Self::body(rest, 32 * 3 + 8)?;
+                let data = array_ref![data, 0, 32 * 3 + 8];
+                let (pool_buf, user_buf, referrer_buf, amount_buf) =
+                    array_refs![data, 32, 32, 32, 8];
+                Self::Referral {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    user: Pubkey::new_from_array(*user_buf),
+                    referrer: Pubkey::new_from_array(*referrer_buf),
+                    amount: u64::from_le_bytes(*amount_buf),
+                }
+            }
+            14 => {
+                let data = Self::body(rest, 32 * 2 + 8 * 3)?;
+                let data = array_ref![data, 0, 32 * 2 + 8 * 3];
+                let (pool_buf, owner_buf, amount_buf, reward_rate_buf, end_slot_buf) =
+                    array_refs![data, 32, 32, 8, 8, 8];
+                Self::FundFarm {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    amount: u64::from_le_bytes(*amount_buf),
+                    reward_rate: u64::from_le_bytes(*reward_rate_buf),
+                    end_slot: u64::from_le_bytes(*end_slot_buf),
+                }
+            }
+            15 => {
+                let data = Self::body(rest, 32 * 2 + 8 * 5)?;
+                let data = array_ref![data, 0, 32 * 2 + 8 * 5];
+                let (
+                    pool_buf,
+                    owner_buf,
+                    amount_a_buf,
+                    amount_b_buf,
+                    shares_buf,
+                    reserve_a_buf,
+                    reserve_b_buf,
+                ) = array_refs![data, 32, 32, 8, 8, 8, 8, 8];
+                Self::Stake {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    amount_a: u64::from_le_bytes(*amount_a_buf),
+                    amount_b: u64::from_le_bytes(*amount_b_buf),
+                    shares: u64::from_le_bytes(*shares_buf),
+                    reserve_a: u64::from_le_bytes(*reserve_a_buf),
+                    reserve_b: u64::from_le_bytes(*reserve_b_buf),
+                }
+            }
+            16 => {
+                let data = Self::body(rest, 32 * 2 + 8 * 6)?;
+                let data = array_ref![data, 0, 32 * 2 + 8 * 6];
+                let (
+                    pool_buf,
+                    owner_buf,
+                    amount_a_buf,
+                    amount_b_buf,
+                    shares_buf,
+                    rewards_buf,
+                    reserve_a_buf,
+                    reserve_b_buf,
+                ) = array_refs![data, 32, 32, 8, 8, 8, 8, 8, 8];
+                Self::Unstake {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    amount_a: u64::from_le_bytes(*amount_a_buf),
+                    amount_b: u64::from_le_bytes(*amount_b_buf),
+                    shares: u64::from_le_bytes(*shares_buf),
+                    rewards: u64::from_le_bytes(*rewards_buf),
+                    reserve_a: u64::from_le_bytes(*reserve_a_buf),
+                    reserve_b: u64::from_le_bytes(*reserve_b_buf),
+                }
+            }
+            17 => {
+                let data = Self::body(rest, 32 * 2 + 8)?;
+                let data = array_ref![data, 0, 32 * 2 + 8];
+                let (pool_buf, owner_buf, amount_buf) = array_refs![data, 32, 32, 8];
+                Self::ClaimRewards {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    amount: u64::from_le_bytes(*amount_buf),
+                }
+            }
+            18 => {
+                let data = Self::body(rest, 32 * 2 + 1 + 8 * 2)?;
+                let data = array_ref![data, 0, 32 * 2 + 1 + 8 * 2];
+                let (pool_buf, owner_buf, mode_buf, min_fee_buf, max_fee_buf) =
+                    array_refs![data, 32, 32, 1, 8, 8];
+                Self::UpdateDynamicFee {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    mode: FeeMode::try_from(u8::from_le_bytes(*mode_buf))?,
+                    min_fee: u64::from_le_bytes(*min_fee_buf),
+                    max_fee: u64::from_le_bytes(*max_fee_buf),
+                }
+            }
+            19 => {
+                let data = Self::body(rest, 32 * 2 + 2)?;
+                let data = array_ref![data, 0, 32 * 2 + 2];
+                let (pool_buf, owner_buf, decimals_a_buf, decimals_b_buf) =
+                    array_refs![data, 32, 32, 1, 1];
+                Self::Migrate {
+                    pool: Pubkey::new_from_array(*pool_buf),
+                    owner: Pubkey::new_from_array(*owner_buf),
+                    decimals_a: u8::from_le_bytes(*decimals_a_buf),
+                    decimals_b: u8::from_le_bytes(*decimals_b_buf),
+                }
+            }
+            _ => return Err(AmmError::InvalidInput.into()),
+        })
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(2 + size_of::<Self>());
+        buf.push(EVENT_VERSION);
+        match *self {
+            Self::Initialize {
+                pool,
+                owner,
+                fee_rate,
+                tolerance,
+                reserve_a,
+                reserve_b,
+            } => {
+                buf.push(0);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&fee_rate.to_le_bytes());
+                buf.extend_from_slice(&tolerance.to_le_bytes());
+                buf.extend_from_slice(&reserve_a.to_le_bytes());
+                buf.extend_from_slice(&reserve_b.to_le_bytes());
+            }
+            Self::Swap {
+                pool,
+                user,
+                direction,
+                amount_in,
+                amount_out,
+                fee,
+                reserve_a,
+                reserve_b,
+            } => {
+                buf.push(1);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(user.as_ref());
+                let direction_buf: u8 = direction.into();
+                buf.push(direction_buf);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&amount_out.to_le_bytes());
+                buf.extend_from_slice(&fee.to_le_bytes());
+                buf.extend_from_slice(&reserve_a.to_le_bytes());
+                buf.extend_from_slice(&reserve_b.to_le_bytes());
+            }
+            Self::WithdrawalFee {
+                pool,
+                owner,
+                amount,
+            } => {
+                buf.push(2);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::UpdateStatus {
+                pool,
+                owner,
+                status,
+            } => {
+                buf.push(3);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                let status_buf: u8 = status.into();
+                buf.push(status_buf);
+            }
+            Self::UpdateTolerance {
+                pool,
+                owner,
+                tolerance,
+            } => {
+                buf.push(4);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&tolerance.to_le_bytes());
+            }
+            Self::Terminate {
+                pool,
+                owner,
+                amount_a,
+                amount_b,
+                fee,
+            } => {
+                buf.push(5);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&amount_a.to_le_bytes());
+                buf.extend_from_slice(&amount_b.to_le_bytes());
+                buf.extend_from_slice(&fee.to_le_bytes());
+            }
+            Self::PlaceOrder {
+                pool,
+                order,
+                owner,
+                direction,
+                amount_in,
+                min_amount_out,
+                reward,
+            } => {
+                buf.push(6);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(order.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                let direction_buf: u8 = direction.into();
+                buf.push(direction_buf);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&min_amount_out.to_le_bytes());
+                buf.extend_from_slice(&reward.to_le_bytes());
+            }
+            Self::CancelOrder {
+                pool,
+                order,
+                owner,
+                amount,
+            } => {
+                buf.push(7);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(order.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::FillOrder {
+                pool,
+                order,
+                keeper,
+                direction,
+                amount_in,
+                amount_out,
+                fee,
+                reserve_a,
+                reserve_b,
+            } => {
+                buf.push(8);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(order.as_ref());
+                buf.extend_from_slice(keeper.as_ref());
+                let direction_buf: u8 = direction.into();
+                buf.push(direction_buf);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&amount_out.to_le_bytes());
+                buf.extend_from_slice(&fee.to_le_bytes());
+                buf.extend_from_slice(&reserve_a.to_le_bytes());
+                buf.extend_from_slice(&reserve_b.to_le_bytes());
+            }
+            Self::PlaceLongTermOrder {
+                pool,
+                order,
+                owner,
+                direction,
+                amount_in,
+                sell_rate,
+                end_slot,
+            } => {
+                buf.push(9);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(order.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                let direction_buf: u8 = direction.into();
+                buf.push(direction_buf);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&sell_rate.to_le_bytes());
+                buf.extend_from_slice(&end_slot.to_le_bytes());
+            }
+            Self::CancelLongTermOrder {
+                pool,
+                order,
+                owner,
+                unsold,
+                proceeds,
+            } => {
+                buf.push(10);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(order.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&unsold.to_le_bytes());
+                buf.extend_from_slice(&proceeds.to_le_bytes());
+            }
+            Self::VirtualOrders {
+                pool,
+                slot,
+                amount_a_in,
+                amount_a_out,
+                amount_b_in,
+                amount_b_out,
+                fee,
+                reserve_a,
+                reserve_b,
+            } => {
+                buf.push(11);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(&slot.to_le_bytes());
+                buf.extend_from_slice(&amount_a_in.to_le_bytes());
+                buf.extend_from_slice(&amount_a_out.to_le_bytes());
+                buf.extend_from_slice(&amount_b_in.to_le_bytes());
+                buf.extend_from_slice(&amount_b_out.to_le_bytes());
+                buf.extend_from_slice(&fee.to_le_bytes());
+                buf.extend_from_slice(&reserve_a.to_le_bytes());
+                buf.extend_from_slice(&reserve_b.to_le_bytes());
+            }
+            Self::UpdateReferralShare { pool, owner, share } => {
+                buf.push(12);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&share.to_le_bytes());
+            }
+            Self::Referral {
+                pool,
+                user,
+                referrer,
+                amount,
+            } => {
+                buf.push(13);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(user.as_ref());
+                buf.extend_from_slice(referrer.as_ref());
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::FundFarm {
+                pool,
+                owner,
+                amount,
+                reward_rate,
+                end_slot,
+            } => {
+                buf.push(14);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&reward_rate.to_le_bytes());
+                buf.extend_from_slice(&end_slot.to_le_bytes());
+            }
+            Self::Stake {
+                pool,
+                owner,
+                amount_a,
+                amount_b,
+                shares,
+                reserve_a,
+                reserve_b,
+            } => {
+                buf.push(15);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&amount_a.to_le_bytes());
+                buf.extend_from_slice(&amount_b.to_le_bytes());
+                buf.extend_from_slice(&shares.to_le_bytes());
+                buf.extend_from_slice(&reserve_a.to_le_bytes());
+                buf.extend_from_slice(&reserve_b.to_le_bytes());
+            }
+            Self::Unstake {
+                pool,
+                owner,
+                amount_a,
+                amount_b,
+                shares,
+                rewards,
+                reserve_a,
+                reserve_b,
+            } => {
+                buf.push(16);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&amount_a.to_le_bytes());
+                buf.extend_from_slice(&amount_b.to_le_bytes());
+                buf.extend_from_slice(&shares.to_le_bytes());
+                buf.extend_from_slice(&rewards.to_le_bytes());
+                buf.extend_from_slice(&reserve_a.to_le_bytes());
+                buf.extend_from_slice(&reserve_b.to_le_bytes());
+            }
+            Self::ClaimRewards {
+                pool,
+                owner,
+                amount,
+            } => {
+                buf.push(17);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::UpdateDynamicFee {
+                pool,
+                owner,
+                mode,
+                min_fee,
+                max_fee,
+            } => {
+                buf.push(18);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                let mode_buf: u8 = mode.into();
+                buf.push(mode_buf);
+                buf.extend_from_slice(&min_fee.to_le_bytes());
+                buf.extend_from_slice(&max_fee.to_le_bytes());
+            }
+            Self::Migrate {
+                pool,
+                owner,
+                decimals_a,
+                decimals_b,
+            } => {
+                buf.push(19);
+                buf.extend_from_slice(pool.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.push(decimals_a);
+                buf.push(decimals_b);
+            }
+        }
+        buf
+    }
+
+    /// Decode an event from a transaction log line.
+    /// Returns `None` if the line is not a `Program data:` line.
+    #[cfg(not(target_arch = "bpf"))]
+    pub fn from_log(line: &str) -> Option<Result<Self, ProgramError>> {
+        let data = line.strip_prefix(EVENT_LOG_PREFIX)?;
+        // events are logged as a single field
+        let data = data.split_whitespace().next()?;
+        Some(
+            base64::decode(data)
+                .map_err(|_| AmmError::InvalidInput.into())
+                .and_then(|bytes| Self::unpack(&bytes)),
+        )
+    }
+
+    /// Decode every amm event in a transaction's log messages.
+    /// The invoke stack is tracked from the `invoke` and `success`/`failed` lines, only
+    /// data logged while `program_id` is the innermost program is read, so data of
+    /// other programs, or of programs the amm invokes, is never taken for an event.
+    /// Lines that are not events, or fail to decode, are skipped.
+    #[cfg(not(target_arch = "bpf"))]
+    pub fn from_logs<S: AsRef<str>>(program_id: &Pubkey, logs: &[S]) -> Vec<Self> {
+        let program_id = program_id.to_string();
+        let mut stack: Vec<&str> = Vec::new();
+        let mut events = Vec::new();
+        for line in logs.iter().map(AsRef::as_ref) {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("Program"), Some(id), Some("invoke")) => stack.push(id),
+                (Some("Program"), Some(_), Some("success" | "failed:")) => {
+                    stack.pop();
+                }
+                _ if stack.last() == Some(&program_id.as_str()) => {
+                    if let Some(Ok(event)) = Self::from_log(line) {
+                        events.push(event);
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// check body length before `array_ref!`
+    fn body(rest: &[u8], len: usize) -> Result<&[u8], ProgramError> {
+        if rest.len() < len {
+            return Err(AmmError::InvalidInput.into());
+        }
+        Ok(&rest[..len])
+    }
+}
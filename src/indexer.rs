@@ -0,0 +1,746 @@
+//! Log-replay indexer
+//!
+//! Rebuilds the history of every pool from recorded transactions, as returned by
+//! the `getTransaction` rpc method with `json` encoding. Nothing here touches the
+//! network, so it runs against JSON files on disk.
+//!
+//! Reserves are taken from the swap events when present, and from the vault
+//! token balances otherwise. Fees are taken from the swap events, or from the
+//! fee vault balance change for older transactions without events. Virtual orders
+//! of long-term orders are applied from their events, whichever instruction ran them.
+use crate::{error::AmmError, event::AmmEvent, instruction::AmmInstruction, state::PoolStatus};
+use serde_json::Value;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// Accounts of an amm instruction, by role.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AmmAccounts {
+    pub pool: Pubkey,
+    pub signer: Option<Pubkey>,
+    pub vault_a: Option<Pubkey>,
+    pub vault_b: Option<Pubkey>,
+    pub fee_vault: Option<Pubkey>,
+    // limit orders the instruction places, cancels or fills
+    pub orders: Vec<Pubkey>,
+}
+
+impl AmmAccounts {
+    /// Map the account list of `instruction` to roles, following the layouts in `processor.rs`.
+    pub fn from_keys(instruction: &AmmInstruction, keys: &[Pubkey]) -> Result<Self, AmmError> {
+        let key = |i: usize| keys.get(i).copied().ok_or(AmmError::InvalidInput);
+        let pool = key(0)?;
+        Ok(match instruction {
+            AmmInstruction::Initialize { .. } => Self {
+                pool,
+                signer: Some(key(1)?),
+                vault_a: Some(key(4)?),
+                vault_b: Some(key(5)?),
+                fee_vault: Some(key(6)?),
+                ..Self::default()
+            },
+            AmmInstruction::UpdateStatus { .. }
+            | AmmInstruction::UpdateTolerance { .. }
+            | AmmInstruction::UpdateReferralShare { .. }
+            | AmmInstruction::UpdateDynamicFee { .. } => Self {
+                pool,
+                signer: Some(key(1)?),
+                ..Self::default()
+            },
+            AmmInstruction::Terminate { .. } => Self {
+                pool,
+                signer: Some(key(1)?),
+                vault_a: Some(key(2)?),
+                vault_b: Some(key(3)?),
+                fee_vault: Some(key(4)?),
+                ..Self::default()
+            },
+            AmmInstruction::Swap { .. } => Self {
+                pool,
+                signer: Some(key(5)?),
+                vault_a: Some(key(1)?),
+                vault_b: Some(key(2)?),
+                fee_vault: Some(key(3)?),
+                ..Self::default()
+            },
+            AmmInstruction::WithdrawalFee {} => Self {
+                pool,
+                signer: Some(key(1)?),
+                fee_vault: Some(key(2)?),
+                ..Self::default()
+            },
+            AmmInstruction::PlaceOrder { .. } | AmmInstruction::CancelOrder {} => Self {
+                pool,
+                signer: Some(key(2)?),
+                orders: vec![key(1)?],
+                ..Self::default()
+            },
+            AmmInstruction::FillOrders {} => Self {
+                pool,
+                signer: Some(key(5)?),
+                vault_a: Some(key(1)?),
+                vault_b: Some(key(2)?),
+                fee_vault: Some(key(3)?),
+                // groups of order, escrow, owner and token out after the pool accounts and
+                // the three accounts of the virtual orders, when they are passed
+                orders: keys
+                    .iter()
+                    .skip(9 + keys.len().saturating_sub(9) % 4)
+                    .step_by(4)
+                    .copied()
+                    .collect(),
+            },
+            AmmInstruction::PlaceLongTermOrder { .. } | AmmInstruction::CancelLongTermOrder {} => {
+                Self {
+                    pool,
+                    signer: Some(key(2)?),
+                    vault_a: Some(key(6)?),
+                    vault_b: Some(key(7)?),
+                    fee_vault: Some(key(8)?),
+                    orders: vec![key(1)?],
+                }
+            }
+            AmmInstruction::ExecuteVirtualOrders {} => Self {
+                pool,
+                vault_a: Some(key(4)?),
+                vault_b: Some(key(5)?),
+                fee_vault: Some(key(6)?),
+                ..Self::default()
+            },
+            AmmInstruction::FundFarm { .. } => Self {
+                pool,
+                signer: Some(key(1)?),
+                vault_a: Some(key(4)?),
+                ..Self::default()
+            },
+            AmmInstruction::Stake { .. } | AmmInstruction::Unstake { .. } => Self {
+                pool,
+                signer: Some(key(3)?),
+                vault_a: Some(key(4)?),
+                vault_b: Some(key(5)?),
+                fee_vault: Some(key(6)?),
+                ..Self::default()
+            },
+            AmmInstruction::ClaimRewards {} => Self {
+                pool,
+                signer: Some(key(3)?),
+                ..Self::default()
+            },
+            AmmInstruction::Migrate {} => Self {
+                pool,
+                signer: Some(key(1)?),
+                ..Self::default()
+            },
+        })
+    }
+}
+
+/// An amm instruction decoded from a recorded transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedInstruction {
+    pub instruction: AmmInstruction,
+    pub accounts: AmmAccounts,
+}
+
+/// Token balance of one account, before and after a transaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TokenBalance {
+    pub pre: Option<u64>,
+    pub post: Option<u64>,
+}
+
+/// A recorded transaction, reduced to what the indexer needs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecordedTransaction {
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub signature: String,
+    pub failed: bool,
+    pub instructions: Vec<DecodedInstruction>,
+    pub events: Vec<AmmEvent>,
+    pub token_balances: HashMap<Pubkey, TokenBalance>,
+}
+
+impl RecordedTransaction {
+    /// Parse one `getTransaction` result, keeping instructions of `program_id` only.
+    /// A full rpc response, with the transaction under `result`, is accepted too.
+    pub fn from_json(program_id: &Pubkey, value: &Value) -> Result<Self, ProgramError> {
+        let value = value.get("result").unwrap_or(value);
+        let message = value
+            .pointer("/transaction/message")
+            .ok_or(AmmError::InvalidInput)?;
+        let keys = message
+            .get("accountKeys")
+            .and_then(Value::as_array)
+            .ok_or(AmmError::InvalidInput)?
+            .iter()
+            .map(|key| {
+                key.as_str()
+                    .and_then(|key| Pubkey::from_str(key).ok())
+                    .ok_or(AmmError::InvalidInput)
+            })
+            .collect::<Result<Vec<Pubkey>, AmmError>>()?;
+        let meta = value.get("meta");
+        let failed = matches!(meta.and_then(|meta| meta.get("err")), Some(err) if !err.is_null());
+        // top level instructions, then inner instructions in order
+        let mut raw = vec![];
+        if let Some(list) = message.get("instructions").and_then(Value::as_array) {
+            raw.extend(list.iter());
+        }
+        if let Some(inner) = meta
+            .and_then(|meta| meta.get("innerInstructions"))
+            .and_then(Value::as_array)
+        {
+            for entry in inner {
+                if let Some(list) = entry.get("instructions").and_then(Value::as_array) {
+                    raw.extend(list.iter());
+                }
+            }
+        }
+        let mut instructions = vec![];
+        // failed transactions change nothing, and may carry garbage data
+        if failed {
+            raw.clear();
+        }
+        for ix in raw {
+            let program_index = ix
+                .get("programIdIndex")
+                .and_then(Value::as_u64)
+                .ok_or(AmmError::InvalidInput)?;
+            if keys.get(program_index as usize) != Some(program_id) {
+                continue;
+            }
+            let data = ix
+                .get("data")
+                .and_then(Value::as_str)
+                .and_then(|data| bs58::decode(data).into_vec().ok())
+                .ok_or(AmmError::InvalidInput)?;
+            let accounts = ix
+                .get("accounts")
+                .and_then(Value::as_array)
+                .ok_or(AmmError::InvalidInput)?
+                .iter()
+                .map(|i| {
+                    i.as_u64()
+                        .and_then(|i| keys.get(i as usize).copied())
+                        .ok_or(AmmError::InvalidInput)
+                })
+                .collect::<Result<Vec<Pubkey>, AmmError>>()?;
+            let instruction = AmmInstruction::unpack(&data)?;
+            let accounts = AmmAccounts::from_keys(&instruction, &accounts)?;
+            instructions.push(DecodedInstruction {
+                instruction,
+                accounts,
+            });
+        }
+        let mut token_balances: HashMap<Pubkey, TokenBalance> = HashMap::new();
+        for (field, post) in [("preTokenBalances", false), ("postTokenBalances", true)] {
+            let list = meta
+                .and_then(|meta| meta.get(field))
+                .and_then(Value::as_array);
+            for balance in list.into_iter().flatten() {
+                let key = balance
+                    .get("accountIndex")
+                    .and_then(Value::as_u64)
+                    .and_then(|i| keys.get(i as usize).copied());
+                let amount = balance
+                    .pointer("/uiTokenAmount/amount")
+                    .and_then(Value::as_str)
+                    .and_then(|amount| amount.parse::<u64>().ok());
+                if let (Some(key), Some(amount)) = (key, amount) {
+                    let entry = token_balances.entry(key).or_default();
+                    if post {
+                        entry.post = Some(amount);
+                    } else {
+                        entry.pre = Some(amount);
+                    }
+                }
+            }
+        }
+        let logs: Vec<&str> = meta
+            .and_then(|meta| meta.get("logMessages"))
+            .and_then(Value::as_array)
+            .map(|logs| logs.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        Ok(Self {
+            slot: value.get("slot").and_then(Value::as_u64).unwrap_or(0),
+            block_time: value.get("blockTime").and_then(Value::as_i64),
+            signature: value
+                .pointer("/transaction/signatures/0")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            failed,
+            instructions,
+            events: AmmEvent::from_logs(program_id, &logs),
+            token_balances,
+        })
+    }
+
+    /// Parse a JSON document holding one transaction, or an array of them.
+    pub fn from_json_str(program_id: &Pubkey, input: &str) -> Result<Vec<Self>, ProgramError> {
+        let value: Value = serde_json::from_str(input).map_err(|_| AmmError::InvalidInput)?;
+        match value.as_array() {
+            Some(list) => list
+                .iter()
+                .map(|value| Self::from_json(program_id, value))
+                .collect(),
+            None => Ok(vec![Self::from_json(program_id, &value)?]),
+        }
+    }
+
+    fn post_balance(&self, key: Option<Pubkey>) -> Option<u64> {
+        key.and_then(|key| self.token_balances.get(&key))
+            .and_then(|balance| balance.post)
+    }
+
+    fn balance_change(&self, key: Option<Pubkey>) -> Option<u64> {
+        let balance = key.and_then(|key| self.token_balances.get(&key))?;
+        balance
+            .post
+            .unwrap_or(0)
+            .checked_sub(balance.pre.unwrap_or(0))
+    }
+}
+
+/// Reconstructed state of one pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolState {
+    pub status: PoolStatus,
+    pub terminated: bool,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    // fee charged by swaps, including withdrawn fee
+    pub cumulative_fee: u64,
+    pub fee_withdrawn: u64,
+    pub swap_count: u64,
+    pub open_orders: u64,
+    pub long_term_orders: u64,
+    // farm shares held by stakes
+    pub staked_shares: u64,
+}
+
+/// One row of pool history, written after every amm instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub signature: String,
+    pub pool: Pubkey,
+    pub instruction: &'static str,
+    pub state: PoolState,
+}
+
+/// Replays recorded transactions of one amm program.
+#[derive(Clone, Debug)]
+pub struct Indexer {
+    pub program_id: Pubkey,
+    pub pools: HashMap<Pubkey, PoolState>,
+    pub history: Vec<HistoryEntry>,
+}
+
+impl Indexer {
+    pub fn new(program_id: Pubkey) -> Self {
+        Self {
+            program_id,
+            pools: HashMap::new(),
+            history: vec![],
+        }
+    }
+
+    /// Replay recorded transaction files, ordered by slot.
+    pub fn process_files<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<(), IndexerError> {
+        let mut transactions = vec![];
+        for path in paths {
+            let input = fs::read_to_string(path)?;
+            transactions.extend(RecordedTransaction::from_json_str(
+                &self.program_id,
+                &input,
+            )?);
+        }
+        // stable, so transactions of the same slot keep file order
+        transactions.sort_by_key(|tx| tx.slot);
+        for tx in transactions.iter() {
+            self.process_transaction(tx);
+        }
+        Ok(())
+    }
+
+    /// Replay every `*.json` file of a directory.
+    pub fn process_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), IndexerError> {
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, io::Error>>()?;
+        paths.retain(|path| path.extension() == Some(OsStr::new("json")));
+        paths.sort();
+        self.process_files(&paths)
+    }
+
+    /// Apply one transaction. Failed transactions change nothing.
+    pub fn process_transaction(&mut self, tx: &RecordedTransaction) {
+        if tx.failed {
+            return;
+        }
+        let mut events: Vec<&AmmEvent> = tx.events.iter().collect();
+        for decoded in tx.instructions.iter() {
+            let accounts = &decoded.accounts;
+            let virtual_orders =
+                Self::next_virtual_orders_event(&mut events, &decoded.instruction, &accounts.pool);
+            let referral =
+                Self::next_referral_event(&mut events, &decoded.instruction, &accounts.pool);
+            let event = Self::next_event(&mut events, &decoded.instruction, &accounts.pool);
+            let state = self.pools.entry(accounts.pool).or_default();
+            // the virtual orders trade before the instruction itself
+            if let Some(AmmEvent::VirtualOrders {
+                fee,
+                reserve_a,
+                reserve_b,
+                ..
+            }) = virtual_orders
+            {
+                state.reserve_a = *reserve_a;
+                state.reserve_b = *reserve_b;
+                state.cumulative_fee = state.cumulative_fee.saturating_add(*fee);
+            }
+            let name = match decoded.instruction {
+                AmmInstruction::Initialize {
+                    amount_a, amount_b, ..
+                } => {
+                    // a transfer fee leaves the vaults short of the amounts sent
+                    let (reserve_a, reserve_b) = match event {
+                        Some(AmmEvent::Initialize {
+                            reserve_a,
+                            reserve_b,
+                            ..
+                        }) => (*reserve_a, *reserve_b),
+                        _ => (
+                            tx.post_balance(accounts.vault_a).unwrap_or(amount_a),
+                            tx.post_balance(accounts.vault_b).unwrap_or(amount_b),
+                        ),
+                    };
+                    *state = PoolState {
+                        status: PoolStatus::Nomal,
+                        reserve_a,
+                        reserve_b,
+                        ..PoolState::default()
+                    };
+                    "initialize"
+                }
+                AmmInstruction::UpdateStatus { status } => {
+                    if let Ok(status) = PoolStatus::try_from(status) {
+                        state.status = status;
+                    }
+                    "update_status"
+                }
+                AmmInstruction::UpdateTolerance { .. } => "update_tolerance",
+                AmmInstruction::UpdateReferralShare { .. } => "update_referral_share",
+                AmmInstruction::UpdateDynamicFee { .. } => "update_dynamic_fee",
+                AmmInstruction::Swap { .. } => {
+                    let fee = match event {
+                        Some(AmmEvent::Swap {
+                            fee,
+                            reserve_a,
+                            reserve_b,
+                            ..
+                        }) => {
+                            state.reserve_a = *reserve_a;
+                            state.reserve_b = *reserve_b;
+                            // the referrer's cut never reaches the fee vault
+                            match referral {
+                                Some(AmmEvent::Referral { amount, .. }) => {
+                                    fee.saturating_sub(*amount)
+                                }
+                                _ => *fee,
+                            }
+                        }
+                        _ => {
+                            // no event, fall back to vault balances
+                            if let Some(reserve_a) = tx.post_balance(accounts.vault_a) {
+                                state.reserve_a = reserve_a;
+                            }
+                            if let Some(reserve_b) = tx.post_balance(accounts.vault_b) {
+                                state.reserve_b = reserve_b;
+                            }
+                            tx.balance_change(accounts.fee_vault).unwrap_or(0)
+                        }
+                    };
+                    state.cumulative_fee = state.cumulative_fee.saturating_add(fee);
+                    state.swap_count = state.swap_count.saturating_add(1);
+                    "swap"
+                }
+                AmmInstruction::PlaceOrder { .. } => {
+                    state.open_orders = state.open_orders.saturating_add(1);
+                    "place_order"
+                }
+                AmmInstruction::CancelOrder {} => {
+                    state.open_orders = state.open_orders.saturating_sub(1);
+                    "cancel_order"
+                }
+                AmmInstruction::FillOrders {} => {
+                    // one event per filled order, orders that didn't cross have none
+                    let mut filled = false;
+                    while let Some(event) = Self::next_fill_event(&mut events, accounts) {
+                        if let AmmEvent::FillOrder {
+                            fee,
+                            reserve_a,
+                            reserve_b,
+                            ..
+                        } = event
+                        {
+                            state.reserve_a = *reserve_a;
+                            state.reserve_b = *reserve_b;
+                            state.cumulative_fee = state.cumulative_fee.saturating_add(*fee);
+                            state.open_orders = state.open_orders.saturating_sub(1);
+                            filled = true;
+                        }
+                    }
+                    if !filled {
+                        if let Some(reserve_a) = tx.post_balance(accounts.vault_a) {
+                            state.reserve_a = reserve_a;
+                        }
+                        if let Some(reserve_b) = tx.post_balance(accounts.vault_b) {
+                            state.reserve_b = reserve_b;
+                        }
+                    }
+                    "fill_orders"
+                }
+                AmmInstruction::PlaceLongTermOrder { .. } => {
+                    state.long_term_orders = state.long_term_orders.saturating_add(1);
+                    "place_long_term_order"
+                }
+                AmmInstruction::CancelLongTermOrder {} => {
+                    state.long_term_orders = state.long_term_orders.saturating_sub(1);
+                    "cancel_long_term_order"
+                }
+                AmmInstruction::ExecuteVirtualOrders {} => "execute_virtual_orders",
+                AmmInstruction::FundFarm { .. } => "fund_farm",
+                AmmInstruction::Stake { .. } => {
+                    match event {
+                        Some(AmmEvent::Stake {
+                            shares,
+                            reserve_a,
+                            reserve_b,
+                            ..
+                        }) => {
+                            state.reserve_a = *reserve_a;
+                            state.reserve_b = *reserve_b;
+                            state.staked_shares = state.staked_shares.saturating_add(*shares);
+                        }
+                        _ => {
+                            if let Some(reserve_a) = tx.post_balance(accounts.vault_a) {
+                                state.reserve_a = reserve_a;
+                            }
+                            if let Some(reserve_b) = tx.post_balance(accounts.vault_b) {
+                                state.reserve_b = reserve_b;
+                            }
+                        }
+                    }
+                    "stake"
+                }
+                AmmInstruction::Unstake { shares, .. } => {
+                    match event {
+                        Some(AmmEvent::Unstake {
+                            reserve_a,
+                            reserve_b,
+                            ..
+                        }) => {
+                            state.reserve_a = *reserve_a;
+                            state.reserve_b = *reserve_b;
+                        }
+                        _ => {
+                            if let Some(reserve_a) = tx.post_balance(accounts.vault_a) {
+                                state.reserve_a = reserve_a;
+                            }
+                            if let Some(reserve_b) = tx.post_balance(accounts.vault_b) {
+                                state.reserve_b = reserve_b;
+                            }
+                        }
+                    }
+                    state.staked_shares = state.staked_shares.saturating_sub(shares);
+                    "unstake"
+                }
+                AmmInstruction::ClaimRewards {} => "claim_rewards",
+                AmmInstruction::WithdrawalFee {} => {
+                    let amount = match event {
+                        Some(AmmEvent::WithdrawalFee { amount, .. }) => Some(*amount),
+                        _ => accounts
+                            .fee_vault
+                            .and_then(|key| tx.token_balances.get(&key))
+                            .and_then(|balance| balance.pre),
+                    };
+                    state.fee_withdrawn = state.fee_withdrawn.saturating_add(amount.unwrap_or(0));
+                    "withdrawal_fee"
+                }
+                AmmInstruction::Terminate { .. } => {
+                    state.status = PoolStatus::Closed;
+                    state.terminated = true;
+                    state.reserve_a = 0;
+                    state.reserve_b = 0;
+                    "terminate"
+                }
+                AmmInstruction::Migrate {} => "migrate",
+            };
+            self.history.push(HistoryEntry {
+                slot: tx.slot,
+                block_time: tx.block_time,
+                signature: tx.signature.clone(),
+                pool: accounts.pool,
+                instruction: name,
+                state: *state,
+            });
+        }
+    }
+
+    /// Write history as CSV, one row per instruction.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "slot,block_time,signature,pool,instruction,status,terminated,reserve_a,reserve_b,cumulative_fee,fee_withdrawn,swap_count,open_orders,long_term_orders,staked_shares"
+        )?;
+        for entry in self.history.iter() {
+            let state = &entry.state;
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                entry.slot,
+                entry
+                    .block_time
+                    .map(|time| time.to_string())
+                    .unwrap_or_default(),
+                entry.signature,
+                entry.pool,
+                entry.instruction,
+                state.status,
+                state.terminated,
+                state.reserve_a,
+                state.reserve_b,
+                state.cumulative_fee,
+                state.fee_withdrawn,
+                state.swap_count,
+                state.open_orders,
+                state.long_term_orders,
+                state.staked_shares,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write history to a CSV file, replacing it if it exists.
+    pub fn write_csv_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_csv(io::BufWriter::new(fs::File::create(path)?))
+    }
+
+    /// Take the first remaining event of the same pool and kind.
+    fn next_event<'a>(
+        events: &mut Vec<&'a AmmEvent>,
+        instruction: &AmmInstruction,
+        pool: &Pubkey,
+    ) -> Option<&'a AmmEvent> {
+        let index = events.iter().position(|event| match (instruction, event) {
+            (AmmInstruction::Initialize { .. }, AmmEvent::Initialize { pool: p, .. })
+            | (AmmInstruction::UpdateStatus { .. }, AmmEvent::UpdateStatus { pool: p, .. })
+            | (AmmInstruction::UpdateTolerance { .. }, AmmEvent::UpdateTolerance { pool: p, .. })
+            | (
+                AmmInstruction::UpdateReferralShare { .. },
+                AmmEvent::UpdateReferralShare { pool: p, .. },
+            )
+            | (
+                AmmInstruction::UpdateDynamicFee { .. },
+                AmmEvent::UpdateDynamicFee { pool: p, .. },
+            )
+            | (AmmInstruction::Swap { .. }, AmmEvent::Swap { pool: p, .. })
+            | (AmmInstruction::WithdrawalFee {}, AmmEvent::WithdrawalFee { pool: p, .. })
+            | (AmmInstruction::Terminate { .. }, AmmEvent::Terminate { pool: p, .. })
+            | (AmmInstruction::PlaceOrder { .. }, AmmEvent::PlaceOrder { pool: p, .. })
+            | (AmmInstruction::CancelOrder {}, AmmEvent::CancelOrder { pool: p, .. })
+            | (
+                AmmInstruction::PlaceLongTermOrder { .. },
+                AmmEvent::PlaceLongTermOrder { pool: p, .. },
+            )
+            | (
+                AmmInstruction::CancelLongTermOrder {},
+                AmmEvent::CancelLongTermOrder { pool: p, .. },
+            )
+            | (AmmInstruction::FundFarm { .. }, AmmEvent::FundFarm { pool: p, .. })
+            | (AmmInstruction::Stake { .. }, AmmEvent::Stake { pool: p, .. })
+            | (AmmInstruction::Unstake { .. }, AmmEvent::Unstake { pool: p, .. })
+            | (AmmInstruction::ClaimRewards {}, AmmEvent::ClaimRewards { pool: p, .. })
+            | (AmmInstruction::Migrate {}, AmmEvent::Migrate { pool: p, .. }) => p == pool,
+            _ => false,
+        })?;
+        Some(events.remove(index))
+    }
+
+    /// Take the referral event logged before the next swap event of the pool.
+    fn next_referral_event<'a>(
+        events: &mut Vec<&'a AmmEvent>,
+        instruction: &AmmInstruction,
+        pool: &Pubkey,
+    ) -> Option<&'a AmmEvent> {
+        if !matches!(instruction, AmmInstruction::Swap { .. }) {
+            return None;
+        }
+        let swap = events
+            .iter()
+            .position(|event| matches!(event, AmmEvent::Swap { pool: p, .. } if p == pool))?;
+        let index = events[..swap]
+            .iter()
+            .position(|event| matches!(event, AmmEvent::Referral { pool: p, .. } if p == pool))?;
+        Some(events.remove(index))
+    }
+
+    /// Take the first remaining virtual orders event of the pool, for the instructions
+    /// that run them.
+    fn next_virtual_orders_event<'a>(
+        events: &mut Vec<&'a AmmEvent>,
+        instruction: &AmmInstruction,
+        pool: &Pubkey,
+    ) -> Option<&'a AmmEvent> {
+        match instruction {
+            AmmInstruction::Swap { .. }
+            | AmmInstruction::FillOrders {}
+            | AmmInstruction::PlaceLongTermOrder { .. }
+            | AmmInstruction::CancelLongTermOrder {}
+            | AmmInstruction::ExecuteVirtualOrders {}
+            | AmmInstruction::Stake { .. }
+            | AmmInstruction::Unstake { .. } => {}
+            _ => return None,
+        }
+        let index = events.iter().position(|event| match event {
+            AmmEvent::VirtualOrders { pool: p, .. } => p == pool,
+            _ => false,
+        })?;
+        Some(events.remove(index))
+    }
+
+    /// Take the first remaining fill event of an order passed to `FillOrders`.
+    fn next_fill_event<'a>(
+        events: &mut Vec<&'a AmmEvent>,
+        accounts: &AmmAccounts,
+    ) -> Option<&'a AmmEvent> {
+        let index = events.iter().position(|event| match event {
+            AmmEvent::FillOrder { pool, order, .. } => {
+                *pool == accounts.pool && accounts.orders.contains(order)
+            }
+            _ => false,
+        })?;
+        Some(events.remove(index))
+    }
+}
+
+/// Errors that may be returned by the indexer.
+#[derive(Debug, Error)]
+pub enum IndexerError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("decode error: {0}")]
+    Decode(#[from] ProgramError),
+}
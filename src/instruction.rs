@@ -2,18 +2,49 @@
 #![allow(clippy::too_many_arguments)]
 
 use crate::error::AmmError;
-// use solana_program::{program_error::ProgramError, pubkey::Pubkey};
 use arrayref::{array_ref, array_refs};
-use solana_program::program_error::ProgramError;
+use solana_program::{
+    clock::Clock,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
 use std::{fmt, mem::size_of};
 
+/// seed of the temporary wsol account used to wrap native sol
+pub const WSOL_SEED: &[u8] = b"wsol";
+
+/// seeds of the vaults `Initialize` creates for a pool
+pub const VAULT_A_SEED: &[u8] = b"vault_a";
+pub const VAULT_B_SEED: &[u8] = b"vault_b";
+pub const FEE_VAULT_SEED: &[u8] = b"fee_vault";
+
+/// seed of the token account escrowing a limit order
+pub const ORDER_ESCROW_SEED: &[u8] = b"order_escrow";
+
+/// seed of the virtual orders account of a pool
+pub const TWAMM_SEED: &[u8] = b"twamm";
+
+/// seeds of the token accounts escrowing the long-term orders of a pool
+pub const TWAMM_ESCROW_A_SEED: &[u8] = b"twamm_escrow_a";
+pub const TWAMM_ESCROW_B_SEED: &[u8] = b"twamm_escrow_b";
+
+/// seed of the farm account of a pool
+pub const FARM_SEED: &[u8] = b"farm";
+
+/// seed of the token account holding the rewards of a farm
+pub const FARM_REWARD_SEED: &[u8] = b"farm_reward";
+
+/// seed of the stake account of a wallet in a pool
+pub const STAKE_SEED: &[u8] = b"stake";
+
 /// swap direction
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Direction {
     A2B,
     B2A,
-    InvalidDirection,
 }
 
 impl fmt::Display for Direction {
@@ -21,7 +52,6 @@ impl fmt::Display for Direction {
         let status: String = match self {
             Direction::A2B => "Swap direction: A to B".to_string(),
             Direction::B2A => "Swap direction: B to A".to_string(),
-            Direction::InvalidDirection => "InvalidDirection".to_string(),
         };
         write!(f, "{}", status)
     }
@@ -30,12 +60,14 @@ impl fmt::Display for Direction {
 impl Eq for Direction {}
 
 // 1 is a2b, 2 is b2a
-impl From<u8> for Direction {
-    fn from(data: u8) -> Direction {
+impl TryFrom<u8> for Direction {
+    type Error = AmmError;
+
+    fn try_from(data: u8) -> Result<Direction, AmmError> {
         match data {
-            1 => Direction::A2B,
-            2 => Direction::B2A,
-            _ => Direction::InvalidDirection,
+            1 => Ok(Direction::A2B),
+            2 => Ok(Direction::B2A),
+            _ => Err(AmmError::InvalidDirection),
         }
     }
 }
@@ -46,7 +78,62 @@ impl Into<u8> for Direction {
         match self {
             Direction::A2B => 1,
             Direction::B2A => 2,
-            Direction::InvalidDirection => 0,
+        }
+    }
+}
+
+/// deadline of a user instruction
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Deadline {
+    /// compared with `Clock::unix_timestamp`
+    UnixTimestamp(i64),
+    /// compared with `Clock::slot`
+    Slot(u64),
+}
+
+impl Eq for Deadline {}
+
+impl Deadline {
+    pub const LEN: usize = 1 + 8;
+
+    /// true once the clock has passed the deadline
+    pub fn is_expired(&self, clock: &Clock) -> bool {
+        match *self {
+            Deadline::UnixTimestamp(timestamp) => clock.unix_timestamp > timestamp,
+            Deadline::Slot(slot) => clock.slot > slot,
+        }
+    }
+
+    /// Unpack the optional trailing deadline, no bytes means no deadline.
+    /// 1 is unix timestamp, 2 is slot
+    pub fn unpack_option(input: &[u8]) -> Result<Option<Self>, ProgramError> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        if input.len() != Self::LEN {
+            return Err(AmmError::InvalidInstruction.into());
+        }
+        let data = array_ref![input, 0, Deadline::LEN];
+        let (kind_buf, value_buf) = array_refs![data, 1, 8];
+        Ok(Some(match kind_buf[0] {
+            1 => Deadline::UnixTimestamp(i64::from_le_bytes(*value_buf)),
+            2 => Deadline::Slot(u64::from_le_bytes(*value_buf)),
+            _ => return Err(AmmError::InvalidInstruction.into()),
+        }))
+    }
+
+    pub fn pack_option(deadline: &Option<Self>, buf: &mut Vec<u8>) {
+        match *deadline {
+            Some(Deadline::UnixTimestamp(timestamp)) => {
+                buf.push(1);
+                buf.extend_from_slice(&timestamp.to_le_bytes());
+            }
+            Some(Deadline::Slot(slot)) => {
+                buf.push(2);
+                buf.extend_from_slice(&slot.to_le_bytes());
+            }
+            None => {}
         }
     }
 }
@@ -61,6 +148,7 @@ pub enum AmmInstruction {
         amount_a: u64,
         amount_b: u64,
         tolerance: u64,
+        deadline: Option<Deadline>,
     },
     UpdateStatus {
         status: u8,
@@ -68,12 +156,65 @@ pub enum AmmInstruction {
     UpdateTolerance {
         tolerance: u64,
     },
+    /// set the bps of the swap fee paid to referrers
+    UpdateReferralShare {
+        share: u64,
+    },
+    /// set how the swap fee follows the market and the bounds of a dynamic fee,
+    /// `mode` is a `FeeMode`
+    UpdateDynamicFee {
+        mode: u8,
+        min_fee: u64,
+        max_fee: u64,
+    },
     Swap {
         amount: u64,
         direction: Direction,
+        deadline: Option<Deadline>,
     },
     WithdrawalFee {},
-    Terminate {},
+    Terminate {
+        deadline: Option<Deadline>,
+    },
+    /// escrow `amount_in` until the pool pays at least `min_amount_out` for it
+    PlaceOrder {
+        amount_in: u64,
+        direction: Direction,
+        min_amount_out: u64,
+        reward: u64,
+    },
+    CancelOrder {},
+    /// fill every crossing order passed, permissionless
+    FillOrders {},
+    /// sell `amount_in` evenly over at least `slots` slots
+    PlaceLongTermOrder {
+        amount_in: u64,
+        direction: Direction,
+        slots: u64,
+    },
+    /// stop a long-term order, refund the unsold remainder and pay the proceeds
+    CancelLongTermOrder {},
+    /// run the virtual orders of the pool up to the current slot, permissionless
+    ExecuteVirtualOrders {},
+    /// pay out `amount` more rewards, with what is left, evenly over the next `slots` slots
+    FundFarm {
+        amount: u64,
+        slots: u64,
+    },
+    /// add `amount_a` and token b at the pool price to the reserves for farm shares
+    Stake {
+        amount_a: u64,
+        deadline: Option<Deadline>,
+    },
+    /// withdraw the reserves of `shares` farm shares and the rewards
+    Unstake {
+        shares: u64,
+        deadline: Option<Deadline>,
+    },
+    ClaimRewards {},
+    /// grow a pool of an earlier version to the current layout, the decimals are read
+    /// from its mints
+    Migrate {},
 }
 
 impl AmmInstruction {
@@ -83,7 +224,7 @@ impl AmmInstruction {
             .ok_or(crate::error::AmmError::InvalidInstruction)?;
         Ok(match tag {
             0 => {
-                let data = array_ref![rest, 0, 1 + 8 * 4];
+                let data = array_ref![Self::data(rest, 1 + 8 * 4)?, 0, 1 + 8 * 4];
                 let (nonce_buf, fee_buf, amount_a_buf, amount_b_buf, tolerance_buf) =
                     array_refs![data, 1, 8, 8, 8, 8];
                 Self::Initialize {
@@ -92,40 +233,116 @@ impl AmmInstruction {
                     amount_a: u64::from_le_bytes(*amount_a_buf),
                     amount_b: u64::from_le_bytes(*amount_b_buf),
                     tolerance: u64::from_le_bytes(*tolerance_buf),
+                    deadline: Deadline::unpack_option(&rest[1 + 8 * 4..])?,
                 }
             }
             2 => {
-                let data = array_ref![rest, 0, 1];
+                let data = array_ref![Self::data(rest, 1)?, 0, 1];
                 Self::UpdateStatus {
                     status: u8::from_le_bytes(*data),
                 }
             }
             3 => {
-                let data = array_ref![rest, 0, 8];
+                let data = array_ref![Self::data(rest, 8)?, 0, 8];
                 Self::UpdateTolerance {
                     tolerance: u64::from_le_bytes(*data),
                 }
             }
-            9 => Self::Terminate {},
+            4 => {
+                let data = array_ref![Self::data(rest, 8)?, 0, 8];
+                Self::UpdateReferralShare {
+                    share: u64::from_le_bytes(*data),
+                }
+            }
+            5 => {
+                let data = array_ref![Self::data(rest, 1 + 8 * 2)?, 0, 1 + 8 * 2];
+                let (mode_buf, min_fee_buf, max_fee_buf) = array_refs![data, 1, 8, 8];
+                Self::UpdateDynamicFee {
+                    mode: u8::from_le_bytes(*mode_buf),
+                    min_fee: u64::from_le_bytes(*min_fee_buf),
+                    max_fee: u64::from_le_bytes(*max_fee_buf),
+                }
+            }
+            9 => Self::Terminate {
+                deadline: Deadline::unpack_option(rest)?,
+            },
 
             10 => {
-                let data = array_ref![rest, 0, 8 + 1];
+                let data = array_ref![Self::data(rest, 8 + 1)?, 0, 8 + 1];
                 let (amount_buf, direction_buf) = array_refs![data, 8, 1];
                 // 1 is a2b, 2 is b2a
-                let direction = Direction::from(u8::from_le_bytes(*direction_buf));
+                let direction = Direction::try_from(u8::from_le_bytes(*direction_buf))?;
 
                 Self::Swap {
                     amount: u64::from_le_bytes(*amount_buf),
                     direction,
+                    deadline: Deadline::unpack_option(&rest[8 + 1..])?,
                 }
             }
 
+            11 => {
+                let data = array_ref![Self::data(rest, 8 + 1 + 8 * 2)?, 0, 8 + 1 + 8 * 2];
+                let (amount_in_buf, direction_buf, min_amount_out_buf, reward_buf) =
+                    array_refs![data, 8, 1, 8, 8];
+                Self::PlaceOrder {
+                    amount_in: u64::from_le_bytes(*amount_in_buf),
+                    direction: Direction::try_from(u8::from_le_bytes(*direction_buf))?,
+                    min_amount_out: u64::from_le_bytes(*min_amount_out_buf),
+                    reward: u64::from_le_bytes(*reward_buf),
+                }
+            }
+            12 => Self::CancelOrder {},
+            13 => Self::FillOrders {},
+            14 => {
+                let data = array_ref![Self::data(rest, 8 + 1 + 8)?, 0, 8 + 1 + 8];
+                let (amount_in_buf, direction_buf, slots_buf) = array_refs![data, 8, 1, 8];
+                Self::PlaceLongTermOrder {
+                    amount_in: u64::from_le_bytes(*amount_in_buf),
+                    direction: Direction::try_from(u8::from_le_bytes(*direction_buf))?,
+                    slots: u64::from_le_bytes(*slots_buf),
+                }
+            }
+            15 => Self::CancelLongTermOrder {},
+            16 => Self::ExecuteVirtualOrders {},
+            17 => {
+                let data = array_ref![Self::data(rest, 8 * 2)?, 0, 8 * 2];
+                let (amount_buf, slots_buf) = array_refs![data, 8, 8];
+                Self::FundFarm {
+                    amount: u64::from_le_bytes(*amount_buf),
+                    slots: u64::from_le_bytes(*slots_buf),
+                }
+            }
+            18 => {
+                let data = array_ref![Self::data(rest, 8)?, 0, 8];
+                Self::Stake {
+                    amount_a: u64::from_le_bytes(*data),
+                    deadline: Deadline::unpack_option(&rest[8..])?,
+                }
+            }
+            19 => {
+                let data = array_ref![Self::data(rest, 8)?, 0, 8];
+                Self::Unstake {
+                    shares: u64::from_le_bytes(*data),
+                    deadline: Deadline::unpack_option(&rest[8..])?,
+                }
+            }
+            20 => Self::ClaimRewards {},
+            21 => Self::Migrate {},
+
             80 => Self::WithdrawalFee {},
 
             _ => return Err(AmmError::InvalidInstruction.into()),
         })
     }
 
+    /// check data length before `array_ref!`
+    fn data(rest: &[u8], len: usize) -> Result<&[u8], ProgramError> {
+        if rest.len() < len {
+            return Err(AmmError::InvalidInstruction.into());
+        }
+        Ok(&rest[..len])
+    }
+
     // pack function to pack a AmmInstruction enum into a byte array for test convenience
     pub fn pack(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(size_of::<Self>());
@@ -136,6 +353,7 @@ impl AmmInstruction {
                 amount_a,
                 amount_b,
                 tolerance,
+                ref deadline,
             } => {
                 buf.push(0);
                 buf.extend_from_slice(&nonce.to_le_bytes());
@@ -143,6 +361,7 @@ impl AmmInstruction {
                 buf.extend_from_slice(&amount_a.to_le_bytes());
                 buf.extend_from_slice(&amount_b.to_le_bytes());
                 buf.extend_from_slice(&tolerance.to_le_bytes());
+                Deadline::pack_option(deadline, &mut buf);
             }
             &Self::UpdateStatus { status } => {
                 buf.push(2);
@@ -152,15 +371,99 @@ impl AmmInstruction {
                 buf.push(3);
                 buf.extend_from_slice(&tolerance.to_le_bytes());
             }
-            &Self::Terminate {} => {
+            &Self::UpdateReferralShare { share } => {
+                buf.push(4);
+                buf.extend_from_slice(&share.to_le_bytes());
+            }
+            &Self::UpdateDynamicFee {
+                mode,
+                min_fee,
+                max_fee,
+            } => {
+                buf.push(5);
+                buf.extend_from_slice(&mode.to_le_bytes());
+                buf.extend_from_slice(&min_fee.to_le_bytes());
+                buf.extend_from_slice(&max_fee.to_le_bytes());
+            }
+            Self::Terminate { deadline } => {
                 buf.push(9);
+                Deadline::pack_option(deadline, &mut buf);
             }
 
-            &Self::Swap { amount, direction } => {
+            &Self::Swap {
+                amount,
+                direction,
+                ref deadline,
+            } => {
                 buf.push(10);
                 buf.extend_from_slice(&amount.to_le_bytes());
                 let direction_buf: u8 = direction.into();
                 buf.push(direction_buf);
+                Deadline::pack_option(deadline, &mut buf);
+            }
+
+            &Self::PlaceOrder {
+                amount_in,
+                direction,
+                min_amount_out,
+                reward,
+            } => {
+                buf.push(11);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                let direction_buf: u8 = direction.into();
+                buf.push(direction_buf);
+                buf.extend_from_slice(&min_amount_out.to_le_bytes());
+                buf.extend_from_slice(&reward.to_le_bytes());
+            }
+            &Self::CancelOrder {} => {
+                buf.push(12);
+            }
+            &Self::FillOrders {} => {
+                buf.push(13);
+            }
+            &Self::PlaceLongTermOrder {
+                amount_in,
+                direction,
+                slots,
+            } => {
+                buf.push(14);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                let direction_buf: u8 = direction.into();
+                buf.push(direction_buf);
+                buf.extend_from_slice(&slots.to_le_bytes());
+            }
+            &Self::CancelLongTermOrder {} => {
+                buf.push(15);
+            }
+            &Self::ExecuteVirtualOrders {} => {
+                buf.push(16);
+            }
+            &Self::FundFarm { amount, slots } => {
+                buf.push(17);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&slots.to_le_bytes());
+            }
+            &Self::Stake {
+                amount_a,
+                ref deadline,
+            } => {
+                buf.push(18);
+                buf.extend_from_slice(&amount_a.to_le_bytes());
+                Deadline::pack_option(deadline, &mut buf);
+            }
+            &Self::Unstake {
+                shares,
+                ref deadline,
+            } => {
+                buf.push(19);
+                buf.extend_from_slice(&shares.to_le_bytes());
+                Deadline::pack_option(deadline, &mut buf);
+            }
+            &Self::ClaimRewards {} => {
+                buf.push(20);
+            }
+            &Self::Migrate {} => {
+                buf.push(21);
             }
 
             &Self::WithdrawalFee {} => {
@@ -170,3 +473,803 @@ impl AmmInstruction {
         buf
     }
 }
+
+/// Derive the temporary wsol account of a wallet in a pool
+pub fn find_wsol_address(program_id: &Pubkey, pool: &Pubkey, wallet: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[WSOL_SEED, pool.as_ref(), wallet.as_ref()], program_id)
+}
+
+/// Derive a vault of a pool, `seed` is one of the vault seeds
+pub fn find_vault_address(program_id: &Pubkey, pool: &Pubkey, seed: &[u8]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[seed, pool.as_ref()], program_id)
+}
+
+/// Derive the escrow token account of a limit order
+pub fn find_order_escrow_address(program_id: &Pubkey, order: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ORDER_ESCROW_SEED, order.as_ref()], program_id)
+}
+
+/// Derive the virtual orders account of a pool
+pub fn find_twamm_address(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TWAMM_SEED, pool.as_ref()], program_id)
+}
+
+/// Derive the farm account of a pool
+pub fn find_farm_address(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FARM_SEED, pool.as_ref()], program_id)
+}
+
+/// Derive the stake account of a wallet in a pool
+pub fn find_stake_address(program_id: &Pubkey, pool: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STAKE_SEED, pool.as_ref(), owner.as_ref()], program_id)
+}
+
+/// Accounts of the virtual orders of a pool, the account and the escrows of a and b
+pub fn twamm_accounts(program_id: &Pubkey, pool: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(find_twamm_address(program_id, pool).0, false),
+        AccountMeta::new(
+            find_vault_address(program_id, pool, TWAMM_ESCROW_A_SEED).0,
+            false,
+        ),
+        AccountMeta::new(
+            find_vault_address(program_id, pool, TWAMM_ESCROW_B_SEED).0,
+            false,
+        ),
+    ]
+}
+
+/// Accounts appended when a wallet stands in for its native sol token account
+fn native_accounts(program_id: &Pubkey, pool: &Pubkey, wallet: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(find_wsol_address(program_id, pool, wallet).0, false),
+        AccountMeta::new_readonly(spl_token::native_mint::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ]
+}
+
+/// Creates an `Initialize` instruction.
+/// The pool account must sign, the program creates it and its vaults paid by the owner.
+pub fn initialize(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_a: &Pubkey,
+    owner_token_b: &Pubkey,
+    token_program: &Pubkey,
+    nonce: u8,
+    fee: u64,
+    amount_a: u64,
+    amount_b: u64,
+    tolerance: u64,
+    deadline: Option<Deadline>,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::Initialize {
+        nonce,
+        fee,
+        amount_a,
+        amount_b,
+        tolerance,
+        deadline,
+    }
+    .pack();
+    // pass the owner as a token account to fund the pool with native sol
+    let native = *owner_token_a == *owner || *owner_token_b == *owner;
+    let vault = |seed| find_vault_address(program_id, pool, seed).0;
+    let mut accounts = vec![
+        AccountMeta::new(*pool, true),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+        AccountMeta::new(vault(VAULT_A_SEED), false),
+        AccountMeta::new(vault(VAULT_B_SEED), false),
+        AccountMeta::new(vault(FEE_VAULT_SEED), false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_a, false),
+        AccountMeta::new(*owner_token_b, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    if native {
+        accounts.extend(native_accounts(program_id, pool, owner));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `UpdateStatus` instruction.
+pub fn update_status(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    status: u8,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::UpdateStatus { status }.pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*owner, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `UpdateTolerance` instruction.
+pub fn update_tolerance(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    tolerance: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::UpdateTolerance { tolerance }.pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*owner, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `UpdateReferralShare` instruction.
+pub fn update_referral_share(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    share: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::UpdateReferralShare { share }.pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*owner, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `UpdateDynamicFee` instruction.
+pub fn update_dynamic_fee(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    mode: u8,
+    min_fee: u64,
+    max_fee: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::UpdateDynamicFee {
+        mode,
+        min_fee,
+        max_fee,
+    }
+    .pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*owner, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `Swap` instruction.
+/// Set `twamm` while the pool has long-term orders, the swap runs them first.
+/// `referrer` is a token b account receiving the referral share of the fee.
+pub fn swap(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    vault_a: &Pubkey,
+    vault_b: &Pubkey,
+    fee_vault: &Pubkey,
+    pool_pda: &Pubkey,
+    user_wallet: &Pubkey,
+    user_token_a: &Pubkey,
+    user_token_b: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    amount: u64,
+    direction: Direction,
+    deadline: Option<Deadline>,
+    twamm: bool,
+    referrer: Option<&Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::Swap {
+        amount,
+        direction,
+        deadline,
+    }
+    .pack();
+    // pass the wallet as a token account to swap native sol
+    let native = *user_token_a == *user_wallet || *user_token_b == *user_wallet;
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*vault_a, false),
+        AccountMeta::new(*vault_b, false),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new_readonly(*user_wallet, true),
+        AccountMeta::new(*user_token_a, false),
+        AccountMeta::new(*user_token_b, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+    ];
+    if twamm {
+        accounts.extend(twamm_accounts(program_id, pool));
+    }
+    if native {
+        accounts[5].is_writable = true;
+        accounts.extend(native_accounts(program_id, pool, user_wallet));
+    }
+    if let Some(referrer) = referrer {
+        accounts.push(AccountMeta::new(*referrer, false));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `WithdrawalFee` instruction.
+pub fn withdrawal_fee(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    fee_vault: &Pubkey,
+    fee_receiver: &Pubkey,
+    pool_pda: &Pubkey,
+    token_program: &Pubkey,
+    mint_b: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::WithdrawalFee {}.pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new(*fee_receiver, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*mint_b, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// The farm accounts `Terminate` sweeps the rewards left in the reward vault to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RewardKeys {
+    pub reward_mint: Pubkey,
+    pub owner_token_reward: Pubkey,
+}
+
+/// Creates a `Terminate` instruction.
+/// Pass `twamm_payer` when the pool had long-term orders, their escrows and account close
+/// too and the wallet that placed the first order gets the rent back.
+/// Pass `rewards` when the pool has a farm, its reward vault and account close too.
+pub fn terminate(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    vault_a: &Pubkey,
+    vault_b: &Pubkey,
+    fee_vault: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_a: &Pubkey,
+    owner_token_b: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    deadline: Option<Deadline>,
+    twamm_payer: Option<&Pubkey>,
+    rewards: Option<&RewardKeys>,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::Terminate { deadline }.pack();
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new(*vault_a, false),
+        AccountMeta::new(*vault_b, false),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_a, false),
+        AccountMeta::new(*owner_token_b, false),
+        AccountMeta::new_readonly(*token_program, false),
+        // writable to harvest withheld token-2022 transfer fees
+        AccountMeta::new(*mint_a, false),
+        AccountMeta::new(*mint_b, false),
+    ];
+    if let Some(twamm_payer) = twamm_payer {
+        accounts.extend(twamm_accounts(program_id, pool));
+        accounts.push(AccountMeta::new(*twamm_payer, false));
+    }
+    if let Some(rewards) = rewards {
+        accounts.extend([
+            AccountMeta::new(find_farm_address(program_id, pool).0, false),
+            AccountMeta::new(
+                find_vault_address(program_id, pool, FARM_REWARD_SEED).0,
+                false,
+            ),
+            AccountMeta::new(rewards.owner_token_reward, false),
+            AccountMeta::new_readonly(rewards.reward_mint, false),
+        ]);
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `PlaceOrder` instruction.
+/// The order account must sign, the program creates it and its escrow paid by the owner.
+pub fn place_order(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    order: &Pubkey,
+    owner: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_in: &Pubkey,
+    owner_token_out: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    amount_in: u64,
+    direction: Direction,
+    min_amount_out: u64,
+    reward: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::PlaceOrder {
+        amount_in,
+        direction,
+        min_amount_out,
+        reward,
+    }
+    .pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*order, true),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new(find_order_escrow_address(program_id, order).0, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_in, false),
+        AccountMeta::new_readonly(*owner_token_out, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `CancelOrder` instruction.
+/// The owner needn't sign once the pool winds down, see `CLOSE_GRACE_PERIOD`.
+pub fn cancel_order(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    order: &Pubkey,
+    owner: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_in: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    owner_signs: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::CancelOrder {}.pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*order, false),
+        AccountMeta::new(*owner, owner_signs),
+        AccountMeta::new(find_order_escrow_address(program_id, order).0, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_in, false),
+        AccountMeta::new_readonly(*token_program, false),
+        // writable to harvest withheld token-2022 transfer fees
+        AccountMeta::new(*mint_a, false),
+        AccountMeta::new(*mint_b, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// An order passed to `FillOrders`, the accounts its fill pays
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrderKeys {
+    pub order: Pubkey,
+    pub owner: Pubkey,
+    pub token_out: Pubkey,
+}
+
+/// Creates a `FillOrders` instruction, orders that don't cross are skipped.
+/// Set `twamm` while the pool has long-term orders, the fills run them first.
+pub fn fill_orders(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    vault_a: &Pubkey,
+    vault_b: &Pubkey,
+    fee_vault: &Pubkey,
+    pool_pda: &Pubkey,
+    keeper: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    orders: &[OrderKeys],
+    twamm: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::FillOrders {}.pack();
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*vault_a, false),
+        AccountMeta::new(*vault_b, false),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*keeper, true),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new(*mint_a, false),
+        AccountMeta::new(*mint_b, false),
+    ];
+    if twamm {
+        accounts.extend(twamm_accounts(program_id, pool));
+    }
+    for keys in orders {
+        accounts.extend([
+            AccountMeta::new(keys.order, false),
+            AccountMeta::new(find_order_escrow_address(program_id, &keys.order).0, false),
+            AccountMeta::new(keys.owner, false),
+            AccountMeta::new(keys.token_out, false),
+        ]);
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `PlaceLongTermOrder` instruction.
+/// The order account must sign, the program creates it paid by the owner. The first
+/// order of a pool creates the virtual orders account and its escrows too.
+pub fn place_long_term_order(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    order: &Pubkey,
+    owner: &Pubkey,
+    vault_a: &Pubkey,
+    vault_b: &Pubkey,
+    fee_vault: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_in: &Pubkey,
+    owner_token_out: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    amount_in: u64,
+    direction: Direction,
+    slots: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::PlaceLongTermOrder {
+        amount_in,
+        direction,
+        slots,
+    }
+    .pack();
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*order, true),
+        AccountMeta::new(*owner, true),
+    ];
+    accounts.extend(twamm_accounts(program_id, pool));
+    accounts.extend([
+        AccountMeta::new(*vault_a, false),
+        AccountMeta::new(*vault_b, false),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_in, false),
+        AccountMeta::new_readonly(*owner_token_out, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ]);
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `CancelLongTermOrder` instruction, it withdraws an order that ended too.
+/// The owner needn't sign once the order ended or the pool winds down, see
+/// `CLOSE_GRACE_PERIOD`.
+pub fn cancel_long_term_order(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    order: &Pubkey,
+    owner: &Pubkey,
+    vault_a: &Pubkey,
+    vault_b: &Pubkey,
+    fee_vault: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_in: &Pubkey,
+    owner_token_out: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    owner_signs: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::CancelLongTermOrder {}.pack();
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*order, false),
+        AccountMeta::new(*owner, owner_signs),
+    ];
+    accounts.extend(twamm_accounts(program_id, pool));
+    accounts.extend([
+        AccountMeta::new(*vault_a, false),
+        AccountMeta::new(*vault_b, false),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_in, false),
+        AccountMeta::new(*owner_token_out, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+    ]);
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `ExecuteVirtualOrders` instruction.
+pub fn execute_virtual_orders(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    vault_a: &Pubkey,
+    vault_b: &Pubkey,
+    fee_vault: &Pubkey,
+    pool_pda: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::ExecuteVirtualOrders {}.pack();
+    let mut accounts = vec![AccountMeta::new_readonly(*pool, false)];
+    accounts.extend(twamm_accounts(program_id, pool));
+    accounts.extend([
+        AccountMeta::new(*vault_a, false),
+        AccountMeta::new(*vault_b, false),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+    ]);
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `FundFarm` instruction.
+/// The first one creates the farm account and its reward vault, paid by the owner.
+pub fn fund_farm(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    vault_a: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_reward: &Pubkey,
+    token_program: &Pubkey,
+    reward_mint: &Pubkey,
+    amount: u64,
+    slots: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::FundFarm { amount, slots }.pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new(find_farm_address(program_id, pool).0, false),
+        AccountMeta::new(
+            find_vault_address(program_id, pool, FARM_REWARD_SEED).0,
+            false,
+        ),
+        AccountMeta::new_readonly(*vault_a, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_reward, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*reward_mint, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `Stake` instruction.
+/// The first stake of a wallet creates its stake account, later ones must pass the
+/// token accounts it recorded. Set `twamm` while the pool has long-term orders.
+pub fn stake(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    vault_a: &Pubkey,
+    vault_b: &Pubkey,
+    fee_vault: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_a: &Pubkey,
+    owner_token_b: &Pubkey,
+    owner_token_reward: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    amount_a: u64,
+    deadline: Option<Deadline>,
+    twamm: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::Stake { amount_a, deadline }.pack();
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(find_farm_address(program_id, pool).0, false),
+        AccountMeta::new(find_stake_address(program_id, pool, owner).0, false),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new(*vault_a, false),
+        AccountMeta::new(*vault_b, false),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_a, false),
+        AccountMeta::new(*owner_token_b, false),
+        AccountMeta::new_readonly(*owner_token_reward, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    if twamm {
+        accounts.extend(twamm_accounts(program_id, pool));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `Unstake` instruction, the token accounts are the ones the stake recorded.
+/// The owner needn't sign once the pool winds down, see `CLOSE_GRACE_PERIOD`.
+pub fn unstake(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    vault_a: &Pubkey,
+    vault_b: &Pubkey,
+    fee_vault: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_a: &Pubkey,
+    owner_token_b: &Pubkey,
+    owner_token_reward: &Pubkey,
+    token_program: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    reward_mint: &Pubkey,
+    shares: u64,
+    deadline: Option<Deadline>,
+    twamm: bool,
+    owner_signs: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::Unstake { shares, deadline }.pack();
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(find_farm_address(program_id, pool).0, false),
+        AccountMeta::new(find_stake_address(program_id, pool, owner).0, false),
+        AccountMeta::new(*owner, owner_signs),
+        AccountMeta::new(*vault_a, false),
+        AccountMeta::new(*vault_b, false),
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_a, false),
+        AccountMeta::new(*owner_token_b, false),
+        AccountMeta::new(
+            find_vault_address(program_id, pool, FARM_REWARD_SEED).0,
+            false,
+        ),
+        AccountMeta::new(*owner_token_reward, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+        AccountMeta::new_readonly(*reward_mint, false),
+    ];
+    if twamm {
+        accounts.extend(twamm_accounts(program_id, pool));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `ClaimRewards` instruction, paid to the token account the stake recorded.
+pub fn claim_rewards(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    pool_pda: &Pubkey,
+    owner_token_reward: &Pubkey,
+    token_program: &Pubkey,
+    reward_mint: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::ClaimRewards {}.pack();
+    let accounts = vec![
+        AccountMeta::new_readonly(*pool, false),
+        AccountMeta::new(find_farm_address(program_id, pool).0, false),
+        AccountMeta::new(find_stake_address(program_id, pool, owner).0, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(
+            find_vault_address(program_id, pool, FARM_REWARD_SEED).0,
+            false,
+        ),
+        AccountMeta::new_readonly(*pool_pda, false),
+        AccountMeta::new(*owner_token_reward, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*reward_mint, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `Migrate` instruction, the owner pays the rent of the grown pool account.
+pub fn migrate(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    owner: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::Migrate {}.pack();
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new_readonly(*mint_a, false),
+        AccountMeta::new_readonly(*mint_b, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
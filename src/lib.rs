@@ -2,9 +2,14 @@
 
 //! A program for creating pools of Solana stakes managed by a Stake-o-Matic
 
+pub mod accounts;
 mod entrypoint;
 pub mod error;
+pub mod event;
+#[cfg(feature = "indexer")]
+pub mod indexer;
 pub mod instruction;
+pub mod math;
 pub mod processor;
 pub mod state;
 
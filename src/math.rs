@@ -0,0 +1,368 @@
+//! Constant product pricing
+//!
+//! k is taken from the live vault balances and every division rounds in
+//! favor of the pool, so the product of the reserves never decreases.
+use crate::error::AmmError;
+
+/// fee rate denominator, a fee rate of 3000 is 0.3%
+pub const PERCENT_MUL: u64 = u64::pow(10, 6);
+
+/// tolerance denominator, a tolerance of 10 lets a trade move the price by 0.1%
+pub const BPS_MUL: u64 = 10_000;
+
+/// decimals of a price from `calculate_price`
+pub const PRICE_DECIMALS: u8 = 12;
+
+/// price denominator, a price of 10^12 is one token b per token a
+pub const PRICE_MUL: u128 = u128::pow(10, PRICE_DECIMALS as u32);
+
+/// denominator of the proceeds a long-term order earns per unit of sell rate
+pub const RATE_MUL: u128 = u128::pow(10, 12);
+
+/// slots the volatility of a pool takes to halve
+pub const VOLATILITY_HALF_LIFE: u64 = 150;
+
+/// calculate a2b amount
+/// A*B=k
+/// (A+a)*(B-b)>=k
+/// b=B-ceil(k/(A+a))
+pub fn calculate_amount_a2b(
+    reserve_a: u64,
+    reserve_b: u64,
+    amount_a: u64,
+) -> Result<u64, AmmError> {
+    let k = (reserve_a as u128)
+        .checked_mul(reserve_b as u128)
+        .ok_or(AmmError::CalculationError)?;
+    let changed_a = (reserve_a as u128)
+        .checked_add(amount_a as u128)
+        .ok_or(AmmError::CalculationError)?;
+    let changed_b = ceil_div(k, changed_a)?;
+    let amount_b = (reserve_b as u128)
+        .checked_sub(changed_b)
+        .ok_or(AmmError::CalculationError)?;
+    // vault b can never be emptied
+    if amount_b >= reserve_b as u128 {
+        return Err(AmmError::CalculationError);
+    }
+    Ok(amount_b as u64)
+}
+
+/// calculate b2a amount
+/// A*B=k
+/// (A-a)*(B+b)>=k
+/// b=ceil(k/(A-a))-B
+pub fn calculate_amount_b2a(
+    reserve_a: u64,
+    reserve_b: u64,
+    amount_a: u64,
+) -> Result<u64, AmmError> {
+    let k = (reserve_a as u128)
+        .checked_mul(reserve_b as u128)
+        .ok_or(AmmError::CalculationError)?;
+    // vault a can never be emptied
+    let changed_a = (reserve_a as u128)
+        .checked_sub(amount_a as u128)
+        .filter(|v| *v > 0)
+        .ok_or(AmmError::CalculationError)?;
+    let changed_b = ceil_div(k, changed_a)?;
+    let amount_b = changed_b
+        .checked_sub(reserve_b as u128)
+        .ok_or(AmmError::CalculationError)?;
+    if amount_b > u64::MAX as u128 {
+        return Err(AmmError::CalculationError);
+    }
+    Ok(amount_b as u64)
+}
+
+/// calculate fee of amount
+/// fee=ceil(amount*rate/PERCENT_MUL)
+/// rounded up, a mint with few decimals never trades for free
+pub fn calculate_fee(amount: u64, fee_rate: u64) -> Result<u64, AmmError> {
+    let fee = (amount as u128)
+        .checked_mul(fee_rate as u128)
+        .ok_or(AmmError::CalculationError)?;
+    let fee = ceil_div(fee, PERCENT_MUL as u128)?;
+    u64::try_from(fee).map_err(|_| AmmError::CalculationError)
+}
+
+/// split an amount paying its own fee into the net amount and the fee
+/// net=floor(amount*PERCENT_MUL/(PERCENT_MUL+rate)), fee=amount-net
+/// the fee rounds up, it is never below calculate_fee(net)
+pub fn split_fee(amount: u64, fee_rate: u64) -> (u64, u64) {
+    let net = amount as u128 * PERCENT_MUL as u128 / (PERCENT_MUL as u128 + fee_rate as u128);
+    // net is at most amount
+    let net = net as u64;
+    (net, amount - net)
+}
+
+/// calculate the part of a fee paid to the referrer of a swap
+/// referral=floor(fee*share/BPS_MUL), rounded down for the pool
+pub fn calculate_referral_fee(fee: u64, share: u64) -> u64 {
+    // share is at most BPS_MUL, the referral fee at most the fee
+    (fee as u128 * share.min(BPS_MUL) as u128 / BPS_MUL as u128) as u64
+}
+
+/// calculate a dynamic fee rate, the base rate plus a surcharge within the bounds
+/// fee=min(max(fee+surcharge, min_fee), max_fee)
+pub fn calculate_dynamic_fee(fee_rate: u64, surcharge: u64, min_fee: u64, max_fee: u64) -> u64 {
+    fee_rate.saturating_add(surcharge).max(min_fee).min(max_fee)
+}
+
+/// calculate how far the price B/A moved between two reserves, in fee rate units
+/// move=|B1*A0-B0*A1|*PERCENT_MUL/(B0*A1), a move of 100% or more is PERCENT_MUL
+pub fn calculate_price_move(
+    reserve_a0: u64,
+    reserve_b0: u64,
+    reserve_a1: u64,
+    reserve_b1: u64,
+) -> u64 {
+    let before = reserve_b0 as u128 * reserve_a1 as u128;
+    let after = reserve_b1 as u128 * reserve_a0 as u128;
+    let delta = after.max(before) - after.min(before);
+    // an empty side is as far as it gets
+    if delta >= before {
+        return PERCENT_MUL;
+    }
+    // drop low bits of both so the product fits, delta stays below before
+    let shift = (128 - delta.leading_zeros()).saturating_sub(100);
+    ((delta >> shift) * PERCENT_MUL as u128 / (before >> shift)) as u64
+}
+
+/// decay a volatility over `slots` slots, halved every `VOLATILITY_HALF_LIFE`
+pub fn decay_volatility(volatility: u64, slots: u64) -> u64 {
+    let halvings = slots / VOLATILITY_HALF_LIFE;
+    if halvings >= 64 {
+        return 0;
+    }
+    volatility >> halvings
+}
+
+/// calculate price of one whole token a in token b
+/// price=B/10^decimals_b/(A/10^decimals_a)*PRICE_MUL
+pub fn calculate_price(
+    reserve_a: u64,
+    reserve_b: u64,
+    decimals_a: u8,
+    decimals_b: u8,
+) -> Result<u128, AmmError> {
+    // scale whichever side has fewer decimals, so both count in the same unit
+    let exp_a = decimals_a as u32 + PRICE_DECIMALS as u32;
+    let exp_b = decimals_b as u32;
+    let (numerator_exp, denominator_exp) = if exp_a >= exp_b {
+        (exp_a - exp_b, 0)
+    } else {
+        (0, exp_b - exp_a)
+    };
+    let numerator = (reserve_b as u128)
+        .checked_mul(pow10(numerator_exp)?)
+        .ok_or(AmmError::CalculationError)?;
+    let denominator = (reserve_a as u128)
+        .checked_mul(pow10(denominator_exp)?)
+        .ok_or(AmmError::CalculationError)?;
+    numerator
+        .checked_div(denominator)
+        .ok_or(AmmError::CalculationError)
+}
+
+/// calculate k of the reserves
+/// k=A*B, two u64 never overflow a u128
+pub fn calculate_k(reserve_a: u64, reserve_b: u64) -> u128 {
+    reserve_a as u128 * reserve_b as u128
+}
+
+/// check k of the reserves did not drop over a trade
+/// every division rounds in favor of the pool, so k only drops when a transfer moved
+/// other amounts than the ones priced
+pub fn check_k(k_before: u128, k_after: u128) -> Result<(), AmmError> {
+    if k_after < k_before {
+        return Err(AmmError::CalculationError);
+    }
+    Ok(())
+}
+
+/// check a trade moved the price B/A by at most tolerance basis points
+/// |B1*A0-B0*A1|*BPS_MUL<=tolerance*B0*A1, a tolerance of 0 disables the bound
+pub fn check_price_impact(
+    reserve_a0: u64,
+    reserve_b0: u64,
+    reserve_a1: u64,
+    reserve_b1: u64,
+    tolerance: u64,
+) -> Result<(), AmmError> {
+    if tolerance == 0 {
+        return Ok(());
+    }
+    let before = reserve_b0 as u128 * reserve_a1 as u128;
+    let after = reserve_b1 as u128 * reserve_a0 as u128;
+    let delta = after.max(before) - after.min(before);
+    // drop low bits of both so the products by BPS_MUL, 14 bits, fit
+    let shift = 14u32.saturating_sub(after.max(before).leading_zeros());
+    let tolerance = tolerance.min(BPS_MUL) as u128;
+    if (delta >> shift) * BPS_MUL as u128 > tolerance * (before >> shift) {
+        return Err(AmmError::OutOfTolerance);
+    }
+    Ok(())
+}
+
+/// Tokens moved by the virtual orders of one stretch of slots.
+///
+/// At most one side trades through the curve in a stretch, the sum of several
+/// stretches may have both.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VirtualTrade {
+    /// token a the sellers of a pay into the pool
+    pub amount_a_in: u64,
+    /// token b the sellers of b pay into the pool, without the fee
+    pub amount_b_in: u64,
+    /// token a the pool pays to the sellers of b
+    pub amount_a_out: u64,
+    /// token b the pool pays to the sellers of a, with the fee
+    pub amount_b_out: u64,
+    /// fee in token b, paid out of the escrow of b
+    pub fee: u64,
+    /// token a the sellers of b earned
+    pub proceeds_a: u64,
+    /// token b the sellers of a earned, after the fee
+    pub proceeds_b: u64,
+}
+
+impl VirtualTrade {
+    /// add the trade of a later stretch
+    pub fn add(&mut self, other: &VirtualTrade) -> Result<(), AmmError> {
+        let add = |a: u64, b: u64| a.checked_add(b).ok_or(AmmError::CalculationError);
+        self.amount_a_in = add(self.amount_a_in, other.amount_a_in)?;
+        self.amount_b_in = add(self.amount_b_in, other.amount_b_in)?;
+        self.amount_a_out = add(self.amount_a_out, other.amount_a_out)?;
+        self.amount_b_out = add(self.amount_b_out, other.amount_b_out)?;
+        self.fee = add(self.fee, other.fee)?;
+        self.proceeds_a = add(self.proceeds_a, other.proceeds_a)?;
+        self.proceeds_b = add(self.proceeds_b, other.proceeds_b)?;
+        Ok(())
+    }
+}
+
+/// calculate the virtual trade of `sold_a` and `sold_b` sold over one stretch of slots
+/// the two sides are matched at the spot price B/A first, only the rest trades
+/// through the curve and pays the fee, as a swap of the same size would
+pub fn calculate_virtual_trade(
+    reserve_a: u64,
+    reserve_b: u64,
+    sold_a: u64,
+    sold_b: u64,
+    fee_rate: u64,
+) -> Result<VirtualTrade, AmmError> {
+    if reserve_a == 0 || reserve_b == 0 {
+        return Err(AmmError::CalculationError);
+    }
+    // sold b worth in token a at the spot price, rounded for the sellers of a
+    let sold_b_in_a = sold_b as u128 * reserve_a as u128 / reserve_b as u128;
+    if sold_b == 0 || (sold_a > 0 && sold_a as u128 >= sold_b_in_a) {
+        // sold_b_in_a is at most sold_a
+        let matched_a = sold_b_in_a as u64;
+        let amount_a_in = sold_a - matched_a;
+        let amount_b_out = if amount_a_in > 0 {
+            calculate_amount_a2b(reserve_a, reserve_b, amount_a_in)?
+        } else {
+            0
+        };
+        let fee = calculate_fee(amount_b_out, fee_rate)?.min(amount_b_out);
+        Ok(VirtualTrade {
+            amount_a_in,
+            amount_b_out,
+            fee,
+            proceeds_a: matched_a,
+            proceeds_b: (sold_b as u128 + (amount_b_out - fee) as u128)
+                .try_into()
+                .map_err(|_| AmmError::CalculationError)?,
+            ..VirtualTrade::default()
+        })
+    } else {
+        // sold a worth in token b, at most sold b
+        let matched_b = (sold_a as u128 * reserve_b as u128 / reserve_a as u128) as u64;
+        let (amount_b_in, fee) = split_fee(sold_b - matched_b, fee_rate);
+        let amount_a_out = if amount_b_in > 0 {
+            calculate_amount_a2b(reserve_b, reserve_a, amount_b_in)?
+        } else {
+            0
+        };
+        Ok(VirtualTrade {
+            amount_b_in,
+            amount_a_out,
+            fee,
+            proceeds_a: sold_a
+                .checked_add(amount_a_out)
+                .ok_or(AmmError::CalculationError)?,
+            proceeds_b: matched_b,
+            ..VirtualTrade::default()
+        })
+    }
+}
+
+/// calculate the proceeds of a long-term order selling `sell_rate` per slot
+/// proceeds=floor(sell_rate*(end-start)/RATE_MUL), start and end are proceeds per
+/// unit of sell rate
+pub fn calculate_proceeds(sell_rate: u64, start: u128, end: u128) -> Result<u64, AmmError> {
+    let proceeds = end
+        .checked_sub(start)
+        .and_then(|delta| delta.checked_mul(sell_rate as u128))
+        .ok_or(AmmError::CalculationError)?
+        / RATE_MUL;
+    u64::try_from(proceeds).map_err(|_| AmmError::CalculationError)
+}
+
+/// calculate the shares and the token b a stake of `amount_a` adds to the reserves
+/// s=floor(a*S/A), b=ceil(a*B/A), both rounded in favor of the pool
+pub fn calculate_stake(
+    reserve_a: u64,
+    reserve_b: u64,
+    total_shares: u64,
+    amount_a: u64,
+) -> Result<(u64, u64), AmmError> {
+    if reserve_a == 0 {
+        return Err(AmmError::CalculationError);
+    }
+    let shares = (amount_a as u128)
+        .checked_mul(total_shares as u128)
+        .ok_or(AmmError::CalculationError)?
+        / reserve_a as u128;
+    let amount_b = ceil_div(
+        (amount_a as u128)
+            .checked_mul(reserve_b as u128)
+            .ok_or(AmmError::CalculationError)?,
+        reserve_a as u128,
+    )?;
+    Ok((
+        u64::try_from(shares).map_err(|_| AmmError::CalculationError)?,
+        u64::try_from(amount_b).map_err(|_| AmmError::CalculationError)?,
+    ))
+}
+
+/// calculate the reserves `shares` of `total_shares` withdraw
+/// a=floor(s*A/S), b=floor(s*B/S)
+pub fn calculate_unstake(
+    reserve_a: u64,
+    reserve_b: u64,
+    total_shares: u64,
+    shares: u64,
+) -> Result<(u64, u64), AmmError> {
+    if total_shares == 0 || shares > total_shares {
+        return Err(AmmError::CalculationError);
+    }
+    let part = |reserve: u64| (shares as u128 * reserve as u128 / total_shares as u128) as u64;
+    Ok((part(reserve_a), part(reserve_b)))
+}
+
+fn pow10(exp: u32) -> Result<u128, AmmError> {
+    10u128.checked_pow(exp).ok_or(AmmError::CalculationError)
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> Result<u128, AmmError> {
+    if denominator == 0 {
+        return Err(AmmError::CalculationError);
+    }
+    numerator
+        .checked_add(denominator - 1)
+        .map(|v| v / denominator)
+        .ok_or(AmmError::CalculationError)
+}
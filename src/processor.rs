@@ -1,27 +1,52 @@
 //! Program state processor
-// use solana_program::sysvar::Sysvar;
 use {
     crate::{
+        accounts::{
+            check_len, check_referrer, check_system_program, is_native, unpack_mint,
+            unpack_token_account, CancelLongTermOrderAccounts, CancelOrderAccounts,
+            ClaimRewardsAccounts, FillOrdersAccounts, FundFarmAccounts, InitializeAccounts,
+            MigrateAccounts, OrderAccounts, OwnerAccounts, PlaceLongTermOrderAccounts,
+            PlaceOrderAccounts, StakeAccounts, SwapAccounts, TerminateAccounts, TwammAccounts,
+            UnstakeAccounts, VirtualOrdersAccounts, WithdrawalFeeAccounts, NATIVE_LEN,
+        },
         error::AmmError,
-        instruction::{AmmInstruction, Direction},
-        state::{AmmPool, PoolStatus},
+        event::AmmEvent,
+        instruction::{
+            find_wsol_address, AmmInstruction, Deadline, Direction, FARM_REWARD_SEED, FARM_SEED,
+            FEE_VAULT_SEED, ORDER_ESCROW_SEED, STAKE_SEED, TWAMM_ESCROW_A_SEED,
+            TWAMM_ESCROW_B_SEED, TWAMM_SEED, VAULT_A_SEED, VAULT_B_SEED, WSOL_SEED,
+        },
+        math::{self, VirtualTrade},
+        state::{
+            AmmPool, AmmPoolView, Farm, FeeMode, LongTermOrder, Order, OrderStatus, PoolStatus,
+            Stake, Twamm, CLOSE_GRACE_PERIOD, MIN_LONG_TERM_DEPOSIT_BPS, TWAMM_INTERVAL,
+        },
     },
     arrayref::array_ref,
+    bytemuck::Zeroable,
     num_traits::FromPrimitive,
     solana_program::{
         account_info::AccountInfo,
-        // clock::Clock,
+        clock::Clock,
         decode_error::DecodeError,
         entrypoint::ProgramResult,
         msg,
         program::{invoke, invoke_signed},
         program_error::{PrintProgramError, ProgramError},
-        program_memory::sol_memset,
         program_pack::Pack,
         pubkey::Pubkey,
-        // sysvar::Sysvar,
+        rent::Rent,
+        system_instruction,
+        sysvar::Sysvar,
         // commitment_config::CommitmentConfig,
     },
+    spl_token_2022::{
+        extension::{
+            transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType,
+            StateWithExtensions,
+        },
+        state::{Account, Mint},
+    },
 };
 
 /// Program state handler.
@@ -37,8 +62,10 @@ impl Processor {
                 amount_a,
                 amount_b,
                 tolerance,
+                deadline,
             } => {
                 msg!("Instruction: Init");
+                Self::check_deadline(deadline)?;
                 Self::process_initialize(
                     program_id, accounts, nonce, fee, amount_a, amount_b, tolerance,
                 )
@@ -51,18 +78,100 @@ impl Processor {
                 msg!("Instruction: Update Tolerance");
                 Self::process_update_tolerance(program_id, accounts, tolerance)
             }
-            AmmInstruction::Terminate {} => {
+            AmmInstruction::UpdateReferralShare { share } => {
+                msg!("Instruction: Update Referral Share");
+                Self::process_update_referral_share(program_id, accounts, share)
+            }
+            AmmInstruction::UpdateDynamicFee {
+                mode,
+                min_fee,
+                max_fee,
+            } => {
+                msg!("Instruction: Update Dynamic Fee");
+                Self::process_update_dynamic_fee(program_id, accounts, mode, min_fee, max_fee)
+            }
+            AmmInstruction::Terminate { deadline } => {
                 msg!("Instruction: Terminate");
+                Self::check_deadline(deadline)?;
                 Self::process_terminate(program_id, accounts)
             }
-            AmmInstruction::Swap { amount, direction } => {
+            AmmInstruction::Swap {
+                amount,
+                direction,
+                deadline,
+            } => {
                 msg!("Instruction: Swap");
+                Self::check_deadline(deadline)?;
                 Self::process_swap(program_id, accounts, amount, direction)
             }
             AmmInstruction::WithdrawalFee {} => {
                 msg!("Instruction: Withdrawal Fee");
                 Self::process_withdrawal_fee(program_id, accounts)
             }
+            AmmInstruction::PlaceOrder {
+                amount_in,
+                direction,
+                min_amount_out,
+                reward,
+            } => {
+                msg!("Instruction: Place Order");
+                Self::process_place_order(
+                    program_id,
+                    accounts,
+                    amount_in,
+                    direction,
+                    min_amount_out,
+                    reward,
+                )
+            }
+            AmmInstruction::CancelOrder {} => {
+                msg!("Instruction: Cancel Order");
+                Self::process_cancel_order(program_id, accounts)
+            }
+            AmmInstruction::FillOrders {} => {
+                msg!("Instruction: Fill Orders");
+                Self::process_fill_orders(program_id, accounts)
+            }
+            AmmInstruction::PlaceLongTermOrder {
+                amount_in,
+                direction,
+                slots,
+            } => {
+                msg!("Instruction: Place Long Term Order");
+                Self::process_place_long_term_order(
+                    program_id, accounts, amount_in, direction, slots,
+                )
+            }
+            AmmInstruction::CancelLongTermOrder {} => {
+                msg!("Instruction: Cancel Long Term Order");
+                Self::process_cancel_long_term_order(program_id, accounts)
+            }
+            AmmInstruction::ExecuteVirtualOrders {} => {
+                msg!("Instruction: Execute Virtual Orders");
+                Self::process_execute_virtual_orders(program_id, accounts)
+            }
+            AmmInstruction::FundFarm { amount, slots } => {
+                msg!("Instruction: Fund Farm");
+                Self::process_fund_farm(program_id, accounts, amount, slots)
+            }
+            AmmInstruction::Stake { amount_a, deadline } => {
+                msg!("Instruction: Stake");
+                Self::check_deadline(deadline)?;
+                Self::process_stake(program_id, accounts, amount_a)
+            }
+            AmmInstruction::Unstake { shares, deadline } => {
+                msg!("Instruction: Unstake");
+                Self::check_deadline(deadline)?;
+                Self::process_unstake(program_id, accounts, shares)
+            }
+            AmmInstruction::ClaimRewards {} => {
+                msg!("Instruction: Claim Rewards");
+                Self::process_claim_rewards(program_id, accounts)
+            }
+            AmmInstruction::Migrate {} => {
+                msg!("Instruction: Migrate");
+                Self::process_migrate(program_id, accounts)
+            }
         }
     }
 
@@ -76,108 +185,161 @@ impl Processor {
         amount_b: u64,
         tolerance: u64,
     ) -> ProgramResult {
-        let accounts = array_ref![accounts, 0, 11];
-        let [pool_acc, owner_acc, mint_a_acc, mint_b_acc, vault_a_acc, vault_b_acc, fee_vault_acc, pool_pda, owner_token_a_acc, owner_token_b_acc, token_program_acc] =
-            accounts;
-        // use data
-        let mut pool = AmmPool::unpack_unchecked(&pool_acc.data.borrow())?;
-        let pda_seed = &[pool_acc.key.as_ref(), &[nonce]];
-        let pda = solana_program::pubkey::Pubkey::create_program_address(pda_seed, program_id)?;
-        // check
-        if !owner_acc.is_signer {
-            msg!("owner must sign");
-            return Err(AmmError::InvalidSignAccount.into());
-        }
-        Self::check_account_owner(pool_acc, program_id)?;
-        if pool.status != PoolStatus::NotInit {
-            return Err(AmmError::PoolExist.into());
-        }
-        // check mint
-        let mint_a = Self::unpack_mint(mint_a_acc)?;
-        let mint_b = Self::unpack_mint(mint_b_acc)?;
-        if mint_a.decimals > mint_b.decimals {
-            return Err(AmmError::InvalidMintDecimals.into());
-        }
-        // check vault a
-        let vault_a = Self::unpack_token_account(vault_a_acc)?;
-        if vault_a.mint != *mint_a_acc.key {
-            msg!(
-                "vault a mint not match {} {}",
-                vault_a.mint,
-                *mint_a_acc.key
-            );
-            return Err(AmmError::InvalidMint.into());
-        }
-        if vault_a.owner != *pool_pda.key {
-            msg!("vault a owner not match {} {}", vault_a.mint, *pool_pda.key);
-            return Err(AmmError::InvalidOwner.into());
-        }
-        // check vault b
-        let vault_b = Self::unpack_token_account(vault_b_acc)?;
-        if vault_b.mint != *mint_b_acc.key {
-            msg!(
-                "vault b mint not match {} {}",
-                vault_b.mint,
-                *mint_b_acc.key
-            );
-            return Err(AmmError::InvalidMint.into());
-        }
-        if vault_b.owner != *pool_pda.key {
-            msg!("vault b owner not match {} {}", vault_b.mint, *pool_pda.key);
-            return Err(AmmError::InvalidOwner.into());
-        }
-        // check fee vault
-        let fee_vault = Self::unpack_token_account(fee_vault_acc)?;
-        if fee_vault.mint != *mint_b_acc.key {
-            msg!(
-                "fee vault mint not match {} {}",
-                fee_vault.mint,
-                *mint_b_acc.key
-            );
+        let accounts = InitializeAccounts::load(accounts)?;
+        let (mint_a, mint_b, vault_bumps) = accounts.validate(program_id, nonce)?;
+        Self::check_tolerance_input(tolerance)?;
+        let InitializeAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            vault_a: vault_a_acc,
+            vault_b: vault_b_acc,
+            fee_vault: fee_vault_acc,
+            pool_pda,
+            owner_token_a: owner_token_a_acc,
+            owner_token_b: owner_token_b_acc,
+            token_program: token_program_acc,
+            system_program: system_program_acc,
+            native: native_accounts,
+        } = accounts;
+        // check owner balance, the owner stands in for a native sol token account
+        let native_a = is_native(owner_token_a_acc, owner_acc, mint_a_acc.key)?;
+        let native_b = is_native(owner_token_b_acc, owner_acc, mint_b_acc.key)?;
+        if native_a && native_b {
+            msg!("only one side can be native sol");
             return Err(AmmError::InvalidMint.into());
         }
-        if fee_vault.owner != *pool_pda.key {
+        let owner_amount_a = if native_a {
+            Self::native_balance(owner_acc)?
+        } else {
+            unpack_token_account(owner_token_a_acc)?.amount
+        };
+        let owner_amount_b = if native_b {
+            Self::native_balance(owner_acc)?
+        } else {
+            unpack_token_account(owner_token_b_acc)?.amount
+        };
+        if owner_amount_a < amount_a || owner_amount_b < amount_b {
             msg!(
-                "fee vault owner not match {} {}",
-                fee_vault.owner,
-                *pool_pda.key
+                "owner token not enough {} {}",
+                owner_amount_a,
+                owner_amount_b
             );
-            return Err(AmmError::InvalidOwner.into());
+            return Err(AmmError::InsufficientFunds.into());
         }
-        // check pda
-        if pda != *pool_pda.key {
-            return Err(AmmError::InvalidPDA.into());
+        // create the pool and its vaults, the owner pays the rent
+        let rent = Rent::get()?;
+        Self::create_account(
+            owner_acc,
+            pool_acc,
+            system_program_acc,
+            AmmPool::LEN,
+            rent.minimum_balance(AmmPool::LEN),
+            program_id,
+            &[],
+        )?;
+        for (vault_acc, mint_acc, seed, bump) in [
+            (vault_a_acc, mint_a_acc, VAULT_A_SEED, vault_bumps[0]),
+            (vault_b_acc, mint_b_acc, VAULT_B_SEED, vault_bumps[1]),
+            (fee_vault_acc, mint_b_acc, FEE_VAULT_SEED, vault_bumps[2]),
+        ] {
+            let space = Self::vault_len(mint_acc)?;
+            Self::create_account(
+                owner_acc,
+                vault_acc,
+                system_program_acc,
+                space,
+                rent.minimum_balance(space),
+                token_program_acc.key,
+                &[seed, pool_acc.key.as_ref(), &[bump]],
+            )?;
+            let ix = spl_token_2022::instruction::initialize_account3(
+                token_program_acc.key,
+                vault_acc.key,
+                mint_acc.key,
+                pool_pda.key,
+            )?;
+            invoke(
+                &ix,
+                &[
+                    vault_acc.clone(),
+                    mint_acc.clone(),
+                    token_program_acc.clone(),
+                ],
+            )?;
         }
-        // transfer asset to vault
+        // wrap the native sol side
+        let wsol_acc = if native_a || native_b {
+            Some(Self::wrap_sol(
+                program_id,
+                pool_acc,
+                owner_acc,
+                native_accounts,
+                token_program_acc,
+                if native_a { amount_a } else { amount_b },
+            )?)
+        } else {
+            None
+        };
+        let (owner_token_a_acc, owner_token_b_acc) = match wsol_acc {
+            Some(ref wsol_acc) if native_a => (wsol_acc, owner_token_b_acc),
+            Some(ref wsol_acc) => (owner_token_a_acc, wsol_acc),
+            None => (owner_token_a_acc, owner_token_b_acc),
+        };
+        // transfer asset to vault, the vaults keep what is left after the transfer fee
+        let reserve_a = amount_a
+            .checked_sub(Self::transfer_fee(mint_a_acc, amount_a)?)
+            .ok_or(AmmError::CalculationError)?;
+        let reserve_b = amount_b
+            .checked_sub(Self::transfer_fee(mint_b_acc, amount_b)?)
+            .ok_or(AmmError::CalculationError)?;
         Self::token_transfer(
             token_program_acc.clone(),
             owner_token_a_acc.clone(),
+            mint_a_acc.clone(),
             vault_a_acc.clone(),
             owner_acc.clone(),
             amount_a,
+            mint_a.decimals,
         )?;
         Self::token_transfer(
             token_program_acc.clone(),
             owner_token_b_acc.clone(),
+            mint_b_acc.clone(),
             vault_b_acc.clone(),
             owner_acc.clone(),
             amount_b,
+            mint_b.decimals,
         )?;
+        if let Some(wsol_acc) = wsol_acc {
+            Self::unwrap_sol(token_program_acc.clone(), wsol_acc, owner_acc.clone())?;
+        }
         // init pool
-        pool.status = PoolStatus::Nomal;
+        let mut pool = AmmPoolView::load_mut_unchecked(pool_acc)?;
+        pool.set_status(PoolStatus::Nomal);
         pool.nonce = nonce;
-        pool.ka = amount_a;
-        pool.kb = amount_b;
-        pool.tolerance = tolerance;
-        pool.fee = fee;
+        pool.set_ka(reserve_a);
+        pool.set_kb(reserve_b);
+        pool.set_tolerance(tolerance);
+        pool.set_fee(fee);
         pool.owner = *owner_acc.key;
         pool.mint_a = *mint_a_acc.key;
         pool.mint_b = *mint_b_acc.key;
         pool.vault_a = *vault_a_acc.key;
         pool.vault_b = *vault_b_acc.key;
         pool.fee_vault = *fee_vault_acc.key;
-        // pack pool
-        AmmPool::pack(pool, &mut pool_acc.data.borrow_mut())?;
+        pool.decimals_a = mint_a.decimals;
+        pool.decimals_b = mint_b.decimals;
+        AmmEvent::Initialize {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            fee_rate: fee,
+            tolerance,
+            reserve_a,
+            reserve_b,
+        }
+        .emit();
         Ok(())
     }
 
@@ -187,34 +349,34 @@ impl Processor {
         accounts: &[AccountInfo],
         status: u8,
     ) -> ProgramResult {
-        let accounts = array_ref![accounts, 0, 2];
-        let [pool_acc, owner_acc] = accounts;
-        // use data
-        let mut pool = AmmPool::unpack_unchecked(&pool_acc.data.borrow())?;
-        // check
-        if !owner_acc.is_signer {
-            msg!("owner must sign");
-            return Err(AmmError::InvalidSignAccount.into());
-        }
-        Self::check_account_owner(pool_acc, program_id)?;
-        if pool.owner != *owner_acc.key {
-            msg!("owner not match {} {}", pool.owner, *owner_acc.key);
-            return Err(AmmError::InvalidOwner.into());
-        }
-        if pool.status == PoolStatus::NotInit {
-            msg!("pool status:{}", pool.status);
-            return Err(AmmError::InvalidStatus.into());
-        }
-        // update pool
-        pool.status = match status {
-            1 => PoolStatus::Nomal,
-            2 => PoolStatus::Lock,
-            _ => {
+        let accounts = OwnerAccounts::load(accounts)?;
+        let mut pool = accounts.validate(program_id)?;
+        let OwnerAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+        } = accounts;
+        // update pool, only terminate closes it
+        match PoolStatus::try_from(status)? {
+            PoolStatus::NotInit | PoolStatus::Closed => {
                 return Err(AmmError::InvalidStatus.into());
             }
-        };
-        // pack pool
-        AmmPool::pack(pool, &mut pool_acc.data.borrow_mut())?;
+            // locking again keeps the time of the first lock
+            PoolStatus::Lock if pool.status()? == PoolStatus::Lock => {}
+            PoolStatus::Lock => {
+                pool.set_status(PoolStatus::Lock);
+                pool.set_lock_time(Clock::get()?.unix_timestamp);
+            }
+            PoolStatus::Nomal => {
+                pool.set_status(PoolStatus::Nomal);
+                pool.set_lock_time(0);
+            }
+        }
+        AmmEvent::UpdateStatus {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            status: pool.status()?,
+        }
+        .emit();
         Ok(())
     }
 
@@ -224,113 +386,188 @@ impl Processor {
         accounts: &[AccountInfo],
         tolerance: u64,
     ) -> ProgramResult {
-        let accounts = array_ref![accounts, 0, 2];
-        let [pool_acc, owner_acc] = accounts;
-        // use data
-        let mut pool = AmmPool::unpack_unchecked(&pool_acc.data.borrow())?;
-        // check
-        if !owner_acc.is_signer {
-            msg!("owner must sign");
-            return Err(AmmError::InvalidSignAccount.into());
-        }
-        Self::check_account_owner(pool_acc, program_id)?;
-        if pool.owner != *owner_acc.key {
-            msg!("owner not match {} {}", pool.owner, *owner_acc.key);
-            return Err(AmmError::InvalidOwner.into());
-        }
-        if pool.status == PoolStatus::NotInit {
-            msg!("pool status:{}", pool.status);
-            return Err(AmmError::InvalidStatus.into());
-        }
+        let accounts = OwnerAccounts::load(accounts)?;
+        let mut pool = accounts.validate(program_id)?;
+        let OwnerAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+        } = accounts;
+        Self::check_tolerance_input(tolerance)?;
         // update pool
-        pool.tolerance = tolerance;
-        // pack pool
-        AmmPool::pack(pool, &mut pool_acc.data.borrow_mut())?;
+        pool.set_tolerance(tolerance);
+        AmmEvent::UpdateTolerance {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            tolerance,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Processes `Update Referral Share` instruction.
+    fn process_update_referral_share(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        share: u64,
+    ) -> ProgramResult {
+        let accounts = OwnerAccounts::load(accounts)?;
+        let mut pool = accounts.validate(program_id)?;
+        let OwnerAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+        } = accounts;
+        if share > math::BPS_MUL {
+            msg!("referral share bps:{} over {}", share, math::BPS_MUL);
+            return Err(AmmError::InvalidInput.into());
+        }
+        pool.set_referral_share(share);
+        AmmEvent::UpdateReferralShare {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            share,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Processes `Update Dynamic Fee` instruction.
+    fn process_update_dynamic_fee(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        mode: u8,
+        min_fee: u64,
+        max_fee: u64,
+    ) -> ProgramResult {
+        let accounts = OwnerAccounts::load(accounts)?;
+        let mut pool = accounts.validate(program_id)?;
+        let OwnerAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+        } = accounts;
+        let mode = FeeMode::try_from(mode)?;
+        if min_fee > max_fee || max_fee > math::PERCENT_MUL {
+            msg!(
+                "fee bounds min:{} max:{} over {}",
+                min_fee,
+                max_fee,
+                math::PERCENT_MUL
+            );
+            return Err(AmmError::InvalidInput.into());
+        }
+        pool.set_fee_mode(mode);
+        pool.set_min_fee(min_fee);
+        pool.set_max_fee(max_fee);
+        // the volatility of an earlier mode doesn't carry over
+        pool.set_volatility(0);
+        pool.set_volatility_slot(Clock::get()?.slot);
+        AmmEvent::UpdateDynamicFee {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            mode,
+            min_fee,
+            max_fee,
+        }
+        .emit();
         Ok(())
     }
 
     /// Processes `Terminate` instruction.
     fn process_terminate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
-        let accounts = array_ref![accounts, 0, 9];
-        let [pool_acc, owner_acc, vault_a_acc, vault_b_acc, fee_vault_acc, pool_pda, owner_token_a_acc, owner_token_b_acc, token_program_acc] =
-            accounts;
-        // use data
-        let pool = AmmPool::unpack_unchecked(&pool_acc.data.borrow())?;
-        let vault_a = Self::unpack_token_account(vault_a_acc)?;
-        let vault_b = Self::unpack_token_account(vault_b_acc)?;
-        let fee_vault = Self::unpack_token_account(fee_vault_acc)?;
-        // check
-        if !owner_acc.is_signer {
-            msg!("owner must sign");
-            return Err(AmmError::InvalidSignAccount.into());
-        }
-        Self::check_account_owner(pool_acc, program_id)?;
-        if pool.owner != *owner_acc.key {
-            msg!("owner not match {} {}", pool.owner, *owner_acc.key);
-            return Err(AmmError::InvalidOwner.into());
-        }
-        if pool.status == PoolStatus::NotInit {
-            msg!("pool status:{}", pool.status);
-            return Err(AmmError::InvalidStatus.into());
-        }
-        if pool.vault_a != *vault_a_acc.key {
-            msg!("vault a not match {} {}", pool.vault_a, *vault_a_acc.key);
-            return Err(AmmError::InvalidVault.into());
-        }
-        if pool.vault_b != *vault_b_acc.key {
-            msg!("vault b not match {} {}", pool.vault_b, *vault_b_acc.key);
-            return Err(AmmError::InvalidVault.into());
-        }
-        if pool.fee_vault != *fee_vault_acc.key {
-            msg!("vault b not match {} {}", pool.vault_b, *vault_b_acc.key);
-            return Err(AmmError::InvalidVault.into());
+        let accounts = TerminateAccounts::load(accounts)?;
+        let (pool, vault_a, vault_b, fee_vault) = accounts.validate(program_id)?;
+        let rewards = accounts.validate_rewards(program_id, &pool)?;
+        let twamm = accounts.validate_twamm(program_id, &pool)?;
+        let TerminateAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+            vault_a: vault_a_acc,
+            vault_b: vault_b_acc,
+            fee_vault: fee_vault_acc,
+            pool_pda,
+            owner_token_a: owner_token_a_acc,
+            owner_token_b: owner_token_b_acc,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            ..
+        } = accounts;
+        Self::check_grace_period(&pool)?;
+        if pool.open_orders() > 0 || pool.long_term_orders() > 0 {
+            msg!(
+                "{} orders and {} long-term orders left, cancel them first",
+                pool.open_orders(),
+                pool.long_term_orders()
+            );
+            return Err(AmmError::OpenOrders.into());
+        }
+        if pool.stakes() > 0 {
+            msg!(
+                "{} stakes left in the farm, unstake them first",
+                pool.stakes()
+            );
+            return Err(AmmError::OpenStakes.into());
         }
+        // a native sol vault pays out by closing it to the owner
+        let native_a = is_native(owner_token_a_acc, owner_acc, &vault_a.mint)?;
+        let native_b = is_native(owner_token_b_acc, owner_acc, &vault_b.mint)?;
         // transfer vault a
-        Self::token_transfer_signed(
-            pool_acc.clone(),
-            pool.nonce,
-            token_program_acc.clone(),
-            vault_a_acc.clone(),
-            owner_token_a_acc.clone(),
-            pool_pda.clone(),
-            vault_a.amount,
-        )?;
+        if !native_a {
+            Self::token_transfer_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                vault_a_acc.clone(),
+                mint_a_acc.clone(),
+                owner_token_a_acc.clone(),
+                pool_pda.clone(),
+                vault_a.amount,
+                pool.decimals_a,
+            )?;
+        }
         Self::token_close_signed(
             pool_acc.clone(),
             pool.nonce,
             token_program_acc.clone(),
             vault_a_acc.clone(),
+            mint_a_acc.clone(),
             owner_acc.clone(),
             pool_pda.clone(),
         )?;
         // transfer vault b
-        Self::token_transfer_signed(
-            pool_acc.clone(),
-            pool.nonce,
-            token_program_acc.clone(),
-            vault_b_acc.clone(),
-            owner_token_b_acc.clone(),
-            pool_pda.clone(),
-            vault_b.amount,
-        )?;
+        if !native_b {
+            Self::token_transfer_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                vault_b_acc.clone(),
+                mint_b_acc.clone(),
+                owner_token_b_acc.clone(),
+                pool_pda.clone(),
+                vault_b.amount,
+                pool.decimals_b,
+            )?;
+        }
         Self::token_close_signed(
             pool_acc.clone(),
             pool.nonce,
             token_program_acc.clone(),
             vault_b_acc.clone(),
+            mint_b_acc.clone(),
             owner_acc.clone(),
             pool_pda.clone(),
         )?;
         // transfer fee vault
-        if fee_vault.amount > 0 {
+        if fee_vault.amount > 0 && !native_b {
             Self::token_transfer_signed(
                 pool_acc.clone(),
                 pool.nonce,
                 token_program_acc.clone(),
                 fee_vault_acc.clone(),
+                mint_b_acc.clone(),
                 owner_token_b_acc.clone(),
                 pool_pda.clone(),
                 fee_vault.amount,
+                pool.decimals_b,
             )?;
         }
         Self::token_close_signed(
@@ -338,17 +575,120 @@ impl Processor {
             pool.nonce,
             token_program_acc.clone(),
             fee_vault_acc.clone(),
+            mint_b_acc.clone(),
             owner_acc.clone(),
             pool_pda.clone(),
         )?;
-        // close account
+        // rewards nobody earned go back to the owner with the farm
+        let farm_acc = if let Some((
+            [farm_acc, reward_vault_acc, owner_token_reward_acc, reward_mint_acc],
+            reward_vault,
+        )) = rewards
+        {
+            if reward_vault.amount > 0 {
+                Self::token_transfer_signed(
+                    pool_acc.clone(),
+                    pool.nonce,
+                    token_program_acc.clone(),
+                    reward_vault_acc.clone(),
+                    reward_mint_acc.clone(),
+                    owner_token_reward_acc.clone(),
+                    pool_pda.clone(),
+                    reward_vault.amount,
+                    unpack_mint(reward_mint_acc)?.decimals,
+                )?;
+            }
+            Self::token_close_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                reward_vault_acc.clone(),
+                reward_mint_acc.clone(),
+                owner_acc.clone(),
+                pool_pda.clone(),
+            )?;
+            Some(farm_acc)
+        } else {
+            None
+        };
+        // with every long-term order closed the escrows hold only rounding dust, it goes
+        // to the owner and the rent of the virtual orders back to the wallet that paid it,
+        // closing a native sol escrow pays its dust with the rent
+        let twamm_acc = if let Some((twamm_accounts, payer_acc, escrow_a, escrow_b)) = twamm {
+            for (escrow_acc, escrow, mint_acc, owner_token_acc, native, decimals) in [
+                (
+                    twamm_accounts.escrow_a,
+                    escrow_a,
+                    mint_a_acc,
+                    owner_token_a_acc,
+                    native_a,
+                    pool.decimals_a,
+                ),
+                (
+                    twamm_accounts.escrow_b,
+                    escrow_b,
+                    mint_b_acc,
+                    owner_token_b_acc,
+                    native_b,
+                    pool.decimals_b,
+                ),
+            ] {
+                if escrow.amount > 0 && !native {
+                    Self::token_transfer_signed(
+                        pool_acc.clone(),
+                        pool.nonce,
+                        token_program_acc.clone(),
+                        escrow_acc.clone(),
+                        mint_acc.clone(),
+                        owner_token_acc.clone(),
+                        pool_pda.clone(),
+                        escrow.amount,
+                        decimals,
+                    )?;
+                }
+                Self::token_close_signed(
+                    pool_acc.clone(),
+                    pool.nonce,
+                    token_program_acc.clone(),
+                    escrow_acc.clone(),
+                    mint_acc.clone(),
+                    payer_acc.clone(),
+                    pool_pda.clone(),
+                )?;
+            }
+            Some((twamm_accounts.twamm, payer_acc))
+        } else {
+            None
+        };
+        // the accounts of the program close once the token program calls are done, they
+        // check the lamports of the accounts passed did not move
+        if let Some(farm_acc) = farm_acc {
+            Self::close_account(farm_acc, owner_acc)?;
+        }
+        if let Some((twamm_acc, payer_acc)) = twamm_acc {
+            Self::close_account(twamm_acc, payer_acc)?;
+        }
+        // close the pool, the data keeps only the closed status so the account can not
+        // be used again, even if lamports are sent back to it in the same transaction
+        drop(pool);
         {
-            let user_lamports = owner_acc.lamports();
-            **owner_acc.lamports.borrow_mut() =
-                user_lamports.checked_add(pool_acc.lamports()).unwrap();
-            **pool_acc.lamports.borrow_mut() = 0;
-            sol_memset(*pool_acc.data.borrow_mut(), 0, AmmPool::LEN);
+            let mut pool = AmmPoolView::load_mut_unchecked(pool_acc)?;
+            *pool = AmmPoolView::zeroed();
+            pool.set_status(PoolStatus::Closed);
+        }
+        let owner_lamports = owner_acc.lamports();
+        **owner_acc.lamports.borrow_mut() = owner_lamports
+            .checked_add(pool_acc.lamports())
+            .ok_or(AmmError::CalculationError)?;
+        **pool_acc.lamports.borrow_mut() = 0;
+        AmmEvent::Terminate {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            amount_a: vault_a.amount,
+            amount_b: vault_b.amount,
+            fee: fee_vault.amount,
         }
+        .emit();
         Ok(())
     }
 
@@ -359,63 +699,177 @@ impl Processor {
         amount: u64,
         direction: Direction,
     ) -> ProgramResult {
-        let accounts = array_ref![accounts, 0, 9];
-        let [pool_acc, vault_a_acc, vault_b_acc, fee_vault_acc, pool_pda, user_wallet_acc, user_token_a_acc, user_token_b_acc, token_program_acc] =
-            accounts;
-        // use data
-        let pool = AmmPool::unpack_unchecked(&pool_acc.data.borrow())?;
-        let vault_a = Self::unpack_token_account(vault_a_acc)?;
-        let vault_b = Self::unpack_token_account(vault_b_acc)?;
-        // check
-        if !user_wallet_acc.is_signer {
-            msg!("user wallet must sign");
-            return Err(AmmError::InvalidSignAccount.into());
-        }
-        Self::check_account_owner(pool_acc, program_id)?;
-        if pool.vault_a != *vault_a_acc.key {
-            msg!("vault a not match {} {}", pool.vault_a, *vault_a_acc.key);
-            return Err(AmmError::InvalidVault.into());
-        }
-        if pool.vault_b != *vault_b_acc.key {
-            msg!("vault b not match {} {}", pool.vault_b, *vault_b_acc.key);
-            return Err(AmmError::InvalidVault.into());
-        }
-        if pool.status != PoolStatus::Nomal {
-            msg!("pool status:{}", pool.status);
-            return Err(AmmError::PoolLock.into());
-        }
-        // check user token
-        let user_token_a = Self::unpack_token_account(user_token_a_acc)?;
-        let user_token_b = Self::unpack_token_account(user_token_b_acc)?;
-        if user_token_a.mint != vault_a.mint {
-            msg!(
-                "user token a not match {} {}",
-                user_token_a.mint,
-                vault_a.mint
-            );
-            return Err(AmmError::InvalidMint.into());
+        let accounts = SwapAccounts::load(accounts)?;
+        let (mut pool, mut vault_a, mut vault_b) = accounts.validate(program_id)?;
+        let SwapAccounts {
+            pool: pool_acc,
+            vault_a: vault_a_acc,
+            vault_b: vault_b_acc,
+            fee_vault: fee_vault_acc,
+            pool_pda,
+            user_wallet: user_wallet_acc,
+            user_token_a: user_token_a_acc,
+            user_token_b: user_token_b_acc,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            native: native_accounts,
+        } = accounts;
+        // the virtual orders trade first, the swap prices against what they leave
+        let (twamm_accounts, native_accounts) = TwammAccounts::split(&pool, native_accounts)?;
+        if let Some(twamm_accounts) = twamm_accounts {
+            let virtual_orders = VirtualOrdersAccounts {
+                pool: pool_acc,
+                twamm: twamm_accounts,
+                vault_a: vault_a_acc,
+                vault_b: vault_b_acc,
+                fee_vault: fee_vault_acc,
+                pool_pda,
+                token_program: token_program_acc,
+                mint_a: mint_a_acc,
+                mint_b: mint_b_acc,
+            };
+            let mut twamm =
+                twamm_accounts.validate(program_id, pool_acc, pool_pda, token_program_acc)?;
+            Self::execute_virtual_orders(&virtual_orders, &pool, &mut twamm)?;
+            vault_a = unpack_token_account(vault_a_acc)?;
+            vault_b = unpack_token_account(vault_b_acc)?;
         }
-        if user_token_b.mint != vault_b.mint {
-            msg!(
-                "user token b not match {} {}",
-                user_token_b.mint,
-                vault_b.mint
-            );
+        // check user token, the wallet stands in for a native sol token account
+        let native_a = is_native(user_token_a_acc, user_wallet_acc, &vault_a.mint)?;
+        let native_b = is_native(user_token_b_acc, user_wallet_acc, &vault_b.mint)?;
+        if native_a && native_b {
+            msg!("only one side can be native sol");
             return Err(AmmError::InvalidMint.into());
         }
+        // an optional referrer follows the native accounts
+        let referrer_acc = native_accounts.get(if native_a || native_b { NATIVE_LEN } else { 0 });
+        if let Some(referrer_acc) = referrer_acc {
+            check_referrer(referrer_acc, &pool, user_wallet_acc.key)?;
+        }
+        let user_amount_a = if native_a {
+            Self::native_balance(user_wallet_acc)?
+        } else {
+            let user_token_a = unpack_token_account(user_token_a_acc)?;
+            if user_token_a.mint != vault_a.mint {
+                msg!(
+                    "user token a not match {} {}",
+                    user_token_a.mint,
+                    vault_a.mint
+                );
+                return Err(AmmError::InvalidMint.into());
+            }
+            user_token_a.amount
+        };
+        let user_amount_b = if native_b {
+            Self::native_balance(user_wallet_acc)?
+        } else {
+            let user_token_b = unpack_token_account(user_token_b_acc)?;
+            if user_token_b.mint != vault_b.mint {
+                msg!(
+                    "user token b not match {} {}",
+                    user_token_b.mint,
+                    vault_b.mint
+                );
+                return Err(AmmError::InvalidMint.into());
+            }
+            user_token_b.amount
+        };
+        let slot = Self::fee_slot(&pool)?;
+        let fee_rate = pool.effective_fee(slot, vault_a.amount, vault_b.amount, Some(direction))?;
         // match direction
-        let amount_transfer: u64;
-        msg!("{}", direction);
+        // amount_in is what the user sends, amount_received what arrives in the vault
+        // after the transfer fee of a token-2022 mint
+        let (amount_in, amount_transfer, fee_mount) = match direction {
+            Direction::A2B => {
+                let amount_received = amount
+                    .checked_sub(Self::transfer_fee(mint_a_acc, amount)?)
+                    .ok_or(AmmError::CalculationError)?;
+                let amount_transfer = match math::calculate_amount_a2b(
+                    vault_a.amount,
+                    vault_b.amount,
+                    amount_received,
+                ) {
+                    Ok(amount_transfer) => amount_transfer,
+                    Err(e) => {
+                        msg!(
+                            "calculate a2b failed, vault a:{}, vault b:{}, amount:{}",
+                            vault_a.amount,
+                            vault_b.amount,
+                            amount
+                        );
+                        return Err(e.into());
+                    }
+                };
+                let fee_mount = math::calculate_fee(amount_transfer, fee_rate)?;
+                if user_amount_a < amount {
+                    msg!("user token a not enough {} {}", user_amount_a, amount);
+                    return Err(AmmError::InsufficientFunds.into());
+                }
+                (amount, amount_transfer, fee_mount)
+            }
+            Direction::B2A => {
+                let amount_transfer =
+                    match math::calculate_amount_b2a(vault_a.amount, vault_b.amount, amount) {
+                        Ok(amount_transfer) => amount_transfer,
+                        Err(e) => {
+                            msg!(
+                                "calculate b2a failed, vault a:{}, vault b:{}, amount:{}",
+                                vault_a.amount,
+                                vault_b.amount,
+                                amount
+                            );
+                            return Err(e.into());
+                        }
+                    };
+                let fee_mount = math::calculate_fee(amount_transfer, fee_rate)?;
+                let amount_in = amount_transfer
+                    .checked_add(Self::inverse_transfer_fee(mint_b_acc, amount_transfer)?)
+                    .ok_or(AmmError::CalculationError)?;
+                let amount_pay = amount_in
+                    .checked_add(fee_mount)
+                    .ok_or(AmmError::CalculationError)?;
+                if user_amount_b < amount_pay {
+                    msg!("user token b not enough {} {}", user_amount_b, amount_pay);
+                    return Err(AmmError::InsufficientFunds.into());
+                }
+                (amount_in, amount_transfer, fee_mount)
+            }
+        };
+        // wrap what the user pays in native sol, the paid out side only needs rent
+        let wsol_acc = if native_a || native_b {
+            let amount_wrap = match direction {
+                Direction::A2B if native_a => amount,
+                Direction::B2A if native_b => amount_in + fee_mount,
+                _ => 0,
+            };
+            Some(Self::wrap_sol(
+                program_id,
+                pool_acc,
+                user_wallet_acc,
+                native_accounts,
+                token_program_acc,
+                amount_wrap,
+            )?)
+        } else {
+            None
+        };
+        let (user_token_a_acc, user_token_b_acc) = match wsol_acc {
+            Some(ref wsol_acc) if native_a => (wsol_acc, user_token_b_acc),
+            Some(ref wsol_acc) => (user_token_a_acc, wsol_acc),
+            None => (user_token_a_acc, user_token_b_acc),
+        };
         match direction {
             Direction::A2B => {
-                amount_transfer = Self::calculate_amount_a2b(pool, amount, vault_a, vault_b)?;
                 // transfer user token to vault
                 Self::token_transfer(
                     token_program_acc.clone(),
                     user_token_a_acc.clone(),
+                    mint_a_acc.clone(),
                     vault_a_acc.clone(),
                     user_wallet_acc.clone(),
-                    amount,
+                    amount_in,
+                    pool.decimals_a,
                 )?;
                 // transfer vault token to user
                 Self::token_transfer_signed(
@@ -423,20 +877,23 @@ impl Processor {
                     pool.nonce,
                     token_program_acc.clone(),
                     vault_b_acc.clone(),
+                    mint_b_acc.clone(),
                     user_token_b_acc.clone(),
                     pool_pda.clone(),
                     amount_transfer,
+                    pool.decimals_b,
                 )?;
             }
-            Direction::B2A => {
-                amount_transfer = Self::calculate_amount_b2a(pool, amount, vault_a, vault_b)?;
+            _ => {
                 // transfer user token to vault
                 Self::token_transfer(
                     token_program_acc.clone(),
                     user_token_b_acc.clone(),
+                    mint_b_acc.clone(),
                     vault_b_acc.clone(),
                     user_wallet_acc.clone(),
-                    amount_transfer,
+                    amount_in,
+                    pool.decimals_b,
                 )?;
                 // transfer vault token to user
                 Self::token_transfer_signed(
@@ -444,62 +901,94 @@ impl Processor {
                     pool.nonce,
                     token_program_acc.clone(),
                     vault_a_acc.clone(),
+                    mint_a_acc.clone(),
                     user_token_a_acc.clone(),
                     pool_pda.clone(),
                     amount,
+                    pool.decimals_a,
                 )?;
             }
-            Direction::InvalidDirection => {
-                return Err(AmmError::InvalidDirection.into());
+        }
+        // check the reserves the vaults hold after the trade
+        let reserve_a = unpack_token_account(vault_a_acc)?.amount;
+        let reserve_b = unpack_token_account(vault_b_acc)?.amount;
+        Self::check_tolerance(&pool, &vault_a, &vault_b, reserve_a, reserve_b)?;
+        Self::record_price_move(&mut pool, slot, &vault_a, &vault_b, reserve_a, reserve_b)?;
+        // transfer fee, the referrer takes its share first
+        let referral = referrer_acc
+            .map(|acc| {
+                (
+                    acc,
+                    math::calculate_referral_fee(fee_mount, pool.referral_share()),
+                )
+            })
+            .filter(|(_, amount)| *amount > 0);
+        let referral_fee = referral.map_or(0, |(_, amount)| amount);
+        if let Some((referrer_acc, referral_fee)) = referral {
+            Self::token_transfer(
+                token_program_acc.clone(),
+                user_token_b_acc.clone(),
+                mint_b_acc.clone(),
+                referrer_acc.clone(),
+                user_wallet_acc.clone(),
+                referral_fee,
+                pool.decimals_b,
+            )?;
+            AmmEvent::Referral {
+                pool: *pool_acc.key,
+                user: *user_wallet_acc.key,
+                referrer: *referrer_acc.key,
+                amount: referral_fee,
             }
+            .emit();
         }
-        // check if k is within tolerance
-        // Self::check_amount_tolerance(pool, direction, amount, amount_transfer, vault_a, vault_b)?;
-        // transfer fee
-        let fee_mount = amount_transfer
-            .checked_mul(pool.fee)
-            .and_then(|v| v.checked_div(PERCENT_MUL))
-            .unwrap();
-        if fee_mount > 0 {
+        if fee_mount > referral_fee {
             // transfer user token to vault
             Self::token_transfer(
                 token_program_acc.clone(),
                 user_token_b_acc.clone(),
+                mint_b_acc.clone(),
                 fee_vault_acc.clone(),
                 user_wallet_acc.clone(),
-                fee_mount,
+                fee_mount - referral_fee,
+                pool.decimals_b,
             )?;
         }
+        // unwrap what is left back to the wallet
+        if let Some(wsol_acc) = wsol_acc {
+            Self::unwrap_sol(token_program_acc.clone(), wsol_acc, user_wallet_acc.clone())?;
+        }
+        let amount_out = match direction {
+            Direction::A2B => amount_transfer,
+            _ => amount,
+        };
+        AmmEvent::Swap {
+            pool: *pool_acc.key,
+            user: *user_wallet_acc.key,
+            direction,
+            amount_in,
+            amount_out,
+            fee: fee_mount,
+            reserve_a,
+            reserve_b,
+        }
+        .emit();
         Ok(())
     }
 
     /// Processes `Withdrawal Fee` instruction.
     fn process_withdrawal_fee(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
-        let accounts = array_ref![accounts, 0, 6];
-        let [pool_acc, owner_acc, fee_vault_acc, fee_receiver_acc, pool_pda, token_program_acc] =
-            accounts;
-        // use data
-        let pool = AmmPool::unpack_unchecked(&pool_acc.data.borrow())?;
-        // check
-        if !owner_acc.is_signer {
-            msg!("owner must sign");
-            return Err(AmmError::InvalidSignAccount.into());
-        }
-        Self::check_account_owner(pool_acc, program_id)?;
-        if pool.owner != *owner_acc.key {
-            msg!("owner not match {} {}", pool.owner, *owner_acc.key);
-            return Err(AmmError::InvalidOwner.into());
-        }
-        // check fee vault
-        let fee_vault = Self::unpack_token_account(fee_vault_acc)?;
-        if pool.fee_vault != *fee_vault_acc.key {
-            msg!(
-                "fee vault not match {} {}",
-                pool.fee_vault,
-                *fee_vault_acc.key
-            );
-            return Err(AmmError::InvalidVault.into());
-        }
+        let accounts = WithdrawalFeeAccounts::load(accounts)?;
+        let (pool, fee_vault) = accounts.validate(program_id)?;
+        let WithdrawalFeeAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+            fee_vault: fee_vault_acc,
+            fee_receiver: fee_receiver_acc,
+            pool_pda,
+            token_program: token_program_acc,
+            mint_b: mint_b_acc,
+        } = accounts;
         // transfer fee to receiver
         if fee_vault.amount > 0 {
             Self::token_transfer_signed(
@@ -507,220 +996,1536 @@ impl Processor {
                 pool.nonce,
                 token_program_acc.clone(),
                 fee_vault_acc.clone(),
+                mint_b_acc.clone(),
                 fee_receiver_acc.clone(),
                 pool_pda.clone(),
                 fee_vault.amount,
+                pool.decimals_b,
             )?;
         } else {
             return Err(AmmError::NoFee.into());
         }
+        AmmEvent::WithdrawalFee {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            amount: fee_vault.amount,
+        }
+        .emit();
         Ok(())
     }
-    /// calculate a2b amount
-    /// A*B=k
-    /// (A+a)*(B-b)=k
-    /// b=B-k/(A+a)
-    fn calculate_amount_a2b(
-        pool: AmmPool,
-        amount_a: u64,
-        vault_a: spl_token::state::Account,
-        vault_b: spl_token::state::Account,
-    ) -> Result<u64, AmmError> {
-        // calcaulate k
-        let ka = pool.ka as u128;
-        let kb = pool.kb as u128;
-        let k: u128 = ka.checked_mul(kb).unwrap();
-        // calculate amount a
-        let amount = amount_a as u128;
-        let vault_a_amount = vault_a.amount as u128;
-        let vault_b_amount = vault_b.amount as u128;
-        let changed_a = vault_a_amount.checked_add(amount).unwrap();
-        let temp: u128 = k.checked_div(changed_a).unwrap();
-        let amount_b: u128 = vault_b_amount.checked_sub(temp).unwrap();
-        if amount_b >= vault_b_amount {
-            msg!(
-                "amount b too big, vault:{}, amount:{}",
-                vault_b.amount,
-                amount_b
-            );
-            return Err(AmmError::CalculationError);
+
+    /// Processes `PlaceOrder` instruction.
+    fn process_place_order(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount_in: u64,
+        direction: Direction,
+        min_amount_out: u64,
+        reward: u64,
+    ) -> ProgramResult {
+        let accounts = PlaceOrderAccounts::load(accounts)?;
+        let (mut pool, escrow_bump) = accounts.validate(program_id, direction)?;
+        let PlaceOrderAccounts {
+            pool: pool_acc,
+            order: order_acc,
+            owner: owner_acc,
+            escrow: escrow_acc,
+            pool_pda,
+            owner_token_in: owner_token_in_acc,
+            owner_token_out: owner_token_out_acc,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            system_program: system_program_acc,
+        } = accounts;
+        if amount_in == 0 {
+            return Err(AmmError::InvalidAmount.into());
         }
-        if amount_b > u64::MAX as u128 {
-            msg!("amount b too big:{}", amount_b);
-            return Err(AmmError::CalculationError);
+        let (mint_in_acc, decimals_in) = match direction {
+            Direction::A2B => (mint_a_acc, pool.decimals_a),
+            Direction::B2A => (mint_b_acc, pool.decimals_b),
+        };
+        // create the order and its escrow, the owner pays the rent and the keeper reward
+        let rent = Rent::get()?;
+        let lamports = rent
+            .minimum_balance(Order::LEN)
+            .checked_add(reward)
+            .ok_or(AmmError::CalculationError)?;
+        Self::create_account(
+            owner_acc,
+            order_acc,
+            system_program_acc,
+            Order::LEN,
+            lamports,
+            program_id,
+            &[],
+        )?;
+        let space = Self::vault_len(mint_in_acc)?;
+        Self::create_account(
+            owner_acc,
+            escrow_acc,
+            system_program_acc,
+            space,
+            rent.minimum_balance(space),
+            token_program_acc.key,
+            &[ORDER_ESCROW_SEED, order_acc.key.as_ref(), &[escrow_bump]],
+        )?;
+        let ix = spl_token_2022::instruction::initialize_account3(
+            token_program_acc.key,
+            escrow_acc.key,
+            mint_in_acc.key,
+            pool_pda.key,
+        )?;
+        invoke(
+            &ix,
+            &[
+                escrow_acc.clone(),
+                mint_in_acc.clone(),
+                token_program_acc.clone(),
+            ],
+        )?;
+        // escrow the sold token, the order keeps what arrives after the transfer fee
+        Self::token_transfer(
+            token_program_acc.clone(),
+            owner_token_in_acc.clone(),
+            mint_in_acc.clone(),
+            escrow_acc.clone(),
+            owner_acc.clone(),
+            amount_in,
+            decimals_in,
+        )?;
+        let amount_received = unpack_token_account(escrow_acc)?.amount;
+        let order = Order {
+            status: OrderStatus::Open,
+            direction,
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            escrow: *escrow_acc.key,
+            token_in: *owner_token_in_acc.key,
+            token_out: *owner_token_out_acc.key,
+            amount_in: amount_received,
+            min_amount_out,
+            reward,
+        };
+        order.pack_into_slice(&mut order_acc.try_borrow_mut_data()?);
+        let open_orders = pool
+            .open_orders()
+            .checked_add(1)
+            .ok_or(AmmError::CalculationError)?;
+        pool.set_open_orders(open_orders);
+        AmmEvent::PlaceOrder {
+            pool: *pool_acc.key,
+            order: *order_acc.key,
+            owner: *owner_acc.key,
+            direction,
+            amount_in: amount_received,
+            min_amount_out,
+            reward,
         }
-        Ok(amount_b as u64)
+        .emit();
+        Ok(())
     }
 
-    /// calculate b2a amount
-    /// A*B=k
-    /// (A-a)*(B+b)=k
-    /// b=k/(A-a)-B
-    fn calculate_amount_b2a(
-        pool: AmmPool,
-        amount_a: u64,
-        vault_a: spl_token::state::Account,
-        vault_b: spl_token::state::Account,
-    ) -> Result<u64, AmmError> {
-        // calculatek
-        let ka = pool.ka as u128;
-        let kb = pool.kb as u128;
-        let k: u128 = ka.checked_mul(kb).unwrap();
-        // calculate amount
-        let amount = amount_a as u128;
-        let vault_a_amount = vault_a.amount as u128;
-        let vault_b_amount = vault_b.amount as u128;
-        let changed_a: u128 = vault_a_amount.checked_sub(amount).unwrap();
-        if changed_a == 0 {
-            msg!(
-                "amount a too big, vault:{}, amount:{}",
-                vault_a.amount,
-                amount_a
-            );
-            return Err(AmmError::CalculationError);
+    /// Processes `CancelOrder` instruction.
+    fn process_cancel_order(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let accounts = CancelOrderAccounts::load(accounts)?;
+        let (mut pool, order, escrow) = accounts.validate(program_id)?;
+        let CancelOrderAccounts {
+            pool: pool_acc,
+            order: order_acc,
+            owner: owner_acc,
+            escrow: escrow_acc,
+            pool_pda,
+            owner_token_in: owner_token_in_acc,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+        } = accounts;
+        Self::check_owner_or_wind_down(&pool, owner_acc)?;
+        let (mint_in_acc, decimals_in) = match order.direction {
+            Direction::A2B => (mint_a_acc, pool.decimals_a),
+            Direction::B2A => (mint_b_acc, pool.decimals_b),
+        };
+        // refund the escrow
+        if escrow.amount > 0 {
+            Self::token_transfer_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                escrow_acc.clone(),
+                mint_in_acc.clone(),
+                owner_token_in_acc.clone(),
+                pool_pda.clone(),
+                escrow.amount,
+                decimals_in,
+            )?;
         }
-        let temp: u128 = k.checked_div(changed_a).unwrap();
-        let amount_b: u128 = temp.checked_sub(vault_b_amount).unwrap();
-        if amount_b > u64::MAX as u128 {
-            msg!("amount b too big:{}", amount_b);
-            return Err(AmmError::CalculationError);
+        Self::token_close_signed(
+            pool_acc.clone(),
+            pool.nonce,
+            token_program_acc.clone(),
+            escrow_acc.clone(),
+            mint_in_acc.clone(),
+            owner_acc.clone(),
+            pool_pda.clone(),
+        )?;
+        // the reward goes back to the owner with the rent
+        Self::close_order(order_acc, order, owner_acc, owner_acc)?;
+        Self::remove_order(&mut pool)?;
+        AmmEvent::CancelOrder {
+            pool: *pool_acc.key,
+            order: *order_acc.key,
+            owner: *owner_acc.key,
+            amount: escrow.amount,
         }
-        Ok(amount_b as u64)
+        .emit();
+        Ok(())
     }
 
-    fn _check_amount_tolerance(
-        pool: AmmPool,
-        direction: Direction,
-        amount: u64,
-        amount_transfer: u64,
-        vault_a: spl_token::state::Account,
-        vault_b: spl_token::state::Account,
-    ) -> Result<(), AmmError> {
-        // calculate k
-        let ka = pool.ka as u128;
-        let kb = pool.kb as u128;
-        let k_origin: u128 = ka.checked_mul(kb).unwrap();
-        let ka_new: u128;
-        let kb_new: u128;
-        // amount
-        let vault_a_amount = vault_a.amount as u128;
-        let vault_b_amount = vault_b.amount as u128;
-        let amount_big = amount as u128;
-        let amount_transfer_big = amount_transfer as u128;
-        msg!("vault a:{}, vault b:{}", vault_a_amount, vault_b_amount);
-        msg!(
-            "amount:{}, amount transfer:{}",
-            amount_big,
-            amount_transfer_big
-        );
-        match direction {
-            Direction::A2B => {
-                ka_new = vault_a_amount.checked_add(amount_big).unwrap();
-                kb_new = vault_b_amount.checked_sub(amount_transfer_big).unwrap();
-            }
-            Direction::B2A => {
-                ka_new = vault_a_amount.checked_sub(amount_big).unwrap();
-                kb_new = vault_b_amount.checked_add(amount_transfer_big).unwrap();
-            }
-            Direction::InvalidDirection => {
-                return Err(AmmError::InvalidDirection.into());
-            }
-        }
-        let k_new: u128 = ka_new.checked_mul(kb_new).unwrap();
-        msg!("k:{}, k new:{}", k_origin, k_new);
-        let tolerance: u128;
-        if k_origin > k_new {
-            tolerance = k_origin - k_new;
-        } else if k_new > k_origin {
-            tolerance = k_new - k_origin;
-        } else {
-            tolerance = 0;
+    /// Processes `FillOrders` instruction.
+    fn process_fill_orders(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let accounts = FillOrdersAccounts::load(accounts)?;
+        let mut pool = accounts.validate(program_id)?;
+        // the virtual orders trade first, the fills price against what they leave
+        let (twamm_accounts, orders) = accounts.split(&pool)?;
+        if let Some(twamm_accounts) = twamm_accounts {
+            let virtual_orders = VirtualOrdersAccounts {
+                pool: accounts.pool,
+                twamm: twamm_accounts,
+                vault_a: accounts.vault_a,
+                vault_b: accounts.vault_b,
+                fee_vault: accounts.fee_vault,
+                pool_pda: accounts.pool_pda,
+                token_program: accounts.token_program,
+                mint_a: accounts.mint_a,
+                mint_b: accounts.mint_b,
+            };
+            let mut twamm = twamm_accounts.validate(
+                program_id,
+                accounts.pool,
+                accounts.pool_pda,
+                accounts.token_program,
+            )?;
+            Self::execute_virtual_orders(&virtual_orders, &pool, &mut twamm)?;
         }
-        if tolerance > pool.tolerance as u128 {
-            msg!(
-                "tolerance too big, pool tolerance:{}, calculated tolerance:{}",
-                pool.tolerance,
-                tolerance
-            );
-            return Err(AmmError::OutOfTolerance);
+        let slot = Self::fee_slot(&pool)?;
+        for order_accounts in orders {
+            let (order, escrow) = match order_accounts.validate(
+                program_id,
+                accounts.pool,
+                accounts.pool_pda,
+                accounts.token_program,
+            )? {
+                Some(order) => order,
+                None => {
+                    msg!("order {} is not open, skipped", order_accounts.order.key);
+                    continue;
+                }
+            };
+            Self::fill_order(&accounts, &mut pool, slot, &order_accounts, order, escrow)?;
         }
         Ok(())
     }
 
-    /// Check account owner is the given program
-    fn check_account_owner(
-        account_info: &AccountInfo,
-        program_id: &Pubkey,
-    ) -> Result<(), AmmError> {
-        if *program_id != *account_info.owner {
+    /// Fill one order against the pool curve, an order that doesn't cross is left open
+    fn fill_order<'a>(
+        accounts: &FillOrdersAccounts<'_, 'a>,
+        pool: &mut AmmPoolView,
+        slot: u64,
+        order_accounts: &OrderAccounts<'_, 'a>,
+        order: Order,
+        escrow: Account,
+    ) -> ProgramResult {
+        let FillOrdersAccounts {
+            pool: pool_acc,
+            vault_a: vault_a_acc,
+            vault_b: vault_b_acc,
+            fee_vault: fee_vault_acc,
+            pool_pda,
+            keeper: keeper_acc,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            ..
+        } = *accounts;
+        let OrderAccounts {
+            order: order_acc,
+            escrow: escrow_acc,
+            owner: owner_acc,
+            token_out: token_out_acc,
+        } = *order_accounts;
+        // earlier fills of the instruction moved the reserves
+        let vault_a = unpack_token_account(vault_a_acc)?;
+        let vault_b = unpack_token_account(vault_b_acc)?;
+        let amount = escrow.amount;
+        let fee_rate =
+            pool.effective_fee(slot, vault_a.amount, vault_b.amount, Some(order.direction))?;
+        // quote the fill, the limit is checked against what arrives in the owner account
+        let quote = match order.direction {
+            // the fee is taken in token b out of the vault, as a swap pays it
+            Direction::A2B => amount
+                .checked_sub(Self::transfer_fee(mint_a_acc, amount)?)
+                .and_then(|received| {
+                    math::calculate_amount_a2b(vault_a.amount, vault_b.amount, received).ok()
+                })
+                .and_then(|amount_out| {
+                    let fee = math::calculate_fee(amount_out, fee_rate).ok()?;
+                    Some((amount, amount_out.checked_sub(fee)?, fee))
+                }),
+            // the escrow pays the fee in token b on top of what it trades
+            Direction::B2A => {
+                let (amount_trade, fee) = math::split_fee(amount, fee_rate);
+                amount_trade
+                    .checked_sub(Self::transfer_fee(mint_b_acc, amount_trade)?)
+                    .and_then(|received| {
+                        math::calculate_amount_a2b(vault_b.amount, vault_a.amount, received).ok()
+                    })
+                    .map(|amount_out| (amount_trade, amount_out, fee))
+            }
+        };
+        let (amount_trade, amount_out, fee) = match quote {
+            Some(quote) if quote.0 > 0 && quote.1 > 0 => quote,
+            _ => {
+                msg!("order {} can't be quoted, skipped", order_acc.key);
+                return Ok(());
+            }
+        };
+        let mint_out_acc = match order.direction {
+            Direction::A2B => mint_b_acc,
+            Direction::B2A => mint_a_acc,
+        };
+        let amount_arrived = amount_out
+            .checked_sub(Self::transfer_fee(mint_out_acc, amount_out)?)
+            .ok_or(AmmError::CalculationError)?;
+        if amount_arrived < order.min_amount_out {
             msg!(
-                "Expected account to be owned by program {}, received {}",
-                program_id,
-                account_info.owner
+                "order {} doesn't cross, out:{}, min out:{}",
+                order_acc.key,
+                amount_arrived,
+                order.min_amount_out
             );
-            Err(AmmError::InvalidProgramAddress)
-        } else {
-            Ok(())
+            return Ok(());
         }
-    }
+        match order.direction {
+            Direction::A2B => {
+                // transfer escrow token to vault
+                Self::token_transfer_signed(
+                    pool_acc.clone(),
+                    pool.nonce,
+                    token_program_acc.clone(),
+                    escrow_acc.clone(),
+                    mint_a_acc.clone(),
+                    vault_a_acc.clone(),
+                    pool_pda.clone(),
+                    amount_trade,
+                    pool.decimals_a,
+                )?;
+                // transfer vault token to owner
+                Self::token_transfer_signed(
+                    pool_acc.clone(),
+                    pool.nonce,
+                    token_program_acc.clone(),
+                    vault_b_acc.clone(),
+                    mint_b_acc.clone(),
+                    token_out_acc.clone(),
+                    pool_pda.clone(),
+                    amount_out,
+                    pool.decimals_b,
+                )?;
+                // transfer fee
+                if fee > 0 {
+                    Self::token_transfer_signed(
+                        pool_acc.clone(),
+                        pool.nonce,
+                        token_program_acc.clone(),
+                        vault_b_acc.clone(),
+                        mint_b_acc.clone(),
+                        fee_vault_acc.clone(),
+                        pool_pda.clone(),
+                        fee,
+                        pool.decimals_b,
+                    )?;
+                }
+            }
+            Direction::B2A => {
+                // transfer escrow token to vault
+                Self::token_transfer_signed(
+                    pool_acc.clone(),
+                    pool.nonce,
+                    token_program_acc.clone(),
+                    escrow_acc.clone(),
+                    mint_b_acc.clone(),
+                    vault_b_acc.clone(),
+                    pool_pda.clone(),
+                    amount_trade,
+                    pool.decimals_b,
+                )?;
+                // transfer fee
+                if fee > 0 {
+                    Self::token_transfer_signed(
+                        pool_acc.clone(),
+                        pool.nonce,
+                        token_program_acc.clone(),
+                        escrow_acc.clone(),
+                        mint_b_acc.clone(),
+                        fee_vault_acc.clone(),
+                        pool_pda.clone(),
+                        fee,
+                        pool.decimals_b,
+                    )?;
+                }
+                // transfer vault token to owner
+                Self::token_transfer_signed(
+                    pool_acc.clone(),
+                    pool.nonce,
+                    token_program_acc.clone(),
+                    vault_a_acc.clone(),
+                    mint_a_acc.clone(),
+                    token_out_acc.clone(),
+                    pool_pda.clone(),
+                    amount_out,
+                    pool.decimals_a,
+                )?;
+            }
+        }
+        // check the reserves the vaults hold after the fill
+        let reserve_a = unpack_token_account(vault_a_acc)?.amount;
+        let reserve_b = unpack_token_account(vault_b_acc)?.amount;
+        Self::check_tolerance(pool, &vault_a, &vault_b, reserve_a, reserve_b)?;
+        Self::record_price_move(pool, slot, &vault_a, &vault_b, reserve_a, reserve_b)?;
+        // the escrow is empty, its rent goes back to the owner
+        let mint_in_acc = match order.direction {
+            Direction::A2B => mint_a_acc,
+            Direction::B2A => mint_b_acc,
+        };
+        Self::token_close_signed(
+            pool_acc.clone(),
+            pool.nonce,
+            token_program_acc.clone(),
+            escrow_acc.clone(),
+            mint_in_acc.clone(),
+            owner_acc.clone(),
+            pool_pda.clone(),
+        )?;
+        Self::close_order(order_acc, order, owner_acc, keeper_acc)?;
+        Self::remove_order(pool)?;
+        AmmEvent::FillOrder {
+            pool: *pool_acc.key,
+            order: *order_acc.key,
+            keeper: *keeper_acc.key,
+            direction: order.direction,
+            amount_in: amount,
+            amount_out,
+            fee,
+            reserve_a,
+            reserve_b,
+        }
+        .emit();
+        Ok(())
+    }
 
-    /// Unpacks a spl_token `Account`.
-    fn unpack_token_account(
-        account_info: &AccountInfo,
-    ) -> Result<spl_token::state::Account, AmmError> {
-        if account_info.owner != &spl_token::ID {
-            Err(AmmError::InvalidTokenProgramId)
-        } else {
-            spl_token::state::Account::unpack(&account_info.data.borrow())
-                .map_err(|_| AmmError::ExpectedAccount)
+    /// Processes `PlaceLongTermOrder` instruction.
+    fn process_place_long_term_order(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount_in: u64,
+        direction: Direction,
+        slots: u64,
+    ) -> ProgramResult {
+        let accounts = PlaceLongTermOrderAccounts::load(accounts)?;
+        let (mut pool, twamm) = accounts.validate(program_id, direction)?;
+        let PlaceLongTermOrderAccounts {
+            order: order_acc,
+            owner: owner_acc,
+            virtual_orders,
+            owner_token_in: owner_token_in_acc,
+            owner_token_out: owner_token_out_acc,
+            system_program: system_program_acc,
+        } = accounts;
+        let VirtualOrdersAccounts {
+            pool: pool_acc,
+            twamm: twamm_accounts,
+            vault_a: vault_a_acc,
+            vault_b: vault_b_acc,
+            pool_pda,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            ..
+        } = virtual_orders;
+        if amount_in == 0 || slots == 0 {
+            return Err(AmmError::InvalidAmount.into());
         }
+        // the escrows settle by balance, a transfer fee would leave them short
+        for mint_acc in [mint_a_acc, mint_b_acc] {
+            if Self::transfer_fee_config(mint_acc)?.is_some() {
+                msg!("long-term orders of transfer fee mint {}", mint_acc.key);
+                return Err(AmmError::UnsupportedMint.into());
+            }
+        }
+        let clock = Clock::get()?;
+        let rent = Rent::get()?;
+        // the first order of the pool creates its virtual orders
+        let mut twamm = match twamm {
+            Some(twamm) => twamm,
+            None => {
+                let bumps = twamm_accounts.validate_new(program_id, pool_acc)?;
+                Self::create_account(
+                    owner_acc,
+                    twamm_accounts.twamm,
+                    system_program_acc,
+                    Twamm::LEN,
+                    rent.minimum_balance(Twamm::LEN),
+                    program_id,
+                    &[TWAMM_SEED, pool_acc.key.as_ref(), &[bumps[0]]],
+                )?;
+                for (escrow_acc, mint_acc, seed, bump) in [
+                    (
+                        twamm_accounts.escrow_a,
+                        mint_a_acc,
+                        TWAMM_ESCROW_A_SEED,
+                        bumps[1],
+                    ),
+                    (
+                        twamm_accounts.escrow_b,
+                        mint_b_acc,
+                        TWAMM_ESCROW_B_SEED,
+                        bumps[2],
+                    ),
+                ] {
+                    let space = Self::vault_len(mint_acc)?;
+                    Self::create_account(
+                        owner_acc,
+                        escrow_acc,
+                        system_program_acc,
+                        space,
+                        rent.minimum_balance(space),
+                        token_program_acc.key,
+                        &[seed, pool_acc.key.as_ref(), &[bump]],
+                    )?;
+                    let ix = spl_token_2022::instruction::initialize_account3(
+                        token_program_acc.key,
+                        escrow_acc.key,
+                        mint_acc.key,
+                        pool_pda.key,
+                    )?;
+                    invoke(
+                        &ix,
+                        &[
+                            escrow_acc.clone(),
+                            mint_acc.clone(),
+                            token_program_acc.clone(),
+                        ],
+                    )?;
+                }
+                pool.set_twamm(true);
+                Twamm {
+                    pool: *pool_acc.key,
+                    escrow_a: *twamm_accounts.escrow_a.key,
+                    escrow_b: *twamm_accounts.escrow_b.key,
+                    payer: *owner_acc.key,
+                    last_slot: clock.slot,
+                    ..Twamm::default()
+                }
+            }
+        };
+        // the order starts selling from the current slot
+        Self::execute_virtual_orders(&virtual_orders, &pool, &mut twamm)?;
+        let reserve_in = match direction {
+            Direction::A2B => unpack_token_account(vault_a_acc)?.amount,
+            Direction::B2A => unpack_token_account(vault_b_acc)?.amount,
+        };
+        if (amount_in as u128) * (math::BPS_MUL as u128)
+            < (reserve_in as u128) * (MIN_LONG_TERM_DEPOSIT_BPS as u128)
+        {
+            msg!(
+                "amount {} under {} bps of the reserve {}",
+                amount_in,
+                MIN_LONG_TERM_DEPOSIT_BPS,
+                reserve_in
+            );
+            return Err(AmmError::InvalidAmount.into());
+        }
+        let end_slot = clock
+            .slot
+            .checked_add(slots)
+            .and_then(|end| end.checked_add(TWAMM_INTERVAL - 1))
+            .ok_or(AmmError::CalculationError)?
+            / TWAMM_INTERVAL
+            * TWAMM_INTERVAL;
+        let sell_rate = amount_in / (end_slot - clock.slot);
+        if sell_rate == 0 {
+            msg!("amount {} sells nothing per slot", amount_in);
+            return Err(AmmError::InvalidAmount.into());
+        }
+        let (escrow_in_acc, mint_in_acc, decimals_in, proceeds_per_rate) = match direction {
+            Direction::A2B => (
+                twamm_accounts.escrow_a,
+                mint_a_acc,
+                pool.decimals_a,
+                twamm.proceeds_per_rate_a,
+            ),
+            Direction::B2A => (
+                twamm_accounts.escrow_b,
+                mint_b_acc,
+                pool.decimals_b,
+                twamm.proceeds_per_rate_b,
+            ),
+        };
+        twamm.add_order(direction, sell_rate, end_slot)?;
+        twamm.pack_into_slice(&mut twamm_accounts.twamm.try_borrow_mut_data()?);
+        // create the order, the owner pays the rent
+        Self::create_account(
+            owner_acc,
+            order_acc,
+            system_program_acc,
+            LongTermOrder::LEN,
+            rent.minimum_balance(LongTermOrder::LEN),
+            program_id,
+            &[],
+        )?;
+        Self::token_transfer(
+            token_program_acc.clone(),
+            owner_token_in_acc.clone(),
+            mint_in_acc.clone(),
+            escrow_in_acc.clone(),
+            owner_acc.clone(),
+            amount_in,
+            decimals_in,
+        )?;
+        let order = LongTermOrder {
+            status: OrderStatus::Open,
+            direction,
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            token_in: *owner_token_in_acc.key,
+            token_out: *owner_token_out_acc.key,
+            deposit: amount_in,
+            sell_rate,
+            start_slot: clock.slot,
+            end_slot,
+            proceeds_per_rate,
+        };
+        order.pack_into_slice(&mut order_acc.try_borrow_mut_data()?);
+        let long_term_orders = pool
+            .long_term_orders()
+            .checked_add(1)
+            .ok_or(AmmError::CalculationError)?;
+        pool.set_long_term_orders(long_term_orders);
+        AmmEvent::PlaceLongTermOrder {
+            pool: *pool_acc.key,
+            order: *order_acc.key,
+            owner: *owner_acc.key,
+            direction,
+            amount_in,
+            sell_rate,
+            end_slot,
+        }
+        .emit();
+        Ok(())
     }
 
-    /// Unpacks a spl_token `Mint`.
-    fn unpack_mint(account_info: &AccountInfo) -> Result<spl_token::state::Mint, AmmError> {
-        if account_info.owner != &spl_token::ID {
-            Err(AmmError::InvalidTokenProgramId)
+    /// Processes `CancelLongTermOrder` instruction.
+    fn process_cancel_long_term_order(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let accounts = CancelLongTermOrderAccounts::load(accounts)?;
+        let (mut pool, mut order, mut twamm) = accounts.validate(program_id)?;
+        let CancelLongTermOrderAccounts {
+            order: order_acc,
+            owner: owner_acc,
+            virtual_orders,
+            owner_token_in: owner_token_in_acc,
+            owner_token_out: owner_token_out_acc,
+        } = accounts;
+        let VirtualOrdersAccounts {
+            pool: pool_acc,
+            twamm: twamm_accounts,
+            pool_pda,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            ..
+        } = virtual_orders;
+        // settle up to the current slot, a locked pool stopped selling at the last run
+        Self::execute_virtual_orders(&virtual_orders, &pool, &mut twamm)?;
+        // anyone may withdraw an order the virtual orders ran to its end, which frees its
+        // expiry for new orders
+        if order.end_slot > twamm.last_slot {
+            Self::check_owner_or_wind_down(&pool, owner_acc)?;
+        }
+        let (unsold, proceeds) = twamm.remove_order(&order)?;
+        twamm.pack_into_slice(&mut twamm_accounts.twamm.try_borrow_mut_data()?);
+        let (escrow_in_acc, escrow_out_acc) = match order.direction {
+            Direction::A2B => (twamm_accounts.escrow_a, twamm_accounts.escrow_b),
+            Direction::B2A => (twamm_accounts.escrow_b, twamm_accounts.escrow_a),
+        };
+        let (mint_in_acc, decimals_in, mint_out_acc, decimals_out) = match order.direction {
+            Direction::A2B => (mint_a_acc, pool.decimals_a, mint_b_acc, pool.decimals_b),
+            Direction::B2A => (mint_b_acc, pool.decimals_b, mint_a_acc, pool.decimals_a),
+        };
+        if unsold > 0 {
+            Self::token_transfer_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                escrow_in_acc.clone(),
+                mint_in_acc.clone(),
+                owner_token_in_acc.clone(),
+                pool_pda.clone(),
+                unsold,
+                decimals_in,
+            )?;
+        }
+        if proceeds > 0 {
+            Self::token_transfer_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                escrow_out_acc.clone(),
+                mint_out_acc.clone(),
+                owner_token_out_acc.clone(),
+                pool_pda.clone(),
+                proceeds,
+                decimals_out,
+            )?;
+        }
+        // the data keeps the closed status, the rent goes back to the owner
+        order.status = OrderStatus::Closed;
+        order.pack_into_slice(&mut order_acc.try_borrow_mut_data()?);
+        **owner_acc.lamports.borrow_mut() = owner_acc
+            .lamports()
+            .checked_add(order_acc.lamports())
+            .ok_or(AmmError::CalculationError)?;
+        **order_acc.lamports.borrow_mut() = 0;
+        let long_term_orders = pool
+            .long_term_orders()
+            .checked_sub(1)
+            .ok_or(AmmError::CalculationError)?;
+        pool.set_long_term_orders(long_term_orders);
+        AmmEvent::CancelLongTermOrder {
+            pool: *pool_acc.key,
+            order: *order_acc.key,
+            owner: *owner_acc.key,
+            unsold,
+            proceeds,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Processes `ExecuteVirtualOrders` instruction.
+    fn process_execute_virtual_orders(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let accounts = VirtualOrdersAccounts::load(accounts)?;
+        let (pool, mut twamm) = accounts.validate(program_id)?;
+        Self::execute_virtual_orders(&accounts, &pool, &mut twamm)
+    }
+
+    /// Run the virtual orders of the pool up to the current slot and move what they
+    /// trade between the escrows and the vaults. A locked pool doesn't trade, its
+    /// orders catch up in one run once it opens again.
+    fn execute_virtual_orders(
+        accounts: &VirtualOrdersAccounts,
+        pool: &AmmPoolView,
+        twamm: &mut Twamm,
+    ) -> ProgramResult {
+        if pool.status()? != PoolStatus::Nomal {
+            return Ok(());
+        }
+        let VirtualOrdersAccounts {
+            pool: pool_acc,
+            twamm: twamm_accounts,
+            vault_a: vault_a_acc,
+            vault_b: vault_b_acc,
+            fee_vault: fee_vault_acc,
+            pool_pda,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+        } = *accounts;
+        let vault_a = unpack_token_account(vault_a_acc)?;
+        let vault_b = unpack_token_account(vault_b_acc)?;
+        let slot = Clock::get()?.slot;
+        // the net direction is known only once they trade, they pay the higher fee
+        let fee_rate = pool.effective_fee(slot, vault_a.amount, vault_b.amount, None)?;
+        let trade = twamm.execute(vault_a.amount, vault_b.amount, slot, fee_rate)?;
+        twamm.pack_into_slice(&mut twamm_accounts.twamm.try_borrow_mut_data()?);
+        if trade == VirtualTrade::default() {
+            return Ok(());
+        }
+        // only the net flow of each token moves, the matched part stays in the escrows
+        for (escrow_acc, vault_acc, mint_acc, decimals, amount_in, amount_out) in [
+            (
+                twamm_accounts.escrow_a,
+                vault_a_acc,
+                mint_a_acc,
+                pool.decimals_a,
+                trade.amount_a_in,
+                trade.amount_a_out,
+            ),
+            (
+                twamm_accounts.escrow_b,
+                vault_b_acc,
+                mint_b_acc,
+                pool.decimals_b,
+                trade.amount_b_in,
+                trade.amount_b_out,
+            ),
+        ] {
+            let (source_acc, destination_acc, amount) = if amount_in >= amount_out {
+                (escrow_acc, vault_acc, amount_in - amount_out)
+            } else {
+                (vault_acc, escrow_acc, amount_out - amount_in)
+            };
+            if amount > 0 {
+                Self::token_transfer_signed(
+                    pool_acc.clone(),
+                    pool.nonce,
+                    token_program_acc.clone(),
+                    source_acc.clone(),
+                    mint_acc.clone(),
+                    destination_acc.clone(),
+                    pool_pda.clone(),
+                    amount,
+                    decimals,
+                )?;
+            }
+        }
+        if trade.fee > 0 {
+            Self::token_transfer_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                twamm_accounts.escrow_b.clone(),
+                mint_b_acc.clone(),
+                fee_vault_acc.clone(),
+                pool_pda.clone(),
+                trade.fee,
+                pool.decimals_b,
+            )?;
+        }
+        // check k of the reserves the vaults hold after the run, the tolerance bounds a
+        // single trade and the run may cover any number of slots
+        let reserve_a = unpack_token_account(vault_a_acc)?.amount;
+        let reserve_b = unpack_token_account(vault_b_acc)?.amount;
+        Self::check_k(&vault_a, &vault_b, reserve_a, reserve_b)?;
+        AmmEvent::VirtualOrders {
+            pool: *pool_acc.key,
+            slot,
+            amount_a_in: trade.amount_a_in,
+            amount_a_out: trade.amount_a_out,
+            amount_b_in: trade.amount_b_in,
+            amount_b_out: trade.amount_b_out,
+            fee: trade.fee,
+            reserve_a,
+            reserve_b,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Processes `FundFarm` instruction.
+    fn process_fund_farm(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount: u64,
+        slots: u64,
+    ) -> ProgramResult {
+        let accounts = FundFarmAccounts::load(accounts)?;
+        let (mut pool, vault_a, farm) = accounts.validate(program_id)?;
+        let FundFarmAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+            farm: farm_acc,
+            reward_vault: reward_vault_acc,
+            pool_pda,
+            owner_token_reward: owner_token_reward_acc,
+            token_program: token_program_acc,
+            reward_mint: reward_mint_acc,
+            system_program: system_program_acc,
+            ..
+        } = accounts;
+        if amount == 0 || slots == 0 {
+            return Err(AmmError::InvalidAmount.into());
+        }
+        // the rewards are paid by balance, a transfer fee would leave the vault short
+        if Self::transfer_fee_config(reward_mint_acc)?.is_some() {
+            msg!("rewards of transfer fee mint {}", reward_mint_acc.key);
+            return Err(AmmError::UnsupportedMint.into());
+        }
+        let slot = Clock::get()?.slot;
+        // the first funding creates the farm, the owner holds every share of the reserves
+        let mut farm = match farm {
+            Some(farm) => farm,
+            None => {
+                let bumps = accounts.validate_new(program_id)?;
+                let rent = Rent::get()?;
+                Self::create_account(
+                    owner_acc,
+                    farm_acc,
+                    system_program_acc,
+                    Farm::LEN,
+                    rent.minimum_balance(Farm::LEN),
+                    program_id,
+                    &[FARM_SEED, pool_acc.key.as_ref(), &[bumps[0]]],
+                )?;
+                let space = Self::vault_len(reward_mint_acc)?;
+                Self::create_account(
+                    owner_acc,
+                    reward_vault_acc,
+                    system_program_acc,
+                    space,
+                    rent.minimum_balance(space),
+                    token_program_acc.key,
+                    &[FARM_REWARD_SEED, pool_acc.key.as_ref(), &[bumps[1]]],
+                )?;
+                let ix = spl_token_2022::instruction::initialize_account3(
+                    token_program_acc.key,
+                    reward_vault_acc.key,
+                    reward_mint_acc.key,
+                    pool_pda.key,
+                )?;
+                invoke(
+                    &ix,
+                    &[
+                        reward_vault_acc.clone(),
+                        reward_mint_acc.clone(),
+                        token_program_acc.clone(),
+                    ],
+                )?;
+                pool.set_farm(true);
+                Farm {
+                    pool: *pool_acc.key,
+                    reward_mint: *reward_mint_acc.key,
+                    reward_vault: *reward_vault_acc.key,
+                    total_shares: vault_a.amount,
+                    last_slot: slot,
+                    ..Farm::default()
+                }
+            }
+        };
+        farm.accrue(slot)?;
+        farm.schedule(slot, amount, slots)?;
+        let decimals = unpack_mint(reward_mint_acc)?.decimals;
+        Self::token_transfer(
+            token_program_acc.clone(),
+            owner_token_reward_acc.clone(),
+            reward_mint_acc.clone(),
+            reward_vault_acc.clone(),
+            owner_acc.clone(),
+            amount,
+            decimals,
+        )?;
+        farm.pack_into_slice(&mut farm_acc.try_borrow_mut_data()?);
+        AmmEvent::FundFarm {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            amount,
+            reward_rate: farm.reward_rate,
+            end_slot: farm.end_slot,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Processes `Stake` instruction.
+    fn process_stake(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount_a: u64,
+    ) -> ProgramResult {
+        let accounts = StakeAccounts::load(accounts)?;
+        let (mut pool, mut farm, stake, bump) = accounts.validate(program_id)?;
+        let StakeAccounts {
+            pool: pool_acc,
+            farm: farm_acc,
+            stake: stake_acc,
+            owner: owner_acc,
+            vault_a: vault_a_acc,
+            vault_b: vault_b_acc,
+            pool_pda,
+            owner_token_a: owner_token_a_acc,
+            owner_token_b: owner_token_b_acc,
+            owner_token_reward: owner_token_reward_acc,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            system_program: system_program_acc,
+            ..
+        } = accounts;
+        if amount_a == 0 {
+            return Err(AmmError::InvalidAmount.into());
+        }
+        // the shares are priced by the amounts sent, a transfer fee would dilute the farm
+        for mint_acc in [mint_a_acc, mint_b_acc] {
+            if Self::transfer_fee_config(mint_acc)?.is_some() {
+                msg!("stake of transfer fee mint {}", mint_acc.key);
+                return Err(AmmError::UnsupportedMint.into());
+            }
+        }
+        // the virtual orders trade first, the stake prices against what they leave
+        if let Some(virtual_orders) = accounts.virtual_orders(&pool)? {
+            let mut twamm =
+                virtual_orders
+                    .twamm
+                    .validate(program_id, pool_acc, pool_pda, token_program_acc)?;
+            Self::execute_virtual_orders(&virtual_orders, &pool, &mut twamm)?;
+        }
+        let reserve_a = unpack_token_account(vault_a_acc)?.amount;
+        let reserve_b = unpack_token_account(vault_b_acc)?.amount;
+        let (shares, amount_b) =
+            math::calculate_stake(reserve_a, reserve_b, farm.total_shares, amount_a)?;
+        if shares == 0 {
+            msg!("amount {} buys no share", amount_a);
+            return Err(AmmError::InvalidAmount.into());
+        }
+        let slot = Clock::get()?.slot;
+        farm.accrue(slot)?;
+        // the first stake of the owner creates its account, the owner pays the rent
+        let mut stake = match stake {
+            Some(stake) => stake,
+            None => {
+                Self::create_account(
+                    owner_acc,
+                    stake_acc,
+                    system_program_acc,
+                    Stake::LEN,
+                    Rent::get()?.minimum_balance(Stake::LEN),
+                    program_id,
+                    &[
+                        STAKE_SEED,
+                        pool_acc.key.as_ref(),
+                        owner_acc.key.as_ref(),
+                        &[bump],
+                    ],
+                )?;
+                let stakes = pool
+                    .stakes()
+                    .checked_add(1)
+                    .ok_or(AmmError::CalculationError)?;
+                pool.set_stakes(stakes);
+                Stake {
+                    pool: *pool_acc.key,
+                    owner: *owner_acc.key,
+                    token_a: *owner_token_a_acc.key,
+                    token_b: *owner_token_b_acc.key,
+                    token_reward: *owner_token_reward_acc.key,
+                    reward_per_share: farm.reward_per_share,
+                    ..Stake::default()
+                }
+            }
+        };
+        farm.settle(&mut stake)?;
+        stake.shares = stake
+            .shares
+            .checked_add(shares)
+            .ok_or(AmmError::CalculationError)?;
+        farm.total_shares = farm
+            .total_shares
+            .checked_add(shares)
+            .ok_or(AmmError::CalculationError)?;
+        farm.staked_shares = farm
+            .staked_shares
+            .checked_add(shares)
+            .ok_or(AmmError::CalculationError)?;
+        Self::token_transfer(
+            token_program_acc.clone(),
+            owner_token_a_acc.clone(),
+            mint_a_acc.clone(),
+            vault_a_acc.clone(),
+            owner_acc.clone(),
+            amount_a,
+            pool.decimals_a,
+        )?;
+        if amount_b > 0 {
+            Self::token_transfer(
+                token_program_acc.clone(),
+                owner_token_b_acc.clone(),
+                mint_b_acc.clone(),
+                vault_b_acc.clone(),
+                owner_acc.clone(),
+                amount_b,
+                pool.decimals_b,
+            )?;
+        }
+        stake.pack_into_slice(&mut stake_acc.try_borrow_mut_data()?);
+        farm.pack_into_slice(&mut farm_acc.try_borrow_mut_data()?);
+        AmmEvent::Stake {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            amount_a,
+            amount_b,
+            shares,
+            reserve_a: reserve_a + amount_a,
+            reserve_b: reserve_b + amount_b,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Processes `Unstake` instruction.
+    fn process_unstake(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        shares: u64,
+    ) -> ProgramResult {
+        let accounts = UnstakeAccounts::load(accounts)?;
+        let (mut pool, mut farm, mut stake) = accounts.validate(program_id)?;
+        let UnstakeAccounts {
+            pool: pool_acc,
+            farm: farm_acc,
+            stake: stake_acc,
+            owner: owner_acc,
+            vault_a: vault_a_acc,
+            vault_b: vault_b_acc,
+            pool_pda,
+            owner_token_a: owner_token_a_acc,
+            owner_token_b: owner_token_b_acc,
+            reward_vault: reward_vault_acc,
+            owner_token_reward: owner_token_reward_acc,
+            token_program: token_program_acc,
+            mint_a: mint_a_acc,
+            mint_b: mint_b_acc,
+            reward_mint: reward_mint_acc,
+            ..
+        } = accounts;
+        Self::check_owner_or_wind_down(&pool, owner_acc)?;
+        if shares == 0 || shares > stake.shares {
+            msg!("unstake {} of {} shares", shares, stake.shares);
+            return Err(AmmError::InvalidAmount.into());
+        }
+        if let Some(virtual_orders) = accounts.virtual_orders(&pool)? {
+            let mut twamm =
+                virtual_orders
+                    .twamm
+                    .validate(program_id, pool_acc, pool_pda, token_program_acc)?;
+            Self::execute_virtual_orders(&virtual_orders, &pool, &mut twamm)?;
+        }
+        let reserve_a = unpack_token_account(vault_a_acc)?.amount;
+        let reserve_b = unpack_token_account(vault_b_acc)?.amount;
+        let (amount_a, amount_b) =
+            math::calculate_unstake(reserve_a, reserve_b, farm.total_shares, shares)?;
+        farm.accrue(Clock::get()?.slot)?;
+        farm.settle(&mut stake)?;
+        stake.shares -= shares;
+        farm.total_shares -= shares;
+        farm.staked_shares -= shares;
+        let rewards = stake.rewards;
+        stake.rewards = 0;
+        for (vault_acc, mint_acc, owner_token_acc, amount, decimals) in [
+            (
+                vault_a_acc,
+                mint_a_acc,
+                owner_token_a_acc,
+                amount_a,
+                pool.decimals_a,
+            ),
+            (
+                vault_b_acc,
+                mint_b_acc,
+                owner_token_b_acc,
+                amount_b,
+                pool.decimals_b,
+            ),
+        ] {
+            if amount > 0 {
+                Self::token_transfer_signed(
+                    pool_acc.clone(),
+                    pool.nonce,
+                    token_program_acc.clone(),
+                    vault_acc.clone(),
+                    mint_acc.clone(),
+                    owner_token_acc.clone(),
+                    pool_pda.clone(),
+                    amount,
+                    decimals,
+                )?;
+            }
+        }
+        if rewards > 0 {
+            Self::token_transfer_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                reward_vault_acc.clone(),
+                reward_mint_acc.clone(),
+                owner_token_reward_acc.clone(),
+                pool_pda.clone(),
+                rewards,
+                unpack_mint(reward_mint_acc)?.decimals,
+            )?;
+        }
+        if stake.shares > 0 {
+            stake.pack_into_slice(&mut stake_acc.try_borrow_mut_data()?);
         } else {
-            spl_token::state::Mint::unpack(&account_info.data.borrow())
-                .map_err(|_| AmmError::ExpectedMint)
+            // the last share closes the stake, the rent goes back to the owner
+            Self::close_account(stake_acc, owner_acc)?;
+            let stakes = pool
+                .stakes()
+                .checked_sub(1)
+                .ok_or(AmmError::CalculationError)?;
+            pool.set_stakes(stakes);
+        }
+        farm.pack_into_slice(&mut farm_acc.try_borrow_mut_data()?);
+        AmmEvent::Unstake {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            amount_a,
+            amount_b,
+            shares,
+            rewards,
+            reserve_a: reserve_a - amount_a,
+            reserve_b: reserve_b - amount_b,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Processes `ClaimRewards` instruction.
+    fn process_claim_rewards(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let accounts = ClaimRewardsAccounts::load(accounts)?;
+        let (pool, mut farm, mut stake) = accounts.validate(program_id)?;
+        let ClaimRewardsAccounts {
+            pool: pool_acc,
+            farm: farm_acc,
+            stake: stake_acc,
+            owner: owner_acc,
+            reward_vault: reward_vault_acc,
+            pool_pda,
+            owner_token_reward: owner_token_reward_acc,
+            token_program: token_program_acc,
+            reward_mint: reward_mint_acc,
+        } = accounts;
+        farm.accrue(Clock::get()?.slot)?;
+        farm.settle(&mut stake)?;
+        let amount = stake.rewards;
+        if amount > 0 {
+            Self::token_transfer_signed(
+                pool_acc.clone(),
+                pool.nonce,
+                token_program_acc.clone(),
+                reward_vault_acc.clone(),
+                reward_mint_acc.clone(),
+                owner_token_reward_acc.clone(),
+                pool_pda.clone(),
+                amount,
+                unpack_mint(reward_mint_acc)?.decimals,
+            )?;
+        }
+        stake.rewards = 0;
+        stake.pack_into_slice(&mut stake_acc.try_borrow_mut_data()?);
+        farm.pack_into_slice(&mut farm_acc.try_borrow_mut_data()?);
+        AmmEvent::ClaimRewards {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            amount,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Close an account of the program, its rent goes to `destination_acc`
+    fn close_account(account_acc: &AccountInfo, destination_acc: &AccountInfo) -> ProgramResult {
+        account_acc.try_borrow_mut_data()?.fill(0);
+        **destination_acc.lamports.borrow_mut() = destination_acc
+            .lamports()
+            .checked_add(account_acc.lamports())
+            .ok_or(AmmError::CalculationError)?;
+        **account_acc.lamports.borrow_mut() = 0;
+        Ok(())
+    }
+
+    /// Mark the order closed and pay out its lamports, the reward to the keeper and the
+    /// rest to the owner. The data keeps the closed status, so lamports sent back to the
+    /// account in the same transaction can't reopen it.
+    fn close_order(
+        order_acc: &AccountInfo,
+        mut order: Order,
+        owner_acc: &AccountInfo,
+        keeper_acc: &AccountInfo,
+    ) -> ProgramResult {
+        order.status = OrderStatus::Closed;
+        order.pack_into_slice(&mut order_acc.try_borrow_mut_data()?);
+        let lamports = order_acc.lamports();
+        let reward = order.reward.min(lamports);
+        **keeper_acc.lamports.borrow_mut() = keeper_acc
+            .lamports()
+            .checked_add(reward)
+            .ok_or(AmmError::CalculationError)?;
+        **owner_acc.lamports.borrow_mut() = owner_acc
+            .lamports()
+            .checked_add(lamports - reward)
+            .ok_or(AmmError::CalculationError)?;
+        **order_acc.lamports.borrow_mut() = 0;
+        Ok(())
+    }
+
+    /// Count an order of the pool as filled or cancelled
+    fn remove_order(pool: &mut AmmPoolView) -> Result<(), AmmError> {
+        let open_orders = pool
+            .open_orders()
+            .checked_sub(1)
+            .ok_or(AmmError::CalculationError)?;
+        pool.set_open_orders(open_orders);
+        Ok(())
+    }
+
+    /// Processes `Migrate` instruction.
+    fn process_migrate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let accounts = MigrateAccounts::load(accounts)?;
+        let (mut pool, mint_a, mint_b) = accounts.validate(program_id)?;
+        let MigrateAccounts {
+            pool: pool_acc,
+            owner: owner_acc,
+            mint_a: _,
+            mint_b: _,
+            system_program: system_program_acc,
+        } = accounts;
+        // the owner pays the rent of the grown account
+        let top_up = Rent::get()?
+            .minimum_balance(AmmPool::LEN)
+            .saturating_sub(pool_acc.lamports());
+        if top_up > 0 {
+            let ix = system_instruction::transfer(owner_acc.key, pool_acc.key, top_up);
+            invoke(
+                &ix,
+                &[
+                    owner_acc.clone(),
+                    pool_acc.clone(),
+                    system_program_acc.clone(),
+                ],
+            )?;
+        }
+        // the new fields are appended, they start zero
+        pool_acc.realloc(AmmPool::LEN, true)?;
+        pool.decimals_a = mint_a.decimals;
+        pool.decimals_b = mint_b.decimals;
+        // the tolerance of the first version bounded a drop of k, not the price
+        pool.tolerance = 0;
+        // a pool locked before migrating starts its grace period now
+        if pool.status == PoolStatus::Lock {
+            pool.lock_time = Clock::get()?.unix_timestamp;
+        }
+        AmmPool::pack(pool, &mut pool_acc.data.borrow_mut())?;
+        AmmEvent::Migrate {
+            pool: *pool_acc.key,
+            owner: *owner_acc.key,
+            decimals_a: mint_a.decimals,
+            decimals_b: mint_b.decimals,
+        }
+        .emit();
+        Ok(())
+    }
+
+    /// Fail if k of the reserves dropped over a trade
+    fn check_k(
+        vault_a: &Account,
+        vault_b: &Account,
+        reserve_a: u64,
+        reserve_b: u64,
+    ) -> Result<(), AmmError> {
+        let k_before = math::calculate_k(vault_a.amount, vault_b.amount);
+        let k_after = math::calculate_k(reserve_a, reserve_b);
+        if let Err(e) = math::check_k(k_before, k_after) {
+            msg!("k dropped, k before:{}, k after:{}", k_before, k_after);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Fail if k of the reserves dropped over a trade, or the trade moved the price by
+    /// more than the pool tolerance
+    fn check_tolerance(
+        pool: &AmmPoolView,
+        vault_a: &Account,
+        vault_b: &Account,
+        reserve_a: u64,
+        reserve_b: u64,
+    ) -> Result<(), AmmError> {
+        Self::check_k(vault_a, vault_b, reserve_a, reserve_b)?;
+        if let Err(e) = math::check_price_impact(
+            vault_a.amount,
+            vault_b.amount,
+            reserve_a,
+            reserve_b,
+            pool.tolerance(),
+        ) {
+            msg!(
+                "out of tolerance, reserves before:{} {}, reserves after:{} {}, tolerance bps:{}",
+                vault_a.amount,
+                vault_b.amount,
+                reserve_a,
+                reserve_b,
+                pool.tolerance()
+            );
+            return Err(e);
         }
+        Ok(())
+    }
+
+    /// Fail if the tolerance is more than 100%
+    fn check_tolerance_input(tolerance: u64) -> Result<(), AmmError> {
+        if tolerance > math::BPS_MUL {
+            msg!("tolerance bps:{} over {}", tolerance, math::BPS_MUL);
+            return Err(AmmError::InvalidInput);
+        }
+        Ok(())
+    }
+
+    /// Slot a dynamic fee is taken at, a static fee doesn't read the clock
+    fn fee_slot(pool: &AmmPoolView) -> Result<u64, ProgramError> {
+        Ok(match pool.fee_mode()? {
+            FeeMode::Static => 0,
+            _ => Clock::get()?.slot,
+        })
     }
 
-    /// Issue a spl_token `Transfer` instruction.
+    /// Add the price move of a trade to the volatility the fee of the pool follows
+    fn record_price_move(
+        pool: &mut AmmPoolView,
+        slot: u64,
+        vault_a: &Account,
+        vault_b: &Account,
+        reserve_a: u64,
+        reserve_b: u64,
+    ) -> ProgramResult {
+        if pool.fee_mode()? == FeeMode::Volatility {
+            pool.record_price_move(
+                slot,
+                (vault_a.amount, vault_b.amount),
+                (reserve_a, reserve_b),
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the pool has been locked for the whole grace period, so nobody is surprised by
+    /// the close
+    fn check_grace_period(pool: &AmmPoolView) -> ProgramResult {
+        if pool.status()? != PoolStatus::Lock {
+            msg!("pool is not locked");
+            return Err(AmmError::GracePeriod.into());
+        }
+        let now = Clock::get()?.unix_timestamp;
+        let end = pool.lock_time().saturating_add(CLOSE_GRACE_PERIOD);
+        if now < end {
+            msg!("grace period ends at {}, now {}", end, now);
+            return Err(AmmError::GracePeriod.into());
+        }
+        Ok(())
+    }
+
+    /// Check the owner of an order or a stake signs, unless the pool is winding down.
+    ///
+    /// Once a pool has been locked for the whole grace period anyone may cancel its
+    /// orders and unstake its stakes. The funds still go to the accounts the owner
+    /// recorded, but no user can keep the pool from terminating by leaving something
+    /// open in it; the grace period gave them the time to close it themselves.
+    fn check_owner_or_wind_down(pool: &AmmPoolView, owner_acc: &AccountInfo) -> ProgramResult {
+        if owner_acc.is_signer {
+            return Ok(());
+        }
+        Self::check_grace_period(pool).map_err(|_| {
+            msg!("{} must sign", owner_acc.key);
+            AmmError::InvalidSignAccount.into()
+        })
+    }
+
+    /// Fail once the clock has passed the deadline
+    fn check_deadline(deadline: Option<Deadline>) -> ProgramResult {
+        if let Some(deadline) = deadline {
+            let clock = Clock::get()?;
+            if deadline.is_expired(&clock) {
+                msg!(
+                    "expired {:?}, slot:{}, unix timestamp:{}",
+                    deadline,
+                    clock.slot,
+                    clock.unix_timestamp
+                );
+                return Err(AmmError::Expired.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Token-2022 transfer fee config of the mint, spl_token mints skip the unpack
+    fn transfer_fee_config(
+        mint_acc: &AccountInfo,
+    ) -> Result<Option<TransferFeeConfig>, ProgramError> {
+        if *mint_acc.owner == spl_token::id() {
+            return Ok(None);
+        }
+        let data = mint_acc.data.borrow();
+        let mint = StateWithExtensions::<Mint>::unpack(&data)?;
+        Ok(mint.get_extension::<TransferFeeConfig>().ok().copied())
+    }
+
+    /// Token-2022 transfer fee withheld from `amount` sent of the mint
+    fn transfer_fee(mint_acc: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+        match Self::transfer_fee_config(mint_acc)? {
+            Some(config) => config
+                .calculate_epoch_fee(Clock::get()?.epoch, amount)
+                .ok_or_else(|| AmmError::CalculationError.into()),
+            None => Ok(0),
+        }
+    }
+
+    /// Token-2022 transfer fee to add so that `amount` arrives
+    fn inverse_transfer_fee(mint_acc: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+        match Self::transfer_fee_config(mint_acc)? {
+            Some(config) => config
+                .calculate_inverse_epoch_fee(Clock::get()?.epoch, amount)
+                .ok_or_else(|| AmmError::CalculationError.into()),
+            None => Ok(0),
+        }
+    }
+
+    /// Issue a spl_token `TransferChecked` instruction.
     fn token_transfer<'a>(
         token_program: AccountInfo<'a>,
         source: AccountInfo<'a>,
+        mint: AccountInfo<'a>,
         destination: AccountInfo<'a>,
         authority: AccountInfo<'a>,
         amount: u64,
+        decimals: u8,
     ) -> Result<(), ProgramError> {
         if amount == 0 {
             msg!("transfer amount is zero.");
             return Err(ProgramError::Custom(321));
         }
-        let ix = spl_token::instruction::transfer(
+        let ix = spl_token_2022::instruction::transfer_checked(
             token_program.key,
             source.key,
+            mint.key,
             destination.key,
             authority.key,
-            &[&authority.key],
+            &[],
             amount,
+            decimals,
         )?;
         invoke(
             &ix,
-            &[source, destination, authority, token_program], // signers,
+            &[source, mint, destination, authority, token_program], // signers,
         )
     }
 
-    /// Issue a spl_token `Transfer` instruction by pda.
+    /// Issue a spl_token `TransferChecked` instruction by pda.
+    #[allow(clippy::too_many_arguments)]
     fn token_transfer_signed<'a>(
         pool_acc: AccountInfo<'a>,
         nonce: u8,
         token_program_acc: AccountInfo<'a>,
         source_acc: AccountInfo<'a>,
+        mint_acc: AccountInfo<'a>,
         destination_acc: AccountInfo<'a>,
         pda: AccountInfo<'a>,
         amount: u64,
+        decimals: u8,
     ) -> Result<(), ProgramError> {
         if amount == 0 {
             msg!("transfer amount is zero.");
@@ -728,38 +2533,201 @@ impl Processor {
         }
         let seeds = &[pool_acc.key.as_ref(), &[nonce]];
         let signers = &[&seeds[..]];
-        let ix = spl_token::instruction::transfer(
+        let ix = spl_token_2022::instruction::transfer_checked(
             token_program_acc.key,
             source_acc.key,
+            mint_acc.key,
             destination_acc.key,
             pda.key,
-            &[&pda.key],
+            &[],
             amount,
+            decimals,
         )?;
         invoke_signed(
             &ix,
-            &[source_acc, destination_acc, pda, token_program_acc],
+            &[
+                source_acc,
+                mint_acc,
+                destination_acc,
+                pda,
+                token_program_acc,
+            ],
             signers,
         )
     }
 
+    /// Lamports of the wallet left after the rent of the temporary wsol account
+    fn native_balance(wallet_acc: &AccountInfo) -> Result<u64, ProgramError> {
+        let rent = Rent::get()?.minimum_balance(spl_token::state::Account::LEN);
+        Ok(wallet_acc.lamports().saturating_sub(rent))
+    }
+
+    /// Create the temporary wsol account of the wallet holding `amount` native sol
+    fn wrap_sol<'a>(
+        program_id: &Pubkey,
+        pool_acc: &AccountInfo<'a>,
+        wallet_acc: &AccountInfo<'a>,
+        native_accounts: &[AccountInfo<'a>],
+        token_program_acc: &AccountInfo<'a>,
+        amount: u64,
+    ) -> Result<AccountInfo<'a>, ProgramError> {
+        let native_accounts = array_ref![check_len(native_accounts, NATIVE_LEN)?, 0, NATIVE_LEN];
+        let [wsol_acc, native_mint_acc, system_program_acc] = native_accounts;
+        let (wsol, bump) = find_wsol_address(program_id, pool_acc.key, wallet_acc.key);
+        if wsol != *wsol_acc.key {
+            msg!("wsol account not match {} {}", wsol, *wsol_acc.key);
+            return Err(AmmError::InvalidPDA.into());
+        }
+        if *native_mint_acc.key != spl_token::native_mint::id() {
+            msg!("native mint not match {}", *native_mint_acc.key);
+            return Err(AmmError::InvalidMint.into());
+        }
+        check_system_program(system_program_acc)?;
+        let space = spl_token::state::Account::LEN;
+        let lamports = Rent::get()?
+            .minimum_balance(space)
+            .checked_add(amount)
+            .ok_or(AmmError::CalculationError)?;
+        Self::create_account(
+            wallet_acc,
+            wsol_acc,
+            system_program_acc,
+            space,
+            lamports,
+            token_program_acc.key,
+            &[
+                WSOL_SEED,
+                pool_acc.key.as_ref(),
+                wallet_acc.key.as_ref(),
+                &[bump],
+            ],
+        )?;
+        let ix = spl_token::instruction::initialize_account3(
+            token_program_acc.key,
+            wsol_acc.key,
+            native_mint_acc.key,
+            wallet_acc.key,
+        )?;
+        invoke(
+            &ix,
+            &[
+                wsol_acc.clone(),
+                native_mint_acc.clone(),
+                token_program_acc.clone(),
+            ],
+        )?;
+        Ok(wsol_acc.clone())
+    }
+
+    /// Create an account of the owner program, paid by the payer.
+    /// `seeds` sign for a pda, leave them empty for an account that signed the transaction.
+    fn create_account<'a>(
+        payer_acc: &AccountInfo<'a>,
+        new_acc: &AccountInfo<'a>,
+        system_program_acc: &AccountInfo<'a>,
+        space: usize,
+        lamports: u64,
+        owner: &Pubkey,
+        seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let signers = [seeds];
+        let signers: &[&[&[u8]]] = if seeds.is_empty() { &[] } else { &signers };
+        if new_acc.lamports() == 0 {
+            let ix = system_instruction::create_account(
+                payer_acc.key,
+                new_acc.key,
+                lamports,
+                space as u64,
+                owner,
+            );
+            return invoke_signed(
+                &ix,
+                &[
+                    payer_acc.clone(),
+                    new_acc.clone(),
+                    system_program_acc.clone(),
+                ],
+                signers,
+            );
+        }
+        // lamports sent to the address beforehand must not block the creation
+        let top_up = lamports.saturating_sub(new_acc.lamports());
+        if top_up > 0 {
+            let ix = system_instruction::transfer(payer_acc.key, new_acc.key, top_up);
+            invoke(
+                &ix,
+                &[
+                    payer_acc.clone(),
+                    new_acc.clone(),
+                    system_program_acc.clone(),
+                ],
+            )?;
+        }
+        let ix = system_instruction::allocate(new_acc.key, space as u64);
+        invoke_signed(&ix, &[new_acc.clone(), system_program_acc.clone()], signers)?;
+        let ix = system_instruction::assign(new_acc.key, owner);
+        invoke_signed(&ix, &[new_acc.clone(), system_program_acc.clone()], signers)
+    }
+
+    /// Size of a vault of the mint, with the extensions the mint requires of its accounts
+    fn vault_len(mint_acc: &AccountInfo) -> Result<usize, ProgramError> {
+        let data = mint_acc.data.borrow();
+        let mint = StateWithExtensions::<Mint>::unpack(&data)?;
+        let extensions =
+            ExtensionType::get_required_init_account_extensions(&mint.get_extension_types()?);
+        Ok(ExtensionType::get_account_len::<Account>(&extensions))
+    }
+
+    /// Close the temporary wsol account, its lamports go back to the wallet
+    fn unwrap_sol<'a>(
+        token_program_acc: AccountInfo<'a>,
+        wsol_acc: AccountInfo<'a>,
+        wallet_acc: AccountInfo<'a>,
+    ) -> Result<(), ProgramError> {
+        let ix = spl_token::instruction::close_account(
+            token_program_acc.key,
+            wsol_acc.key,
+            wallet_acc.key,
+            wallet_acc.key,
+            &[],
+        )?;
+        invoke(&ix, &[wsol_acc, wallet_acc, token_program_acc])
+    }
+
     /// Issue a spl_token `Close` instruction by pda.
     fn token_close_signed<'a>(
         pool_acc: AccountInfo<'a>,
         nonce: u8,
         token_program_acc: AccountInfo<'a>,
         account_acc: AccountInfo<'a>,
+        mint_acc: AccountInfo<'a>,
         destination_acc: AccountInfo<'a>,
         pda: AccountInfo<'a>,
     ) -> Result<(), ProgramError> {
         let seeds = &[pool_acc.key.as_ref(), &[nonce]];
         let signers = &[&seeds[..]];
-        let ix = spl_token::instruction::close_account(
+        // withheld transfer fees block closing, move them to the mint first
+        if Self::transfer_fee_config(&mint_acc)?.is_some() {
+            let ix = spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+                token_program_acc.key,
+                mint_acc.key,
+                &[account_acc.key],
+            )?;
+            invoke(
+                &ix,
+                &[
+                    mint_acc.clone(),
+                    account_acc.clone(),
+                    token_program_acc.clone(),
+                ],
+            )?;
+        }
+        let ix = spl_token_2022::instruction::close_account(
             token_program_acc.key,
             account_acc.key,
             destination_acc.key,
             pda.key,
-            &[&pda.key],
+            &[],
         )?;
         invoke_signed(
             &ix,
@@ -784,9 +2752,7 @@ impl PrintProgramError for AmmError {
             AmmError::InvalidSignAccount => msg!("Error: InvalidSignAccount"),
             AmmError::InvalidVault => msg!("Error: InvalidVault"),
             AmmError::InvalidMint => msg!("Error: InvalidMint"),
-            AmmError::InvalidMintDecimals => {
-                msg!("Error: Mint a decimals must not be bigger than mint b.")
-            }
+            AmmError::InvalidMintDecimals => msg!("Error: InvalidMintDecimals"),
             AmmError::InvalidStatus => msg!("Error: InvalidStatus"),
             AmmError::InsufficientFunds => msg!("Error: InsufficientFunds"),
             AmmError::InvalidInput => msg!("Error: InvalidInput"),
@@ -799,6 +2765,17 @@ impl PrintProgramError for AmmError {
             AmmError::OutOfTolerance => msg!("Error: OutOfTolerance"),
             AmmError::NoughtTransfer => msg!("Error: NoughtTransfer"),
             AmmError::InvalidPDA => msg!("Error: InvalidPDA"),
+            AmmError::Expired => msg!("Error: Expired"),
+            AmmError::UnsupportedMint => msg!("Error: UnsupportedMint"),
+            AmmError::LegacyPool => msg!("Error: LegacyPool"),
+            AmmError::GracePeriod => msg!("Error: GracePeriod"),
+            AmmError::PoolClosed => msg!("Error: PoolClosed"),
+            AmmError::OpenOrders => msg!("Error: OpenOrders"),
+            AmmError::InvalidOrder => msg!("Error: InvalidOrder"),
+            AmmError::TooManyExpiries => msg!("Error: TooManyExpiries"),
+            AmmError::SelfReferral => msg!("Error: SelfReferral"),
+            AmmError::OpenStakes => msg!("Error: OpenStakes"),
+            AmmError::InvalidStake => msg!("Error: InvalidStake"),
         }
     }
 }
@@ -815,5 +2792,3 @@ const _USDC_PUBKEY: solana_program::pubkey::Pubkey =
         198, 250, 122, 243, 190, 219, 173, 58, 61, 101, 243, 106, 171, 201, 116, 49, 177, 187, 228,
         194, 210, 246, 224, 228, 124, 166, 2, 3, 69, 47, 93, 97,
     ]);
-
-const PERCENT_MUL: u64 = u64::pow(10, 6);
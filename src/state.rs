@@ -1,11 +1,36 @@
 //! State transition types
+use crate::{
+    error::AmmError,
+    instruction::Direction,
+    math::{self, VirtualTrade, RATE_MUL},
+};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use bytemuck::{Pod, Zeroable};
 use solana_program::{
+    account_info::AccountInfo,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 };
-use std::fmt;
+use std::{
+    cell::{Ref, RefMut},
+    fmt,
+};
+
+/// seconds a pool stays locked before `Terminate` may close it, after them the pool
+/// winds down and anyone may close the orders and stakes left in it for their owners
+pub const CLOSE_GRACE_PERIOD: i64 = 24 * 60 * 60;
+
+/// slots the end of a long-term order is rounded up to, orders ending in the same
+/// interval share an expiry
+pub const TWAMM_INTERVAL: u64 = 1_000;
+
+/// expiries of long-term orders a pool tracks at once
+pub const MAX_EXPIRIES: usize = 16;
+
+/// least deposit of a long-term order in basis points of the reserve it sells into, so
+/// dust orders can't hold the expiries
+pub const MIN_LONG_TERM_DEPOSIT_BPS: u64 = 10;
 
 /// pool status
 #[repr(C)]
@@ -14,6 +39,8 @@ pub enum PoolStatus {
     NotInit,
     Nomal,
     Lock,
+    /// terminated, the account is never used again
+    Closed,
 }
 
 impl Default for PoolStatus {
@@ -28,6 +55,7 @@ impl fmt::Display for PoolStatus {
             PoolStatus::NotInit => "Not Init".to_string(),
             PoolStatus::Nomal => "Nomal".to_string(),
             PoolStatus::Lock => "Lock".to_string(),
+            PoolStatus::Closed => "Closed".to_string(),
         };
         write!(f, "{}", status)
     }
@@ -35,13 +63,16 @@ impl fmt::Display for PoolStatus {
 
 impl Eq for PoolStatus {}
 
-impl From<u8> for PoolStatus {
-    fn from(data: u8) -> PoolStatus {
+impl TryFrom<u8> for PoolStatus {
+    type Error = AmmError;
+
+    fn try_from(data: u8) -> Result<PoolStatus, AmmError> {
         match data {
-            0 => PoolStatus::NotInit,
-            1 => PoolStatus::Nomal,
-            2 => PoolStatus::Lock,
-            _ => PoolStatus::default(),
+            0 => Ok(PoolStatus::NotInit),
+            1 => Ok(PoolStatus::Nomal),
+            2 => Ok(PoolStatus::Lock),
+            3 => Ok(PoolStatus::Closed),
+            _ => Err(AmmError::InvalidStatus),
         }
     }
 }
@@ -52,6 +83,62 @@ impl Into<u8> for PoolStatus {
             PoolStatus::NotInit => 0,
             PoolStatus::Nomal => 1,
             PoolStatus::Lock => 2,
+            PoolStatus::Closed => 3,
+        }
+    }
+}
+
+/// how the swap fee of a pool follows the market, the static fee is the base rate
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeeMode {
+    Static,
+    /// the fee rises with the recent price moves of the pool
+    Volatility,
+    /// trades moving the price further from the initial one pay for how far it is
+    Imbalance,
+}
+
+// `#[default]` on a variant needs a newer compiler than the program is built with
+#[allow(clippy::derivable_impls)]
+impl Default for FeeMode {
+    fn default() -> Self {
+        Self::Static
+    }
+}
+
+impl fmt::Display for FeeMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self {
+            FeeMode::Static => "Static",
+            FeeMode::Volatility => "Volatility",
+            FeeMode::Imbalance => "Imbalance",
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+impl Eq for FeeMode {}
+
+impl TryFrom<u8> for FeeMode {
+    type Error = AmmError;
+
+    fn try_from(data: u8) -> Result<FeeMode, AmmError> {
+        match data {
+            0 => Ok(FeeMode::Static),
+            1 => Ok(FeeMode::Volatility),
+            2 => Ok(FeeMode::Imbalance),
+            _ => Err(AmmError::InvalidInput),
+        }
+    }
+}
+
+impl From<FeeMode> for u8 {
+    fn from(mode: FeeMode) -> u8 {
+        match mode {
+            FeeMode::Static => 0,
+            FeeMode::Volatility => 1,
+            FeeMode::Imbalance => 2,
         }
     }
 }
@@ -80,19 +167,61 @@ pub struct AmmPool {
     pub vault_b: Pubkey,
     // fee receiver
     pub fee_vault: Pubkey,
+    // swap mint decimals
+    pub decimals_a: u8,
+    pub decimals_b: u8,
+    // unix timestamp the pool was locked at, 0 while not locked
+    pub lock_time: i64,
+    // limit orders escrowed for the pool, terminate waits until they are gone
+    pub open_orders: u64,
+    // long-term orders not withdrawn yet, swaps run the virtual orders while there are any
+    pub long_term_orders: u64,
+    // bps of the swap fee paid to the referrer of a swap
+    pub referral_share: u64,
+    // stake accounts of the farm holding liquidity, terminate waits until they are gone
+    pub stakes: u64,
+    // the fee of a dynamic mode stays within min and max fee
+    pub fee_mode: FeeMode,
+    pub min_fee: u64,
+    pub max_fee: u64,
+    // recent price moves in fee rate units, halving every VOLATILITY_HALF_LIFE slots
+    pub volatility: u64,
+    // slot the volatility was last updated at
+    pub volatility_slot: u64,
+    // the first long-term order created the virtual orders, terminate closes them
+    pub twamm: bool,
+    // the first funding created the farm, terminate closes it
+    pub farm: bool,
+}
+
+impl AmmPool {
+    /// length of pools created by the first version of the program, `Migrate` grows
+    /// them and any later layout shorter than `LEN`
+    pub const LEGACY_LEN: usize = 2 + 8 * 4 + 32 * 6;
+
+    /// Unpack a pool of the current or an earlier layout without checking its status,
+    /// the fields an earlier layout doesn't have yet are left zero.
+    pub fn unpack_any(src: &[u8]) -> Result<Self, ProgramError> {
+        if !(Self::LEGACY_LEN..Self::LEN).contains(&src.len()) {
+            return Self::unpack_unchecked(src);
+        }
+        let mut data = [0u8; Self::LEN];
+        data[..src.len()].copy_from_slice(src);
+        Self::unpack_from_slice(&data)
+    }
 }
 
 impl Sealed for AmmPool {}
 impl IsInitialized for AmmPool {
     fn is_initialized(&self) -> bool {
-        self.status == PoolStatus::NotInit
+        self.status != PoolStatus::NotInit
     }
 }
 
 impl Pack for AmmPool {
-    const LEN: usize = 1 * 2 + 8 * 4 + 32 * 6;
+    const LEN: usize = 1 * 7 + 8 * 13 + 32 * 6;
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        const LEN: usize = 1 * 2 + 8 * 4 + 32 * 6;
+        const LEN: usize = 1 * 7 + 8 * 13 + 32 * 6;
         let src = array_ref![src, 0, LEN];
         let (
             status_buf,
@@ -107,9 +236,26 @@ impl Pack for AmmPool {
             vault_a_buf,
             vault_b_buf,
             fee_vault_buf,
-        ) = array_refs![src, 1, 1, 8, 8, 8, 8, 32, 32, 32, 32, 32, 32];
+            decimals_a_buf,
+            decimals_b_buf,
+            lock_time_buf,
+            open_orders_buf,
+            long_term_orders_buf,
+            referral_share_buf,
+            stakes_buf,
+            fee_mode_buf,
+            min_fee_buf,
+            max_fee_buf,
+            volatility_buf,
+            volatility_slot_buf,
+            twamm_buf,
+            farm_buf,
+        ) = array_refs![
+            src, 1, 1, 8, 8, 8, 8, 32, 32, 32, 32, 32, 32, 1, 1, 8, 8, 8, 8, 8, 1, 8, 8, 8, 8, 1, 1
+        ];
 
-        let status: PoolStatus = PoolStatus::from(u8::from_le_bytes(*status_buf));
+        let status = PoolStatus::try_from(u8::from_le_bytes(*status_buf))?;
+        let fee_mode = FeeMode::try_from(u8::from_le_bytes(*fee_mode_buf))?;
 
         Ok(AmmPool {
             status,
@@ -124,11 +270,25 @@ impl Pack for AmmPool {
             vault_a: Pubkey::new_from_array(*vault_a_buf),
             vault_b: Pubkey::new_from_array(*vault_b_buf),
             fee_vault: Pubkey::new_from_array(*fee_vault_buf),
+            decimals_a: u8::from_le_bytes(*decimals_a_buf),
+            decimals_b: u8::from_le_bytes(*decimals_b_buf),
+            lock_time: i64::from_le_bytes(*lock_time_buf),
+            open_orders: u64::from_le_bytes(*open_orders_buf),
+            long_term_orders: u64::from_le_bytes(*long_term_orders_buf),
+            referral_share: u64::from_le_bytes(*referral_share_buf),
+            stakes: u64::from_le_bytes(*stakes_buf),
+            fee_mode,
+            min_fee: u64::from_le_bytes(*min_fee_buf),
+            max_fee: u64::from_le_bytes(*max_fee_buf),
+            volatility: u64::from_le_bytes(*volatility_buf),
+            volatility_slot: u64::from_le_bytes(*volatility_slot_buf),
+            twamm: twamm_buf[0] != 0,
+            farm: farm_buf[0] != 0,
         })
     }
 
     fn pack_into_slice(&self, dst: &mut [u8]) {
-        const LEN: usize = 1 * 2 + 8 * 4 + 32 * 6;
+        const LEN: usize = 1 * 7 + 8 * 13 + 32 * 6;
         let dst = array_mut_ref![dst, 0, LEN];
         let (
             status_buf,
@@ -143,7 +303,23 @@ impl Pack for AmmPool {
             vault_a_buf,
             vault_b_buf,
             fee_vault_buf,
-        ) = mut_array_refs![dst, 1, 1, 8, 8, 8, 8, 32, 32, 32, 32, 32, 32];
+            decimals_a_buf,
+            decimals_b_buf,
+            lock_time_buf,
+            open_orders_buf,
+            long_term_orders_buf,
+            referral_share_buf,
+            stakes_buf,
+            fee_mode_buf,
+            min_fee_buf,
+            max_fee_buf,
+            volatility_buf,
+            volatility_slot_buf,
+            twamm_buf,
+            farm_buf,
+        ) = mut_array_refs![
+            dst, 1, 1, 8, 8, 8, 8, 32, 32, 32, 32, 32, 32, 1, 1, 8, 8, 8, 8, 8, 1, 8, 8, 8, 8, 1, 1
+        ];
         let status: u8 = self.status.into();
         *status_buf = status.to_le_bytes();
         *nonce_buf = self.nonce.to_le_bytes();
@@ -157,5 +333,1093 @@ impl Pack for AmmPool {
         vault_a_buf.copy_from_slice(self.vault_a.as_ref());
         vault_b_buf.copy_from_slice(self.vault_b.as_ref());
         fee_vault_buf.copy_from_slice(self.fee_vault.as_ref());
+        *decimals_a_buf = self.decimals_a.to_le_bytes();
+        *decimals_b_buf = self.decimals_b.to_le_bytes();
+        *lock_time_buf = self.lock_time.to_le_bytes();
+        *open_orders_buf = self.open_orders.to_le_bytes();
+        *long_term_orders_buf = self.long_term_orders.to_le_bytes();
+        *referral_share_buf = self.referral_share.to_le_bytes();
+        *stakes_buf = self.stakes.to_le_bytes();
+        let fee_mode: u8 = self.fee_mode.into();
+        *fee_mode_buf = fee_mode.to_le_bytes();
+        *min_fee_buf = self.min_fee.to_le_bytes();
+        *max_fee_buf = self.max_fee.to_le_bytes();
+        *volatility_buf = self.volatility.to_le_bytes();
+        *volatility_slot_buf = self.volatility_slot.to_le_bytes();
+        twamm_buf[0] = self.twamm.into();
+        farm_buf[0] = self.farm.into();
+    }
+}
+
+/// Zero-copy view of an `AmmPool` account, the same layout as `Pack`.
+///
+/// Integers are kept as little endian bytes, so the view has no padding and maps
+/// straight onto unaligned account data.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct AmmPoolView {
+    status: u8,
+    pub nonce: u8,
+    ka: [u8; 8],
+    kb: [u8; 8],
+    tolerance: [u8; 8],
+    fee: [u8; 8],
+    pub owner: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub vault_a: Pubkey,
+    pub vault_b: Pubkey,
+    pub fee_vault: Pubkey,
+    pub decimals_a: u8,
+    pub decimals_b: u8,
+    lock_time: [u8; 8],
+    open_orders: [u8; 8],
+    long_term_orders: [u8; 8],
+    referral_share: [u8; 8],
+    stakes: [u8; 8],
+    fee_mode: u8,
+    min_fee: [u8; 8],
+    max_fee: [u8; 8],
+    volatility: [u8; 8],
+    volatility_slot: [u8; 8],
+    twamm: u8,
+    farm: u8,
+}
+
+impl IsInitialized for AmmPoolView {
+    fn is_initialized(&self) -> bool {
+        !matches!(self.status(), Ok(PoolStatus::NotInit))
+    }
+}
+
+impl AmmPoolView {
+    /// Borrow an initialized pool of the account data, a closed pool is rejected.
+    pub fn load<'a>(account_info: &'a AccountInfo) -> Result<Ref<'a, Self>, ProgramError> {
+        let pool = Self::load_unchecked(account_info)?;
+        pool.check_open()?;
+        Ok(pool)
+    }
+
+    /// Mutably borrow an initialized pool of the account data, a closed pool is rejected.
+    pub fn load_mut<'a>(account_info: &'a AccountInfo) -> Result<RefMut<'a, Self>, ProgramError> {
+        let pool = Self::load_mut_unchecked(account_info)?;
+        pool.check_open()?;
+        Ok(pool)
+    }
+
+    fn check_open(&self) -> Result<(), ProgramError> {
+        match self.status()? {
+            PoolStatus::NotInit => Err(ProgramError::UninitializedAccount),
+            PoolStatus::Closed => Err(AmmError::PoolClosed.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Borrow the pool of the account data, initialized or not.
+    pub fn load_unchecked<'a>(
+        account_info: &'a AccountInfo,
+    ) -> Result<Ref<'a, Self>, ProgramError> {
+        let data = account_info.try_borrow_data()?;
+        Self::check_len(data.len())?;
+        let pool = Ref::map(data, |data| bytemuck::from_bytes::<Self>(data));
+        // a status byte no version of the program writes is corrupt, not uninitialized
+        pool.status()?;
+        Ok(pool)
+    }
+
+    /// Mutably borrow the pool of the account data, initialized or not.
+    pub fn load_mut_unchecked<'a>(
+        account_info: &'a AccountInfo,
+    ) -> Result<RefMut<'a, Self>, ProgramError> {
+        let data = account_info.try_borrow_mut_data()?;
+        Self::check_len(data.len())?;
+        let pool = RefMut::map(data, |data| bytemuck::from_bytes_mut::<Self>(data));
+        pool.status()?;
+        Ok(pool)
+    }
+
+    /// a pool of an earlier layout must be migrated before the view maps onto it
+    fn check_len(len: usize) -> Result<(), ProgramError> {
+        if len == AmmPool::LEN {
+            Ok(())
+        } else if (AmmPool::LEGACY_LEN..AmmPool::LEN).contains(&len) {
+            Err(AmmError::LegacyPool.into())
+        } else {
+            Err(ProgramError::InvalidAccountData)
+        }
+    }
+
+    pub fn status(&self) -> Result<PoolStatus, AmmError> {
+        PoolStatus::try_from(self.status)
+    }
+
+    pub fn set_status(&mut self, status: PoolStatus) {
+        self.status = status.into();
+    }
+
+    pub fn ka(&self) -> u64 {
+        u64::from_le_bytes(self.ka)
+    }
+
+    pub fn set_ka(&mut self, ka: u64) {
+        self.ka = ka.to_le_bytes();
+    }
+
+    pub fn kb(&self) -> u64 {
+        u64::from_le_bytes(self.kb)
+    }
+
+    pub fn set_kb(&mut self, kb: u64) {
+        self.kb = kb.to_le_bytes();
+    }
+
+    pub fn tolerance(&self) -> u64 {
+        u64::from_le_bytes(self.tolerance)
+    }
+
+    pub fn set_tolerance(&mut self, tolerance: u64) {
+        self.tolerance = tolerance.to_le_bytes();
+    }
+
+    pub fn fee(&self) -> u64 {
+        u64::from_le_bytes(self.fee)
+    }
+
+    pub fn set_fee(&mut self, fee: u64) {
+        self.fee = fee.to_le_bytes();
+    }
+
+    pub fn lock_time(&self) -> i64 {
+        i64::from_le_bytes(self.lock_time)
+    }
+
+    pub fn set_lock_time(&mut self, lock_time: i64) {
+        self.lock_time = lock_time.to_le_bytes();
+    }
+
+    pub fn open_orders(&self) -> u64 {
+        u64::from_le_bytes(self.open_orders)
+    }
+
+    pub fn set_open_orders(&mut self, open_orders: u64) {
+        self.open_orders = open_orders.to_le_bytes();
+    }
+
+    pub fn long_term_orders(&self) -> u64 {
+        u64::from_le_bytes(self.long_term_orders)
+    }
+
+    pub fn set_long_term_orders(&mut self, long_term_orders: u64) {
+        self.long_term_orders = long_term_orders.to_le_bytes();
+    }
+
+    pub fn referral_share(&self) -> u64 {
+        u64::from_le_bytes(self.referral_share)
+    }
+
+    pub fn set_referral_share(&mut self, referral_share: u64) {
+        self.referral_share = referral_share.to_le_bytes();
+    }
+
+    pub fn stakes(&self) -> u64 {
+        u64::from_le_bytes(self.stakes)
+    }
+
+    pub fn set_stakes(&mut self, stakes: u64) {
+        self.stakes = stakes.to_le_bytes();
+    }
+
+    pub fn fee_mode(&self) -> Result<FeeMode, AmmError> {
+        FeeMode::try_from(self.fee_mode)
+    }
+
+    pub fn set_fee_mode(&mut self, fee_mode: FeeMode) {
+        self.fee_mode = fee_mode.into();
+    }
+
+    pub fn min_fee(&self) -> u64 {
+        u64::from_le_bytes(self.min_fee)
+    }
+
+    pub fn set_min_fee(&mut self, min_fee: u64) {
+        self.min_fee = min_fee.to_le_bytes();
+    }
+
+    pub fn max_fee(&self) -> u64 {
+        u64::from_le_bytes(self.max_fee)
+    }
+
+    pub fn set_max_fee(&mut self, max_fee: u64) {
+        self.max_fee = max_fee.to_le_bytes();
+    }
+
+    pub fn volatility(&self) -> u64 {
+        u64::from_le_bytes(self.volatility)
+    }
+
+    pub fn set_volatility(&mut self, volatility: u64) {
+        self.volatility = volatility.to_le_bytes();
+    }
+
+    pub fn volatility_slot(&self) -> u64 {
+        u64::from_le_bytes(self.volatility_slot)
+    }
+
+    pub fn set_volatility_slot(&mut self, volatility_slot: u64) {
+        self.volatility_slot = volatility_slot.to_le_bytes();
+    }
+
+    pub fn twamm(&self) -> bool {
+        self.twamm != 0
+    }
+
+    pub fn set_twamm(&mut self, twamm: bool) {
+        self.twamm = twamm.into();
+    }
+
+    pub fn farm(&self) -> bool {
+        self.farm != 0
+    }
+
+    pub fn set_farm(&mut self, farm: bool) {
+        self.farm = farm.into();
+    }
+
+    /// Fee rate a trade in `direction` pays at `slot` against the reserves. Without a
+    /// direction it is the higher rate of the two, as the virtual orders pay.
+    ///
+    /// Everything comes from the pool account and the vault balances, so clients quote
+    /// the same fee the program charges.
+    pub fn effective_fee(
+        &self,
+        slot: u64,
+        reserve_a: u64,
+        reserve_b: u64,
+        direction: Option<Direction>,
+    ) -> Result<u64, AmmError> {
+        let surcharge = match self.fee_mode()? {
+            FeeMode::Static => return Ok(self.fee()),
+            FeeMode::Volatility => math::decay_volatility(
+                self.volatility(),
+                slot.saturating_sub(self.volatility_slot()),
+            ),
+            FeeMode::Imbalance => {
+                // ka and kb are the reserves the pool was created with
+                let price = reserve_b as u128 * self.ka() as u128;
+                let initial = self.kb() as u128 * reserve_a as u128;
+                let away = match direction {
+                    Some(Direction::A2B) => price <= initial,
+                    Some(Direction::B2A) => price >= initial,
+                    None => true,
+                };
+                if away {
+                    math::calculate_price_move(self.ka(), self.kb(), reserve_a, reserve_b)
+                } else {
+                    0
+                }
+            }
+        };
+        Ok(math::calculate_dynamic_fee(
+            self.fee(),
+            surcharge,
+            self.min_fee(),
+            self.max_fee(),
+        ))
+    }
+
+    /// Add the price move of a trade to the volatility decayed to `slot`, at most
+    /// PERCENT_MUL
+    pub fn record_price_move(
+        &mut self,
+        slot: u64,
+        reserves_before: (u64, u64),
+        reserves_after: (u64, u64),
+    ) {
+        let volatility = math::decay_volatility(
+            self.volatility(),
+            slot.saturating_sub(self.volatility_slot()),
+        );
+        let price_move = math::calculate_price_move(
+            reserves_before.0,
+            reserves_before.1,
+            reserves_after.0,
+            reserves_after.1,
+        );
+        self.set_volatility(volatility.saturating_add(price_move).min(math::PERCENT_MUL));
+        self.set_volatility_slot(slot);
+    }
+}
+
+/// order status
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrderStatus {
+    NotInit,
+    /// escrowed, waiting for the pool price to cross
+    Open,
+    /// filled or cancelled
+    Closed,
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            OrderStatus::NotInit => "Not Init",
+            OrderStatus::Open => "Open",
+            OrderStatus::Closed => "Closed",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+impl Eq for OrderStatus {}
+
+impl TryFrom<u8> for OrderStatus {
+    type Error = AmmError;
+
+    fn try_from(data: u8) -> Result<OrderStatus, AmmError> {
+        match data {
+            0 => Ok(OrderStatus::NotInit),
+            1 => Ok(OrderStatus::Open),
+            2 => Ok(OrderStatus::Closed),
+            _ => Err(AmmError::InvalidStatus),
+        }
+    }
+}
+
+impl From<OrderStatus> for u8 {
+    fn from(status: OrderStatus) -> u8 {
+        match status {
+            OrderStatus::NotInit => 0,
+            OrderStatus::Open => 1,
+            OrderStatus::Closed => 2,
+        }
+    }
+}
+
+/// limit order resting against a pool
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Order {
+    // order status
+    pub status: OrderStatus,
+    // A2B sells token a for token b, B2A sells token b for token a
+    pub direction: Direction,
+    pub pool: Pubkey,
+    // wallet that placed the order, gets the rent back
+    pub owner: Pubkey,
+    // token account of the pool pda holding the sold token
+    pub escrow: Pubkey,
+    // token account the sold token came from, refunded on cancel
+    pub token_in: Pubkey,
+    // token account the bought token is paid to on fill
+    pub token_out: Pubkey,
+    // amount the escrow received
+    pub amount_in: u64,
+    // the order fills once the pool pays at least this, after the pool fee
+    pub min_amount_out: u64,
+    // lamports paid to the keeper that fills the order
+    pub reward: u64,
+}
+
+impl Sealed for Order {}
+impl IsInitialized for Order {
+    fn is_initialized(&self) -> bool {
+        self.status != OrderStatus::NotInit
+    }
+}
+
+impl Pack for Order {
+    const LEN: usize = 2 + 32 * 5 + 8 * 3;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        const LEN: usize = 2 + 32 * 5 + 8 * 3;
+        let src = array_ref![src, 0, LEN];
+        let (
+            status_buf,
+            direction_buf,
+            pool_buf,
+            owner_buf,
+            escrow_buf,
+            token_in_buf,
+            token_out_buf,
+            amount_in_buf,
+            min_amount_out_buf,
+            reward_buf,
+        ) = array_refs![src, 1, 1, 32, 32, 32, 32, 32, 8, 8, 8];
+        Ok(Order {
+            status: OrderStatus::try_from(u8::from_le_bytes(*status_buf))?,
+            direction: Direction::try_from(u8::from_le_bytes(*direction_buf))?,
+            pool: Pubkey::new_from_array(*pool_buf),
+            owner: Pubkey::new_from_array(*owner_buf),
+            escrow: Pubkey::new_from_array(*escrow_buf),
+            token_in: Pubkey::new_from_array(*token_in_buf),
+            token_out: Pubkey::new_from_array(*token_out_buf),
+            amount_in: u64::from_le_bytes(*amount_in_buf),
+            min_amount_out: u64::from_le_bytes(*min_amount_out_buf),
+            reward: u64::from_le_bytes(*reward_buf),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        const LEN: usize = 2 + 32 * 5 + 8 * 3;
+        let dst = array_mut_ref![dst, 0, LEN];
+        let (
+            status_buf,
+            direction_buf,
+            pool_buf,
+            owner_buf,
+            escrow_buf,
+            token_in_buf,
+            token_out_buf,
+            amount_in_buf,
+            min_amount_out_buf,
+            reward_buf,
+        ) = mut_array_refs![dst, 1, 1, 32, 32, 32, 32, 32, 8, 8, 8];
+        *status_buf = [self.status.into()];
+        *direction_buf = [self.direction.into()];
+        pool_buf.copy_from_slice(self.pool.as_ref());
+        owner_buf.copy_from_slice(self.owner.as_ref());
+        escrow_buf.copy_from_slice(self.escrow.as_ref());
+        token_in_buf.copy_from_slice(self.token_in.as_ref());
+        token_out_buf.copy_from_slice(self.token_out.as_ref());
+        *amount_in_buf = self.amount_in.to_le_bytes();
+        *min_amount_out_buf = self.min_amount_out.to_le_bytes();
+        *reward_buf = self.reward.to_le_bytes();
+    }
+}
+
+/// long-term order selling its deposit evenly over a range of slots
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LongTermOrder {
+    // order status
+    pub status: OrderStatus,
+    // A2B sells token a for token b, B2A sells token b for token a
+    pub direction: Direction,
+    pub pool: Pubkey,
+    // wallet that placed the order, gets the rent back
+    pub owner: Pubkey,
+    // token account the unsold remainder is refunded to
+    pub token_in: Pubkey,
+    // token account the proceeds are paid to
+    pub token_out: Pubkey,
+    // amount the escrow received
+    pub deposit: u64,
+    // amount sold per slot, what the rate leaves of the deposit is refunded
+    pub sell_rate: u64,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    // proceeds per unit of sell rate of the pool when the order started
+    pub proceeds_per_rate: u128,
+}
+
+impl Sealed for LongTermOrder {}
+impl IsInitialized for LongTermOrder {
+    fn is_initialized(&self) -> bool {
+        self.status != OrderStatus::NotInit
+    }
+}
+
+impl Pack for LongTermOrder {
+    const LEN: usize = 2 + 32 * 4 + 8 * 4 + 16;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        const LEN: usize = 2 + 32 * 4 + 8 * 4 + 16;
+        let src = array_ref![src, 0, LEN];
+        let (
+            status_buf,
+            direction_buf,
+            pool_buf,
+            owner_buf,
+            token_in_buf,
+            token_out_buf,
+            deposit_buf,
+            sell_rate_buf,
+            start_slot_buf,
+            end_slot_buf,
+            proceeds_per_rate_buf,
+        ) = array_refs![src, 1, 1, 32, 32, 32, 32, 8, 8, 8, 8, 16];
+        Ok(LongTermOrder {
+            status: OrderStatus::try_from(u8::from_le_bytes(*status_buf))?,
+            direction: Direction::try_from(u8::from_le_bytes(*direction_buf))?,
+            pool: Pubkey::new_from_array(*pool_buf),
+            owner: Pubkey::new_from_array(*owner_buf),
+            token_in: Pubkey::new_from_array(*token_in_buf),
+            token_out: Pubkey::new_from_array(*token_out_buf),
+            deposit: u64::from_le_bytes(*deposit_buf),
+            sell_rate: u64::from_le_bytes(*sell_rate_buf),
+            start_slot: u64::from_le_bytes(*start_slot_buf),
+            end_slot: u64::from_le_bytes(*end_slot_buf),
+            proceeds_per_rate: u128::from_le_bytes(*proceeds_per_rate_buf),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        const LEN: usize = 2 + 32 * 4 + 8 * 4 + 16;
+        let dst = array_mut_ref![dst, 0, LEN];
+        let (
+            status_buf,
+            direction_buf,
+            pool_buf,
+            owner_buf,
+            token_in_buf,
+            token_out_buf,
+            deposit_buf,
+            sell_rate_buf,
+            start_slot_buf,
+            end_slot_buf,
+            proceeds_per_rate_buf,
+        ) = mut_array_refs![dst, 1, 1, 32, 32, 32, 32, 8, 8, 8, 8, 16];
+        *status_buf = [self.status.into()];
+        *direction_buf = [self.direction.into()];
+        pool_buf.copy_from_slice(self.pool.as_ref());
+        owner_buf.copy_from_slice(self.owner.as_ref());
+        token_in_buf.copy_from_slice(self.token_in.as_ref());
+        token_out_buf.copy_from_slice(self.token_out.as_ref());
+        *deposit_buf = self.deposit.to_le_bytes();
+        *sell_rate_buf = self.sell_rate.to_le_bytes();
+        *start_slot_buf = self.start_slot.to_le_bytes();
+        *end_slot_buf = self.end_slot.to_le_bytes();
+        *proceeds_per_rate_buf = self.proceeds_per_rate.to_le_bytes();
+    }
+}
+
+/// long-term orders of a pool ending at the same slot
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Expiry {
+    // end slot, 0 while the entry is free
+    pub slot: u64,
+    // sell rates that stop at the slot
+    pub sell_rate_a: u64,
+    pub sell_rate_b: u64,
+    // proceeds per unit of sell rate at the slot, set once the virtual orders pass it
+    pub proceeds_per_rate_a: u128,
+    pub proceeds_per_rate_b: u128,
+    // orders ending at the slot not withdrawn yet, the entry is freed at 0
+    pub orders: u64,
+}
+
+impl Sealed for Expiry {}
+
+impl Pack for Expiry {
+    const LEN: usize = 8 * 4 + 16 * 2;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        const LEN: usize = 8 * 4 + 16 * 2;
+        let src = array_ref![src, 0, LEN];
+        let (
+            slot_buf,
+            sell_rate_a_buf,
+            sell_rate_b_buf,
+            proceeds_per_rate_a_buf,
+            proceeds_per_rate_b_buf,
+            orders_buf,
+        ) = array_refs![src, 8, 8, 8, 16, 16, 8];
+        Ok(Expiry {
+            slot: u64::from_le_bytes(*slot_buf),
+            sell_rate_a: u64::from_le_bytes(*sell_rate_a_buf),
+            sell_rate_b: u64::from_le_bytes(*sell_rate_b_buf),
+            proceeds_per_rate_a: u128::from_le_bytes(*proceeds_per_rate_a_buf),
+            proceeds_per_rate_b: u128::from_le_bytes(*proceeds_per_rate_b_buf),
+            orders: u64::from_le_bytes(*orders_buf),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        const LEN: usize = 8 * 4 + 16 * 2;
+        let dst = array_mut_ref![dst, 0, LEN];
+        let (
+            slot_buf,
+            sell_rate_a_buf,
+            sell_rate_b_buf,
+            proceeds_per_rate_a_buf,
+            proceeds_per_rate_b_buf,
+            orders_buf,
+        ) = mut_array_refs![dst, 8, 8, 8, 16, 16, 8];
+        *slot_buf = self.slot.to_le_bytes();
+        *sell_rate_a_buf = self.sell_rate_a.to_le_bytes();
+        *sell_rate_b_buf = self.sell_rate_b.to_le_bytes();
+        *proceeds_per_rate_a_buf = self.proceeds_per_rate_a.to_le_bytes();
+        *proceeds_per_rate_b_buf = self.proceeds_per_rate_b.to_le_bytes();
+        *orders_buf = self.orders.to_le_bytes();
+    }
+}
+
+/// virtual orders of a pool, the long-term orders of each side summed into one sell rate
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Twamm {
+    pub pool: Pubkey,
+    // token accounts of the pool pda holding the deposits and proceeds of each side
+    pub escrow_a: Pubkey,
+    pub escrow_b: Pubkey,
+    // wallet that paid the rent of the virtual orders and escrows, terminate refunds it
+    pub payer: Pubkey,
+    // virtual orders ran up to this slot
+    pub last_slot: u64,
+    // token sold per slot by all orders of each side
+    pub sell_rate_a: u64,
+    pub sell_rate_b: u64,
+    // proceeds earned per unit of sell rate of each side since the start, times RATE_MUL
+    pub proceeds_per_rate_a: u128,
+    pub proceeds_per_rate_b: u128,
+    pub expiries: [Expiry; MAX_EXPIRIES],
+}
+
+impl Sealed for Twamm {}
+impl IsInitialized for Twamm {
+    fn is_initialized(&self) -> bool {
+        self.pool != Pubkey::default()
+    }
+}
+
+impl Pack for Twamm {
+    const LEN: usize = 32 * 4 + 8 * 3 + 16 * 2 + Expiry::LEN * MAX_EXPIRIES;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        const HEAD_LEN: usize = 32 * 4 + 8 * 3 + 16 * 2;
+        let src = array_ref![src, 0, Twamm::LEN];
+        let (head, expiries_buf) = array_refs![src, HEAD_LEN, Expiry::LEN * MAX_EXPIRIES];
+        let (
+            pool_buf,
+            escrow_a_buf,
+            escrow_b_buf,
+            payer_buf,
+            last_slot_buf,
+            sell_rate_a_buf,
+            sell_rate_b_buf,
+            proceeds_per_rate_a_buf,
+            proceeds_per_rate_b_buf,
+        ) = array_refs![head, 32, 32, 32, 32, 8, 8, 8, 16, 16];
+        let mut expiries = [Expiry::default(); MAX_EXPIRIES];
+        for (expiry, buf) in expiries.iter_mut().zip(expiries_buf.chunks(Expiry::LEN)) {
+            *expiry = Expiry::unpack_from_slice(buf)?;
+        }
+        Ok(Twamm {
+            pool: Pubkey::new_from_array(*pool_buf),
+            escrow_a: Pubkey::new_from_array(*escrow_a_buf),
+            escrow_b: Pubkey::new_from_array(*escrow_b_buf),
+            payer: Pubkey::new_from_array(*payer_buf),
+            last_slot: u64::from_le_bytes(*last_slot_buf),
+            sell_rate_a: u64::from_le_bytes(*sell_rate_a_buf),
+            sell_rate_b: u64::from_le_bytes(*sell_rate_b_buf),
+            proceeds_per_rate_a: u128::from_le_bytes(*proceeds_per_rate_a_buf),
+            proceeds_per_rate_b: u128::from_le_bytes(*proceeds_per_rate_b_buf),
+            expiries,
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        const HEAD_LEN: usize = 32 * 4 + 8 * 3 + 16 * 2;
+        let dst = array_mut_ref![dst, 0, Twamm::LEN];
+        let (head, expiries_buf) = mut_array_refs![dst, HEAD_LEN, Expiry::LEN * MAX_EXPIRIES];
+        let (
+            pool_buf,
+            escrow_a_buf,
+            escrow_b_buf,
+            payer_buf,
+            last_slot_buf,
+            sell_rate_a_buf,
+            sell_rate_b_buf,
+            proceeds_per_rate_a_buf,
+            proceeds_per_rate_b_buf,
+        ) = mut_array_refs![head, 32, 32, 32, 32, 8, 8, 8, 16, 16];
+        pool_buf.copy_from_slice(self.pool.as_ref());
+        escrow_a_buf.copy_from_slice(self.escrow_a.as_ref());
+        escrow_b_buf.copy_from_slice(self.escrow_b.as_ref());
+        payer_buf.copy_from_slice(self.payer.as_ref());
+        *last_slot_buf = self.last_slot.to_le_bytes();
+        *sell_rate_a_buf = self.sell_rate_a.to_le_bytes();
+        *sell_rate_b_buf = self.sell_rate_b.to_le_bytes();
+        *proceeds_per_rate_a_buf = self.proceeds_per_rate_a.to_le_bytes();
+        *proceeds_per_rate_b_buf = self.proceeds_per_rate_b.to_le_bytes();
+        for (expiry, buf) in self
+            .expiries
+            .iter()
+            .zip(expiries_buf.chunks_mut(Expiry::LEN))
+        {
+            expiry.pack_into_slice(buf);
+        }
+    }
+}
+
+impl Twamm {
+    /// Run the virtual orders from the last slot to `slot` against the reserves, the
+    /// sell rates drop at every expiry passed. Returns the trade of the whole range.
+    pub fn execute(
+        &mut self,
+        reserve_a: u64,
+        reserve_b: u64,
+        slot: u64,
+        fee_rate: u64,
+    ) -> Result<VirtualTrade, AmmError> {
+        let (mut reserve_a, mut reserve_b) = (reserve_a, reserve_b);
+        let mut total = VirtualTrade::default();
+        while self.last_slot < slot {
+            // run up to the next expiry, each stretch sells at a constant rate
+            let next = self
+                .expiries
+                .iter()
+                .map(|expiry| expiry.slot)
+                .filter(|end| *end > self.last_slot)
+                .fold(slot, u64::min);
+            let slots = next - self.last_slot;
+            let sold_a = self
+                .sell_rate_a
+                .checked_mul(slots)
+                .ok_or(AmmError::CalculationError)?;
+            let sold_b = self
+                .sell_rate_b
+                .checked_mul(slots)
+                .ok_or(AmmError::CalculationError)?;
+            if sold_a > 0 || sold_b > 0 {
+                let trade =
+                    math::calculate_virtual_trade(reserve_a, reserve_b, sold_a, sold_b, fee_rate)?;
+                reserve_a = reserve_a
+                    .checked_add(trade.amount_a_in)
+                    .and_then(|reserve| reserve.checked_sub(trade.amount_a_out))
+                    .ok_or(AmmError::CalculationError)?;
+                reserve_b = reserve_b
+                    .checked_add(trade.amount_b_in)
+                    .and_then(|reserve| reserve.checked_sub(trade.amount_b_out))
+                    .ok_or(AmmError::CalculationError)?;
+                // the sellers of a earn token b, the sellers of b earn token a
+                if self.sell_rate_a > 0 {
+                    self.proceeds_per_rate_a = self
+                        .proceeds_per_rate_a
+                        .checked_add(trade.proceeds_b as u128 * RATE_MUL / self.sell_rate_a as u128)
+                        .ok_or(AmmError::CalculationError)?;
+                }
+                if self.sell_rate_b > 0 {
+                    self.proceeds_per_rate_b = self
+                        .proceeds_per_rate_b
+                        .checked_add(trade.proceeds_a as u128 * RATE_MUL / self.sell_rate_b as u128)
+                        .ok_or(AmmError::CalculationError)?;
+                }
+                total.add(&trade)?;
+            }
+            self.last_slot = next;
+            for expiry in self
+                .expiries
+                .iter_mut()
+                .filter(|expiry| expiry.slot == next)
+            {
+                self.sell_rate_a = self
+                    .sell_rate_a
+                    .checked_sub(expiry.sell_rate_a)
+                    .ok_or(AmmError::CalculationError)?;
+                self.sell_rate_b = self
+                    .sell_rate_b
+                    .checked_sub(expiry.sell_rate_b)
+                    .ok_or(AmmError::CalculationError)?;
+                expiry.proceeds_per_rate_a = self.proceeds_per_rate_a;
+                expiry.proceeds_per_rate_b = self.proceeds_per_rate_b;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Add an order selling `sell_rate` per slot until `end_slot`
+    pub fn add_order(
+        &mut self,
+        direction: Direction,
+        sell_rate: u64,
+        end_slot: u64,
+    ) -> Result<(), AmmError> {
+        let index = match self.expiries.iter().position(|e| e.slot == end_slot) {
+            Some(index) => index,
+            None => {
+                let index = self
+                    .expiries
+                    .iter()
+                    .position(|e| e.slot == 0)
+                    .ok_or(AmmError::TooManyExpiries)?;
+                self.expiries[index] = Expiry {
+                    slot: end_slot,
+                    ..Expiry::default()
+                };
+                index
+            }
+        };
+        let expiry = &mut self.expiries[index];
+        let (total, ending) = match direction {
+            Direction::A2B => (&mut self.sell_rate_a, &mut expiry.sell_rate_a),
+            Direction::B2A => (&mut self.sell_rate_b, &mut expiry.sell_rate_b),
+        };
+        *total = total
+            .checked_add(sell_rate)
+            .ok_or(AmmError::CalculationError)?;
+        *ending = ending
+            .checked_add(sell_rate)
+            .ok_or(AmmError::CalculationError)?;
+        expiry.orders = expiry
+            .orders
+            .checked_add(1)
+            .ok_or(AmmError::CalculationError)?;
+        Ok(())
+    }
+
+    /// Take an order out of the virtual orders as of the last slot they ran to.
+    /// Returns the unsold remainder of the deposit and the proceeds.
+    pub fn remove_order(&mut self, order: &LongTermOrder) -> Result<(u64, u64), AmmError> {
+        let index = self
+            .expiries
+            .iter()
+            .position(|e| e.slot == order.end_slot)
+            .ok_or(AmmError::InvalidOrder)?;
+        let expiry = &mut self.expiries[index];
+        let (total, ending, proceeds_now, proceeds_at_end) = match order.direction {
+            Direction::A2B => (
+                &mut self.sell_rate_a,
+                &mut expiry.sell_rate_a,
+                self.proceeds_per_rate_a,
+                expiry.proceeds_per_rate_a,
+            ),
+            Direction::B2A => (
+                &mut self.sell_rate_b,
+                &mut expiry.sell_rate_b,
+                self.proceeds_per_rate_b,
+                expiry.proceeds_per_rate_b,
+            ),
+        };
+        let (sold_until, proceeds_end) = if order.end_slot > self.last_slot {
+            // still selling, stop it here
+            *total = total
+                .checked_sub(order.sell_rate)
+                .ok_or(AmmError::CalculationError)?;
+            *ending = ending
+                .checked_sub(order.sell_rate)
+                .ok_or(AmmError::CalculationError)?;
+            (self.last_slot.max(order.start_slot), proceeds_now)
+        } else {
+            (order.end_slot, proceeds_at_end)
+        };
+        let unsold = order
+            .sell_rate
+            .checked_mul(sold_until - order.start_slot)
+            .and_then(|sold| order.deposit.checked_sub(sold))
+            .ok_or(AmmError::CalculationError)?;
+        let proceeds =
+            math::calculate_proceeds(order.sell_rate, order.proceeds_per_rate, proceeds_end)?;
+        expiry.orders = expiry.orders.saturating_sub(1);
+        if expiry.orders == 0 {
+            *expiry = Expiry::default();
+        }
+        Ok((unsold, proceeds))
+    }
+}
+
+/// liquidity mining of a pool, rewards in a third token paid out per slot to the stakes
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Farm {
+    pub pool: Pubkey,
+    pub reward_mint: Pubkey,
+    // token account of the pool pda holding the rewards
+    pub reward_vault: Pubkey,
+    // shares of the pool reserves, the owner holds what the stakes don't and earns no
+    // rewards for them
+    pub total_shares: u64,
+    // shares of all stakes, only they earn rewards
+    pub staked_shares: u64,
+    // rewards paid out per slot until the end slot
+    pub reward_rate: u64,
+    pub end_slot: u64,
+    // rewards accrued up to this slot
+    pub last_slot: u64,
+    // rewards earned per staked share since the start, times RATE_MUL
+    pub reward_per_share: u128,
+}
+
+impl Sealed for Farm {}
+impl IsInitialized for Farm {
+    fn is_initialized(&self) -> bool {
+        self.pool != Pubkey::default()
+    }
+}
+
+impl Pack for Farm {
+    const LEN: usize = 32 * 3 + 8 * 5 + 16;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        const LEN: usize = 32 * 3 + 8 * 5 + 16;
+        let src = array_ref![src, 0, LEN];
+        let (
+            pool_buf,
+            reward_mint_buf,
+            reward_vault_buf,
+            total_shares_buf,
+            staked_shares_buf,
+            reward_rate_buf,
+            end_slot_buf,
+            last_slot_buf,
+            reward_per_share_buf,
+        ) = array_refs![src, 32, 32, 32, 8, 8, 8, 8, 8, 16];
+        Ok(Farm {
+            pool: Pubkey::new_from_array(*pool_buf),
+            reward_mint: Pubkey::new_from_array(*reward_mint_buf),
+            reward_vault: Pubkey::new_from_array(*reward_vault_buf),
+            total_shares: u64::from_le_bytes(*total_shares_buf),
+            staked_shares: u64::from_le_bytes(*staked_shares_buf),
+            reward_rate: u64::from_le_bytes(*reward_rate_buf),
+            end_slot: u64::from_le_bytes(*end_slot_buf),
+            last_slot: u64::from_le_bytes(*last_slot_buf),
+            reward_per_share: u128::from_le_bytes(*reward_per_share_buf),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        const LEN: usize = 32 * 3 + 8 * 5 + 16;
+        let dst = array_mut_ref![dst, 0, LEN];
+        let (
+            pool_buf,
+            reward_mint_buf,
+            reward_vault_buf,
+            total_shares_buf,
+            staked_shares_buf,
+            reward_rate_buf,
+            end_slot_buf,
+            last_slot_buf,
+            reward_per_share_buf,
+        ) = mut_array_refs![dst, 32, 32, 32, 8, 8, 8, 8, 8, 16];
+        pool_buf.copy_from_slice(self.pool.as_ref());
+        reward_mint_buf.copy_from_slice(self.reward_mint.as_ref());
+        reward_vault_buf.copy_from_slice(self.reward_vault.as_ref());
+        *total_shares_buf = self.total_shares.to_le_bytes();
+        *staked_shares_buf = self.staked_shares.to_le_bytes();
+        *reward_rate_buf = self.reward_rate.to_le_bytes();
+        *end_slot_buf = self.end_slot.to_le_bytes();
+        *last_slot_buf = self.last_slot.to_le_bytes();
+        *reward_per_share_buf = self.reward_per_share.to_le_bytes();
+    }
+}
+
+impl Farm {
+    /// Accrue the rewards from the last slot to `slot` to the staked shares. Slots
+    /// without stakes pay nobody, the schedule pauses and ends that much later.
+    pub fn accrue(&mut self, slot: u64) -> Result<(), AmmError> {
+        if slot > self.last_slot && self.end_slot > self.last_slot {
+            if self.staked_shares > 0 {
+                let until = slot.min(self.end_slot);
+                let rewards = (self.reward_rate as u128)
+                    .checked_mul((until - self.last_slot) as u128)
+                    .and_then(|rewards| rewards.checked_mul(RATE_MUL))
+                    .ok_or(AmmError::CalculationError)?;
+                self.reward_per_share = self
+                    .reward_per_share
+                    .checked_add(rewards / self.staked_shares as u128)
+                    .ok_or(AmmError::CalculationError)?;
+            } else {
+                self.end_slot = self
+                    .end_slot
+                    .checked_add(slot - self.last_slot)
+                    .ok_or(AmmError::CalculationError)?;
+            }
+        }
+        self.last_slot = self.last_slot.max(slot);
+        Ok(())
+    }
+
+    /// Pay out `amount` and what is left of the current schedule evenly over `slots`
+    /// slots from `slot`, the farm must have accrued up to `slot`
+    pub fn schedule(&mut self, slot: u64, amount: u64, slots: u64) -> Result<(), AmmError> {
+        let left = self
+            .reward_rate
+            .checked_mul(self.end_slot.saturating_sub(slot))
+            .and_then(|left| left.checked_add(amount))
+            .ok_or(AmmError::CalculationError)?;
+        let reward_rate = left.checked_div(slots).unwrap_or(0);
+        if reward_rate == 0 {
+            return Err(AmmError::InvalidAmount);
+        }
+        self.reward_rate = reward_rate;
+        self.end_slot = slot.checked_add(slots).ok_or(AmmError::CalculationError)?;
+        Ok(())
+    }
+
+    /// Move what the stake earned since it last settled to its unclaimed rewards
+    pub fn settle(&self, stake: &mut Stake) -> Result<(), AmmError> {
+        let earned =
+            math::calculate_proceeds(stake.shares, stake.reward_per_share, self.reward_per_share)?;
+        stake.rewards = stake
+            .rewards
+            .checked_add(earned)
+            .ok_or(AmmError::CalculationError)?;
+        stake.reward_per_share = self.reward_per_share;
+        Ok(())
+    }
+}
+
+/// liquidity a wallet staked in the farm of a pool
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stake {
+    pub pool: Pubkey,
+    // wallet that staked, gets the rent back
+    pub owner: Pubkey,
+    // token accounts the liquidity is paid back to
+    pub token_a: Pubkey,
+    pub token_b: Pubkey,
+    // token account the rewards are paid to
+    pub token_reward: Pubkey,
+    pub shares: u64,
+    // rewards earned and not claimed yet
+    pub rewards: u64,
+    // reward per share of the farm when the stake last settled
+    pub reward_per_share: u128,
+}
+
+impl Sealed for Stake {}
+impl IsInitialized for Stake {
+    fn is_initialized(&self) -> bool {
+        self.pool != Pubkey::default()
+    }
+}
+
+impl Pack for Stake {
+    const LEN: usize = 32 * 5 + 8 * 2 + 16;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        const LEN: usize = 32 * 5 + 8 * 2 + 16;
+        let src = array_ref![src, 0, LEN];
+        let (
+            pool_buf,
+            owner_buf,
+            token_a_buf,
+            token_b_buf,
+            token_reward_buf,
+            shares_buf,
+            rewards_buf,
+            reward_per_share_buf,
+        ) = array_refs![src, 32, 32, 32, 32, 32, 8, 8, 16];
+        Ok(Stake {
+            pool: Pubkey::new_from_array(*pool_buf),
+            owner: Pubkey::new_from_array(*owner_buf),
+            token_a: Pubkey::new_from_array(*token_a_buf),
+            token_b: Pubkey::new_from_array(*token_b_buf),
+            token_reward: Pubkey::new_from_array(*token_reward_buf),
+            shares: u64::from_le_bytes(*shares_buf),
+            rewards: u64::from_le_bytes(*rewards_buf),
+            reward_per_share: u128::from_le_bytes(*reward_per_share_buf),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        const LEN: usize = 32 * 5 + 8 * 2 + 16;
+        let dst = array_mut_ref![dst, 0, LEN];
+        let (
+            pool_buf,
+            owner_buf,
+            token_a_buf,
+            token_b_buf,
+            token_reward_buf,
+            shares_buf,
+            rewards_buf,
+            reward_per_share_buf,
+        ) = mut_array_refs![dst, 32, 32, 32, 32, 32, 8, 8, 16];
+        pool_buf.copy_from_slice(self.pool.as_ref());
+        owner_buf.copy_from_slice(self.owner.as_ref());
+        token_a_buf.copy_from_slice(self.token_a.as_ref());
+        token_b_buf.copy_from_slice(self.token_b.as_ref());
+        token_reward_buf.copy_from_slice(self.token_reward.as_ref());
+        *shares_buf = self.shares.to_le_bytes();
+        *rewards_buf = self.rewards.to_le_bytes();
+        *reward_per_share_buf = self.reward_per_share.to_le_bytes();
     }
 }
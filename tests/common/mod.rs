@@ -0,0 +1,1004 @@
+#![allow(dead_code)]
+
+use amm::{
+    error::AmmError,
+    instruction::{self, Direction, OrderKeys, RewardKeys},
+    processor::Processor,
+    state::{AmmPool, Farm, LongTermOrder, Order, Stake, Twamm, CLOSE_GRACE_PERIOD},
+};
+use solana_program::{
+    clock::Clock,
+    instruction::{Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction,
+};
+use solana_program_test::{
+    processor, BanksClient, ProgramTest, ProgramTestBanksClientExt, ProgramTestContext,
+};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+    transport::TransportError,
+};
+use spl_token_2022::extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions};
+
+pub const FEE: u64 = 3_000;
+pub const AMOUNT_A: u64 = 1_000_000;
+pub const AMOUNT_B: u64 = 2_000_000;
+pub const USER_AMOUNT: u64 = 10_000_000;
+pub const OWNER_LAMPORTS: u64 = 1_000_000_000;
+pub const ORDER_REWARD: u64 = 10_000;
+
+pub fn program_id() -> Pubkey {
+    Pubkey::new_from_array([7; 32])
+}
+
+pub fn program_test() -> ProgramTest {
+    let mut program_test = ProgramTest::new("amm", program_id(), processor!(Processor::process));
+    // run spl-token natively instead of the bundled bpf build
+    program_test.add_program(
+        "spl_token",
+        spl_token::id(),
+        processor!(spl_token::processor::Processor::process),
+    );
+    program_test.add_program(
+        "spl_token_2022",
+        spl_token_2022::id(),
+        processor!(spl_token_2022::processor::Processor::process),
+    );
+    program_test
+}
+
+pub async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), TransportError> {
+    let recent_blockhash = context
+        .banks_client
+        .get_new_latest_blockhash(&context.last_blockhash)
+        .await
+        .unwrap();
+    context.last_blockhash = recent_blockhash;
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        recent_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .map_err(Into::into)
+}
+
+/// Assert the first instruction failed with `error`.
+#[track_caller]
+pub fn assert_error<E: Into<InstructionError>>(result: Result<(), TransportError>, error: E) {
+    match result {
+        Err(TransportError::TransactionError(TransactionError::InstructionError(_, e))) => {
+            assert_eq!(e, error.into())
+        }
+        other => panic!("expected {:?}, got {:?}", error.into(), other),
+    }
+}
+
+pub fn amm_error(error: AmmError) -> InstructionError {
+    InstructionError::Custom(error as u32)
+}
+
+pub async fn get_account(banks_client: &mut BanksClient, key: &Pubkey) -> Option<Account> {
+    banks_client.get_account(*key).await.unwrap()
+}
+
+pub async fn token_balance(banks_client: &mut BanksClient, key: &Pubkey) -> u64 {
+    let account = get_account(banks_client, key).await.unwrap();
+    StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+        .unwrap()
+        .base
+        .amount
+}
+
+/// Move the clock of the bank forward.
+pub async fn warp_seconds(context: &mut ProgramTestContext, seconds: i64) {
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp += seconds;
+    context.set_sysvar(&clock);
+}
+
+/// Move the bank forward by `slots`.
+pub async fn warp_slots(context: &mut ProgramTestContext, slots: u64) {
+    let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    context.warp_to_slot(clock.slot + slots).unwrap();
+}
+
+pub async fn get_pool(banks_client: &mut BanksClient, key: &Pubkey) -> AmmPool {
+    let account = get_account(banks_client, key).await.unwrap();
+    AmmPool::unpack_unchecked(&account.data).unwrap()
+}
+
+pub async fn get_order(banks_client: &mut BanksClient, key: &Pubkey) -> Order {
+    let account = get_account(banks_client, key).await.unwrap();
+    Order::unpack_unchecked(&account.data).unwrap()
+}
+
+pub async fn get_long_term_order(banks_client: &mut BanksClient, key: &Pubkey) -> LongTermOrder {
+    let account = get_account(banks_client, key).await.unwrap();
+    LongTermOrder::unpack_unchecked(&account.data).unwrap()
+}
+
+pub async fn get_twamm(banks_client: &mut BanksClient, pool: &Pubkey) -> Twamm {
+    let (key, _) = instruction::find_twamm_address(&program_id(), pool);
+    let account = get_account(banks_client, &key).await.unwrap();
+    Twamm::unpack_unchecked(&account.data).unwrap()
+}
+
+pub async fn get_farm(banks_client: &mut BanksClient, pool: &Pubkey) -> Farm {
+    let (key, _) = instruction::find_farm_address(&program_id(), pool);
+    let account = get_account(banks_client, &key).await.unwrap();
+    Farm::unpack_unchecked(&account.data).unwrap()
+}
+
+pub async fn get_stake(banks_client: &mut BanksClient, pool: &Pubkey, owner: &Pubkey) -> Stake {
+    let (key, _) = instruction::find_stake_address(&program_id(), pool, owner);
+    let account = get_account(banks_client, &key).await.unwrap();
+    Stake::unpack_unchecked(&account.data).unwrap()
+}
+
+pub async fn create_mint(
+    context: &mut ProgramTestContext,
+    mint: &Keypair,
+    authority: &Pubkey,
+    decimals: u8,
+) {
+    create_mint_with_extensions(
+        context,
+        &spl_token::id(),
+        mint,
+        authority,
+        decimals,
+        &[],
+        &[],
+    )
+    .await
+    .unwrap();
+}
+
+/// Create a mint, `extensions` initialize the `extension_types` before the mint.
+pub async fn create_mint_with_extensions(
+    context: &mut ProgramTestContext,
+    token_program: &Pubkey,
+    mint: &Keypair,
+    authority: &Pubkey,
+    decimals: u8,
+    extension_types: &[ExtensionType],
+    extensions: &[Instruction],
+) -> Result<(), TransportError> {
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let space = ExtensionType::get_account_len::<spl_token_2022::state::Mint>(extension_types);
+    let mut instructions = vec![system_instruction::create_account(
+        &context.payer.pubkey(),
+        &mint.pubkey(),
+        rent.minimum_balance(space),
+        space as u64,
+        token_program,
+    )];
+    instructions.extend_from_slice(extensions);
+    instructions.push(
+        spl_token_2022::instruction::initialize_mint(
+            token_program,
+            &mint.pubkey(),
+            authority,
+            None,
+            decimals,
+        )
+        .unwrap(),
+    );
+    process(context, &instructions, &[mint]).await
+}
+
+/// Create a token account of the program owning `mint`, sized for the extensions of the mint.
+pub async fn create_token_account(
+    context: &mut ProgramTestContext,
+    account: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) {
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let mint_account = get_account(&mut context.banks_client, mint).await.unwrap();
+    let mint_state =
+        StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_account.data).unwrap();
+    let mut extension_types = ExtensionType::get_required_init_account_extensions(
+        &mint_state.get_extension_types().unwrap(),
+    );
+    let mut extensions = vec![];
+    // immutable owner like associated token accounts, non-transferable mints require it
+    if mint_account.owner == spl_token_2022::id() {
+        extension_types.push(ExtensionType::ImmutableOwner);
+        extensions.push(
+            spl_token_2022::instruction::initialize_immutable_owner(
+                &mint_account.owner,
+                &account.pubkey(),
+            )
+            .unwrap(),
+        );
+    }
+    let space = ExtensionType::get_account_len::<spl_token_2022::state::Account>(&extension_types);
+    let mut instructions = vec![system_instruction::create_account(
+        &context.payer.pubkey(),
+        &account.pubkey(),
+        rent.minimum_balance(space),
+        space as u64,
+        &mint_account.owner,
+    )];
+    instructions.extend(extensions);
+    instructions.push(
+        spl_token_2022::instruction::initialize_account(
+            &mint_account.owner,
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    );
+    process(context, &instructions, &[account]).await.unwrap();
+}
+
+pub async fn mint_to(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    account: &Pubkey,
+    authority: &Keypair,
+    amount: u64,
+) {
+    let token_program = get_account(&mut context.banks_client, mint)
+        .await
+        .unwrap()
+        .owner;
+    let instruction = spl_token_2022::instruction::mint_to(
+        &token_program,
+        mint,
+        account,
+        &authority.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    process(context, &[instruction], &[authority])
+        .await
+        .unwrap();
+}
+
+/// Token account funded with `USER_AMOUNT` of each mint.
+pub struct User {
+    pub wallet: Keypair,
+    pub token_a: Keypair,
+    pub token_b: Keypair,
+}
+
+/// Every account of one pool.
+pub struct PoolSetup {
+    pub token_program: Pubkey,
+    pub pool: Keypair,
+    pub pda: Pubkey,
+    pub nonce: u8,
+    pub mint_authority: Keypair,
+    pub mint_a: Keypair,
+    pub mint_b: Keypair,
+    pub vault_a: Pubkey,
+    pub vault_b: Pubkey,
+    pub fee_vault: Pubkey,
+    pub owner: User,
+}
+
+impl PoolSetup {
+    pub fn new() -> Self {
+        Self::with_token_program(spl_token::id())
+    }
+
+    pub fn with_token_program(token_program: Pubkey) -> Self {
+        let pool = Keypair::new();
+        let (pda, nonce) = Pubkey::find_program_address(&[pool.pubkey().as_ref()], &program_id());
+        let pool_key = pool.pubkey();
+        let vault = |seed| instruction::find_vault_address(&program_id(), &pool_key, seed).0;
+        Self {
+            token_program,
+            pool,
+            pda,
+            nonce,
+            mint_authority: Keypair::new(),
+            mint_a: Keypair::new(),
+            mint_b: Keypair::new(),
+            vault_a: vault(instruction::VAULT_A_SEED),
+            vault_b: vault(instruction::VAULT_B_SEED),
+            fee_vault: vault(instruction::FEE_VAULT_SEED),
+            owner: User {
+                wallet: Keypair::new(),
+                token_a: Keypair::new(),
+                token_b: Keypair::new(),
+            },
+        }
+    }
+
+    /// Create mints and a funded owner, initialize creates the pool and its vaults.
+    pub async fn create_accounts(&self, context: &mut ProgramTestContext, decimals: (u8, u8)) {
+        let authority = self.mint_authority.pubkey();
+        for (mint, decimals) in [(&self.mint_a, decimals.0), (&self.mint_b, decimals.1)] {
+            create_mint_with_extensions(
+                context,
+                &self.token_program,
+                mint,
+                &authority,
+                decimals,
+                &[],
+                &[],
+            )
+            .await
+            .unwrap();
+        }
+        self.fund_owner(context).await;
+    }
+
+    /// Fund the owner with tokens of mints that exist.
+    pub async fn fund_owner(&self, context: &mut ProgramTestContext) {
+        self.fund_user(context, &self.owner).await;
+        // the owner pays the rent of the pool and its vaults
+        let instruction = system_instruction::transfer(
+            &context.payer.pubkey(),
+            &self.owner.wallet.pubkey(),
+            OWNER_LAMPORTS,
+        );
+        process(context, &[instruction], &[]).await.unwrap();
+    }
+
+    pub async fn create_user(&self, context: &mut ProgramTestContext) -> User {
+        let user = User {
+            wallet: Keypair::new(),
+            token_a: Keypair::new(),
+            token_b: Keypair::new(),
+        };
+        self.fund_user(context, &user).await;
+        user
+    }
+
+    /// A funded user with lamports to pay for accounts, as an order owner or a keeper.
+    pub async fn create_wallet_user(&self, context: &mut ProgramTestContext) -> User {
+        let user = self.create_user(context).await;
+        let instruction = system_instruction::transfer(
+            &context.payer.pubkey(),
+            &user.wallet.pubkey(),
+            OWNER_LAMPORTS,
+        );
+        process(context, &[instruction], &[]).await.unwrap();
+        user
+    }
+
+    async fn fund_user(&self, context: &mut ProgramTestContext, user: &User) {
+        let wallet = user.wallet.pubkey();
+        create_token_account(context, &user.token_a, &self.mint_a.pubkey(), &wallet).await;
+        create_token_account(context, &user.token_b, &self.mint_b.pubkey(), &wallet).await;
+        for (mint, account) in [(&self.mint_a, &user.token_a), (&self.mint_b, &user.token_b)] {
+            mint_to(
+                context,
+                &mint.pubkey(),
+                &account.pubkey(),
+                &self.mint_authority,
+                USER_AMOUNT,
+            )
+            .await;
+        }
+    }
+
+    pub fn initialize_instruction(&self, fee: u64, tolerance: u64) -> Instruction {
+        instruction::initialize(
+            &program_id(),
+            &self.pool.pubkey(),
+            &self.owner.wallet.pubkey(),
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            &self.pda,
+            &self.owner.token_a.pubkey(),
+            &self.owner.token_b.pubkey(),
+            &self.token_program,
+            self.nonce,
+            fee,
+            AMOUNT_A,
+            AMOUNT_B,
+            tolerance,
+            None,
+        )
+        .unwrap()
+    }
+
+    pub async fn initialize(&self, context: &mut ProgramTestContext) -> Result<(), TransportError> {
+        let instruction = self.initialize_instruction(FEE, 0);
+        process(context, &[instruction], &[&self.owner.wallet, &self.pool]).await
+    }
+
+    pub fn swap_instruction(&self, user: &User, amount: u64, direction: Direction) -> Instruction {
+        self.swap_instruction_of(user, amount, direction, false, None)
+    }
+
+    /// swap paying the referral share of the fee to a token b account
+    pub fn referral_swap_instruction(
+        &self,
+        user: &User,
+        amount: u64,
+        direction: Direction,
+        referrer: &Pubkey,
+    ) -> Instruction {
+        self.swap_instruction_of(user, amount, direction, false, Some(referrer))
+    }
+
+    /// swap of a pool with long-term orders, it runs the virtual orders first
+    pub fn twamm_swap_instruction(
+        &self,
+        user: &User,
+        amount: u64,
+        direction: Direction,
+    ) -> Instruction {
+        self.swap_instruction_of(user, amount, direction, true, None)
+    }
+
+    fn swap_instruction_of(
+        &self,
+        user: &User,
+        amount: u64,
+        direction: Direction,
+        twamm: bool,
+        referrer: Option<&Pubkey>,
+    ) -> Instruction {
+        instruction::swap(
+            &program_id(),
+            &self.pool.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &user.wallet.pubkey(),
+            &user.token_a.pubkey(),
+            &user.token_b.pubkey(),
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            amount,
+            direction,
+            None,
+            twamm,
+            referrer,
+        )
+        .unwrap()
+    }
+
+    pub async fn swap(
+        &self,
+        context: &mut ProgramTestContext,
+        user: &User,
+        amount: u64,
+        direction: Direction,
+    ) -> Result<(), TransportError> {
+        let instruction = self.swap_instruction(user, amount, direction);
+        process(context, &[instruction], &[&user.wallet]).await
+    }
+
+    pub async fn update_status(
+        &self,
+        context: &mut ProgramTestContext,
+        owner: &Keypair,
+        status: u8,
+    ) -> Result<(), TransportError> {
+        let instruction =
+            instruction::update_status(&program_id(), &self.pool.pubkey(), &owner.pubkey(), status)
+                .unwrap();
+        process(context, &[instruction], &[owner]).await
+    }
+
+    pub async fn update_tolerance(
+        &self,
+        context: &mut ProgramTestContext,
+        owner: &Keypair,
+        tolerance: u64,
+    ) -> Result<(), TransportError> {
+        let instruction = instruction::update_tolerance(
+            &program_id(),
+            &self.pool.pubkey(),
+            &owner.pubkey(),
+            tolerance,
+        )
+        .unwrap();
+        process(context, &[instruction], &[owner]).await
+    }
+
+    pub async fn update_referral_share(
+        &self,
+        context: &mut ProgramTestContext,
+        owner: &Keypair,
+        share: u64,
+    ) -> Result<(), TransportError> {
+        let instruction = instruction::update_referral_share(
+            &program_id(),
+            &self.pool.pubkey(),
+            &owner.pubkey(),
+            share,
+        )
+        .unwrap();
+        process(context, &[instruction], &[owner]).await
+    }
+
+    pub async fn update_dynamic_fee(
+        &self,
+        context: &mut ProgramTestContext,
+        owner: &Keypair,
+        mode: u8,
+        min_fee: u64,
+        max_fee: u64,
+    ) -> Result<(), TransportError> {
+        let instruction = instruction::update_dynamic_fee(
+            &program_id(),
+            &self.pool.pubkey(),
+            &owner.pubkey(),
+            mode,
+            min_fee,
+            max_fee,
+        )
+        .unwrap();
+        process(context, &[instruction], &[owner]).await
+    }
+
+    pub fn withdrawal_fee_instruction(&self, owner: &Pubkey, receiver: &Pubkey) -> Instruction {
+        instruction::withdrawal_fee(
+            &program_id(),
+            &self.pool.pubkey(),
+            owner,
+            &self.fee_vault,
+            receiver,
+            &self.pda,
+            &self.token_program,
+            &self.mint_b.pubkey(),
+        )
+        .unwrap()
+    }
+
+    pub async fn withdrawal_fee(
+        &self,
+        context: &mut ProgramTestContext,
+        owner: &Keypair,
+        receiver: &Pubkey,
+    ) -> Result<(), TransportError> {
+        let instruction = self.withdrawal_fee_instruction(&owner.pubkey(), receiver);
+        process(context, &[instruction], &[owner]).await
+    }
+
+    /// Order keys of `user` selling for `direction`, the token accounts of the user.
+    pub fn order_keys(&self, order: &Pubkey, user: &User, direction: Direction) -> OrderKeys {
+        let token_out = match direction {
+            Direction::A2B => user.token_b.pubkey(),
+            Direction::B2A => user.token_a.pubkey(),
+        };
+        OrderKeys {
+            order: *order,
+            owner: user.wallet.pubkey(),
+            token_out,
+        }
+    }
+
+    pub fn place_order_instruction(
+        &self,
+        order: &Pubkey,
+        user: &User,
+        amount_in: u64,
+        direction: Direction,
+        min_amount_out: u64,
+    ) -> Instruction {
+        let (token_in, token_out) = match direction {
+            Direction::A2B => (&user.token_a, &user.token_b),
+            Direction::B2A => (&user.token_b, &user.token_a),
+        };
+        instruction::place_order(
+            &program_id(),
+            &self.pool.pubkey(),
+            order,
+            &user.wallet.pubkey(),
+            &self.pda,
+            &token_in.pubkey(),
+            &token_out.pubkey(),
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            amount_in,
+            direction,
+            min_amount_out,
+            ORDER_REWARD,
+        )
+        .unwrap()
+    }
+
+    /// Place an order of `user`, who needs lamports for the rent and the reward.
+    pub async fn place_order(
+        &self,
+        context: &mut ProgramTestContext,
+        user: &User,
+        amount_in: u64,
+        direction: Direction,
+        min_amount_out: u64,
+    ) -> Result<Keypair, TransportError> {
+        let order = Keypair::new();
+        let instruction = self.place_order_instruction(
+            &order.pubkey(),
+            user,
+            amount_in,
+            direction,
+            min_amount_out,
+        );
+        process(context, &[instruction], &[&user.wallet, &order]).await?;
+        Ok(order)
+    }
+
+    pub fn cancel_order_instruction(
+        &self,
+        order: &Pubkey,
+        user: &User,
+        direction: Direction,
+        owner_signs: bool,
+    ) -> Instruction {
+        let token_in = match direction {
+            Direction::A2B => user.token_a.pubkey(),
+            Direction::B2A => user.token_b.pubkey(),
+        };
+        instruction::cancel_order(
+            &program_id(),
+            &self.pool.pubkey(),
+            order,
+            &user.wallet.pubkey(),
+            &self.pda,
+            &token_in,
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            owner_signs,
+        )
+        .unwrap()
+    }
+
+    pub fn fill_orders_instruction(&self, keeper: &Pubkey, orders: &[OrderKeys]) -> Instruction {
+        self.fill_orders_instruction_of(keeper, orders, false)
+    }
+
+    /// fill of a pool with long-term orders, it runs the virtual orders first
+    pub fn twamm_fill_orders_instruction(
+        &self,
+        keeper: &Pubkey,
+        orders: &[OrderKeys],
+    ) -> Instruction {
+        self.fill_orders_instruction_of(keeper, orders, true)
+    }
+
+    fn fill_orders_instruction_of(
+        &self,
+        keeper: &Pubkey,
+        orders: &[OrderKeys],
+        twamm: bool,
+    ) -> Instruction {
+        instruction::fill_orders(
+            &program_id(),
+            &self.pool.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            keeper,
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            orders,
+            twamm,
+        )
+        .unwrap()
+    }
+
+    pub async fn fill_orders(
+        &self,
+        context: &mut ProgramTestContext,
+        keeper: &Keypair,
+        orders: &[OrderKeys],
+    ) -> Result<(), TransportError> {
+        let instruction = self.fill_orders_instruction(&keeper.pubkey(), orders);
+        process(context, &[instruction], &[keeper]).await
+    }
+
+    pub fn place_long_term_order_instruction(
+        &self,
+        order: &Pubkey,
+        user: &User,
+        amount_in: u64,
+        direction: Direction,
+        slots: u64,
+    ) -> Instruction {
+        let (token_in, token_out) = match direction {
+            Direction::A2B => (&user.token_a, &user.token_b),
+            Direction::B2A => (&user.token_b, &user.token_a),
+        };
+        instruction::place_long_term_order(
+            &program_id(),
+            &self.pool.pubkey(),
+            order,
+            &user.wallet.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &token_in.pubkey(),
+            &token_out.pubkey(),
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            amount_in,
+            direction,
+            slots,
+        )
+        .unwrap()
+    }
+
+    /// Place a long-term order of `user`, who needs lamports for the rent.
+    pub async fn place_long_term_order(
+        &self,
+        context: &mut ProgramTestContext,
+        user: &User,
+        amount_in: u64,
+        direction: Direction,
+        slots: u64,
+    ) -> Result<Keypair, TransportError> {
+        let order = Keypair::new();
+        let instruction = self.place_long_term_order_instruction(
+            &order.pubkey(),
+            user,
+            amount_in,
+            direction,
+            slots,
+        );
+        process(context, &[instruction], &[&user.wallet, &order]).await?;
+        Ok(order)
+    }
+
+    pub fn cancel_long_term_order_instruction(
+        &self,
+        order: &Pubkey,
+        user: &User,
+        direction: Direction,
+        owner_signs: bool,
+    ) -> Instruction {
+        let (token_in, token_out) = match direction {
+            Direction::A2B => (&user.token_a, &user.token_b),
+            Direction::B2A => (&user.token_b, &user.token_a),
+        };
+        instruction::cancel_long_term_order(
+            &program_id(),
+            &self.pool.pubkey(),
+            order,
+            &user.wallet.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &token_in.pubkey(),
+            &token_out.pubkey(),
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            owner_signs,
+        )
+        .unwrap()
+    }
+
+    pub fn execute_virtual_orders_instruction(&self) -> Instruction {
+        instruction::execute_virtual_orders(
+            &program_id(),
+            &self.pool.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+        )
+        .unwrap()
+    }
+
+    /// Fund the farm of the pool from the rewards of the owner.
+    pub fn fund_farm_instruction(
+        &self,
+        reward_mint: &Pubkey,
+        source: &Pubkey,
+        amount: u64,
+        slots: u64,
+    ) -> Instruction {
+        instruction::fund_farm(
+            &program_id(),
+            &self.pool.pubkey(),
+            &self.owner.wallet.pubkey(),
+            &self.vault_a,
+            &self.pda,
+            source,
+            &self.token_program,
+            reward_mint,
+            amount,
+            slots,
+        )
+        .unwrap()
+    }
+
+    pub fn stake_instruction(
+        &self,
+        user: &User,
+        token_reward: &Pubkey,
+        amount_a: u64,
+        twamm: bool,
+    ) -> Instruction {
+        instruction::stake(
+            &program_id(),
+            &self.pool.pubkey(),
+            &user.wallet.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &user.token_a.pubkey(),
+            &user.token_b.pubkey(),
+            token_reward,
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            amount_a,
+            None,
+            twamm,
+        )
+        .unwrap()
+    }
+
+    pub fn unstake_instruction(
+        &self,
+        user: &User,
+        token_reward: &Pubkey,
+        reward_mint: &Pubkey,
+        shares: u64,
+        owner_signs: bool,
+    ) -> Instruction {
+        instruction::unstake(
+            &program_id(),
+            &self.pool.pubkey(),
+            &user.wallet.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &user.token_a.pubkey(),
+            &user.token_b.pubkey(),
+            token_reward,
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            reward_mint,
+            shares,
+            None,
+            false,
+            owner_signs,
+        )
+        .unwrap()
+    }
+
+    pub fn claim_rewards_instruction(
+        &self,
+        user: &User,
+        token_reward: &Pubkey,
+        reward_mint: &Pubkey,
+    ) -> Instruction {
+        instruction::claim_rewards(
+            &program_id(),
+            &self.pool.pubkey(),
+            &user.wallet.pubkey(),
+            &self.pda,
+            token_reward,
+            &self.token_program,
+            reward_mint,
+        )
+        .unwrap()
+    }
+
+    /// Lock the pool and wait out the grace period, so the owner can terminate it.
+    pub async fn lock_for_close(&self, context: &mut ProgramTestContext) {
+        self.update_status(context, &self.owner.wallet, 2)
+            .await
+            .unwrap();
+        warp_seconds(context, CLOSE_GRACE_PERIOD).await;
+    }
+
+    pub fn terminate_instruction(&self, owner: &User) -> Instruction {
+        self.terminate_instruction_of(owner, None, None)
+    }
+
+    /// Terminate, sweeping the rewards left in the farm when `rewards` are given.
+    pub fn terminate_farm_instruction(
+        &self,
+        owner: &User,
+        rewards: Option<&RewardKeys>,
+    ) -> Instruction {
+        self.terminate_instruction_of(owner, None, rewards)
+    }
+
+    /// Terminate a pool that had long-term orders, closing their escrows too and
+    /// refunding their rent to `payer`, the wallet that placed the first order.
+    pub fn terminate_twamm_instruction(&self, owner: &User, payer: &Pubkey) -> Instruction {
+        self.terminate_instruction_of(owner, Some(payer), None)
+    }
+
+    /// Terminate a pool that had long-term orders and a farm.
+    pub fn terminate_twamm_farm_instruction(
+        &self,
+        owner: &User,
+        payer: &Pubkey,
+        rewards: &RewardKeys,
+    ) -> Instruction {
+        self.terminate_instruction_of(owner, Some(payer), Some(rewards))
+    }
+
+    fn terminate_instruction_of(
+        &self,
+        owner: &User,
+        twamm_payer: Option<&Pubkey>,
+        rewards: Option<&RewardKeys>,
+    ) -> Instruction {
+        instruction::terminate(
+            &program_id(),
+            &self.pool.pubkey(),
+            &owner.wallet.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &owner.token_a.pubkey(),
+            &owner.token_b.pubkey(),
+            &self.token_program,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+            None,
+            twamm_payer,
+            rewards,
+        )
+        .unwrap()
+    }
+
+    pub async fn terminate(
+        &self,
+        context: &mut ProgramTestContext,
+        owner: &User,
+    ) -> Result<(), TransportError> {
+        let instruction = self.terminate_instruction(owner);
+        process(context, &[instruction], &[&owner.wallet]).await
+    }
+
+    pub fn migrate_instruction(&self, owner: &Pubkey) -> Instruction {
+        instruction::migrate(
+            &program_id(),
+            &self.pool.pubkey(),
+            owner,
+            &self.mint_a.pubkey(),
+            &self.mint_b.pubkey(),
+        )
+        .unwrap()
+    }
+}
+
+/// Start a test validator with one initialized pool.
+pub async fn setup_pool() -> (ProgramTestContext, PoolSetup) {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    setup.initialize(&mut context).await.unwrap();
+    (context, setup)
+}
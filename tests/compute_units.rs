@@ -0,0 +1,362 @@
+//! Compute unit benchmark of every instruction.
+//!
+//! Units are only metered for the bpf build, build it and point the test at it:
+//!
+//! ```bash
+//! cargo build-sbf
+//! SBF_OUT_DIR=target/deploy cargo test --test compute_units -- --ignored --nocapture
+//! ```
+//!
+//! A plain `cargo test` skips the benchmark, run with `--ignored` it fails without the
+//! bpf build rather than load the program natively.
+mod common;
+
+use amm::{
+    instruction::{self, AmmInstruction, Direction, RewardKeys},
+    math,
+    state::{AmmPool, CLOSE_GRACE_PERIOD, TWAMM_INTERVAL},
+};
+use common::*;
+use solana_program::instruction::Instruction;
+use solana_program_test::{tokio, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::AccountSharedData,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use std::env;
+
+/// compute unit ceilings, a change that needs more fails the benchmark
+const BUDGETS: &[(&str, u64)] = &[
+    ("initialize", 30_000),
+    ("update status", 5_000),
+    ("update tolerance", 5_000),
+    ("update referral share", 5_000),
+    ("update dynamic fee", 5_000),
+    ("swap a2b", 30_000),
+    ("swap b2a", 30_000),
+    ("withdrawal fee", 15_000),
+    ("terminate", 90_000),
+    ("place order", 40_000),
+    ("cancel order", 30_000),
+    ("fill orders", 50_000),
+    ("place long term order", 80_000),
+    ("cancel long term order", 60_000),
+    ("execute virtual orders", 60_000),
+    ("fund farm", 50_000),
+    ("stake", 50_000),
+    ("unstake", 60_000),
+    ("claim rewards", 30_000),
+    ("migrate", 15_000),
+];
+
+/// reserves of a and b the pool is created with
+const RESERVES: &[(u64, u64)] = &[
+    (AMOUNT_A, AMOUNT_B),
+    (1_000_000_000_000, 2_000_000_000_000),
+    (u64::MAX / 4, u64::MAX / 2),
+];
+
+/// Name of the budget of an instruction, a new instruction doesn't build without one.
+fn budget_name(instruction: &Instruction) -> &'static str {
+    match AmmInstruction::unpack(&instruction.data).unwrap() {
+        AmmInstruction::Initialize { .. } => "initialize",
+        AmmInstruction::UpdateStatus { .. } => "update status",
+        AmmInstruction::UpdateTolerance { .. } => "update tolerance",
+        AmmInstruction::UpdateReferralShare { .. } => "update referral share",
+        AmmInstruction::UpdateDynamicFee { .. } => "update dynamic fee",
+        AmmInstruction::Swap {
+            direction: Direction::A2B,
+            ..
+        } => "swap a2b",
+        AmmInstruction::Swap {
+            direction: Direction::B2A,
+            ..
+        } => "swap b2a",
+        AmmInstruction::WithdrawalFee {} => "withdrawal fee",
+        AmmInstruction::Terminate { .. } => "terminate",
+        AmmInstruction::PlaceOrder { .. } => "place order",
+        AmmInstruction::CancelOrder {} => "cancel order",
+        AmmInstruction::FillOrders {} => "fill orders",
+        AmmInstruction::PlaceLongTermOrder { .. } => "place long term order",
+        AmmInstruction::CancelLongTermOrder {} => "cancel long term order",
+        AmmInstruction::ExecuteVirtualOrders {} => "execute virtual orders",
+        AmmInstruction::FundFarm { .. } => "fund farm",
+        AmmInstruction::Stake { .. } => "stake",
+        AmmInstruction::Unstake { .. } => "unstake",
+        AmmInstruction::ClaimRewards {} => "claim rewards",
+        AmmInstruction::Migrate {} => "migrate",
+    }
+}
+
+fn budget(name: &str) -> u64 {
+    BUDGETS
+        .iter()
+        .find(|(budget_name, _)| *budget_name == name)
+        .map(|(_, units)| *units)
+        .unwrap()
+}
+
+/// The bpf build of the program, spl-token comes bundled with `solana-program-test`.
+fn bench_program_test() -> ProgramTest {
+    if env::var("SBF_OUT_DIR").is_err() && env::var("BPF_OUT_DIR").is_err() {
+        panic!("compute units are only metered for the bpf build, set SBF_OUT_DIR");
+    }
+    let mut program_test = ProgramTest::new("amm", program_id(), None);
+    program_test.prefer_bpf(true);
+    program_test
+}
+
+/// Simulate for the units consumed, then process the instruction.
+async fn units(
+    context: &mut ProgramTestContext,
+    instruction: Instruction,
+    signers: &[&Keypair],
+) -> (&'static str, u64) {
+    let name = budget_name(&instruction);
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        std::slice::from_ref(&instruction),
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        context.last_blockhash,
+    );
+    let simulation = context
+        .banks_client
+        .simulate_transaction(transaction)
+        .await
+        .unwrap();
+    simulation.result.unwrap().unwrap();
+    process(context, &[instruction], signers).await.unwrap();
+    (name, simulation.simulation_details.unwrap().units_consumed)
+}
+
+/// Mint the reserves to the owner, an initialize deposits all of them.
+async fn fund_reserves(
+    context: &mut ProgramTestContext,
+    setup: &PoolSetup,
+    reserve_a: u64,
+    reserve_b: u64,
+) {
+    let owner = &setup.owner;
+    for (mint, token, reserve) in [
+        (&setup.mint_a, &owner.token_a, reserve_a),
+        (&setup.mint_b, &owner.token_b, reserve_b),
+    ] {
+        let amount = reserve.saturating_sub(USER_AMOUNT);
+        mint_to(
+            context,
+            &mint.pubkey(),
+            &token.pubkey(),
+            &setup.mint_authority,
+            amount,
+        )
+        .await;
+    }
+}
+
+/// A reward mint and a funded reward account of the owner.
+async fn create_rewards(context: &mut ProgramTestContext, setup: &PoolSetup) -> RewardKeys {
+    let mint = Keypair::new();
+    let authority = setup.mint_authority.pubkey();
+    create_mint_with_extensions(
+        context,
+        &setup.token_program,
+        &mint,
+        &authority,
+        6,
+        &[],
+        &[],
+    )
+    .await
+    .unwrap();
+    let account = Keypair::new();
+    let owner = setup.owner.wallet.pubkey();
+    create_token_account(context, &account, &mint.pubkey(), &owner).await;
+    mint_to(
+        context,
+        &mint.pubkey(),
+        &account.pubkey(),
+        &setup.mint_authority,
+        USER_AMOUNT,
+    )
+    .await;
+    RewardKeys {
+        reward_mint: mint.pubkey(),
+        owner_token_reward: account.pubkey(),
+    }
+}
+
+/// Run every instruction on a pool of the reserves, returns the units of each.
+async fn bench(
+    program_test: ProgramTest,
+    reserve_a: u64,
+    reserve_b: u64,
+) -> Vec<(&'static str, u64)> {
+    let mut context = program_test.start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    fund_reserves(&mut context, &setup, reserve_a, reserve_b).await;
+    let owner = &setup.owner;
+    let user = setup.create_wallet_user(&mut context).await;
+    let mut report = vec![];
+
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.data = AmmInstruction::Initialize {
+        nonce: setup.nonce,
+        fee: FEE,
+        amount_a: reserve_a,
+        amount_b: reserve_b,
+        tolerance: 0,
+        deadline: None,
+    }
+    .pack();
+    let signers = [&owner.wallet, &setup.pool];
+    report.push(units(&mut context, instruction, &signers).await);
+
+    let signers = [&owner.wallet];
+    let pool = setup.pool.pubkey();
+    let owner_key = owner.wallet.pubkey();
+    // the widest bound still runs the price impact check of every swap
+    let instruction =
+        instruction::update_tolerance(&program_id(), &pool, &owner_key, math::BPS_MUL).unwrap();
+    report.push(units(&mut context, instruction, &signers).await);
+    let instruction =
+        instruction::update_referral_share(&program_id(), &pool, &owner_key, 1_000).unwrap();
+    report.push(units(&mut context, instruction, &signers).await);
+
+    let signers = [&user.wallet];
+    let instruction = setup.swap_instruction(&user, 100_000, Direction::A2B);
+    report.push(units(&mut context, instruction, &signers).await);
+    let instruction = setup.swap_instruction(&user, 100_000, Direction::B2A);
+    report.push(units(&mut context, instruction, &signers).await);
+
+    // the volatility mode does the most work on every later trade
+    let signers = [&owner.wallet];
+    let instruction =
+        instruction::update_dynamic_fee(&program_id(), &pool, &owner_key, 1, 1_000, 30_000)
+            .unwrap();
+    report.push(units(&mut context, instruction, &signers).await);
+
+    // one order filled, one cancelled
+    let order = Keypair::new();
+    let instruction =
+        setup.place_order_instruction(&order.pubkey(), &user, 10_000, Direction::A2B, 1);
+    report.push(units(&mut context, instruction, &[&user.wallet, &order]).await);
+    let keys = setup.order_keys(&order.pubkey(), &user, Direction::A2B);
+    let instruction = setup.fill_orders_instruction(&user.wallet.pubkey(), &[keys]);
+    report.push(units(&mut context, instruction, &[&user.wallet]).await);
+    let order = setup
+        .place_order(&mut context, &user, 10_000, Direction::A2B, u64::MAX)
+        .await
+        .unwrap();
+    let instruction = setup.cancel_order_instruction(&order.pubkey(), &user, Direction::A2B, true);
+    report.push(units(&mut context, instruction, &[&user.wallet]).await);
+
+    // the long-term order has to clear the minimum deposit of large reserves
+    let amount = (reserve_a / 100).max(1_000_000);
+    mint_to(
+        &mut context,
+        &setup.mint_a.pubkey(),
+        &user.token_a.pubkey(),
+        &setup.mint_authority,
+        amount,
+    )
+    .await;
+    let order = Keypair::new();
+    let instruction = setup.place_long_term_order_instruction(
+        &order.pubkey(),
+        &user,
+        amount,
+        Direction::A2B,
+        10 * TWAMM_INTERVAL,
+    );
+    report.push(units(&mut context, instruction, &[&user.wallet, &order]).await);
+    warp_slots(&mut context, TWAMM_INTERVAL).await;
+    let instruction = setup.execute_virtual_orders_instruction();
+    report.push(units(&mut context, instruction, &[]).await);
+    let instruction =
+        setup.cancel_long_term_order_instruction(&order.pubkey(), &user, Direction::A2B, true);
+    report.push(units(&mut context, instruction, &[&user.wallet]).await);
+
+    let rewards = create_rewards(&mut context, &setup).await;
+    let instruction = setup.fund_farm_instruction(
+        &rewards.reward_mint,
+        &rewards.owner_token_reward,
+        USER_AMOUNT,
+        TWAMM_INTERVAL,
+    );
+    report.push(units(&mut context, instruction, &[&owner.wallet]).await);
+    let token_reward = Keypair::new();
+    let wallet = user.wallet.pubkey();
+    create_token_account(&mut context, &token_reward, &rewards.reward_mint, &wallet).await;
+    let token_reward = token_reward.pubkey();
+    let instruction = setup.stake_instruction(&user, &token_reward, 100_000, false);
+    report.push(units(&mut context, instruction, &[&user.wallet]).await);
+    warp_slots(&mut context, 10).await;
+    let instruction = setup.claim_rewards_instruction(&user, &token_reward, &rewards.reward_mint);
+    report.push(units(&mut context, instruction, &[&user.wallet]).await);
+    let stake = get_stake(&mut context.banks_client, &pool, &wallet).await;
+    let instruction = setup.unstake_instruction(
+        &user,
+        &token_reward,
+        &rewards.reward_mint,
+        stake.shares,
+        true,
+    );
+    report.push(units(&mut context, instruction, &[&user.wallet]).await);
+
+    let signers = [&owner.wallet];
+    let instruction = setup.withdrawal_fee_instruction(&owner_key, &owner.token_b.pubkey());
+    report.push(units(&mut context, instruction, &signers).await);
+    let instruction = instruction::update_status(&program_id(), &pool, &owner_key, 2).unwrap();
+    report.push(units(&mut context, instruction, &signers).await);
+    warp_seconds(&mut context, CLOSE_GRACE_PERIOD).await;
+    let instruction =
+        setup.terminate_twamm_farm_instruction(owner, &user.wallet.pubkey(), &rewards);
+    report.push(units(&mut context, instruction, &signers).await);
+
+    // a second pool cut back to the legacy layout
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    setup.initialize(&mut context).await.unwrap();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let pool = get_account(&mut context.banks_client, &setup.pool.pubkey())
+        .await
+        .unwrap();
+    let mut account = AccountSharedData::new(
+        rent.minimum_balance(AmmPool::LEGACY_LEN),
+        AmmPool::LEGACY_LEN,
+        &program_id(),
+    );
+    account.set_data(pool.data[..AmmPool::LEGACY_LEN].to_vec());
+    context.set_account(&setup.pool.pubkey(), &account);
+    let instruction = setup.migrate_instruction(&setup.owner.wallet.pubkey());
+    report.push(units(&mut context, instruction, &[&setup.owner.wallet]).await);
+    report
+}
+
+#[tokio::test]
+#[ignore = "needs the bpf build, set SBF_OUT_DIR and run with --ignored"]
+async fn test_compute_units() {
+    let mut over_budget = vec![];
+    println!(
+        "{:>24} {:>24} {:>24} {:>8} {:>8}",
+        "reserve a", "reserve b", "instruction", "units", "budget"
+    );
+    for (reserve_a, reserve_b) in RESERVES {
+        let program_test = bench_program_test();
+        for (name, units) in bench(program_test, *reserve_a, *reserve_b).await {
+            let budget = budget(name);
+            println!(
+                "{:>24} {:>24} {:>24} {:>8} {:>8}",
+                reserve_a, reserve_b, name, units, budget
+            );
+            if units > budget {
+                over_budget.push((name, *reserve_a, units, budget));
+            }
+        }
+    }
+    assert!(over_budget.is_empty(), "over budget: {:?}", over_budget);
+}
@@ -0,0 +1,251 @@
+mod common;
+
+use amm::{
+    error::AmmError,
+    instruction::Direction,
+    math::{self, VOLATILITY_HALF_LIFE},
+    state::{AmmPoolView, FeeMode},
+};
+use common::*;
+use solana_program::clock::Clock;
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::signature::{Keypair, Signer};
+
+const MIN_FEE: u64 = 1_000;
+const MAX_FEE: u64 = 300_000;
+
+/// A pool with a dynamic fee of `mode` and a user.
+async fn setup_dynamic_pool(mode: FeeMode) -> (ProgramTestContext, PoolSetup, User) {
+    let (mut context, setup) = setup_pool().await;
+    setup
+        .update_dynamic_fee(
+            &mut context,
+            &setup.owner.wallet,
+            mode.into(),
+            MIN_FEE,
+            MAX_FEE,
+        )
+        .await
+        .unwrap();
+    let user = setup.create_user(&mut context).await;
+    (context, setup, user)
+}
+
+/// Quote the fee rate of a swap from the pool account, the vaults and the clock,
+/// as a client does.
+async fn quote_fee(
+    context: &mut ProgramTestContext,
+    setup: &PoolSetup,
+    direction: Direction,
+) -> u64 {
+    let banks_client = &mut context.banks_client;
+    let slot = banks_client.get_sysvar::<Clock>().await.unwrap().slot;
+    let reserve_a = token_balance(banks_client, &setup.vault_a).await;
+    let reserve_b = token_balance(banks_client, &setup.vault_b).await;
+    let account = get_account(banks_client, &setup.pool.pubkey())
+        .await
+        .unwrap();
+    let pool: &AmmPoolView = bytemuck::from_bytes(&account.data);
+    pool.effective_fee(slot, reserve_a, reserve_b, Some(direction))
+        .unwrap()
+}
+
+/// Swap and return the fee the fee vault received.
+async fn swap_fee(
+    context: &mut ProgramTestContext,
+    setup: &PoolSetup,
+    user: &User,
+    amount: u64,
+    direction: Direction,
+) -> u64 {
+    let before = token_balance(&mut context.banks_client, &setup.fee_vault).await;
+    setup.swap(context, user, amount, direction).await.unwrap();
+    token_balance(&mut context.banks_client, &setup.fee_vault).await - before
+}
+
+#[tokio::test]
+async fn test_update_dynamic_fee() {
+    let (mut context, setup) = setup_pool().await;
+    let owner = &setup.owner.wallet;
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.fee_mode, FeeMode::Static);
+    assert_eq!((pool.min_fee, pool.max_fee), (0, 0));
+    setup
+        .update_dynamic_fee(
+            &mut context,
+            owner,
+            FeeMode::Volatility.into(),
+            MIN_FEE,
+            MAX_FEE,
+        )
+        .await
+        .unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.fee_mode, FeeMode::Volatility);
+    assert_eq!((pool.min_fee, pool.max_fee), (MIN_FEE, MAX_FEE));
+    assert_eq!(pool.volatility, 0);
+    assert!(pool.volatility_slot > 0);
+
+    // the bounds are ordered and at most the whole amount
+    setup
+        .update_dynamic_fee(&mut context, owner, 2, 0, math::PERCENT_MUL)
+        .await
+        .unwrap();
+    let result = setup
+        .update_dynamic_fee(&mut context, owner, 2, MAX_FEE, MIN_FEE)
+        .await;
+    assert_error(result, amm_error(AmmError::InvalidInput));
+    let result = setup
+        .update_dynamic_fee(&mut context, owner, 2, 0, math::PERCENT_MUL + 1)
+        .await;
+    assert_error(result, amm_error(AmmError::InvalidInput));
+    let result = setup
+        .update_dynamic_fee(&mut context, owner, 3, MIN_FEE, MAX_FEE)
+        .await;
+    assert_error(result, amm_error(AmmError::InvalidInput));
+
+    let other = Keypair::new();
+    let result = setup
+        .update_dynamic_fee(&mut context, &other, 1, MIN_FEE, MAX_FEE)
+        .await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+}
+
+#[tokio::test]
+async fn test_volatility_fee() {
+    let (mut context, setup, user) = setup_dynamic_pool(FeeMode::Volatility).await;
+    // a calm pool charges the base fee
+    assert_eq!(quote_fee(&mut context, &setup, Direction::A2B).await, FEE);
+    let fee = swap_fee(&mut context, &setup, &user, 100_000, Direction::A2B).await;
+    assert_eq!(fee, math::calculate_fee(181_818, FEE).unwrap());
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    let price_move = math::calculate_price_move(AMOUNT_A, AMOUNT_B, 1_100_000, 1_818_182);
+    assert_eq!(pool.volatility, price_move);
+
+    // the next swap pays for the move, whichever way it goes
+    let rate = quote_fee(&mut context, &setup, Direction::B2A).await;
+    assert_eq!(rate, FEE + price_move);
+    let amount_in = math::calculate_amount_b2a(1_100_000, 1_818_182, 50_000).unwrap();
+    let fee = swap_fee(&mut context, &setup, &user, 50_000, Direction::B2A).await;
+    assert_eq!(fee, math::calculate_fee(amount_in, rate).unwrap());
+
+    // and it fades once the market calms down
+    let rate = quote_fee(&mut context, &setup, Direction::A2B).await;
+    warp_slots(&mut context, VOLATILITY_HALF_LIFE * 2).await;
+    let calm = quote_fee(&mut context, &setup, Direction::A2B).await;
+    assert!(FEE < calm && calm < rate);
+    let reserve_a = token_balance(&mut context.banks_client, &setup.vault_a).await;
+    let reserve_b = token_balance(&mut context.banks_client, &setup.vault_b).await;
+    let amount_out = math::calculate_amount_a2b(reserve_a, reserve_b, 10_000).unwrap();
+    let fee = swap_fee(&mut context, &setup, &user, 10_000, Direction::A2B).await;
+    assert_eq!(fee, math::calculate_fee(amount_out, calm).unwrap());
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert!(pool.volatility < rate - FEE);
+}
+
+#[tokio::test]
+async fn test_imbalance_fee() {
+    let (mut context, setup, user) = setup_dynamic_pool(FeeMode::Imbalance).await;
+    // at the initial price both ways pay the base fee
+    let fee = swap_fee(&mut context, &setup, &user, 100_000, Direction::A2B).await;
+    assert_eq!(fee, math::calculate_fee(181_818, FEE).unwrap());
+
+    // selling more a pushes further from the initial price
+    let deviation = math::calculate_price_move(AMOUNT_A, AMOUNT_B, 1_100_000, 1_818_182);
+    assert_eq!(
+        quote_fee(&mut context, &setup, Direction::A2B).await,
+        FEE + deviation
+    );
+    let amount_out = math::calculate_amount_a2b(1_100_000, 1_818_182, 50_000).unwrap();
+    let fee = swap_fee(&mut context, &setup, &user, 50_000, Direction::A2B).await;
+    assert_eq!(
+        fee,
+        math::calculate_fee(amount_out, FEE + deviation).unwrap()
+    );
+
+    // buying it back restores the balance at the base fee
+    assert_eq!(quote_fee(&mut context, &setup, Direction::B2A).await, FEE);
+    let amount_in = math::calculate_amount_b2a(1_150_000, 1_818_182 - amount_out, 100_000).unwrap();
+    let fee = swap_fee(&mut context, &setup, &user, 100_000, Direction::B2A).await;
+    assert_eq!(fee, math::calculate_fee(amount_in, FEE).unwrap());
+}
+
+#[tokio::test]
+async fn test_dynamic_fee_bounds() {
+    let (mut context, setup, user) = setup_dynamic_pool(FeeMode::Imbalance).await;
+    setup
+        .update_dynamic_fee(
+            &mut context,
+            &setup.owner.wallet,
+            FeeMode::Imbalance.into(),
+            MIN_FEE,
+            FEE + 1_000,
+        )
+        .await
+        .unwrap();
+    setup
+        .swap(&mut context, &user, 300_000, Direction::A2B)
+        .await
+        .unwrap();
+    // far off the initial price the fee stops at the max
+    assert_eq!(
+        quote_fee(&mut context, &setup, Direction::A2B).await,
+        FEE + 1_000
+    );
+    assert_eq!(quote_fee(&mut context, &setup, Direction::B2A).await, FEE);
+
+    // and never drops below the min
+    setup
+        .update_dynamic_fee(
+            &mut context,
+            &setup.owner.wallet,
+            FeeMode::Imbalance.into(),
+            FEE * 2,
+            MAX_FEE,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        quote_fee(&mut context, &setup, Direction::B2A).await,
+        FEE * 2
+    );
+}
+
+#[tokio::test]
+async fn test_fill_order_dynamic_fee() {
+    let (mut context, setup) = setup_pool().await;
+    setup
+        .update_dynamic_fee(
+            &mut context,
+            &setup.owner.wallet,
+            FeeMode::Imbalance.into(),
+            MIN_FEE,
+            MAX_FEE,
+        )
+        .await
+        .unwrap();
+    let user = setup.create_wallet_user(&mut context).await;
+    let keeper = setup.create_wallet_user(&mut context).await;
+    setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await
+        .unwrap();
+    let order = setup
+        .place_order(&mut context, &user, 50_000, Direction::A2B, 1)
+        .await
+        .unwrap();
+    // a fill pays the fee a swap of the same size would
+    let rate = quote_fee(&mut context, &setup, Direction::A2B).await;
+    assert!(rate > FEE);
+    let amount_out = math::calculate_amount_a2b(1_100_000, 1_818_182, 50_000).unwrap();
+    let before = token_balance(&mut context.banks_client, &setup.fee_vault).await;
+    let keys = setup.order_keys(&order.pubkey(), &user, Direction::A2B);
+    setup
+        .fill_orders(&mut context, &keeper.wallet, &[keys])
+        .await
+        .unwrap();
+    assert_eq!(
+        token_balance(&mut context.banks_client, &setup.fee_vault).await - before,
+        math::calculate_fee(amount_out, rate).unwrap()
+    );
+}
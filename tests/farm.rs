@@ -0,0 +1,623 @@
+mod common;
+
+use amm::{
+    error::AmmError,
+    instruction::{self, AmmInstruction, Deadline, Direction},
+    state::{Stake, TWAMM_INTERVAL},
+};
+use common::*;
+use solana_program::{clock::Clock, instruction::InstructionError, pubkey::Pubkey};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::signature::{Keypair, Signer};
+
+const REWARD_AMOUNT: u64 = 1_000_000;
+const REWARD_SLOTS: u64 = 1_000;
+const REWARD_RATE: u64 = REWARD_AMOUNT / REWARD_SLOTS;
+const STAKE_AMOUNT: u64 = 100_000;
+
+/// The reward mint and the reward account of the pool owner.
+struct Rewards {
+    mint: Keypair,
+    owner: Keypair,
+}
+
+async fn current_slot(context: &mut ProgramTestContext) -> u64 {
+    context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .slot
+}
+
+async fn create_rewards(context: &mut ProgramTestContext, setup: &PoolSetup) -> Rewards {
+    let mint = Keypair::new();
+    create_mint_with_extensions(
+        context,
+        &setup.token_program,
+        &mint,
+        &setup.mint_authority.pubkey(),
+        6,
+        &[],
+        &[],
+    )
+    .await
+    .unwrap();
+    let owner = Keypair::new();
+    create_token_account(
+        context,
+        &owner,
+        &mint.pubkey(),
+        &setup.owner.wallet.pubkey(),
+    )
+    .await;
+    mint_to(
+        context,
+        &mint.pubkey(),
+        &owner.pubkey(),
+        &setup.mint_authority,
+        2 * REWARD_AMOUNT,
+    )
+    .await;
+    Rewards { mint, owner }
+}
+
+async fn reward_account(
+    context: &mut ProgramTestContext,
+    rewards: &Rewards,
+    user: &User,
+) -> Pubkey {
+    let account = Keypair::new();
+    create_token_account(
+        context,
+        &account,
+        &rewards.mint.pubkey(),
+        &user.wallet.pubkey(),
+    )
+    .await;
+    account.pubkey()
+}
+
+/// A pool whose farm pays `REWARD_RATE` per slot for `REWARD_SLOTS` slots.
+async fn setup_farm() -> (ProgramTestContext, PoolSetup, Rewards) {
+    let (mut context, setup) = setup_pool().await;
+    let rewards = create_rewards(&mut context, &setup).await;
+    let instruction = setup.fund_farm_instruction(
+        &rewards.mint.pubkey(),
+        &rewards.owner.pubkey(),
+        REWARD_AMOUNT,
+        REWARD_SLOTS,
+    );
+    process(&mut context, &[instruction], &[&setup.owner.wallet])
+        .await
+        .unwrap();
+    (context, setup, rewards)
+}
+
+/// A user who staked `STAKE_AMOUNT` of token a, and its reward account.
+async fn stake_user(
+    context: &mut ProgramTestContext,
+    setup: &PoolSetup,
+    rewards: &Rewards,
+) -> (User, Pubkey) {
+    let user = setup.create_wallet_user(context).await;
+    let token_reward = reward_account(context, rewards, &user).await;
+    let instruction = setup.stake_instruction(&user, &token_reward, STAKE_AMOUNT, false);
+    process(context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    (user, token_reward)
+}
+
+fn reward_vault(setup: &PoolSetup) -> Pubkey {
+    instruction::find_vault_address(
+        &program_id(),
+        &setup.pool.pubkey(),
+        instruction::FARM_REWARD_SEED,
+    )
+    .0
+}
+
+#[tokio::test]
+async fn test_fund_farm() {
+    let (mut context, setup) = setup_pool().await;
+    let rewards = create_rewards(&mut context, &setup).await;
+    let pool = setup.pool.pubkey();
+
+    // only the owner funds the farm
+    let other = setup.create_wallet_user(&mut context).await;
+    let instruction = instruction::fund_farm(
+        &program_id(),
+        &pool,
+        &other.wallet.pubkey(),
+        &setup.vault_a,
+        &setup.pda,
+        &rewards.owner.pubkey(),
+        &setup.token_program,
+        &rewards.mint.pubkey(),
+        REWARD_AMOUNT,
+        REWARD_SLOTS,
+    )
+    .unwrap();
+    let result = process(&mut context, &[instruction], &[&other.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+
+    let instruction = setup.fund_farm_instruction(
+        &rewards.mint.pubkey(),
+        &rewards.owner.pubkey(),
+        REWARD_AMOUNT,
+        0,
+    );
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidAmount));
+
+    let instruction = setup.fund_farm_instruction(
+        &rewards.mint.pubkey(),
+        &rewards.owner.pubkey(),
+        REWARD_AMOUNT,
+        REWARD_SLOTS,
+    );
+    process(&mut context, &[instruction], &[&setup.owner.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let farm = get_farm(banks_client, &pool).await;
+    assert_eq!(farm.pool, pool);
+    assert_eq!(farm.reward_mint, rewards.mint.pubkey());
+    assert_eq!(farm.reward_vault, reward_vault(&setup));
+    // the owner holds every share of the reserves
+    assert_eq!(farm.total_shares, AMOUNT_A);
+    assert_eq!(farm.staked_shares, 0);
+    assert_eq!(farm.reward_rate, REWARD_RATE);
+    assert_eq!(farm.end_slot, farm.last_slot + REWARD_SLOTS);
+    assert_eq!(
+        token_balance(banks_client, &reward_vault(&setup)).await,
+        REWARD_AMOUNT
+    );
+
+    // more rewards stretch what is left over the new schedule
+    warp_slots(&mut context, 100).await;
+    let instruction = setup.fund_farm_instruction(
+        &rewards.mint.pubkey(),
+        &rewards.owner.pubkey(),
+        REWARD_AMOUNT,
+        REWARD_SLOTS,
+    );
+    process(&mut context, &[instruction], &[&setup.owner.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let funded = get_farm(banks_client, &pool).await;
+    // nobody staked, so none of the first funding was paid out
+    let left = REWARD_RATE * REWARD_SLOTS;
+    assert_eq!(funded.reward_rate, (left + REWARD_AMOUNT) / REWARD_SLOTS);
+    assert_eq!(funded.end_slot, funded.last_slot + REWARD_SLOTS);
+    assert_eq!(funded.total_shares, AMOUNT_A);
+    assert_eq!(
+        token_balance(banks_client, &reward_vault(&setup)).await,
+        2 * REWARD_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_stake() {
+    let (mut context, setup, rewards) = setup_farm().await;
+    let (user, token_reward) = stake_user(&mut context, &setup, &rewards).await;
+    let pool = setup.pool.pubkey();
+    let banks_client = &mut context.banks_client;
+    // token b follows at the pool price
+    let amount_b = STAKE_AMOUNT * AMOUNT_B / AMOUNT_A;
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT - STAKE_AMOUNT
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT - amount_b
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        AMOUNT_A + STAKE_AMOUNT
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_b).await,
+        AMOUNT_B + amount_b
+    );
+    let stake = get_stake(banks_client, &pool, &user.wallet.pubkey()).await;
+    assert_eq!(
+        stake,
+        Stake {
+            pool,
+            owner: user.wallet.pubkey(),
+            token_a: user.token_a.pubkey(),
+            token_b: user.token_b.pubkey(),
+            token_reward,
+            shares: STAKE_AMOUNT,
+            rewards: 0,
+            reward_per_share: 0,
+        }
+    );
+    let farm = get_farm(banks_client, &pool).await;
+    assert_eq!(farm.total_shares, AMOUNT_A + STAKE_AMOUNT);
+    assert_eq!(farm.staked_shares, STAKE_AMOUNT);
+    assert_eq!(get_pool(banks_client, &pool).await.stakes, 1);
+
+    // staking again adds to the same stake
+    let instruction = setup.stake_instruction(&user, &token_reward, STAKE_AMOUNT / 2, false);
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let stake = get_stake(banks_client, &pool, &user.wallet.pubkey()).await;
+    assert_eq!(stake.shares, STAKE_AMOUNT + STAKE_AMOUNT / 2);
+    assert_eq!(get_pool(banks_client, &pool).await.stakes, 1);
+}
+
+#[tokio::test]
+async fn test_stake_errors() {
+    let (mut context, setup) = setup_pool().await;
+    let rewards = create_rewards(&mut context, &setup).await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let token_reward = reward_account(&mut context, &rewards, &user).await;
+
+    // no farm yet
+    let instruction = setup.stake_instruction(&user, &token_reward, STAKE_AMOUNT, false);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidProgramAddress));
+
+    let instruction = setup.fund_farm_instruction(
+        &rewards.mint.pubkey(),
+        &rewards.owner.pubkey(),
+        REWARD_AMOUNT,
+        REWARD_SLOTS,
+    );
+    process(&mut context, &[instruction], &[&setup.owner.wallet])
+        .await
+        .unwrap();
+    let instruction = setup.stake_instruction(&user, &token_reward, 0, false);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidAmount));
+
+    // the rewards are paid in the reward mint
+    let instruction = setup.stake_instruction(&user, &user.token_b.pubkey(), STAKE_AMOUNT, false);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidMint));
+
+    // later stakes keep the accounts of the first one
+    let instruction = setup.stake_instruction(&user, &token_reward, STAKE_AMOUNT, false);
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let other_reward = reward_account(&mut context, &rewards, &user).await;
+    let instruction = setup.stake_instruction(&user, &other_reward, STAKE_AMOUNT, false);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidStake));
+
+    // a locked pool takes no stakes
+    setup
+        .update_status(&mut context, &setup.owner.wallet, 2)
+        .await
+        .unwrap();
+    let instruction = setup.stake_instruction(&user, &token_reward, STAKE_AMOUNT / 2, false);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::PoolLock));
+}
+
+#[tokio::test]
+async fn test_stake_deadline() {
+    let (mut context, setup, rewards) = setup_farm().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let token_reward = reward_account(&mut context, &rewards, &user).await;
+    context.warp_to_slot(100).unwrap();
+    let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    let stake = |deadline| {
+        let mut instruction = setup.stake_instruction(&user, &token_reward, STAKE_AMOUNT, false);
+        instruction.data = AmmInstruction::Stake {
+            amount_a: STAKE_AMOUNT,
+            deadline: Some(deadline),
+        }
+        .pack();
+        instruction
+    };
+    let unstake = |deadline| {
+        let mut instruction = setup.unstake_instruction(
+            &user,
+            &token_reward,
+            &rewards.mint.pubkey(),
+            STAKE_AMOUNT,
+            true,
+        );
+        instruction.data = AmmInstruction::Unstake {
+            shares: STAKE_AMOUNT,
+            deadline: Some(deadline),
+        }
+        .pack();
+        instruction
+    };
+
+    let instruction = stake(Deadline::Slot(clock.slot - 1));
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::Expired));
+    let instruction = stake(Deadline::UnixTimestamp(clock.unix_timestamp + 3600));
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+
+    let instruction = unstake(Deadline::UnixTimestamp(clock.unix_timestamp - 1));
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::Expired));
+    let instruction = unstake(Deadline::Slot(clock.slot + 100));
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_claim_rewards() {
+    let (mut context, setup, rewards) = setup_farm().await;
+    let (user, token_reward) = stake_user(&mut context, &setup, &rewards).await;
+    let pool = setup.pool.pubkey();
+    let start = get_farm(&mut context.banks_client, &pool).await.last_slot;
+    warp_slots(&mut context, 100).await;
+
+    // paid to the account the stake recorded
+    let other_reward = reward_account(&mut context, &rewards, &user).await;
+    let instruction = setup.claim_rewards_instruction(&user, &other_reward, &rewards.mint.pubkey());
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidStake));
+
+    let instruction = setup.claim_rewards_instruction(&user, &token_reward, &rewards.mint.pubkey());
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let farm = get_farm(banks_client, &pool).await;
+    // the only stake earns the whole rate
+    let earned = REWARD_RATE * (farm.last_slot - start);
+    assert!(earned > 0);
+    assert_eq!(token_balance(banks_client, &token_reward).await, earned);
+    assert_eq!(
+        token_balance(banks_client, &reward_vault(&setup)).await,
+        REWARD_AMOUNT - earned
+    );
+    let stake = get_stake(banks_client, &pool, &user.wallet.pubkey()).await;
+    assert_eq!(stake.rewards, 0);
+    assert_eq!(stake.reward_per_share, farm.reward_per_share);
+}
+
+#[tokio::test]
+async fn test_rewards_split_by_shares() {
+    let (mut context, setup, rewards) = setup_farm().await;
+    let pool = setup.pool.pubkey();
+    let (first, first_reward) = stake_user(&mut context, &setup, &rewards).await;
+    let first_slot = get_farm(&mut context.banks_client, &pool).await.last_slot;
+    warp_slots(&mut context, 10).await;
+    let (second, second_reward) = stake_user(&mut context, &setup, &rewards).await;
+    let second_slot = get_farm(&mut context.banks_client, &pool).await.last_slot;
+    warp_slots(&mut context, 100).await;
+    let mint = rewards.mint.pubkey();
+    let instructions = [
+        setup.claim_rewards_instruction(&first, &first_reward, &mint),
+        setup.claim_rewards_instruction(&second, &second_reward, &mint),
+    ];
+    process(
+        &mut context,
+        &instructions,
+        &[&first.wallet, &second.wallet],
+    )
+    .await
+    .unwrap();
+    let banks_client = &mut context.banks_client;
+    let farm = get_farm(banks_client, &pool).await;
+    let first_earned = token_balance(banks_client, &first_reward).await;
+    let second_earned = token_balance(banks_client, &second_reward).await;
+    // the first stake earned alone until the second one, then both split the rate
+    let shared = REWARD_RATE * (farm.last_slot - second_slot) / 2;
+    assert_eq!(second_earned, shared);
+    assert_eq!(
+        first_earned,
+        REWARD_RATE * (second_slot - first_slot) + shared
+    );
+}
+
+#[tokio::test]
+async fn test_unstake() {
+    let (mut context, setup, rewards) = setup_farm().await;
+    let (user, token_reward) = stake_user(&mut context, &setup, &rewards).await;
+    let pool = setup.pool.pubkey();
+    let mint = rewards.mint.pubkey();
+    let start = get_farm(&mut context.banks_client, &pool).await.last_slot;
+    warp_slots(&mut context, 100).await;
+
+    let instruction =
+        setup.unstake_instruction(&user, &token_reward, &mint, STAKE_AMOUNT + 1, true);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidAmount));
+    // the owner signs while the pool is open
+    let instruction = setup.unstake_instruction(&user, &token_reward, &mint, STAKE_AMOUNT, false);
+    let result = process(&mut context, &[instruction], &[]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    let instruction =
+        setup.unstake_instruction(&user, &token_reward, &mint, STAKE_AMOUNT / 2, true);
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let stake = get_stake(banks_client, &pool, &user.wallet.pubkey()).await;
+    assert_eq!(stake.shares, STAKE_AMOUNT / 2);
+    let amount_b = STAKE_AMOUNT * AMOUNT_B / AMOUNT_A;
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT - STAKE_AMOUNT / 2
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT - amount_b / 2
+    );
+
+    // the last share pays out the rest and closes the stake
+    let instruction =
+        setup.unstake_instruction(&user, &token_reward, &mint, STAKE_AMOUNT / 2, true);
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT
+    );
+    assert_eq!(token_balance(banks_client, &setup.vault_a).await, AMOUNT_A);
+    assert_eq!(token_balance(banks_client, &setup.vault_b).await, AMOUNT_B);
+    let farm = get_farm(banks_client, &pool).await;
+    assert_eq!(
+        token_balance(banks_client, &token_reward).await,
+        REWARD_RATE * (farm.last_slot - start)
+    );
+    assert_eq!(farm.total_shares, AMOUNT_A);
+    assert_eq!(farm.staked_shares, 0);
+    let (stake, _) = instruction::find_stake_address(&program_id(), &pool, &user.wallet.pubkey());
+    assert!(get_account(banks_client, &stake).await.is_none());
+    assert_eq!(get_pool(banks_client, &pool).await.stakes, 0);
+}
+
+#[tokio::test]
+async fn test_terminate_with_stakes() {
+    let (mut context, setup, rewards) = setup_farm().await;
+    let (user, token_reward) = stake_user(&mut context, &setup, &rewards).await;
+    setup.lock_for_close(&mut context).await;
+    let reward_keys = instruction::RewardKeys {
+        reward_mint: rewards.mint.pubkey(),
+        owner_token_reward: rewards.owner.pubkey(),
+    };
+    let instruction = setup.terminate_farm_instruction(&setup.owner, Some(&reward_keys));
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::OpenStakes));
+
+    // anyone unstakes once the grace period is over, the funds go to the owner
+    let instruction = setup.unstake_instruction(
+        &user,
+        &token_reward,
+        &rewards.mint.pubkey(),
+        STAKE_AMOUNT,
+        false,
+    );
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT
+    );
+    let earned = token_balance(banks_client, &token_reward).await;
+
+    // the farm must close with the pool
+    let result = setup.terminate(&mut context, &setup.owner).await;
+    assert_error(result, InstructionError::NotEnoughAccountKeys);
+
+    // the rewards nobody earned go back to the owner, the farm closes with the pool
+    let instruction = setup.terminate_farm_instruction(&setup.owner, Some(&reward_keys));
+    process(&mut context, &[instruction], &[&setup.owner.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &rewards.owner.pubkey()).await,
+        2 * REWARD_AMOUNT - earned
+    );
+    let (farm, _) = instruction::find_farm_address(&program_id(), &setup.pool.pubkey());
+    assert!(get_account(banks_client, &farm).await.is_none());
+    assert!(get_account(banks_client, &reward_vault(&setup))
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_terminate_with_virtual_orders_and_farm() {
+    let (mut context, setup, rewards) = setup_farm().await;
+    let seller = setup.create_wallet_user(&mut context).await;
+    let order = setup
+        .place_long_term_order(
+            &mut context,
+            &seller,
+            STAKE_AMOUNT,
+            Direction::A2B,
+            2 * TWAMM_INTERVAL,
+        )
+        .await
+        .unwrap();
+    setup.lock_for_close(&mut context).await;
+    let instruction =
+        setup.cancel_long_term_order_instruction(&order.pubkey(), &seller, Direction::A2B, false);
+    process(&mut context, &[instruction], &[]).await.unwrap();
+
+    // the farm accounts follow those of the virtual orders
+    let payer = seller.wallet.pubkey();
+    let instruction = setup.terminate_twamm_instruction(&setup.owner, &payer);
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, InstructionError::NotEnoughAccountKeys);
+
+    let reward_keys = instruction::RewardKeys {
+        reward_mint: rewards.mint.pubkey(),
+        owner_token_reward: rewards.owner.pubkey(),
+    };
+    let instruction = setup.terminate_twamm_farm_instruction(&setup.owner, &payer, &reward_keys);
+    process(&mut context, &[instruction], &[&setup.owner.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    // nobody staked, every reward goes back to the owner
+    assert_eq!(
+        token_balance(banks_client, &rewards.owner.pubkey()).await,
+        2 * REWARD_AMOUNT
+    );
+    let pool = setup.pool.pubkey();
+    for key in [
+        pool,
+        instruction::find_twamm_address(&program_id(), &pool).0,
+        instruction::find_farm_address(&program_id(), &pool).0,
+        reward_vault(&setup),
+    ] {
+        assert!(get_account(banks_client, &key).await.is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_stake_runs_virtual_orders() {
+    let (mut context, setup, rewards) = setup_farm().await;
+    let seller = setup.create_wallet_user(&mut context).await;
+    setup
+        .place_long_term_order(
+            &mut context,
+            &seller,
+            STAKE_AMOUNT,
+            Direction::A2B,
+            2 * TWAMM_INTERVAL,
+        )
+        .await
+        .unwrap();
+    warp_slots(&mut context, 10).await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let token_reward = reward_account(&mut context, &rewards, &user).await;
+
+    // the virtual orders trade first, their accounts are required
+    let instruction = setup.stake_instruction(&user, &token_reward, STAKE_AMOUNT, false);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, InstructionError::NotEnoughAccountKeys);
+
+    let instruction = setup.stake_instruction(&user, &token_reward, STAKE_AMOUNT, true);
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let slot = current_slot(&mut context).await;
+    let banks_client = &mut context.banks_client;
+    let twamm = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(twamm.last_slot, slot);
+    assert_eq!(
+        get_farm(banks_client, &setup.pool.pubkey()).await.last_slot,
+        slot
+    );
+}
@@ -0,0 +1,899 @@
+mod common;
+
+use amm::{
+    error::AmmError,
+    instruction::{AmmInstruction, Deadline, Direction},
+    math,
+    state::{AmmPool, PoolStatus, CLOSE_GRACE_PERIOD},
+};
+use common::*;
+use solana_program::{
+    clock::Clock, instruction::InstructionError, program_pack::Pack, pubkey::Pubkey,
+    system_instruction,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    account::AccountSharedData,
+    signature::{Keypair, Signer},
+};
+
+#[tokio::test]
+async fn test_initialize() {
+    let (mut context, setup) = setup_pool().await;
+    let banks_client = &mut context.banks_client;
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.status, PoolStatus::Nomal);
+    assert_eq!(pool.nonce, setup.nonce);
+    assert_eq!(pool.ka, AMOUNT_A);
+    assert_eq!(pool.kb, AMOUNT_B);
+    assert_eq!(pool.fee, FEE);
+    assert_eq!(pool.owner, setup.owner.wallet.pubkey());
+    assert_eq!(pool.mint_a, setup.mint_a.pubkey());
+    assert_eq!(pool.mint_b, setup.mint_b.pubkey());
+    assert_eq!(pool.vault_a, setup.vault_a);
+    assert_eq!(pool.vault_b, setup.vault_b);
+    assert_eq!(pool.fee_vault, setup.fee_vault);
+    assert_eq!((pool.decimals_a, pool.decimals_b), (6, 6));
+    assert_eq!(token_balance(banks_client, &setup.vault_a).await, AMOUNT_A);
+    assert_eq!(token_balance(banks_client, &setup.vault_b).await, AMOUNT_B);
+    assert_eq!(
+        token_balance(banks_client, &setup.owner.token_a.pubkey()).await,
+        USER_AMOUNT - AMOUNT_A
+    );
+}
+
+#[tokio::test]
+async fn test_initialize_twice() {
+    let (mut context, setup) = setup_pool().await;
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::PoolExist));
+
+    // a locked pool is still initialized
+    setup
+        .update_status(&mut context, &setup.owner.wallet, 2)
+        .await
+        .unwrap();
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::PoolExist));
+}
+
+/// Give the pool address a program owned account, as no instruction of the program would
+fn set_pool_data(context: &mut ProgramTestContext, setup: &PoolSetup, data: Vec<u8>) {
+    let mut account = AccountSharedData::new(1_000_000_000, data.len(), &program_id());
+    account.set_data(data);
+    context.set_account(&setup.pool.pubkey(), &account);
+}
+
+#[tokio::test]
+async fn test_initialize_corrupt_status() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    // a status byte no version writes must not pass for an empty pool
+    let mut data = vec![0; AmmPool::LEN];
+    data[0] = 4;
+    set_pool_data(&mut context, &setup, data);
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::InvalidStatus));
+}
+
+#[tokio::test]
+async fn test_initialize_twice_in_transaction() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    let instruction = setup.initialize_instruction(FEE, 0);
+    let result = process(
+        &mut context,
+        &[instruction.clone(), instruction],
+        &[&setup.owner.wallet, &setup.pool],
+    )
+    .await;
+    assert_error(result, amm_error(AmmError::PoolExist));
+}
+
+#[tokio::test]
+async fn test_initialize_errors() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    let signers = &[&setup.owner.wallet, &setup.pool];
+
+    // owner must sign
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.accounts[1].is_signer = false;
+    let result = process(&mut context, &[instruction], &[&setup.pool]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    // pool must sign
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.accounts[0].is_signer = false;
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    // vaults swapped
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.accounts[4].pubkey = setup.vault_b;
+    instruction.accounts[5].pubkey = setup.vault_a;
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidVault));
+
+    // vault not derived from the pool
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.accounts[4].pubkey = setup.owner.token_a.pubkey();
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidVault));
+
+    // fee vault at vault a
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.accounts[6].pubkey = setup.vault_a;
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidVault));
+
+    // pda of another pool
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    let (other_pda, _) = Pubkey::find_program_address(&[&[1; 32]], &program_id());
+    instruction.accounts[7].pubkey = other_pda;
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidPDA));
+
+    // not the system program
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.accounts[11].pubkey = setup.token_program;
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidProgramAddress));
+
+    // mint a is not a mint
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.accounts[2].pubkey = setup.owner.token_a.pubkey();
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::ExpectedMint));
+
+    // not enough token to deposit
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    instruction.data = AmmInstruction::Initialize {
+        nonce: setup.nonce,
+        fee: FEE,
+        amount_a: USER_AMOUNT + 1,
+        amount_b: AMOUNT_B,
+        tolerance: 0,
+        deadline: None,
+    }
+    .pack();
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InsufficientFunds));
+
+    // tolerance over 100%
+    let instruction = setup.initialize_instruction(FEE, math::BPS_MUL + 1);
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidInput));
+
+    // nothing above created the pool or its vaults
+    for key in [
+        setup.pool.pubkey(),
+        setup.vault_a,
+        setup.vault_b,
+        setup.fee_vault,
+    ] {
+        assert!(get_account(&mut context.banks_client, &key).await.is_none());
+    }
+    setup.initialize(&mut context).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_initialize_owner_pays() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    // lamports sent to the pool address before initialize count towards its rent
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let prefunded = rent.minimum_balance(0);
+    let instruction =
+        system_instruction::transfer(&context.payer.pubkey(), &setup.pool.pubkey(), prefunded);
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    setup.initialize(&mut context).await.unwrap();
+
+    let banks_client = &mut context.banks_client;
+    let pool = get_account(banks_client, &setup.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool.owner, program_id());
+    assert_eq!(pool.lamports, rent.minimum_balance(AmmPool::LEN));
+    for key in [setup.vault_a, setup.vault_b, setup.fee_vault] {
+        let vault = get_account(banks_client, &key).await.unwrap();
+        assert_eq!(vault.owner, setup.token_program);
+        assert_eq!(vault.lamports, rent.minimum_balance(vault.data.len()));
+    }
+    let paid = rent.minimum_balance(AmmPool::LEN) - prefunded
+        + 3 * rent.minimum_balance(spl_token::state::Account::LEN);
+    assert_eq!(
+        get_account(banks_client, &setup.owner.wallet.pubkey())
+            .await
+            .unwrap()
+            .lamports,
+        OWNER_LAMPORTS - paid
+    );
+}
+
+#[tokio::test]
+async fn test_initialize_wrong_nonce() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    let mut instruction = setup.initialize_instruction(FEE, 0);
+    // find the next nonce giving a valid, but different, address
+    let nonce = (0..setup.nonce)
+        .rev()
+        .find(|nonce| {
+            Pubkey::create_program_address(
+                &[setup.pool.pubkey().as_ref(), &[*nonce]],
+                &program_id(),
+            )
+            .is_ok()
+        })
+        .unwrap();
+    instruction.data = AmmInstruction::Initialize {
+        nonce,
+        fee: FEE,
+        amount_a: AMOUNT_A,
+        amount_b: AMOUNT_B,
+        tolerance: 0,
+        deadline: None,
+    }
+    .pack();
+    let result = process(
+        &mut context,
+        &[instruction],
+        &[&setup.owner.wallet, &setup.pool],
+    )
+    .await;
+    assert_error(result, amm_error(AmmError::InvalidPDA));
+}
+
+#[tokio::test]
+async fn test_initialize_mint_decimals() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    // mint a may have more decimals than mint b
+    setup.create_accounts(&mut context, (9, 6)).await;
+    setup.initialize(&mut context).await.unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!((pool.decimals_a, pool.decimals_b), (9, 6));
+    let user = setup.create_user(&mut context).await;
+    for direction in [Direction::A2B, Direction::B2A] {
+        setup
+            .swap(&mut context, &user, 100_000, direction)
+            .await
+            .unwrap();
+    }
+    setup.lock_for_close(&mut context).await;
+    setup.terminate(&mut context, &setup.owner).await.unwrap();
+}
+
+/// Cut the pool account back to the layout of the first version of the program.
+async fn set_legacy_pool(context: &mut ProgramTestContext, setup: &PoolSetup) {
+    let pool = get_account(&mut context.banks_client, &setup.pool.pubkey())
+        .await
+        .unwrap();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let mut account = AccountSharedData::new(
+        rent.minimum_balance(AmmPool::LEGACY_LEN),
+        AmmPool::LEGACY_LEN,
+        &program_id(),
+    );
+    account.set_data(pool.data[..AmmPool::LEGACY_LEN].to_vec());
+    context.set_account(&setup.pool.pubkey(), &account);
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_pool() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (9, 6)).await;
+    setup.initialize(&mut context).await.unwrap();
+    let owner = &setup.owner;
+    // the first version bounded a drop of k with it
+    setup
+        .update_tolerance(&mut context, &owner.wallet, 50)
+        .await
+        .unwrap();
+    set_legacy_pool(&mut context, &setup).await;
+    let user = setup.create_user(&mut context).await;
+
+    // a legacy pool reads with the decimals zero, but the program asks for a migration
+    let account = get_account(&mut context.banks_client, &setup.pool.pubkey())
+        .await
+        .unwrap();
+    let legacy = AmmPool::unpack_any(&account.data).unwrap();
+    assert_eq!(legacy.status, PoolStatus::Nomal);
+    assert_eq!(legacy.owner, owner.wallet.pubkey());
+    assert_eq!((legacy.ka, legacy.kb), (AMOUNT_A, AMOUNT_B));
+    assert_eq!(legacy.tolerance, 50);
+    assert_eq!((legacy.decimals_a, legacy.decimals_b), (0, 0));
+    let result = setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await;
+    assert_error(result, amm_error(AmmError::LegacyPool));
+    let result = setup.update_status(&mut context, &owner.wallet, 2).await;
+    assert_error(result, amm_error(AmmError::LegacyPool));
+
+    // only the owner migrates, with the mints of the pool
+    let instruction = setup.migrate_instruction(&user.wallet.pubkey());
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+    let mut instruction = setup.migrate_instruction(&owner.wallet.pubkey());
+    instruction.accounts[2].pubkey = setup.mint_b.pubkey();
+    let result = process(&mut context, &[instruction], &[&owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidMint));
+
+    // the owner pays the rent of the grown account
+    let instruction = system_instruction::transfer(
+        &context.payer.pubkey(),
+        &owner.wallet.pubkey(),
+        1_000_000_000,
+    );
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let lamports = get_account(&mut context.banks_client, &owner.wallet.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+    let instruction = setup.migrate_instruction(&owner.wallet.pubkey());
+    process(&mut context, &[instruction], &[&owner.wallet])
+        .await
+        .unwrap();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let account = get_account(&mut context.banks_client, &setup.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(account.data.len(), AmmPool::LEN);
+    assert_eq!(account.lamports, rent.minimum_balance(AmmPool::LEN));
+    let owner_lamports = get_account(&mut context.banks_client, &owner.wallet.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+    assert_eq!(
+        lamports - owner_lamports,
+        rent.minimum_balance(AmmPool::LEN) - rent.minimum_balance(AmmPool::LEGACY_LEN)
+    );
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(
+        pool,
+        AmmPool {
+            tolerance: 0,
+            decimals_a: 9,
+            decimals_b: 6,
+            ..legacy
+        }
+    );
+
+    // the migrated pool works, and is migrated once
+    setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await
+        .unwrap();
+    let instruction = setup.migrate_instruction(&owner.wallet.pubkey());
+    let result = process(&mut context, &[instruction], &[&owner.wallet]).await;
+    assert_error(result, InstructionError::InvalidAccountData);
+}
+
+#[tokio::test]
+async fn test_migrate_locked_legacy_pool() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (9, 6)).await;
+    setup.initialize(&mut context).await.unwrap();
+    let owner = &setup.owner;
+    setup
+        .update_status(&mut context, &owner.wallet, 2)
+        .await
+        .unwrap();
+    set_legacy_pool(&mut context, &setup).await;
+    let instruction = system_instruction::transfer(
+        &context.payer.pubkey(),
+        &owner.wallet.pubkey(),
+        1_000_000_000,
+    );
+    process(&mut context, &[instruction], &[]).await.unwrap();
+
+    // a legacy pool has no lock time, the grace period starts at the migration
+    warp_seconds(&mut context, CLOSE_GRACE_PERIOD).await;
+    let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    let instruction = setup.migrate_instruction(&owner.wallet.pubkey());
+    process(&mut context, &[instruction], &[&owner.wallet])
+        .await
+        .unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.status, PoolStatus::Lock);
+    assert_eq!(pool.lock_time, clock.unix_timestamp);
+    let result = setup.terminate(&mut context, owner).await;
+    assert_error(result, amm_error(AmmError::GracePeriod));
+    warp_seconds(&mut context, CLOSE_GRACE_PERIOD).await;
+    setup.terminate(&mut context, owner).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_swap_a2b() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    let amount = 100_000;
+    setup
+        .swap(&mut context, &user, amount, Direction::A2B)
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let vault_b = token_balance(banks_client, &setup.vault_b).await;
+    let amount_out = AMOUNT_B - vault_b;
+    // (A+a)*(B-b)>=k, rounded in favor of the pool
+    assert_eq!(amount_out, 181_818);
+    let fee = math::calculate_fee(amount_out, FEE).unwrap();
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        AMOUNT_A + amount
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT - amount
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT + amount_out - fee
+    );
+    assert_eq!(token_balance(banks_client, &setup.fee_vault).await, fee);
+}
+
+#[tokio::test]
+async fn test_swap_b2a() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    let amount = 100_000;
+    setup
+        .swap(&mut context, &user, amount, Direction::B2A)
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let vault_b = token_balance(banks_client, &setup.vault_b).await;
+    let amount_in = vault_b - AMOUNT_B;
+    // (A-a)*(B+b)>=k, rounded in favor of the pool
+    assert_eq!(amount_in, 222_223);
+    let fee = math::calculate_fee(amount_in, FEE).unwrap();
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        AMOUNT_A - amount
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT + amount
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT - amount_in - fee
+    );
+    assert_eq!(token_balance(banks_client, &setup.fee_vault).await, fee);
+}
+
+#[tokio::test]
+async fn test_swap_errors() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    let wallet = &user.wallet;
+
+    // user must sign
+    let mut instruction = setup.swap_instruction(&user, 1_000, Direction::A2B);
+    instruction.accounts[5].is_signer = false;
+    let result = process(&mut context, &[instruction], &[]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    // vault a swapped with vault b
+    let mut instruction = setup.swap_instruction(&user, 1_000, Direction::A2B);
+    instruction.accounts[1].pubkey = setup.vault_b;
+    instruction.accounts[2].pubkey = setup.vault_a;
+    let result = process(&mut context, &[instruction], &[wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidVault));
+
+    // user token a of mint b
+    let mut instruction = setup.swap_instruction(&user, 1_000, Direction::A2B);
+    instruction.accounts[6].pubkey = user.token_b.pubkey();
+    let result = process(&mut context, &[instruction], &[wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidMint));
+
+    // direction
+    let mut instruction = setup.swap_instruction(&user, 1_000, Direction::A2B);
+    instruction.data[9] = 3;
+    let result = process(&mut context, &[instruction], &[wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidDirection));
+
+    // whole vault a
+    let result = setup
+        .swap(&mut context, &user, AMOUNT_A, Direction::B2A)
+        .await;
+    assert_error(result, amm_error(AmmError::CalculationError));
+
+    // more than the user holds
+    let result = setup
+        .swap(&mut context, &user, USER_AMOUNT + 1, Direction::A2B)
+        .await;
+    assert_error(result, amm_error(AmmError::InsufficientFunds));
+    // b cost of 900000 a is 18000000 plus fee
+    let result = setup
+        .swap(&mut context, &user, 900_000, Direction::B2A)
+        .await;
+    assert_error(result, amm_error(AmmError::InsufficientFunds));
+
+    // pool account of another program
+    let mut instruction = setup.swap_instruction(&user, 1_000, Direction::A2B);
+    instruction.accounts[0].pubkey = setup.vault_a;
+    let result = process(&mut context, &[instruction], &[wallet]).await;
+    assert_error(result, InstructionError::InvalidAccountData);
+
+    // nothing moved
+    let banks_client = &mut context.banks_client;
+    assert_eq!(token_balance(banks_client, &setup.vault_a).await, AMOUNT_A);
+    assert_eq!(token_balance(banks_client, &setup.vault_b).await, AMOUNT_B);
+}
+
+#[tokio::test]
+async fn test_swap_deadline() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    context.warp_to_slot(100).unwrap();
+    let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    let swap = |deadline| {
+        let mut instruction = setup.swap_instruction(&user, 1_000, Direction::A2B);
+        instruction.data = AmmInstruction::Swap {
+            amount: 1_000,
+            direction: Direction::A2B,
+            deadline: Some(deadline),
+        }
+        .pack();
+        instruction
+    };
+
+    let instruction = swap(Deadline::Slot(clock.slot - 1));
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::Expired));
+    let instruction = swap(Deadline::UnixTimestamp(clock.unix_timestamp - 1));
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::Expired));
+
+    let instruction = swap(Deadline::Slot(clock.slot + 100));
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let instruction = swap(Deadline::UnixTimestamp(clock.unix_timestamp + 3600));
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+
+    // terminate checks it too
+    let mut instruction = setup.terminate_instruction(&setup.owner);
+    instruction.data = AmmInstruction::Terminate {
+        deadline: Some(Deadline::Slot(clock.slot - 1)),
+    }
+    .pack();
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::Expired));
+}
+
+#[tokio::test]
+async fn test_update_status() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    let owner = &setup.owner.wallet;
+
+    setup.update_status(&mut context, owner, 2).await.unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.status, PoolStatus::Lock);
+    let result = setup.swap(&mut context, &user, 1_000, Direction::A2B).await;
+    assert_error(result, amm_error(AmmError::PoolLock));
+
+    setup.update_status(&mut context, owner, 1).await.unwrap();
+    setup
+        .swap(&mut context, &user, 1_000, Direction::A2B)
+        .await
+        .unwrap();
+
+    // unknown status, and only terminate closes a pool
+    let result = setup.update_status(&mut context, owner, 0).await;
+    assert_error(result, amm_error(AmmError::InvalidStatus));
+    let result = setup.update_status(&mut context, owner, 3).await;
+    assert_error(result, amm_error(AmmError::InvalidStatus));
+    let result = setup.update_status(&mut context, owner, 7).await;
+    assert_error(result, amm_error(AmmError::InvalidStatus));
+
+    // not owner
+    let result = setup.update_status(&mut context, &user.wallet, 2).await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+}
+
+#[tokio::test]
+async fn test_not_init() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    set_pool_data(&mut context, &setup, vec![0; AmmPool::LEN]);
+    let user = setup.create_user(&mut context).await;
+    let owner = &setup.owner;
+    let result = setup.update_status(&mut context, &owner.wallet, 2).await;
+    assert_error(result, InstructionError::UninitializedAccount);
+    let result = setup.update_tolerance(&mut context, &owner.wallet, 5).await;
+    assert_error(result, InstructionError::UninitializedAccount);
+    let result = setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await;
+    assert_error(result, InstructionError::UninitializedAccount);
+    let instruction =
+        setup.withdrawal_fee_instruction(&owner.wallet.pubkey(), &owner.token_b.pubkey());
+    let result = process(&mut context, &[instruction], &[&owner.wallet]).await;
+    assert_error(result, InstructionError::UninitializedAccount);
+    let result = setup.terminate(&mut context, owner).await;
+    assert_error(result, InstructionError::UninitializedAccount);
+}
+
+#[tokio::test]
+async fn test_update_tolerance() {
+    let (mut context, setup) = setup_pool().await;
+    let owner = &setup.owner.wallet;
+    setup
+        .update_tolerance(&mut context, owner, 1_000)
+        .await
+        .unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.tolerance, 1_000);
+
+    let result = setup
+        .update_tolerance(&mut context, owner, math::BPS_MUL + 1)
+        .await;
+    assert_error(result, amm_error(AmmError::InvalidInput));
+
+    let other = Keypair::new();
+    let result = setup.update_tolerance(&mut context, &other, 5).await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+
+    let mut instruction =
+        amm::instruction::update_tolerance(&program_id(), &setup.pool.pubkey(), &owner.pubkey(), 5)
+            .unwrap();
+    instruction.accounts[1].is_signer = false;
+    let result = process(&mut context, &[instruction], &[]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+}
+
+#[tokio::test]
+async fn test_swap_out_of_tolerance() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    setup
+        .update_tolerance(&mut context, &setup.owner.wallet, 100)
+        .await
+        .unwrap();
+    // selling 10% of the reserve moves the price by 17%
+    let result = setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await;
+    assert_error(result, amm_error(AmmError::OutOfTolerance));
+    let result = setup
+        .swap(&mut context, &user, 100_000, Direction::B2A)
+        .await;
+    assert_error(result, amm_error(AmmError::OutOfTolerance));
+    // 0.1% moves it by 0.2%
+    setup
+        .swap(&mut context, &user, 1_000, Direction::A2B)
+        .await
+        .unwrap();
+    setup
+        .update_tolerance(&mut context, &setup.owner.wallet, 0)
+        .await
+        .unwrap();
+    setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_withdrawal_fee() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    let owner = &setup.owner;
+
+    // nothing to withdraw yet
+    let result = setup
+        .withdrawal_fee(&mut context, &owner.wallet, &owner.token_b.pubkey())
+        .await;
+    assert_error(result, amm_error(AmmError::NoFee));
+
+    setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await
+        .unwrap();
+    let fee = token_balance(&mut context.banks_client, &setup.fee_vault).await;
+    assert!(fee > 0);
+
+    // not owner
+    let result = setup
+        .withdrawal_fee(&mut context, &user.wallet, &user.token_b.pubkey())
+        .await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+
+    // vault b is not the fee vault
+    let mut instruction =
+        setup.withdrawal_fee_instruction(&owner.wallet.pubkey(), &owner.token_b.pubkey());
+    instruction.accounts[2].pubkey = setup.vault_b;
+    let result = process(&mut context, &[instruction], &[&owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidVault));
+
+    let before = token_balance(&mut context.banks_client, &owner.token_b.pubkey()).await;
+    setup
+        .withdrawal_fee(&mut context, &owner.wallet, &owner.token_b.pubkey())
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &owner.token_b.pubkey()).await,
+        before + fee
+    );
+    assert_eq!(token_balance(banks_client, &setup.fee_vault).await, 0);
+}
+
+#[tokio::test]
+async fn test_terminate() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let vault_a = token_balance(banks_client, &setup.vault_a).await;
+    let vault_b = token_balance(banks_client, &setup.vault_b).await;
+    let fee = token_balance(banks_client, &setup.fee_vault).await;
+    let owner_a = token_balance(banks_client, &setup.owner.token_a.pubkey()).await;
+    let owner_b = token_balance(banks_client, &setup.owner.token_b.pubkey()).await;
+
+    // not owner
+    setup.lock_for_close(&mut context).await;
+    let result = setup.terminate(&mut context, &user).await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+
+    setup.terminate(&mut context, &setup.owner).await.unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &setup.owner.token_a.pubkey()).await,
+        owner_a + vault_a
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.owner.token_b.pubkey()).await,
+        owner_b + vault_b + fee
+    );
+    for key in [
+        setup.pool.pubkey(),
+        setup.vault_a,
+        setup.vault_b,
+        setup.fee_vault,
+    ] {
+        assert!(get_account(banks_client, &key).await.is_none());
+    }
+    // owner got the rent of every account back
+    let owner = get_account(banks_client, &setup.owner.wallet.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(owner.lamports, OWNER_LAMPORTS);
+}
+
+#[tokio::test]
+async fn test_terminate_grace_period() {
+    let (mut context, setup) = setup_pool().await;
+    let owner = &setup.owner;
+    // an open pool can not be terminated
+    let result = setup.terminate(&mut context, owner).await;
+    assert_error(result, amm_error(AmmError::GracePeriod));
+
+    // nor one locked for less than the grace period
+    let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    setup
+        .update_status(&mut context, &owner.wallet, 2)
+        .await
+        .unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.lock_time, clock.unix_timestamp);
+    warp_seconds(&mut context, CLOSE_GRACE_PERIOD - 1).await;
+    let result = setup.terminate(&mut context, owner).await;
+    assert_error(result, amm_error(AmmError::GracePeriod));
+
+    // locking again keeps the first lock time
+    setup
+        .update_status(&mut context, &owner.wallet, 2)
+        .await
+        .unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.lock_time, clock.unix_timestamp);
+
+    // unlocking starts over
+    setup
+        .update_status(&mut context, &owner.wallet, 1)
+        .await
+        .unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.lock_time, 0);
+    setup
+        .update_status(&mut context, &owner.wallet, 2)
+        .await
+        .unwrap();
+    warp_seconds(&mut context, CLOSE_GRACE_PERIOD - 1).await;
+    let result = setup.terminate(&mut context, owner).await;
+    assert_error(result, amm_error(AmmError::GracePeriod));
+
+    warp_seconds(&mut context, 1).await;
+    setup.terminate(&mut context, owner).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_use_after_terminate() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    let owner = &setup.owner;
+    setup.lock_for_close(&mut context).await;
+    // anything after terminate in the same transaction sees the pool closed
+    let terminate = setup.terminate_instruction(owner);
+    for (instruction, signer) in [
+        (
+            setup.swap_instruction(&user, 100_000, Direction::A2B),
+            &user.wallet,
+        ),
+        (
+            amm::instruction::update_status(
+                &program_id(),
+                &setup.pool.pubkey(),
+                &owner.wallet.pubkey(),
+                1,
+            )
+            .unwrap(),
+            &owner.wallet,
+        ),
+    ] {
+        let result = process(
+            &mut context,
+            &[terminate.clone(), instruction],
+            &[&owner.wallet, signer],
+        )
+        .await;
+        assert_error(result, amm_error(AmmError::PoolClosed));
+    }
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.status, PoolStatus::Lock);
+
+    // lamports sent back keep the account alive, but closed for good
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let revive = system_instruction::transfer(
+        &context.payer.pubkey(),
+        &setup.pool.pubkey(),
+        rent.minimum_balance(AmmPool::LEN),
+    );
+    process(&mut context, &[terminate, revive], &[&owner.wallet])
+        .await
+        .unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(
+        pool,
+        AmmPool {
+            status: PoolStatus::Closed,
+            ..AmmPool::default()
+        }
+    );
+    let result = setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await;
+    assert_error(result, amm_error(AmmError::PoolClosed));
+    let result = setup.update_status(&mut context, &owner.wallet, 1).await;
+    assert_error(result, amm_error(AmmError::PoolClosed));
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::PoolClosed));
+}
+
+#[tokio::test]
+async fn test_terminate_wrong_vault() {
+    let (mut context, setup) = setup_pool().await;
+    let mut instruction = setup.terminate_instruction(&setup.owner);
+    instruction.accounts[4].pubkey = setup.vault_b;
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidVault));
+}
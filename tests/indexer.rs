@@ -0,0 +1,415 @@
+use amm::{
+    event::AmmEvent,
+    indexer::{AmmAccounts, DecodedInstruction, Indexer, RecordedTransaction},
+    instruction::{AmmInstruction, Direction},
+    state::PoolStatus,
+};
+use solana_program::pubkey::Pubkey;
+use std::{path::PathBuf, str::FromStr};
+
+const PROGRAM_ID: &str = "aAmLZ9yP1adeZyRC9qMskX9e1Ma2gR4ktpyrDCWPkdm";
+const POOL: &str = "3gLESRnfLgzAqu6PwGhBwsiBsnQ7BAtyWHhZ5zNcDPMF";
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/indexer")
+}
+
+fn replay() -> Indexer {
+    let mut indexer = Indexer::new(Pubkey::from_str(PROGRAM_ID).unwrap());
+    indexer.process_dir(fixtures()).unwrap();
+    indexer
+}
+
+#[test]
+fn test_replay_history() {
+    let indexer = replay();
+    let pool = Pubkey::from_str(POOL).unwrap();
+    let names: Vec<&str> = indexer.history.iter().map(|e| e.instruction).collect();
+    // failed swap is skipped, file order is fixed by slot
+    assert_eq!(
+        names,
+        vec![
+            "initialize",
+            "swap",
+            "swap",
+            "withdrawal_fee",
+            "update_status",
+            "terminate"
+        ]
+    );
+    assert!(indexer.history.iter().all(|e| e.pool == pool));
+    let slots: Vec<u64> = indexer.history.iter().map(|e| e.slot).collect();
+    assert_eq!(slots, vec![100, 105, 110, 120, 130, 140]);
+
+    // swap without event, from token balances
+    let state = indexer.history[1].state;
+    assert_eq!(state.reserve_a, 1_100_000);
+    assert_eq!(state.reserve_b, 1_818_181);
+    assert_eq!(state.cumulative_fee, 545);
+    // swap with event
+    let state = indexer.history[2].state;
+    assert_eq!(state.reserve_a, 1_000_000);
+    assert_eq!(state.reserve_b, 2_000_001);
+    assert_eq!(state.cumulative_fee, 1_090);
+    assert_eq!(state.swap_count, 2);
+    // withdrawal and lock
+    assert_eq!(indexer.history[3].state.fee_withdrawn, 1_090);
+    assert_eq!(indexer.history[4].state.status, PoolStatus::Lock);
+
+    let state = indexer.pools[&pool];
+    assert!(state.terminated);
+    assert_eq!(state.status, PoolStatus::Closed);
+    assert_eq!(state.reserve_a, 0);
+    assert_eq!(state.reserve_b, 0);
+    assert_eq!(state.cumulative_fee, 1_090);
+}
+
+#[test]
+fn test_write_csv() {
+    let indexer = replay();
+    let mut out = vec![];
+    indexer.write_csv(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 1 + indexer.history.len());
+    assert!(lines[0].starts_with("slot,block_time,signature,pool,instruction"));
+    assert_eq!(
+        lines[3],
+        format!(
+            "110,1650000110,sig3swapevent,{},swap,Nomal,false,1000000,2000001,1090,0,2,0,0,0",
+            POOL
+        )
+    );
+}
+
+#[test]
+fn test_replay_orders() {
+    let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+    let pool = Pubkey::from_str(POOL).unwrap();
+    let orders = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let owner = Pubkey::new_unique();
+    let keeper = Pubkey::new_unique();
+    let place = |order: Pubkey| DecodedInstruction {
+        instruction: AmmInstruction::PlaceOrder {
+            amount_in: 10_000,
+            direction: Direction::A2B,
+            min_amount_out: 19_000,
+            reward: 10_000,
+        },
+        accounts: AmmAccounts {
+            pool,
+            signer: Some(owner),
+            orders: vec![order],
+            ..AmmAccounts::default()
+        },
+    };
+    let mut indexer = Indexer::new(program_id);
+    indexer.process_transaction(&RecordedTransaction {
+        slot: 100,
+        instructions: vec![place(orders[0]), place(orders[1])],
+        ..RecordedTransaction::default()
+    });
+    assert_eq!(indexer.pools[&pool].open_orders, 2);
+
+    // only the first order crossed
+    let fill = AmmEvent::FillOrder {
+        pool,
+        order: orders[0],
+        keeper,
+        direction: Direction::A2B,
+        amount_in: 10_000,
+        amount_out: 19_743,
+        fee: 60,
+        reserve_a: 1_010_000,
+        reserve_b: 1_980_197,
+    };
+    indexer.process_transaction(&RecordedTransaction {
+        slot: 110,
+        instructions: vec![DecodedInstruction {
+            instruction: AmmInstruction::FillOrders {},
+            accounts: AmmAccounts {
+                pool,
+                signer: Some(keeper),
+                orders: orders.to_vec(),
+                ..AmmAccounts::default()
+            },
+        }],
+        events: vec![fill],
+        ..RecordedTransaction::default()
+    });
+    let state = indexer.pools[&pool];
+    assert_eq!(state.open_orders, 1);
+    assert_eq!((state.reserve_a, state.reserve_b), (1_010_000, 1_980_197));
+    assert_eq!(state.cumulative_fee, 60);
+
+    indexer.process_transaction(&RecordedTransaction {
+        slot: 120,
+        instructions: vec![DecodedInstruction {
+            instruction: AmmInstruction::CancelOrder {},
+            accounts: AmmAccounts {
+                pool,
+                signer: Some(owner),
+                orders: vec![orders[1]],
+                ..AmmAccounts::default()
+            },
+        }],
+        ..RecordedTransaction::default()
+    });
+    assert_eq!(indexer.pools[&pool].open_orders, 0);
+    let names: Vec<&str> = indexer.history.iter().map(|e| e.instruction).collect();
+    assert_eq!(
+        names,
+        vec!["place_order", "place_order", "fill_orders", "cancel_order"]
+    );
+}
+
+#[test]
+fn test_replay_long_term_orders() {
+    let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+    let pool = Pubkey::from_str(POOL).unwrap();
+    let owner = Pubkey::new_unique();
+    let order = Pubkey::new_unique();
+    let accounts = AmmAccounts {
+        pool,
+        signer: Some(owner),
+        orders: vec![order],
+        ..AmmAccounts::default()
+    };
+    let mut indexer = Indexer::new(program_id);
+    indexer.process_transaction(&RecordedTransaction {
+        slot: 1_000,
+        instructions: vec![DecodedInstruction {
+            instruction: AmmInstruction::PlaceLongTermOrder {
+                amount_in: 1_000_000,
+                direction: Direction::A2B,
+                slots: 2_000,
+            },
+            accounts: accounts.clone(),
+        }],
+        ..RecordedTransaction::default()
+    });
+    assert_eq!(indexer.pools[&pool].long_term_orders, 1);
+
+    // the virtual orders ran before the cancel
+    let virtual_orders = AmmEvent::VirtualOrders {
+        pool,
+        slot: 2_000,
+        amount_a_in: 500_000,
+        amount_a_out: 0,
+        amount_b_in: 0,
+        amount_b_out: 666_667,
+        fee: 2_001,
+        reserve_a: 1_500_000,
+        reserve_b: 1_333_333,
+    };
+    indexer.process_transaction(&RecordedTransaction {
+        slot: 2_000,
+        instructions: vec![DecodedInstruction {
+            instruction: AmmInstruction::CancelLongTermOrder {},
+            accounts,
+        }],
+        events: vec![virtual_orders],
+        ..RecordedTransaction::default()
+    });
+    let state = indexer.pools[&pool];
+    assert_eq!(state.long_term_orders, 0);
+    assert_eq!((state.reserve_a, state.reserve_b), (1_500_000, 1_333_333));
+    assert_eq!(state.cumulative_fee, 2_001);
+    let names: Vec<&str> = indexer.history.iter().map(|e| e.instruction).collect();
+    assert_eq!(
+        names,
+        vec!["place_long_term_order", "cancel_long_term_order"]
+    );
+}
+
+#[test]
+fn test_replay_referral() {
+    let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+    let pool = Pubkey::from_str(POOL).unwrap();
+    let user = Pubkey::new_unique();
+    let accounts = AmmAccounts {
+        pool,
+        signer: Some(user),
+        ..AmmAccounts::default()
+    };
+    let swap = DecodedInstruction {
+        instruction: AmmInstruction::Swap {
+            amount: 100_000,
+            direction: Direction::A2B,
+            deadline: None,
+        },
+        accounts,
+    };
+    let swap_event = |reserve_a, reserve_b| AmmEvent::Swap {
+        pool,
+        user,
+        direction: Direction::A2B,
+        amount_in: 100_000,
+        amount_out: 181_818,
+        fee: 546,
+        reserve_a,
+        reserve_b,
+    };
+    // only the second swap has a referrer, its share never reaches the fee vault
+    let mut indexer = Indexer::new(program_id);
+    indexer.process_transaction(&RecordedTransaction {
+        slot: 1_000,
+        instructions: vec![swap.clone(), swap],
+        events: vec![
+            swap_event(1_100_000, 1_818_182),
+            AmmEvent::Referral {
+                pool,
+                user,
+                referrer: Pubkey::new_unique(),
+                amount: 109,
+            },
+            swap_event(1_200_000, 1_666_667),
+        ],
+        ..RecordedTransaction::default()
+    });
+    assert_eq!(indexer.history[0].state.cumulative_fee, 546);
+    let state = indexer.pools[&pool];
+    assert_eq!(state.cumulative_fee, 546 + 546 - 109);
+    assert_eq!(state.swap_count, 2);
+    assert_eq!((state.reserve_a, state.reserve_b), (1_200_000, 1_666_667));
+}
+
+#[test]
+fn test_replay_farm() {
+    let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+    let pool = Pubkey::from_str(POOL).unwrap();
+    let owner = Pubkey::new_unique();
+    let accounts = AmmAccounts {
+        pool,
+        signer: Some(owner),
+        ..AmmAccounts::default()
+    };
+    let mut indexer = Indexer::new(program_id);
+    indexer.process_transaction(&RecordedTransaction {
+        slot: 1_000,
+        instructions: vec![
+            DecodedInstruction {
+                instruction: AmmInstruction::Stake {
+                    amount_a: 100_000,
+                    deadline: None,
+                },
+                accounts: accounts.clone(),
+            },
+            DecodedInstruction {
+                instruction: AmmInstruction::Unstake {
+                    shares: 40_000,
+                    deadline: None,
+                },
+                accounts: accounts.clone(),
+            },
+            DecodedInstruction {
+                instruction: AmmInstruction::ClaimRewards {},
+                accounts,
+            },
+        ],
+        events: vec![
+            AmmEvent::Stake {
+                pool,
+                owner,
+                amount_a: 100_000,
+                amount_b: 200_000,
+                shares: 100_000,
+                reserve_a: 1_100_000,
+                reserve_b: 2_200_000,
+            },
+            AmmEvent::Unstake {
+                pool,
+                owner,
+                amount_a: 40_000,
+                amount_b: 80_000,
+                shares: 40_000,
+                rewards: 500,
+                reserve_a: 1_060_000,
+                reserve_b: 2_120_000,
+            },
+            AmmEvent::ClaimRewards {
+                pool,
+                owner,
+                amount: 0,
+            },
+        ],
+        ..RecordedTransaction::default()
+    });
+    let names: Vec<&str> = indexer.history.iter().map(|e| e.instruction).collect();
+    assert_eq!(names, vec!["stake", "unstake", "claim_rewards"]);
+    assert_eq!(indexer.history[0].state.staked_shares, 100_000);
+    let state = indexer.pools[&pool];
+    assert_eq!(state.staked_shares, 60_000);
+    assert_eq!((state.reserve_a, state.reserve_b), (1_060_000, 2_120_000));
+}
+
+#[test]
+fn test_replay_initialize_event_reserves() {
+    let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+    let pool = Pubkey::from_str(POOL).unwrap();
+    let accounts = AmmAccounts {
+        pool,
+        ..AmmAccounts::default()
+    };
+    // a transfer fee on mint a kept 1% of the deposit out of the vault
+    let mut indexer = Indexer::new(program_id);
+    indexer.process_transaction(&RecordedTransaction {
+        slot: 100,
+        instructions: vec![DecodedInstruction {
+            instruction: AmmInstruction::Initialize {
+                nonce: 255,
+                fee: 3_000,
+                amount_a: 1_000_000,
+                amount_b: 2_000_000,
+                tolerance: 0,
+                deadline: None,
+            },
+            accounts,
+        }],
+        events: vec![AmmEvent::Initialize {
+            pool,
+            owner: Pubkey::new_unique(),
+            fee_rate: 3_000,
+            tolerance: 0,
+            reserve_a: 990_000,
+            reserve_b: 2_000_000,
+        }],
+        ..RecordedTransaction::default()
+    });
+    let state = indexer.pools[&pool];
+    assert_eq!(state.status, PoolStatus::Nomal);
+    assert_eq!((state.reserve_a, state.reserve_b), (990_000, 2_000_000));
+}
+
+#[test]
+fn test_events_from_logs_of_program() {
+    let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+    let other = Pubkey::new_unique();
+    let event = |amount| AmmEvent::WithdrawalFee {
+        pool: Pubkey::from_str(POOL).unwrap(),
+        owner: Pubkey::new_unique(),
+        amount,
+    };
+    let data = |amount| format!("Program data: {}", base64::encode(event(amount).pack()));
+    let logs = vec![
+        format!("Program {} invoke [1]", other),
+        data(1),
+        format!("Program {} success", other),
+        format!("Program {} invoke [1]", program_id),
+        data(2),
+        format!("Program {} invoke [2]", other),
+        data(3),
+        format!("Program {} failed: custom program error: 0x0", other),
+        data(4),
+        format!("Program {} success", program_id),
+        data(5),
+    ];
+    let amounts: Vec<u64> = AmmEvent::from_logs(&program_id, &logs)
+        .into_iter()
+        .map(|event| match event {
+            AmmEvent::WithdrawalFee { amount, .. } => amount,
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(amounts, vec![2, 4]);
+}
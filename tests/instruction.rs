@@ -0,0 +1,153 @@
+use amm::{
+    error::AmmError,
+    instruction::{AmmInstruction, Deadline, Direction},
+};
+use solana_program::program_error::ProgramError;
+
+#[test]
+fn test_unpack_short_input() {
+    let instructions = [
+        AmmInstruction::Initialize {
+            nonce: 255,
+            fee: 3_000,
+            amount_a: 1_000_000,
+            amount_b: 2_000_000,
+            tolerance: 10,
+            deadline: None,
+        },
+        AmmInstruction::UpdateStatus { status: 2 },
+        AmmInstruction::UpdateTolerance { tolerance: 10 },
+        AmmInstruction::UpdateReferralShare { share: 2_000 },
+        AmmInstruction::Swap {
+            amount: 100_000,
+            direction: Direction::A2B,
+            deadline: None,
+        },
+        AmmInstruction::Swap {
+            amount: 100_000,
+            direction: Direction::B2A,
+            deadline: Some(Deadline::Slot(1_000)),
+        },
+        AmmInstruction::Terminate {
+            deadline: Some(Deadline::UnixTimestamp(1_650_000_000)),
+        },
+        AmmInstruction::PlaceOrder {
+            amount_in: 10_000,
+            direction: Direction::B2A,
+            min_amount_out: 4_900,
+            reward: 10_000,
+        },
+        AmmInstruction::CancelOrder {},
+        AmmInstruction::FillOrders {},
+        AmmInstruction::PlaceLongTermOrder {
+            amount_in: 1_000_000,
+            direction: Direction::A2B,
+            slots: 10_000,
+        },
+        AmmInstruction::CancelLongTermOrder {},
+        AmmInstruction::ExecuteVirtualOrders {},
+        AmmInstruction::FundFarm {
+            amount: 1_000_000,
+            slots: 1_000,
+        },
+        AmmInstruction::Stake {
+            amount_a: 100_000,
+            deadline: None,
+        },
+        AmmInstruction::Stake {
+            amount_a: 100_000,
+            deadline: Some(Deadline::Slot(1_000)),
+        },
+        AmmInstruction::Unstake {
+            shares: 100_000,
+            deadline: None,
+        },
+        AmmInstruction::Unstake {
+            shares: 100_000,
+            deadline: Some(Deadline::UnixTimestamp(1_650_000_000)),
+        },
+        AmmInstruction::ClaimRewards {},
+        AmmInstruction::UpdateDynamicFee {
+            mode: 1,
+            min_fee: 1_000,
+            max_fee: 30_000,
+        },
+        AmmInstruction::Migrate {},
+    ];
+    for instruction in instructions.iter() {
+        let packed = instruction.pack();
+        assert_eq!(AmmInstruction::unpack(&packed).as_ref(), Ok(instruction));
+        // every truncation fails instead of panicking, deadline is optional
+        let fixed_len = match instruction {
+            AmmInstruction::Swap {
+                deadline: Some(_), ..
+            }
+            | AmmInstruction::Stake {
+                deadline: Some(_), ..
+            }
+            | AmmInstruction::Unstake {
+                deadline: Some(_), ..
+            }
+            | AmmInstruction::Terminate { .. } => packed.len() - Deadline::LEN,
+            _ => packed.len(),
+        };
+        for len in (0..packed.len()).filter(|len| *len != fixed_len) {
+            assert_eq!(
+                AmmInstruction::unpack(&packed[..len]),
+                Err(ProgramError::from(AmmError::InvalidInstruction))
+            );
+        }
+    }
+}
+
+#[test]
+fn test_unpack_deadline() {
+    // swap without a deadline, as sent by older clients
+    let mut data = vec![10];
+    data.extend_from_slice(&100_000u64.to_le_bytes());
+    data.push(1);
+    assert_eq!(
+        AmmInstruction::unpack(&data),
+        Ok(AmmInstruction::Swap {
+            amount: 100_000,
+            direction: Direction::A2B,
+            deadline: None,
+        })
+    );
+    // so do stake and unstake
+    let mut stake = vec![18];
+    stake.extend_from_slice(&100_000u64.to_le_bytes());
+    assert_eq!(
+        AmmInstruction::unpack(&stake),
+        Ok(AmmInstruction::Stake {
+            amount_a: 100_000,
+            deadline: None,
+        })
+    );
+    // unknown deadline kind
+    data.push(3);
+    data.extend_from_slice(&1u64.to_le_bytes());
+    assert_eq!(
+        AmmInstruction::unpack(&data),
+        Err(ProgramError::from(AmmError::InvalidInstruction))
+    );
+}
+
+#[test]
+fn test_unpack_direction() {
+    assert_eq!(Direction::try_from(1), Ok(Direction::A2B));
+    assert_eq!(Direction::try_from(2), Ok(Direction::B2A));
+    for direction in [0, 3, u8::MAX] {
+        assert_eq!(
+            Direction::try_from(direction),
+            Err(AmmError::InvalidDirection)
+        );
+        let mut data = vec![10];
+        data.extend_from_slice(&100_000u64.to_le_bytes());
+        data.push(direction);
+        assert_eq!(
+            AmmInstruction::unpack(&data),
+            Err(ProgramError::from(AmmError::InvalidDirection))
+        );
+    }
+}
@@ -0,0 +1,509 @@
+use amm::{
+    error::AmmError,
+    math::{
+        calculate_amount_a2b, calculate_amount_b2a, calculate_dynamic_fee, calculate_fee,
+        calculate_k, calculate_price, calculate_price_move, calculate_proceeds,
+        calculate_referral_fee, calculate_stake, calculate_unstake, calculate_virtual_trade,
+        check_k, check_price_impact, decay_volatility, split_fee, VirtualTrade, BPS_MUL,
+        PERCENT_MUL, PRICE_MUL, RATE_MUL, VOLATILITY_HALF_LIFE,
+    },
+};
+use proptest::prelude::*;
+
+fn product(a: u128, b: u128) -> u128 {
+    // an overflowing product is bigger than any product of two u64
+    a.saturating_mul(b)
+}
+
+/// pricing before the switch to live reserves: fixed k, both ways floored
+fn old_a2b(k: u128, reserve_a: u64, reserve_b: u64, amount_a: u64) -> Option<u64> {
+    let changed_b = k.checked_div(reserve_a as u128 + amount_a as u128)?;
+    let amount_b = (reserve_b as u128).checked_sub(changed_b)?;
+    if amount_b >= reserve_b as u128 {
+        return None;
+    }
+    Some(amount_b as u64)
+}
+
+fn old_b2a(k: u128, reserve_a: u64, reserve_b: u64, amount_a: u64) -> Option<u64> {
+    let changed_a = (reserve_a as u128)
+        .checked_sub(amount_a as u128)
+        .filter(|v| *v > 0)?;
+    let amount_b = (k / changed_a).checked_sub(reserve_b as u128)?;
+    if amount_b > u64::MAX as u128 {
+        return None;
+    }
+    Some(amount_b as u64)
+}
+
+prop_compose! {
+    /// reserves with an amount of a that fits in vault a
+    fn pool_and_amount_a()(reserve_a in 1..u64::MAX, reserve_b in 1..u64::MAX)
+        (amount_a in 0..reserve_a, reserve_a in Just(reserve_a), reserve_b in Just(reserve_b))
+        -> (u64, u64, u64) {
+        (reserve_a, reserve_b, amount_a)
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_no_panic(reserve_a: u64, reserve_b: u64, amount_a: u64) {
+        let _ = calculate_amount_a2b(reserve_a, reserve_b, amount_a);
+        let _ = calculate_amount_b2a(reserve_a, reserve_b, amount_a);
+    }
+
+    #[test]
+    fn test_a2b_k_never_decreases(reserve_a: u64, reserve_b: u64, amount_a: u64) {
+        if let Ok(amount_b) = calculate_amount_a2b(reserve_a, reserve_b, amount_a) {
+            prop_assert!(amount_b < reserve_b);
+            let k = product(reserve_a as u128, reserve_b as u128);
+            let k_new = product(
+                reserve_a as u128 + amount_a as u128,
+                (reserve_b - amount_b) as u128,
+            );
+            prop_assert!(k_new >= k);
+        }
+    }
+
+    #[test]
+    fn test_b2a_k_never_decreases((reserve_a, reserve_b, amount_a) in pool_and_amount_a()) {
+        if let Ok(amount_b) = calculate_amount_b2a(reserve_a, reserve_b, amount_a) {
+            prop_assert!(amount_a < reserve_a);
+            let k = product(reserve_a as u128, reserve_b as u128);
+            let k_new = product(
+                (reserve_a - amount_a) as u128,
+                reserve_b as u128 + amount_b as u128,
+            );
+            prop_assert!(k_new >= k);
+        }
+    }
+
+    #[test]
+    fn test_b2a_amount_over_vault(reserve_a: u64, reserve_b: u64, extra: u64) {
+        let amount_a = reserve_a.saturating_add(extra);
+        prop_assert!(calculate_amount_b2a(reserve_a, reserve_b, amount_a).is_err());
+    }
+
+    #[test]
+    fn test_round_trip_a2b2a(reserve_a: u64, reserve_b: u64, amount_a: u64) {
+        if let Ok(amount_b) = calculate_amount_a2b(reserve_a, reserve_b, amount_a) {
+            // buying the same a back costs at least the b received
+            let reserve_a = reserve_a as u128 + amount_a as u128;
+            if reserve_a <= u64::MAX as u128 {
+                let cost = calculate_amount_b2a(reserve_a as u64, reserve_b - amount_b, amount_a);
+                if let Ok(cost) = cost {
+                    prop_assert!(cost >= amount_b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_b2a2b((reserve_a, reserve_b, amount_a) in pool_and_amount_a()) {
+        if let Ok(amount_b) = calculate_amount_b2a(reserve_a, reserve_b, amount_a) {
+            // selling the a received back returns at most the b paid
+            if let Some(reserve_b) = reserve_b.checked_add(amount_b) {
+                let amount = calculate_amount_a2b(reserve_a - amount_a, reserve_b, amount_a);
+                if let Ok(amount) = amount {
+                    prop_assert!(amount <= amount_b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_a2b_against_old_pricing(reserve_a: u64, reserve_b: u64, amount_a: u64) {
+        // while the reserves are the initial deposits, the new pricing pays
+        // the same or one unit less
+        let k = reserve_a as u128 * reserve_b as u128;
+        if let Ok(amount_b) = calculate_amount_a2b(reserve_a, reserve_b, amount_a) {
+            // old_a2b refuses to empty vault b, compare the raw formula
+            let old = reserve_b as u128 - k / (reserve_a as u128 + amount_a as u128);
+            prop_assert!(amount_b as u128 == old || amount_b as u128 + 1 == old);
+        }
+    }
+
+    #[test]
+    fn test_b2a_against_old_pricing((reserve_a, reserve_b, amount_a) in pool_and_amount_a()) {
+        // while the reserves are the initial deposits, the new pricing
+        // charges the same or one unit more
+        let k = reserve_a as u128 * reserve_b as u128;
+        if let Ok(amount_b) = calculate_amount_b2a(reserve_a, reserve_b, amount_a) {
+            let old = old_b2a(k, reserve_a, reserve_b, amount_a).unwrap();
+            prop_assert!(amount_b == old || amount_b == old + 1);
+        }
+    }
+
+    #[test]
+    fn test_fee(amount: u64, fee_rate in 0..=PERCENT_MUL) {
+        prop_assert!(calculate_fee(amount, fee_rate).unwrap() <= amount);
+    }
+
+    #[test]
+    fn test_fee_no_panic(amount: u64, fee_rate: u64) {
+        let _ = calculate_fee(amount, fee_rate);
+    }
+
+    #[test]
+    fn test_fee_never_zero(amount in 1..u64::MAX, fee_rate in 1..=PERCENT_MUL) {
+        prop_assert!(calculate_fee(amount, fee_rate).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_split_fee_covers_fee(amount: u64, fee_rate in 0..=PERCENT_MUL) {
+        let (net, fee) = split_fee(amount, fee_rate);
+        prop_assert_eq!(net + fee, amount);
+        prop_assert!(fee >= calculate_fee(net, fee_rate).unwrap());
+    }
+
+    #[test]
+    fn test_referral_fee_within_fee(fee: u64, share: u64) {
+        prop_assert!(calculate_referral_fee(fee, share) <= fee);
+    }
+
+    #[test]
+    fn test_price_move_at_most_percent_mul(
+        reserve_a0: u64,
+        reserve_b0: u64,
+        reserve_a1: u64,
+        reserve_b1: u64,
+    ) {
+        prop_assert!(calculate_price_move(reserve_a0, reserve_b0, reserve_a1, reserve_b1) <= PERCENT_MUL);
+    }
+
+    #[test]
+    fn test_price_move_of_same_price(reserve_a in 1..u64::MAX, reserve_b in 1..u64::MAX, scale in 1..1_000u64) {
+        // scaling both reserves keeps the price
+        if let (Some(scaled_a), Some(scaled_b)) =
+            (reserve_a.checked_mul(scale), reserve_b.checked_mul(scale))
+        {
+            prop_assert_eq!(calculate_price_move(reserve_a, reserve_b, scaled_a, scaled_b), 0);
+        }
+    }
+
+    #[test]
+    fn test_volatility_decays(volatility: u64, slots: u64, more: u64) {
+        let decayed = decay_volatility(volatility, slots);
+        prop_assert!(decayed <= volatility);
+        prop_assert!(decay_volatility(volatility, slots.saturating_add(more)) <= decayed);
+    }
+
+    #[test]
+    fn test_swap_keeps_k(reserve_a: u64, reserve_b: u64, amount_a: u64) {
+        let amount_b = calculate_amount_a2b(reserve_a, reserve_b, amount_a);
+        if let (Some(changed_a), Ok(amount_b)) = (reserve_a.checked_add(amount_a), amount_b) {
+            let k_after = calculate_k(changed_a, reserve_b - amount_b);
+            prop_assert!(check_k(calculate_k(reserve_a, reserve_b), k_after).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_price_impact_no_panic(
+        reserve_a0: u64,
+        reserve_b0: u64,
+        reserve_a1: u64,
+        reserve_b1: u64,
+        tolerance: u64,
+    ) {
+        let _ = check_price_impact(reserve_a0, reserve_b0, reserve_a1, reserve_b1, tolerance);
+    }
+
+    #[test]
+    fn test_price_impact_exact(
+        reserve_a0: u32,
+        reserve_b0: u32,
+        reserve_a1: u32,
+        reserve_b1: u32,
+        tolerance in 1..=BPS_MUL,
+    ) {
+        // the products of u32 need no shift, the bound is exact
+        let before = reserve_b0 as u128 * reserve_a1 as u128;
+        let after = reserve_b1 as u128 * reserve_a0 as u128;
+        let within = after.abs_diff(before) * BPS_MUL as u128 <= tolerance as u128 * before;
+        let result = check_price_impact(
+            reserve_a0 as u64,
+            reserve_b0 as u64,
+            reserve_a1 as u64,
+            reserve_b1 as u64,
+            tolerance,
+        );
+        prop_assert_eq!(result.is_ok(), within);
+    }
+
+    #[test]
+    fn test_zero_tolerance(reserve_a0: u64, reserve_b0: u64, reserve_a1: u64, reserve_b1: u64) {
+        // 0 disables the bound
+        prop_assert!(check_price_impact(reserve_a0, reserve_b0, reserve_a1, reserve_b1, 0).is_ok());
+    }
+
+    #[test]
+    fn test_price_no_panic(reserve_a: u64, reserve_b: u64, decimals_a: u8, decimals_b: u8) {
+        let _ = calculate_price(reserve_a, reserve_b, decimals_a, decimals_b);
+    }
+
+    #[test]
+    fn test_price_same_decimals(reserve_a in 1..u64::MAX, reserve_b: u64, decimals in 0..=18u8) {
+        // equal decimals cancel out
+        let price = calculate_price(reserve_a, reserve_b, decimals, decimals).unwrap();
+        prop_assert_eq!(price, calculate_price(reserve_a, reserve_b, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_virtual_trade_conserves_tokens(
+        reserve_a in 1..u64::MAX,
+        reserve_b in 1..u64::MAX,
+        sold_a in 0..u64::MAX / 2,
+        sold_b in 0..u64::MAX / 2,
+        fee_rate in 0..=PERCENT_MUL,
+    ) {
+        if let Ok(trade) = calculate_virtual_trade(reserve_a, reserve_b, sold_a, sold_b, fee_rate) {
+            // what the escrows pay in and the pool pays out is what the sellers earn
+            prop_assert_eq!(
+                sold_a as u128 + trade.amount_a_out as u128,
+                trade.proceeds_a as u128 + trade.amount_a_in as u128
+            );
+            prop_assert_eq!(
+                sold_b as u128 + trade.amount_b_out as u128,
+                trade.proceeds_b as u128 + trade.amount_b_in as u128 + trade.fee as u128
+            );
+            // only one side trades through the curve
+            prop_assert!(trade.amount_a_in == 0 || trade.amount_b_in == 0);
+        }
+    }
+
+    #[test]
+    fn test_virtual_trade_k_never_decreases(
+        reserve_a in 1..u64::MAX / 2,
+        reserve_b in 1..u64::MAX / 2,
+        sold_a in 0..u64::MAX / 2,
+        sold_b in 0..u64::MAX / 2,
+        fee_rate in 0..=PERCENT_MUL,
+    ) {
+        if let Ok(trade) = calculate_virtual_trade(reserve_a, reserve_b, sold_a, sold_b, fee_rate) {
+            let changed_a = reserve_a as u128 + trade.amount_a_in as u128 - trade.amount_a_out as u128;
+            let changed_b = reserve_b as u128 + trade.amount_b_in as u128 - trade.amount_b_out as u128;
+            prop_assert!(
+                product(changed_a, changed_b) >= product(reserve_a as u128, reserve_b as u128)
+            );
+        }
+    }
+
+    #[test]
+    fn test_proceeds_no_panic(sell_rate: u64, start: u128, end: u128) {
+        let _ = calculate_proceeds(sell_rate, start, end);
+    }
+
+    #[test]
+    fn test_stake_unstake_never_profits(
+        reserve_a in 1..u64::MAX / 2,
+        reserve_b in 0..u64::MAX / 2,
+        total_shares in 1..u64::MAX / 2,
+        amount_a in 0..u64::MAX / 2,
+    ) {
+        // the shares of a stake withdraw no more than it added
+        if let Ok((shares, amount_b)) = calculate_stake(reserve_a, reserve_b, total_shares, amount_a) {
+            if let (Some(total), Some(after_b)) =
+                (total_shares.checked_add(shares), reserve_b.checked_add(amount_b))
+            {
+                let (out_a, out_b) =
+                    calculate_unstake(reserve_a + amount_a, after_b, total, shares).unwrap();
+                prop_assert!(out_a <= amount_a);
+                prop_assert!(out_b <= amount_b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unstake_within_reserves(reserve_a: u64, reserve_b: u64, total_shares: u64, shares: u64) {
+        if let Ok((amount_a, amount_b)) =
+            calculate_unstake(reserve_a, reserve_b, total_shares, shares)
+        {
+            prop_assert!(amount_a <= reserve_a);
+            prop_assert!(amount_b <= reserve_b);
+        }
+    }
+}
+
+#[test]
+fn test_dynamic_fee() {
+    // a 1% move either way is 10_000
+    assert_eq!(calculate_price_move(100, 100, 100, 101), 10_000);
+    assert_eq!(calculate_price_move(100, 101, 100, 100), 9_900);
+    assert_eq!(calculate_price_move(100, 100, 200, 200), 0);
+    assert_eq!(calculate_price_move(100, 100, 100, 300), PERCENT_MUL);
+    assert_eq!(calculate_price_move(100, 0, 100, 100), PERCENT_MUL);
+    assert_eq!(calculate_price_move(0, 100, 100, 100), PERCENT_MUL);
+    assert_eq!(
+        calculate_price_move(u64::MAX, u64::MAX, u64::MAX - 1, u64::MAX),
+        0
+    );
+
+    assert_eq!(decay_volatility(1_000, VOLATILITY_HALF_LIFE - 1), 1_000);
+    assert_eq!(decay_volatility(1_000, VOLATILITY_HALF_LIFE), 500);
+    assert_eq!(decay_volatility(1_000, VOLATILITY_HALF_LIFE * 3), 125);
+    assert_eq!(decay_volatility(u64::MAX, VOLATILITY_HALF_LIFE * 64), 0);
+    assert_eq!(decay_volatility(u64::MAX, u64::MAX), 0);
+
+    assert_eq!(calculate_dynamic_fee(3_000, 2_000, 1_000, 10_000), 5_000);
+    assert_eq!(calculate_dynamic_fee(3_000, 9_000, 1_000, 10_000), 10_000);
+    assert_eq!(calculate_dynamic_fee(0, 0, 1_000, 10_000), 1_000);
+    assert_eq!(
+        calculate_dynamic_fee(u64::MAX, u64::MAX, 0, PERCENT_MUL),
+        PERCENT_MUL
+    );
+}
+
+#[test]
+fn test_stake() {
+    // 100_000 a of a 1:2 pool adds 200_000 b for a tenth of the shares
+    assert_eq!(
+        calculate_stake(1_000_000, 2_000_000, 1_000_000, 100_000).unwrap(),
+        (100_000, 200_000)
+    );
+    // b rounds up and the shares down, in favor of the pool
+    assert_eq!(calculate_stake(3, 2, 3, 1).unwrap(), (1, 1));
+    assert_eq!(calculate_stake(3, 2, 2, 1).unwrap(), (0, 1));
+    assert!(calculate_stake(0, 2, 2, 1).is_err());
+    assert_eq!(
+        calculate_unstake(1_100_000, 2_200_000, 1_100_000, 100_000).unwrap(),
+        (100_000, 200_000)
+    );
+    assert_eq!(calculate_unstake(3, 2, 3, 1).unwrap(), (1, 0));
+    assert!(calculate_unstake(3, 2, 3, 4).is_err());
+    assert!(calculate_unstake(3, 2, 0, 0).is_err());
+}
+
+#[test]
+fn test_virtual_trade() {
+    // 100 a against 200 b at a price of 2 b per a match in full, nothing moves the pool
+    let trade = calculate_virtual_trade(1_000_000, 2_000_000, 100, 200, 3_000).unwrap();
+    assert_eq!(
+        trade,
+        VirtualTrade {
+            proceeds_a: 100,
+            proceeds_b: 200,
+            ..VirtualTrade::default()
+        }
+    );
+    // the excess 100_000 a trades as a swap would, 181818 b less the 546 fee
+    let trade = calculate_virtual_trade(1_000_000, 2_000_000, 100_100, 200, 3_000).unwrap();
+    assert_eq!(trade.amount_a_in, 100_000);
+    assert_eq!(trade.amount_b_out, 181_818);
+    assert_eq!(trade.fee, 546);
+    assert_eq!(trade.proceeds_b, 200 + 181_818 - 546);
+    assert!(calculate_virtual_trade(0, 2_000_000, 1, 0, 3_000).is_err());
+}
+
+#[test]
+fn test_proceeds() {
+    // 2.5 per unit of rate for 10 per slot
+    assert_eq!(
+        calculate_proceeds(10, RATE_MUL, RATE_MUL * 7 / 2).unwrap(),
+        25
+    );
+    // rounded down for the escrow
+    assert_eq!(calculate_proceeds(1, 0, RATE_MUL - 1).unwrap(), 0);
+    assert!(calculate_proceeds(1, 1, 0).is_err());
+}
+
+#[test]
+fn test_rounding() {
+    // 2e12 / 1.1e6 = 1818181.8, vault b keeps 1818182
+    assert_eq!(
+        calculate_amount_a2b(1_000_000, 2_000_000, 100_000).unwrap(),
+        181_818
+    );
+    // 2e12 / 9e5 = 2222222.2, vault b needs 2222223
+    assert_eq!(
+        calculate_amount_b2a(1_000_000, 2_000_000, 100_000).unwrap(),
+        222_223
+    );
+    assert!(calculate_amount_b2a(1_000_000, 2_000_000, 1_000_000).is_err());
+    assert!(calculate_amount_a2b(0, 2_000_000, 1).is_err());
+}
+
+#[test]
+fn test_old_pricing() {
+    // the example pool of 1e6 a and 2e6 b, old pricing floored both ways
+    let k = 1_000_000u128 * 2_000_000;
+    assert_eq!(
+        old_a2b(k, 1_000_000, 2_000_000, 100_000).unwrap(),
+        calculate_amount_a2b(1_000_000, 2_000_000, 100_000).unwrap() + 1
+    );
+    assert_eq!(
+        old_b2a(k, 1_000_000, 2_000_000, 100_000).unwrap() + 1,
+        calculate_amount_b2a(1_000_000, 2_000_000, 100_000).unwrap()
+    );
+    // 10000 a sent straight to the vault: the old k ignored it and sold the
+    // pool's b and a below the reserve price
+    assert_eq!(old_a2b(k, 1_010_000, 2_000_000, 100_000).unwrap(), 198_199);
+    assert_eq!(
+        calculate_amount_a2b(1_010_000, 2_000_000, 100_000).unwrap(),
+        180_180
+    );
+    assert_eq!(old_b2a(k, 1_010_000, 2_000_000, 100_000).unwrap(), 197_802);
+    assert_eq!(
+        calculate_amount_b2a(1_010_000, 2_000_000, 100_000).unwrap(),
+        219_781
+    );
+}
+
+#[test]
+fn test_fee_rounding() {
+    // 181818 * 0.3% = 545.45, the pool takes 546
+    assert_eq!(calculate_fee(181_818, 3_000).unwrap(), 546);
+    assert_eq!(calculate_fee(1, 3_000).unwrap(), 1);
+    assert_eq!(calculate_fee(1_000_000, 3_000).unwrap(), 3_000);
+    assert_eq!(calculate_fee(1_000_000, 0).unwrap(), 0);
+    // 1003000 pays 1000000 and 0.3% of it
+    assert_eq!(split_fee(1_003_000, 3_000), (1_000_000, 3_000));
+    assert_eq!(split_fee(1, 3_000), (0, 1));
+    assert_eq!(split_fee(1_000_000, 0), (1_000_000, 0));
+    // 20% of 546 is 109.2, the referrer gets 109
+    assert_eq!(calculate_referral_fee(546, 2_000), 109);
+    assert_eq!(calculate_referral_fee(546, 0), 0);
+    assert_eq!(calculate_referral_fee(546, BPS_MUL), 546);
+    assert_eq!(calculate_referral_fee(u64::MAX, BPS_MUL + 1), u64::MAX);
+}
+
+#[test]
+fn test_price() {
+    // 1 a of 9 decimals for 2 b of 6 decimals
+    assert_eq!(
+        calculate_price(1_000_000_000, 2_000_000, 9, 6).unwrap(),
+        2 * PRICE_MUL
+    );
+    // and the other way round
+    assert_eq!(
+        calculate_price(2_000_000, 1_000_000_000, 6, 9).unwrap(),
+        PRICE_MUL / 2
+    );
+    assert!(calculate_price(0, 1, 6, 6).is_err());
+}
+
+#[test]
+fn test_k_drop() {
+    assert!(check_k(1_000_000, 1_000_000).is_ok());
+    assert!(check_k(1_000_000, 2_000_000).is_ok());
+    assert_eq!(check_k(1_000_000, 999_999), Err(AmmError::CalculationError));
+}
+
+#[test]
+fn test_price_impact() {
+    // selling 100,000 a into 1,000,000 a and 2,000,000 b moves the price by 1735.5 bps
+    let (reserve_a1, reserve_b1) = (1_100_000, 2_000_000 - 181_818);
+    assert!(check_price_impact(1_000_000, 2_000_000, reserve_a1, reserve_b1, 1_736).is_ok());
+    assert_eq!(
+        check_price_impact(1_000_000, 2_000_000, reserve_a1, reserve_b1, 1_735),
+        Err(AmmError::OutOfTolerance)
+    );
+    // buying a moves it up, by more than 100% here
+    assert!(check_price_impact(1_000_000, 2_000_000, 400_000, 5_000_000, BPS_MUL).is_err());
+    assert!(check_price_impact(1_000_000, 2_000_000, 1_000_000, 2_000_000, 1).is_ok());
+    // the largest reserves are shifted instead of overflowing
+    let max = u64::MAX;
+    assert!(check_price_impact(max, max, max, max - max / 10_000, 1).is_ok());
+    assert!(check_price_impact(max, max, max, max - max / 5_000, 1).is_err());
+    assert!(check_price_impact(max, max, max / 2 + 1, max, BPS_MUL).is_ok());
+    assert!(check_price_impact(max, max, max / 2 + 1, max, BPS_MUL - 1).is_err());
+}
@@ -0,0 +1,416 @@
+mod common;
+
+use amm::{
+    error::AmmError,
+    instruction::{
+        self, find_vault_address, find_wsol_address, Direction, FEE_VAULT_SEED, VAULT_A_SEED,
+        VAULT_B_SEED,
+    },
+    math,
+    state::{AmmPool, CLOSE_GRACE_PERIOD},
+};
+use common::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::signature::{Keypair, Signer};
+use spl_token::state::Account;
+
+const WALLET_LAMPORTS: u64 = 1_000_000_000;
+
+/// A pool of a token a and native sol as b, the owner funds b from its wallet.
+struct NativePool {
+    pool: Keypair,
+    pda: Pubkey,
+    nonce: u8,
+    mint_authority: Keypair,
+    mint_a: Keypair,
+    vault_a: Pubkey,
+    vault_b: Pubkey,
+    fee_vault: Pubkey,
+    owner: Keypair,
+    owner_token_a: Keypair,
+}
+
+impl NativePool {
+    async fn new(context: &mut ProgramTestContext) -> Self {
+        let pool = Keypair::new();
+        let (pda, nonce) = Pubkey::find_program_address(&[pool.pubkey().as_ref()], &program_id());
+        let vault = |seed| find_vault_address(&program_id(), &pool.pubkey(), seed).0;
+        let (vault_a, vault_b, fee_vault) = (
+            vault(VAULT_A_SEED),
+            vault(VAULT_B_SEED),
+            vault(FEE_VAULT_SEED),
+        );
+        let setup = Self {
+            pool,
+            pda,
+            nonce,
+            mint_authority: Keypair::new(),
+            mint_a: Keypair::new(),
+            vault_a,
+            vault_b,
+            fee_vault,
+            owner: Keypair::new(),
+            owner_token_a: Keypair::new(),
+        };
+        create_mint(context, &setup.mint_a, &setup.mint_authority.pubkey(), 6).await;
+        setup
+            .fund(context, &setup.owner, &setup.owner_token_a)
+            .await;
+        setup
+    }
+
+    /// Fund a wallet with sol and `USER_AMOUNT` of token a.
+    async fn fund(&self, context: &mut ProgramTestContext, wallet: &Keypair, token_a: &Keypair) {
+        let instruction = system_instruction::transfer(
+            &context.payer.pubkey(),
+            &wallet.pubkey(),
+            WALLET_LAMPORTS,
+        );
+        process(context, &[instruction], &[]).await.unwrap();
+        create_token_account(context, token_a, &self.mint_a.pubkey(), &wallet.pubkey()).await;
+        mint_to(
+            context,
+            &self.mint_a.pubkey(),
+            &token_a.pubkey(),
+            &self.mint_authority,
+            USER_AMOUNT,
+        )
+        .await;
+    }
+
+    fn initialize_instruction(&self) -> Instruction {
+        let owner = self.owner.pubkey();
+        instruction::initialize(
+            &program_id(),
+            &self.pool.pubkey(),
+            &owner,
+            &self.mint_a.pubkey(),
+            &spl_token::native_mint::id(),
+            &self.pda,
+            &self.owner_token_a.pubkey(),
+            &owner,
+            &spl_token::id(),
+            self.nonce,
+            FEE,
+            AMOUNT_A,
+            AMOUNT_B,
+            0,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn swap_instruction(
+        &self,
+        wallet: &Keypair,
+        token_a: &Pubkey,
+        token_b: &Pubkey,
+        amount: u64,
+        direction: Direction,
+    ) -> Instruction {
+        instruction::swap(
+            &program_id(),
+            &self.pool.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &wallet.pubkey(),
+            token_a,
+            token_b,
+            &spl_token::id(),
+            &self.mint_a.pubkey(),
+            &spl_token::native_mint::id(),
+            amount,
+            direction,
+            None,
+            false,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn terminate_instruction(&self) -> Instruction {
+        instruction::terminate(
+            &program_id(),
+            &self.pool.pubkey(),
+            &self.owner.pubkey(),
+            &self.vault_a,
+            &self.vault_b,
+            &self.fee_vault,
+            &self.pda,
+            &self.owner_token_a.pubkey(),
+            &self.owner.pubkey(),
+            &spl_token::id(),
+            &self.mint_a.pubkey(),
+            &spl_token::native_mint::id(),
+            None,
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn wsol(&self, wallet: &Keypair) -> Pubkey {
+        find_wsol_address(&program_id(), &self.pool.pubkey(), &wallet.pubkey()).0
+    }
+}
+
+async fn lamports(context: &mut ProgramTestContext, key: &Pubkey) -> u64 {
+    get_account(&mut context.banks_client, key)
+        .await
+        .map(|account| account.lamports)
+        .unwrap_or(0)
+}
+
+/// Start a test validator with one initialized native sol pool.
+async fn setup_native_pool() -> (ProgramTestContext, NativePool) {
+    let mut context = program_test().start_with_context().await;
+    let setup = NativePool::new(&mut context).await;
+    let instruction = setup.initialize_instruction();
+    process(&mut context, &[instruction], &[&setup.owner, &setup.pool])
+        .await
+        .unwrap();
+    (context, setup)
+}
+
+#[tokio::test]
+async fn test_native_initialize() {
+    let (mut context, setup) = setup_native_pool().await;
+    assert_eq!(
+        token_balance(&mut context.banks_client, &setup.vault_b).await,
+        AMOUNT_B
+    );
+    // the owner paid the rent of the pool and its vaults too
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let rent = rent.minimum_balance(AmmPool::LEN) + 3 * rent.minimum_balance(Account::LEN);
+    assert_eq!(
+        lamports(&mut context, &setup.owner.pubkey()).await,
+        WALLET_LAMPORTS - AMOUNT_B - rent
+    );
+    // the temporary wsol account is closed
+    let wsol = setup.wsol(&setup.owner);
+    assert_eq!(lamports(&mut context, &wsol).await, 0);
+}
+
+#[tokio::test]
+async fn test_native_swap() {
+    let (mut context, setup) = setup_native_pool().await;
+    let wallet = Keypair::new();
+    let token_a = Keypair::new();
+    setup.fund(&mut context, &wallet, &token_a).await;
+    let amount = 100_000;
+
+    // a2b pays out native sol
+    let instruction = setup.swap_instruction(
+        &wallet,
+        &token_a.pubkey(),
+        &wallet.pubkey(),
+        amount,
+        Direction::A2B,
+    );
+    process(&mut context, &[instruction], &[&wallet])
+        .await
+        .unwrap();
+    let amount_out = 181_818;
+    let fee = math::calculate_fee(amount_out, FEE).unwrap();
+    assert_eq!(
+        token_balance(&mut context.banks_client, &setup.vault_b).await,
+        AMOUNT_B - amount_out
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, &setup.fee_vault).await,
+        fee
+    );
+    assert_eq!(
+        lamports(&mut context, &wallet.pubkey()).await,
+        WALLET_LAMPORTS + amount_out - fee
+    );
+    assert_eq!(lamports(&mut context, &setup.wsol(&wallet)).await, 0);
+
+    // b2a pays in native sol
+    let before = lamports(&mut context, &wallet.pubkey()).await;
+    let vault_b = token_balance(&mut context.banks_client, &setup.vault_b).await;
+    let instruction = setup.swap_instruction(
+        &wallet,
+        &token_a.pubkey(),
+        &wallet.pubkey(),
+        amount,
+        Direction::B2A,
+    );
+    process(&mut context, &[instruction], &[&wallet])
+        .await
+        .unwrap();
+    let amount_in = token_balance(&mut context.banks_client, &setup.vault_b).await - vault_b;
+    let fee_in = math::calculate_fee(amount_in, FEE).unwrap();
+    assert_eq!(
+        lamports(&mut context, &wallet.pubkey()).await,
+        before - amount_in - fee_in
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, &token_a.pubkey()).await,
+        USER_AMOUNT
+    );
+    assert_eq!(lamports(&mut context, &setup.wsol(&wallet)).await, 0);
+}
+
+#[tokio::test]
+async fn test_native_swap_errors() {
+    let (mut context, setup) = setup_native_pool().await;
+    let wallet = Keypair::new();
+    let token_a = Keypair::new();
+    setup.fund(&mut context, &wallet, &token_a).await;
+
+    // the wallet can only stand in for native sol
+    let instruction = setup.swap_instruction(
+        &wallet,
+        &wallet.pubkey(),
+        &wallet.pubkey(),
+        100_000,
+        Direction::A2B,
+    );
+    let result = process(&mut context, &[instruction], &[&wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidMint));
+
+    // not enough sol
+    let instruction = setup.swap_instruction(
+        &wallet,
+        &token_a.pubkey(),
+        &wallet.pubkey(),
+        AMOUNT_A - 1,
+        Direction::B2A,
+    );
+    let result = process(&mut context, &[instruction], &[&wallet]).await;
+    assert_error(result, amm_error(AmmError::InsufficientFunds));
+
+    // wsol account of another wallet
+    let mut instruction = setup.swap_instruction(
+        &wallet,
+        &token_a.pubkey(),
+        &wallet.pubkey(),
+        100_000,
+        Direction::A2B,
+    );
+    instruction.accounts[11].pubkey = setup.wsol(&setup.owner);
+    let result = process(&mut context, &[instruction], &[&wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidPDA));
+}
+
+#[tokio::test]
+async fn test_native_swap_prefunded_wsol() {
+    let (mut context, setup) = setup_native_pool().await;
+    let wallet = Keypair::new();
+    let token_a = Keypair::new();
+    setup.fund(&mut context, &wallet, &token_a).await;
+    // lamports sent to the wsol address do not block the swap
+    let wsol = setup.wsol(&wallet);
+    let instruction = system_instruction::transfer(&context.payer.pubkey(), &wsol, 1_000_000);
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let instruction = setup.swap_instruction(
+        &wallet,
+        &token_a.pubkey(),
+        &wallet.pubkey(),
+        100_000,
+        Direction::A2B,
+    );
+    process(&mut context, &[instruction], &[&wallet])
+        .await
+        .unwrap();
+    assert_eq!(lamports(&mut context, &wsol).await, 0);
+}
+
+#[tokio::test]
+async fn test_native_swap_referral() {
+    let (mut context, setup) = setup_native_pool().await;
+    let share = 2_000;
+    let instruction = instruction::update_referral_share(
+        &program_id(),
+        &setup.pool.pubkey(),
+        &setup.owner.pubkey(),
+        share,
+    )
+    .unwrap();
+    process(&mut context, &[instruction], &[&setup.owner])
+        .await
+        .unwrap();
+    let wallet = Keypair::new();
+    let token_a = Keypair::new();
+    setup.fund(&mut context, &wallet, &token_a).await;
+    // the referrer is paid in wsol, after the native accounts
+    let referrer = Keypair::new();
+    create_token_account(
+        &mut context,
+        &referrer,
+        &spl_token::native_mint::id(),
+        &Pubkey::new_unique(),
+    )
+    .await;
+    let mut instruction = setup.swap_instruction(
+        &wallet,
+        &token_a.pubkey(),
+        &wallet.pubkey(),
+        100_000,
+        Direction::A2B,
+    );
+    instruction
+        .accounts
+        .push(AccountMeta::new(referrer.pubkey(), false));
+    process(&mut context, &[instruction], &[&wallet])
+        .await
+        .unwrap();
+    let amount_out = 181_818;
+    let fee = math::calculate_fee(amount_out, FEE).unwrap();
+    let referral = math::calculate_referral_fee(fee, share);
+    assert_eq!(
+        lamports(&mut context, &wallet.pubkey()).await,
+        WALLET_LAMPORTS + amount_out - fee
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, &referrer.pubkey()).await,
+        referral
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, &setup.fee_vault).await,
+        fee - referral
+    );
+}
+
+#[tokio::test]
+async fn test_native_terminate() {
+    let (mut context, setup) = setup_native_pool().await;
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let token_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+    let pool_rent = rent.minimum_balance(AmmPool::LEN);
+    let instruction = instruction::update_status(
+        &program_id(),
+        &setup.pool.pubkey(),
+        &setup.owner.pubkey(),
+        2,
+    )
+    .unwrap();
+    process(&mut context, &[instruction], &[&setup.owner])
+        .await
+        .unwrap();
+    warp_seconds(&mut context, CLOSE_GRACE_PERIOD).await;
+    let before = lamports(&mut context, &setup.owner.pubkey()).await;
+    let instruction = setup.terminate_instruction();
+    process(&mut context, &[instruction], &[&setup.owner])
+        .await
+        .unwrap();
+    // vault b pays out by closing, every account returns its rent
+    assert_eq!(
+        lamports(&mut context, &setup.owner.pubkey()).await,
+        before + AMOUNT_B + token_rent * 3 + pool_rent
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, &setup.owner_token_a.pubkey()).await,
+        USER_AMOUNT
+    );
+    assert_eq!(lamports(&mut context, &setup.vault_b).await, 0);
+}
@@ -0,0 +1,462 @@
+mod common;
+
+use amm::{
+    error::AmmError,
+    instruction::Direction,
+    math,
+    state::{Order, OrderStatus, TWAMM_INTERVAL},
+};
+use common::*;
+use solana_program::{clock::Clock, instruction::InstructionError, program_pack::Pack};
+use solana_program_test::tokio;
+use solana_sdk::signature::{Keypair, Signer};
+
+const ORDER_AMOUNT: u64 = 10_000;
+
+/// What a fill of an a2b order pays to the owner and to the fee vault
+fn quote_a2b(reserve_a: u64, reserve_b: u64, amount: u64) -> (u64, u64) {
+    let amount_out = math::calculate_amount_a2b(reserve_a, reserve_b, amount).unwrap();
+    let fee = math::calculate_fee(amount_out, FEE).unwrap();
+    (amount_out - fee, fee)
+}
+
+#[tokio::test]
+async fn test_place_order() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let order = setup
+        .place_order(&mut context, &user, ORDER_AMOUNT, Direction::A2B, u64::MAX)
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let state = get_order(banks_client, &order.pubkey()).await;
+    let (escrow, _) = amm::instruction::find_order_escrow_address(&program_id(), &order.pubkey());
+    assert_eq!(
+        state,
+        Order {
+            status: OrderStatus::Open,
+            direction: Direction::A2B,
+            pool: setup.pool.pubkey(),
+            owner: user.wallet.pubkey(),
+            escrow,
+            token_in: user.token_a.pubkey(),
+            token_out: user.token_b.pubkey(),
+            amount_in: ORDER_AMOUNT,
+            min_amount_out: u64::MAX,
+            reward: ORDER_REWARD,
+        }
+    );
+    let rent = banks_client.get_rent().await.unwrap();
+    let account = get_account(banks_client, &order.pubkey()).await.unwrap();
+    assert_eq!(
+        account.lamports,
+        rent.minimum_balance(Order::LEN) + ORDER_REWARD
+    );
+    assert_eq!(token_balance(banks_client, &escrow).await, ORDER_AMOUNT);
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT - ORDER_AMOUNT
+    );
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.open_orders, 1);
+}
+
+#[tokio::test]
+async fn test_place_order_errors() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let order = Keypair::new();
+    let signers = &[&user.wallet, &order];
+
+    // order must sign
+    let mut instruction =
+        setup.place_order_instruction(&order.pubkey(), &user, ORDER_AMOUNT, Direction::A2B, 0);
+    instruction.accounts[1].is_signer = false;
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    // escrow of another order
+    let mut instruction =
+        setup.place_order_instruction(&order.pubkey(), &user, ORDER_AMOUNT, Direction::A2B, 0);
+    instruction.accounts[3].pubkey =
+        amm::instruction::find_order_escrow_address(&program_id(), &user.wallet.pubkey()).0;
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidVault));
+
+    // selling token b from the token a account
+    let mut instruction =
+        setup.place_order_instruction(&order.pubkey(), &user, ORDER_AMOUNT, Direction::B2A, 0);
+    instruction.accounts[5].pubkey = user.token_a.pubkey();
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidMint));
+
+    // nothing to sell
+    let instruction = setup.place_order_instruction(&order.pubkey(), &user, 0, Direction::A2B, 0);
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::InvalidAmount));
+
+    // a locked pool takes no orders
+    setup
+        .update_status(&mut context, &setup.owner.wallet, 2)
+        .await
+        .unwrap();
+    let instruction =
+        setup.place_order_instruction(&order.pubkey(), &user, ORDER_AMOUNT, Direction::A2B, 0);
+    let result = process(&mut context, &[instruction], signers).await;
+    assert_error(result, amm_error(AmmError::PoolLock));
+}
+
+#[tokio::test]
+async fn test_fill_order_a2b() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let keeper = setup.create_wallet_user(&mut context).await;
+    let (amount_out, fee) = quote_a2b(AMOUNT_A, AMOUNT_B, ORDER_AMOUNT);
+    let order = setup
+        .place_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::A2B,
+            amount_out,
+        )
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let order_lamports = get_account(banks_client, &order.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+    let (escrow, _) = amm::instruction::find_order_escrow_address(&program_id(), &order.pubkey());
+    let escrow_lamports = get_account(banks_client, &escrow).await.unwrap().lamports;
+    let user_lamports = get_account(banks_client, &user.wallet.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+    let keeper_lamports = get_account(banks_client, &keeper.wallet.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+
+    let keys = setup.order_keys(&order.pubkey(), &user, Direction::A2B);
+    setup
+        .fill_orders(&mut context, &keeper.wallet, &[keys])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT + amount_out
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        AMOUNT_A + ORDER_AMOUNT
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_b).await,
+        AMOUNT_B - amount_out - fee
+    );
+    assert_eq!(token_balance(banks_client, &setup.fee_vault).await, fee);
+    // the keeper earns the reward, the owner gets the rent back
+    assert_eq!(
+        get_account(banks_client, &keeper.wallet.pubkey())
+            .await
+            .unwrap()
+            .lamports,
+        keeper_lamports + ORDER_REWARD
+    );
+    assert_eq!(
+        get_account(banks_client, &user.wallet.pubkey())
+            .await
+            .unwrap()
+            .lamports,
+        user_lamports + order_lamports - ORDER_REWARD + escrow_lamports
+    );
+    assert!(get_account(banks_client, &order.pubkey()).await.is_none());
+    assert!(get_account(banks_client, &escrow).await.is_none());
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.open_orders, 0);
+
+    // a filled order is skipped
+    setup
+        .fill_orders(&mut context, &keeper.wallet, &[keys])
+        .await
+        .unwrap();
+    assert_eq!(
+        token_balance(&mut context.banks_client, &setup.fee_vault).await,
+        fee
+    );
+}
+
+#[tokio::test]
+async fn test_fill_order_b2a() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let keeper = setup.create_wallet_user(&mut context).await;
+    // the escrow pays the fee on top of what it trades
+    let (amount_trade, fee) = math::split_fee(ORDER_AMOUNT, FEE);
+    let amount_out = math::calculate_amount_a2b(AMOUNT_B, AMOUNT_A, amount_trade).unwrap();
+    let order = setup
+        .place_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::B2A,
+            amount_out,
+        )
+        .await
+        .unwrap();
+    let keys = setup.order_keys(&order.pubkey(), &user, Direction::B2A);
+    setup
+        .fill_orders(&mut context, &keeper.wallet, &[keys])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT + amount_out
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT - ORDER_AMOUNT
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        AMOUNT_A - amount_out
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_b).await,
+        AMOUNT_B + amount_trade
+    );
+    assert_eq!(token_balance(banks_client, &setup.fee_vault).await, fee);
+    assert!(fee >= math::calculate_fee(amount_trade, FEE).unwrap());
+}
+
+#[tokio::test]
+async fn test_fill_order_after_swap() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let trader = setup.create_user(&mut context).await;
+    let keeper = setup.create_wallet_user(&mut context).await;
+    // a limit above the pool price
+    let (amount_out, _) = quote_a2b(AMOUNT_A, AMOUNT_B, ORDER_AMOUNT);
+    let limit = amount_out + amount_out / 10;
+    let order = setup
+        .place_order(&mut context, &user, ORDER_AMOUNT, Direction::A2B, limit)
+        .await
+        .unwrap();
+    let keys = setup.order_keys(&order.pubkey(), &user, Direction::A2B);
+
+    // the order doesn't cross and stays open
+    setup
+        .fill_orders(&mut context, &keeper.wallet, &[keys])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let state = get_order(banks_client, &order.pubkey()).await;
+    assert_eq!(state.status, OrderStatus::Open);
+    assert_eq!(token_balance(banks_client, &setup.vault_a).await, AMOUNT_A);
+
+    // a swap buying token a pushes the price over the limit, the crank fills in the same
+    // transaction
+    let swap = setup.swap_instruction(&trader, 100_000, Direction::B2A);
+    let fill = setup.fill_orders_instruction(&keeper.wallet.pubkey(), &[keys]);
+    process(
+        &mut context,
+        &[swap, fill],
+        &[&trader.wallet, &keeper.wallet],
+    )
+    .await
+    .unwrap();
+    let banks_client = &mut context.banks_client;
+    assert!(get_account(banks_client, &order.pubkey()).await.is_none());
+    assert!(token_balance(banks_client, &user.token_b.pubkey()).await >= USER_AMOUNT + limit);
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.open_orders, 0);
+}
+
+#[tokio::test]
+async fn test_fill_order_after_virtual_orders() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let seller = setup.create_wallet_user(&mut context).await;
+    let keeper = setup.create_wallet_user(&mut context).await;
+    // a limit above the pool price, a long-term order buying token a pushes it over
+    let (amount_out, _) = quote_a2b(AMOUNT_A, AMOUNT_B, ORDER_AMOUNT);
+    let limit = amount_out + amount_out / 20;
+    let order = setup
+        .place_order(&mut context, &user, ORDER_AMOUNT, Direction::A2B, limit)
+        .await
+        .unwrap();
+    setup
+        .place_long_term_order(
+            &mut context,
+            &seller,
+            200_000,
+            Direction::B2A,
+            2 * TWAMM_INTERVAL,
+        )
+        .await
+        .unwrap();
+    warp_slots(&mut context, TWAMM_INTERVAL).await;
+    let keys = setup.order_keys(&order.pubkey(), &user, Direction::A2B);
+
+    // the pending sales must run before the fill
+    let result = setup
+        .fill_orders(&mut context, &keeper.wallet, &[keys])
+        .await;
+    assert_error(result, InstructionError::NotEnoughAccountKeys);
+
+    let banks_client = &mut context.banks_client;
+    let mut expected = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    let instruction = setup.twamm_fill_orders_instruction(&keeper.wallet.pubkey(), &[keys]);
+    process(&mut context, &[instruction], &[&keeper.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let slot = banks_client.get_sysvar::<Clock>().await.unwrap().slot;
+    let trade = expected.execute(AMOUNT_A, AMOUNT_B, slot, FEE).unwrap();
+    assert!(trade.amount_b_in > 0);
+    assert_eq!(
+        get_twamm(banks_client, &setup.pool.pubkey()).await,
+        expected
+    );
+    // the order filled against the reserves the virtual orders left
+    let (amount_out, _) = quote_a2b(
+        AMOUNT_A + trade.amount_a_in - trade.amount_a_out,
+        AMOUNT_B + trade.amount_b_in - trade.amount_b_out,
+        ORDER_AMOUNT,
+    );
+    assert!(amount_out >= limit);
+    assert!(get_account(banks_client, &order.pubkey()).await.is_none());
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT + amount_out
+    );
+}
+
+#[tokio::test]
+async fn test_fill_orders_errors() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let keeper = setup.create_wallet_user(&mut context).await;
+    let order = setup
+        .place_order(&mut context, &user, ORDER_AMOUNT, Direction::A2B, 0)
+        .await
+        .unwrap();
+    let keys = setup.order_keys(&order.pubkey(), &user, Direction::A2B);
+
+    // paid to another account than the order recorded
+    let mut wrong = keys;
+    wrong.token_out = keeper.token_b.pubkey();
+    let result = setup
+        .fill_orders(&mut context, &keeper.wallet, &[wrong])
+        .await;
+    assert_error(result, amm_error(AmmError::InvalidOrder));
+
+    // order accounts come in groups
+    let mut instruction = setup.fill_orders_instruction(&keeper.wallet.pubkey(), &[keys]);
+    instruction.accounts.pop();
+    let result = process(&mut context, &[instruction], &[&keeper.wallet]).await;
+    assert_error(result, InstructionError::NotEnoughAccountKeys);
+
+    // keeper must sign
+    let mut instruction = setup.fill_orders_instruction(&keeper.wallet.pubkey(), &[keys]);
+    instruction.accounts[5].is_signer = false;
+    let result = process(&mut context, &[instruction], &[]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    // a locked pool fills nothing
+    setup
+        .update_status(&mut context, &setup.owner.wallet, 2)
+        .await
+        .unwrap();
+    let result = setup
+        .fill_orders(&mut context, &keeper.wallet, &[keys])
+        .await;
+    assert_error(result, amm_error(AmmError::PoolLock));
+}
+
+#[tokio::test]
+async fn test_cancel_order() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let other = setup.create_wallet_user(&mut context).await;
+    let order = setup
+        .place_order(&mut context, &user, ORDER_AMOUNT, Direction::B2A, u64::MAX)
+        .await
+        .unwrap();
+    let user_lamports = get_account(&mut context.banks_client, &user.wallet.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+
+    // owner must sign while the pool trades
+    let instruction = setup.cancel_order_instruction(&order.pubkey(), &user, Direction::B2A, false);
+    let result = process(&mut context, &[instruction], &[]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    // refunded to the account the order recorded
+    let mut instruction =
+        setup.cancel_order_instruction(&order.pubkey(), &user, Direction::B2A, true);
+    instruction.accounts[5].pubkey = other.token_b.pubkey();
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidOrder));
+
+    let instruction = setup.cancel_order_instruction(&order.pubkey(), &user, Direction::B2A, true);
+    process(
+        &mut context,
+        std::slice::from_ref(&instruction),
+        &[&user.wallet],
+    )
+    .await
+    .unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT
+    );
+    // the rent and the unpaid reward go back to the owner
+    let rent = banks_client.get_rent().await.unwrap();
+    let (escrow, _) = amm::instruction::find_order_escrow_address(&program_id(), &order.pubkey());
+    assert!(get_account(banks_client, &escrow).await.is_none());
+    assert!(get_account(banks_client, &order.pubkey()).await.is_none());
+    assert_eq!(
+        get_account(banks_client, &user.wallet.pubkey())
+            .await
+            .unwrap()
+            .lamports,
+        user_lamports
+            + rent.minimum_balance(Order::LEN)
+            + ORDER_REWARD
+            + rent.minimum_balance(spl_token::state::Account::LEN)
+    );
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.open_orders, 0);
+
+    // cancelled once
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidOrder));
+}
+
+#[tokio::test]
+async fn test_terminate_with_open_orders() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let order = setup
+        .place_order(&mut context, &user, ORDER_AMOUNT, Direction::A2B, u64::MAX)
+        .await
+        .unwrap();
+    setup.lock_for_close(&mut context).await;
+    let result = setup.terminate(&mut context, &setup.owner).await;
+    assert_error(result, amm_error(AmmError::OpenOrders));
+
+    // the grace period is over, anyone refunds the order to its owner
+    let instruction = setup.cancel_order_instruction(&order.pubkey(), &user, Direction::A2B, false);
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    assert_eq!(
+        token_balance(&mut context.banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT
+    );
+    setup.terminate(&mut context, &setup.owner).await.unwrap();
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use amm::{error::AmmError, instruction::Direction, math};
+use common::*;
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::signature::{Keypair, Signer};
+
+const SHARE: u64 = 2_000;
+
+/// A pool paying `SHARE` of the swap fee to referrers, with a user and a referrer.
+async fn setup_referral_pool() -> (ProgramTestContext, PoolSetup, User, User) {
+    let (mut context, setup) = setup_pool().await;
+    setup
+        .update_referral_share(&mut context, &setup.owner.wallet, SHARE)
+        .await
+        .unwrap();
+    let user = setup.create_user(&mut context).await;
+    let referrer = setup.create_user(&mut context).await;
+    (context, setup, user, referrer)
+}
+
+#[tokio::test]
+async fn test_update_referral_share() {
+    let (mut context, setup) = setup_pool().await;
+    let owner = &setup.owner.wallet;
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.referral_share, 0);
+    setup
+        .update_referral_share(&mut context, owner, SHARE)
+        .await
+        .unwrap();
+    let pool = get_pool(&mut context.banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.referral_share, SHARE);
+
+    // the whole fee at most
+    setup
+        .update_referral_share(&mut context, owner, math::BPS_MUL)
+        .await
+        .unwrap();
+    let result = setup
+        .update_referral_share(&mut context, owner, math::BPS_MUL + 1)
+        .await;
+    assert_error(result, amm_error(AmmError::InvalidInput));
+
+    let other = Keypair::new();
+    let result = setup.update_referral_share(&mut context, &other, 5).await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+}
+
+#[tokio::test]
+async fn test_referral_swap_a2b() {
+    let (mut context, setup, user, referrer) = setup_referral_pool().await;
+    let amount = 100_000;
+    let instruction =
+        setup.referral_swap_instruction(&user, amount, Direction::A2B, &referrer.token_b.pubkey());
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let amount_out = 181_818;
+    let fee = math::calculate_fee(amount_out, FEE).unwrap();
+    let referral = math::calculate_referral_fee(fee, SHARE);
+    assert!(referral > 0);
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_b).await,
+        AMOUNT_B - amount_out
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT + amount_out - fee
+    );
+    assert_eq!(
+        token_balance(banks_client, &referrer.token_b.pubkey()).await,
+        USER_AMOUNT + referral
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.fee_vault).await,
+        fee - referral
+    );
+}
+
+#[tokio::test]
+async fn test_referral_swap_b2a() {
+    let (mut context, setup, user, referrer) = setup_referral_pool().await;
+    let amount = 100_000;
+    let instruction =
+        setup.referral_swap_instruction(&user, amount, Direction::B2A, &referrer.token_b.pubkey());
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let amount_in = 222_223;
+    let fee = math::calculate_fee(amount_in, FEE).unwrap();
+    let referral = math::calculate_referral_fee(fee, SHARE);
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT - amount_in - fee
+    );
+    assert_eq!(
+        token_balance(banks_client, &referrer.token_b.pubkey()).await,
+        USER_AMOUNT + referral
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.fee_vault).await,
+        fee - referral
+    );
+}
+
+#[tokio::test]
+async fn test_referral_swap_no_share() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_user(&mut context).await;
+    let referrer = setup.create_user(&mut context).await;
+    // a pool without a share leaves the whole fee in the vault
+    let instruction =
+        setup.referral_swap_instruction(&user, 100_000, Direction::A2B, &referrer.token_b.pubkey());
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let fee = math::calculate_fee(181_818, FEE).unwrap();
+    assert_eq!(
+        token_balance(banks_client, &referrer.token_b.pubkey()).await,
+        USER_AMOUNT
+    );
+    assert_eq!(token_balance(banks_client, &setup.fee_vault).await, fee);
+}
+
+#[tokio::test]
+async fn test_referral_swap_errors() {
+    let (mut context, setup, user, referrer) = setup_referral_pool().await;
+    // the referrer is paid in token b
+    let instruction =
+        setup.referral_swap_instruction(&user, 100_000, Direction::A2B, &referrer.token_a.pubkey());
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidMint));
+
+    // nor can its wallet stand in for a token account
+    let instruction =
+        setup.referral_swap_instruction(&user, 100_000, Direction::A2B, &referrer.wallet.pubkey());
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidTokenProgramId));
+
+    // users can't refer themselves
+    let instruction =
+        setup.referral_swap_instruction(&user, 100_000, Direction::A2B, &user.token_b.pubkey());
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::SelfReferral));
+}
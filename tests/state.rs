@@ -0,0 +1,760 @@
+use amm::{
+    error::AmmError,
+    instruction::Direction,
+    state::{
+        AmmPool, AmmPoolView, Expiry, Farm, FeeMode, LongTermOrder, Order, OrderStatus, PoolStatus,
+        Stake, Twamm, MAX_EXPIRIES, TWAMM_INTERVAL,
+    },
+};
+use proptest::prelude::*;
+use solana_program::{
+    account_info::AccountInfo,
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+};
+use std::mem::size_of;
+
+prop_compose! {
+    fn pubkey()(bytes in any::<[u8; 32]>()) -> Pubkey {
+        Pubkey::new_from_array(bytes)
+    }
+}
+
+prop_compose! {
+    fn pool()(
+        status in 0..4u8,
+        nonce in any::<u8>(),
+        (ka, kb, tolerance, fee) in any::<(u64, u64, u64, u64)>(),
+        (owner, mint_a, mint_b) in (pubkey(), pubkey(), pubkey()),
+        (vault_a, vault_b, fee_vault) in (pubkey(), pubkey(), pubkey()),
+        (decimals_a, decimals_b) in any::<(u8, u8)>(),
+        (lock_time, open_orders, long_term_orders) in any::<(i64, u64, u64)>(),
+        (referral_share, stakes) in any::<(u64, u64)>(),
+        fee_mode in 0..3u8,
+        (min_fee, max_fee, volatility, volatility_slot) in any::<(u64, u64, u64, u64)>(),
+        (twamm, farm) in any::<(bool, bool)>(),
+    ) -> AmmPool {
+        AmmPool {
+            status: PoolStatus::try_from(status).unwrap(),
+            nonce,
+            ka,
+            kb,
+            tolerance,
+            fee,
+            owner,
+            mint_a,
+            mint_b,
+            vault_a,
+            vault_b,
+            fee_vault,
+            decimals_a,
+            decimals_b,
+            lock_time,
+            open_orders,
+            long_term_orders,
+            referral_share,
+            stakes,
+            fee_mode: FeeMode::try_from(fee_mode).unwrap(),
+            min_fee,
+            max_fee,
+            volatility,
+            volatility_slot,
+            twamm,
+            farm,
+        }
+    }
+}
+
+prop_compose! {
+    fn order()(
+        status in 0..3u8,
+        direction in 1..3u8,
+        (pool, owner, escrow) in (pubkey(), pubkey(), pubkey()),
+        (token_in, token_out) in (pubkey(), pubkey()),
+        (amount_in, min_amount_out, reward) in any::<(u64, u64, u64)>(),
+    ) -> Order {
+        Order {
+            status: OrderStatus::try_from(status).unwrap(),
+            direction: Direction::try_from(direction).unwrap(),
+            pool,
+            owner,
+            escrow,
+            token_in,
+            token_out,
+            amount_in,
+            min_amount_out,
+            reward,
+        }
+    }
+}
+
+prop_compose! {
+    fn long_term_order()(
+        status in 0..3u8,
+        direction in 1..3u8,
+        (pool, owner, token_in, token_out) in (pubkey(), pubkey(), pubkey(), pubkey()),
+        (deposit, sell_rate, start_slot, end_slot) in any::<(u64, u64, u64, u64)>(),
+        proceeds_per_rate in any::<u128>(),
+    ) -> LongTermOrder {
+        LongTermOrder {
+            status: OrderStatus::try_from(status).unwrap(),
+            direction: Direction::try_from(direction).unwrap(),
+            pool,
+            owner,
+            token_in,
+            token_out,
+            deposit,
+            sell_rate,
+            start_slot,
+            end_slot,
+            proceeds_per_rate,
+        }
+    }
+}
+
+prop_compose! {
+    fn expiry()(
+        (slot, sell_rate_a, sell_rate_b, orders) in any::<(u64, u64, u64, u64)>(),
+        (proceeds_per_rate_a, proceeds_per_rate_b) in any::<(u128, u128)>(),
+    ) -> Expiry {
+        Expiry {
+            slot,
+            sell_rate_a,
+            sell_rate_b,
+            proceeds_per_rate_a,
+            proceeds_per_rate_b,
+            orders,
+        }
+    }
+}
+
+prop_compose! {
+    fn twamm()(
+        (pool, escrow_a, escrow_b, payer) in (pubkey(), pubkey(), pubkey(), pubkey()),
+        (last_slot, sell_rate_a, sell_rate_b) in any::<(u64, u64, u64)>(),
+        (proceeds_per_rate_a, proceeds_per_rate_b) in any::<(u128, u128)>(),
+        expiries in prop::collection::vec(expiry(), MAX_EXPIRIES),
+    ) -> Twamm {
+        let mut twamm = Twamm {
+            pool,
+            escrow_a,
+            escrow_b,
+            payer,
+            last_slot,
+            sell_rate_a,
+            sell_rate_b,
+            proceeds_per_rate_a,
+            proceeds_per_rate_b,
+            ..Twamm::default()
+        };
+        twamm.expiries.copy_from_slice(&expiries);
+        twamm
+    }
+}
+
+prop_compose! {
+    fn farm()(
+        (pool, reward_mint, reward_vault) in (pubkey(), pubkey(), pubkey()),
+        (total_shares, staked_shares, reward_rate) in any::<(u64, u64, u64)>(),
+        (end_slot, last_slot) in any::<(u64, u64)>(),
+        reward_per_share in any::<u128>(),
+    ) -> Farm {
+        Farm {
+            pool,
+            reward_mint,
+            reward_vault,
+            total_shares,
+            staked_shares,
+            reward_rate,
+            end_slot,
+            last_slot,
+            reward_per_share,
+        }
+    }
+}
+
+prop_compose! {
+    fn stake()(
+        (pool, owner) in (pubkey(), pubkey()),
+        (token_a, token_b, token_reward) in (pubkey(), pubkey(), pubkey()),
+        (shares, rewards) in any::<(u64, u64)>(),
+        reward_per_share in any::<u128>(),
+    ) -> Stake {
+        Stake {
+            pool,
+            owner,
+            token_a,
+            token_b,
+            token_reward,
+            shares,
+            rewards,
+            reward_per_share,
+        }
+    }
+}
+
+/// an order placed at `slot` into `twamm`, the way place long-term order does
+fn place(
+    twamm: &mut Twamm,
+    direction: Direction,
+    deposit: u64,
+    slot: u64,
+    end_slot: u64,
+) -> LongTermOrder {
+    let sell_rate = deposit / (end_slot - slot);
+    twamm.add_order(direction, sell_rate, end_slot).unwrap();
+    LongTermOrder {
+        status: OrderStatus::Open,
+        direction,
+        pool: twamm.pool,
+        owner: Pubkey::new_unique(),
+        token_in: Pubkey::new_unique(),
+        token_out: Pubkey::new_unique(),
+        deposit,
+        sell_rate,
+        start_slot: slot,
+        end_slot,
+        proceeds_per_rate: match direction {
+            Direction::A2B => twamm.proceeds_per_rate_a,
+            Direction::B2A => twamm.proceeds_per_rate_b,
+        },
+    }
+}
+
+fn assert_view_eq(view: &AmmPoolView, pool: &AmmPool) {
+    assert_eq!(view.status(), Ok(pool.status));
+    assert_eq!(view.nonce, pool.nonce);
+    assert_eq!(view.ka(), pool.ka);
+    assert_eq!(view.kb(), pool.kb);
+    assert_eq!(view.tolerance(), pool.tolerance);
+    assert_eq!(view.fee(), pool.fee);
+    assert_eq!(view.owner, pool.owner);
+    assert_eq!(view.mint_a, pool.mint_a);
+    assert_eq!(view.mint_b, pool.mint_b);
+    assert_eq!(view.vault_a, pool.vault_a);
+    assert_eq!(view.vault_b, pool.vault_b);
+    assert_eq!(view.fee_vault, pool.fee_vault);
+    assert_eq!(view.decimals_a, pool.decimals_a);
+    assert_eq!(view.decimals_b, pool.decimals_b);
+    assert_eq!(view.lock_time(), pool.lock_time);
+    assert_eq!(view.open_orders(), pool.open_orders);
+    assert_eq!(view.long_term_orders(), pool.long_term_orders);
+    assert_eq!(view.referral_share(), pool.referral_share);
+    assert_eq!(view.stakes(), pool.stakes);
+    assert_eq!(view.fee_mode(), Ok(pool.fee_mode));
+    assert_eq!(view.min_fee(), pool.min_fee);
+    assert_eq!(view.max_fee(), pool.max_fee);
+    assert_eq!(view.volatility(), pool.volatility);
+    assert_eq!(view.volatility_slot(), pool.volatility_slot);
+    assert_eq!(view.twamm(), pool.twamm);
+    assert_eq!(view.farm(), pool.farm);
+}
+
+#[test]
+fn test_view_size() {
+    assert_eq!(size_of::<AmmPoolView>(), AmmPool::LEN);
+}
+
+#[test]
+fn test_view_wrong_len() {
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = vec![0; AmmPool::LEN + 1];
+    let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0);
+    assert!(AmmPoolView::load_unchecked(&account).is_err());
+    assert!(AmmPoolView::load_mut_unchecked(&account).is_err());
+}
+
+#[test]
+fn test_invalid_status() {
+    for byte in 0..4u8 {
+        let status: u8 = PoolStatus::try_from(byte).unwrap().into();
+        assert_eq!(status, byte);
+    }
+    assert_eq!(PoolStatus::try_from(4), Err(AmmError::InvalidStatus));
+
+    // a corrupt status byte is never mistaken for an uninitialized pool
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = vec![0; AmmPool::LEN];
+    data[0] = 4;
+    assert_eq!(
+        AmmPool::unpack_unchecked(&data),
+        Err(AmmError::InvalidStatus.into())
+    );
+    let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0);
+    for result in [
+        AmmPoolView::load(&account).err(),
+        AmmPoolView::load_unchecked(&account).err(),
+    ] {
+        assert_eq!(result, Some(AmmError::InvalidStatus.into()));
+    }
+    for result in [
+        AmmPoolView::load_mut(&account).err(),
+        AmmPoolView::load_mut_unchecked(&account).err(),
+    ] {
+        assert_eq!(result, Some(AmmError::InvalidStatus.into()));
+    }
+}
+
+#[test]
+fn test_fee_mode() {
+    for byte in 0..3u8 {
+        let mode: u8 = FeeMode::try_from(byte).unwrap().into();
+        assert_eq!(mode, byte);
+    }
+    assert_eq!(FeeMode::try_from(3), Err(AmmError::InvalidInput));
+
+    let mut data = vec![0; AmmPool::LEN];
+    AmmPool {
+        status: PoolStatus::Nomal,
+        ..AmmPool::default()
+    }
+    .pack_into_slice(&mut data);
+    // the mode precedes the bounds, the volatility and the twamm and farm flags
+    data[AmmPool::LEN - 2 - 8 * 4 - 1] = 3;
+    assert_eq!(AmmPool::unpack(&data), Err(AmmError::InvalidInput.into()));
+}
+
+#[test]
+fn test_effective_fee() {
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = vec![0; AmmPool::LEN];
+    let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0);
+    let mut pool = AmmPoolView::load_mut_unchecked(&account).unwrap();
+    pool.set_ka(1_000_000);
+    pool.set_kb(1_000_000);
+    pool.set_fee(3_000);
+    pool.set_min_fee(1_000);
+    pool.set_max_fee(30_000);
+    pool.set_volatility(10_000);
+    pool.set_volatility_slot(100);
+    let fee = |pool: &AmmPoolView, slot, reserve_a, reserve_b, direction| {
+        pool.effective_fee(slot, reserve_a, reserve_b, direction)
+            .unwrap()
+    };
+    let a2b = Some(Direction::A2B);
+    let b2a = Some(Direction::B2A);
+
+    // a static fee ignores the bounds
+    assert_eq!(fee(&pool, 1_000, 1_010_000, 990_100, a2b), 3_000);
+
+    // the volatility halves every half life
+    pool.set_fee_mode(FeeMode::Volatility);
+    assert_eq!(fee(&pool, 100, 1_000_000, 1_000_000, a2b), 13_000);
+    assert_eq!(fee(&pool, 249, 1_000_000, 1_000_000, b2a), 13_000);
+    assert_eq!(fee(&pool, 250, 1_000_000, 1_000_000, a2b), 8_000);
+    assert_eq!(fee(&pool, 400, 1_000_000, 1_000_000, None), 5_500);
+    pool.set_volatility(50_000);
+    assert_eq!(fee(&pool, 100, 1_000_000, 1_000_000, a2b), 30_000);
+    // a 1% move adds 10_000 to what is left of the volatility
+    pool.record_price_move(250, (1_000_000, 1_000_000), (1_000_000, 1_010_000));
+    assert_eq!(pool.volatility(), 35_000);
+    assert_eq!(pool.volatility_slot(), 250);
+    pool.set_fee(0);
+    pool.set_volatility(0);
+    assert_eq!(fee(&pool, 250, 1_000_000, 1_000_000, a2b), 1_000);
+
+    // only a trade moving further from the initial price pays for the imbalance
+    pool.set_fee(3_000);
+    pool.set_fee_mode(FeeMode::Imbalance);
+    assert_eq!(fee(&pool, 0, 1_000_000, 1_000_000, a2b), 3_000);
+    assert_eq!(fee(&pool, 0, 1_000_000, 1_000_000, b2a), 3_000);
+    assert_eq!(fee(&pool, 0, 1_010_000, 990_100, a2b), 22_702);
+    assert_eq!(fee(&pool, 0, 1_010_000, 990_100, b2a), 3_000);
+    assert_eq!(fee(&pool, 0, 1_010_000, 990_100, None), 22_702);
+    assert_eq!(fee(&pool, 0, 990_100, 1_010_000, b2a), 23_098);
+    assert_eq!(fee(&pool, 0, 990_100, 1_010_000, a2b), 3_000);
+}
+
+#[test]
+fn test_not_init() {
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = vec![0; AmmPool::LEN];
+    assert_eq!(
+        AmmPool::unpack(&data),
+        Err(ProgramError::UninitializedAccount)
+    );
+    let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0);
+    assert_eq!(
+        AmmPoolView::load(&account).err(),
+        Some(ProgramError::UninitializedAccount)
+    );
+    assert_eq!(
+        AmmPoolView::load_mut(&account).err(),
+        Some(ProgramError::UninitializedAccount)
+    );
+    AmmPoolView::load_mut_unchecked(&account)
+        .unwrap()
+        .set_status(PoolStatus::Lock);
+    assert!(AmmPoolView::load(&account).is_ok());
+    assert!(AmmPool::unpack(&account.data.borrow()).is_ok());
+}
+
+#[test]
+fn test_closed() {
+    let key = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut data = vec![0; AmmPool::LEN];
+    data[0] = PoolStatus::Closed.into();
+    let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0);
+    assert_eq!(
+        AmmPoolView::load(&account).err(),
+        Some(AmmError::PoolClosed.into())
+    );
+    assert_eq!(
+        AmmPoolView::load_mut(&account).err(),
+        Some(AmmError::PoolClosed.into())
+    );
+    // initialize still sees the account is taken
+    assert!(AmmPoolView::load_unchecked(&account)
+        .unwrap()
+        .is_initialized());
+}
+
+#[test]
+fn test_order_status() {
+    for byte in 0..3u8 {
+        let status: u8 = OrderStatus::try_from(byte).unwrap().into();
+        assert_eq!(status, byte);
+    }
+    assert_eq!(OrderStatus::try_from(3), Err(AmmError::InvalidStatus));
+    let data = vec![0; Order::LEN];
+    assert_eq!(
+        Order::unpack(&data),
+        Err(ProgramError::from(AmmError::InvalidDirection))
+    );
+}
+
+#[test]
+fn test_twamm_cancel_midway() {
+    let mut twamm = Twamm {
+        pool: Pubkey::new_unique(),
+        last_slot: TWAMM_INTERVAL,
+        ..Twamm::default()
+    };
+    let order = place(
+        &mut twamm,
+        Direction::A2B,
+        1_000_000,
+        TWAMM_INTERVAL,
+        3 * TWAMM_INTERVAL,
+    );
+    assert_eq!(order.sell_rate, 500);
+    let trade = twamm
+        .execute(1_000_000_000, 1_000_000_000, 2 * TWAMM_INTERVAL, 30)
+        .unwrap();
+    assert_eq!(trade.amount_a_in, 500_000);
+    assert_eq!(trade.amount_b_in, 0);
+    assert!(trade.amount_b_out > 0);
+
+    let (unsold, proceeds) = twamm.remove_order(&order).unwrap();
+    assert_eq!(unsold, 500_000);
+    // the only order earns all of the proceeds, less rounding
+    assert!(proceeds <= trade.proceeds_b && proceeds + 1 >= trade.proceeds_b);
+    assert_eq!(twamm.sell_rate_a, 0);
+    assert_eq!(twamm.expiries[0], Expiry::default());
+}
+
+#[test]
+fn test_twamm_expiry() {
+    let mut twamm = Twamm {
+        pool: Pubkey::new_unique(),
+        last_slot: TWAMM_INTERVAL,
+        ..Twamm::default()
+    };
+    let early = place(
+        &mut twamm,
+        Direction::B2A,
+        100_000,
+        TWAMM_INTERVAL,
+        2 * TWAMM_INTERVAL,
+    );
+    let late = place(
+        &mut twamm,
+        Direction::B2A,
+        300_000,
+        TWAMM_INTERVAL,
+        4 * TWAMM_INTERVAL,
+    );
+    let trade = twamm
+        .execute(1_000_000_000, 1_000_000_000, 10 * TWAMM_INTERVAL, 0)
+        .unwrap();
+    // both orders sold out and stopped at their end
+    assert_eq!(trade.amount_b_in, 400_000);
+    assert_eq!(twamm.sell_rate_b, 0);
+    assert_eq!(twamm.last_slot, 10 * TWAMM_INTERVAL);
+    let (unsold, early_proceeds) = twamm.remove_order(&early).unwrap();
+    assert_eq!(unsold, 0);
+    let (unsold, late_proceeds) = twamm.remove_order(&late).unwrap();
+    assert_eq!(unsold, 0);
+    assert!(early_proceeds + late_proceeds <= trade.proceeds_a);
+    // the early order sold its share at the better price
+    assert!(early_proceeds > 0 && late_proceeds > 2 * early_proceeds);
+    assert!(twamm.expiries.iter().all(|expiry| expiry.slot == 0));
+}
+
+#[test]
+fn test_twamm_too_many_expiries() {
+    let mut twamm = Twamm::default();
+    for i in 1..=MAX_EXPIRIES as u64 {
+        twamm
+            .add_order(Direction::A2B, 1, i * TWAMM_INTERVAL)
+            .unwrap();
+    }
+    // an existing expiry is shared
+    twamm.add_order(Direction::B2A, 1, TWAMM_INTERVAL).unwrap();
+    assert_eq!(twamm.expiries[0].orders, 2);
+    assert_eq!(
+        twamm.add_order(
+            Direction::A2B,
+            1,
+            (MAX_EXPIRIES as u64 + 1) * TWAMM_INTERVAL
+        ),
+        Err(AmmError::TooManyExpiries)
+    );
+}
+
+#[test]
+fn test_twamm_expiry_freed() {
+    let mut twamm = Twamm::default();
+    let orders: Vec<LongTermOrder> = (1..=MAX_EXPIRIES as u64)
+        .map(|i| place(&mut twamm, Direction::A2B, 10_000, 0, i * TWAMM_INTERVAL))
+        .collect();
+    let end_slot = (MAX_EXPIRIES as u64 + 1) * TWAMM_INTERVAL;
+    assert_eq!(
+        twamm.add_order(Direction::B2A, 1, end_slot),
+        Err(AmmError::TooManyExpiries)
+    );
+    // the first order ended, withdrawing it frees its expiry
+    twamm
+        .execute(1_000_000, 1_000_000, TWAMM_INTERVAL + 1, 0)
+        .unwrap();
+    let (unsold, proceeds) = twamm.remove_order(&orders[0]).unwrap();
+    assert_eq!(unsold, 10_000 % TWAMM_INTERVAL);
+    assert!(proceeds > 0);
+    assert!(twamm.expiries.iter().all(|e| e.slot != TWAMM_INTERVAL));
+    twamm.add_order(Direction::B2A, 1, end_slot).unwrap();
+}
+
+proptest! {
+    #[test]
+    fn test_view_reads_packed(pool in pool()) {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = vec![0; AmmPool::LEN];
+        AmmPool::pack(pool, &mut data).unwrap();
+        let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0);
+        assert_view_eq(&AmmPoolView::load_unchecked(&account).unwrap(), &pool);
+    }
+
+    #[test]
+    fn test_legacy_pool(pool in pool()) {
+        // the first version of the program wrote the leading fields only
+        let mut data = vec![0; AmmPool::LEN];
+        AmmPool::pack(pool, &mut data).unwrap();
+        data.truncate(AmmPool::LEGACY_LEN);
+        let legacy = AmmPool {
+            status: pool.status,
+            nonce: pool.nonce,
+            ka: pool.ka,
+            kb: pool.kb,
+            tolerance: pool.tolerance,
+            fee: pool.fee,
+            owner: pool.owner,
+            mint_a: pool.mint_a,
+            mint_b: pool.mint_b,
+            vault_a: pool.vault_a,
+            vault_b: pool.vault_b,
+            fee_vault: pool.fee_vault,
+            ..AmmPool::default()
+        };
+        prop_assert_eq!(AmmPool::unpack_any(&data), Ok(legacy));
+        prop_assert!(AmmPool::unpack_unchecked(&data).is_err());
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0);
+        prop_assert_eq!(
+            AmmPoolView::load(&account).err(),
+            Some(AmmError::LegacyPool.into())
+        );
+        prop_assert_eq!(
+            AmmPoolView::load_mut(&account).err(),
+            Some(AmmError::LegacyPool.into())
+        );
+    }
+
+    #[test]
+    fn test_view_writes_packed(pool in pool()) {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = vec![0; AmmPool::LEN];
+        let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0);
+        {
+            let mut view = AmmPoolView::load_mut_unchecked(&account).unwrap();
+            view.set_status(pool.status);
+            view.nonce = pool.nonce;
+            view.set_ka(pool.ka);
+            view.set_kb(pool.kb);
+            view.set_tolerance(pool.tolerance);
+            view.set_fee(pool.fee);
+            view.owner = pool.owner;
+            view.mint_a = pool.mint_a;
+            view.mint_b = pool.mint_b;
+            view.vault_a = pool.vault_a;
+            view.vault_b = pool.vault_b;
+            view.fee_vault = pool.fee_vault;
+            view.decimals_a = pool.decimals_a;
+            view.decimals_b = pool.decimals_b;
+            view.set_lock_time(pool.lock_time);
+            view.set_open_orders(pool.open_orders);
+            view.set_long_term_orders(pool.long_term_orders);
+            view.set_referral_share(pool.referral_share);
+            view.set_stakes(pool.stakes);
+            view.set_fee_mode(pool.fee_mode);
+            view.set_min_fee(pool.min_fee);
+            view.set_max_fee(pool.max_fee);
+            view.set_volatility(pool.volatility);
+            view.set_volatility_slot(pool.volatility_slot);
+            view.set_twamm(pool.twamm);
+            view.set_farm(pool.farm);
+        }
+        let unpacked = AmmPool::unpack_unchecked(&account.data.borrow()).unwrap();
+        prop_assert_eq!(unpacked, pool);
+    }
+
+    #[test]
+    fn test_effective_fee_within_bounds(
+        pool in pool(),
+        slot: u64,
+        (reserve_a, reserve_b) in any::<(u64, u64)>(),
+        direction in proptest::option::of(1..3u8),
+    ) {
+        prop_assume!(pool.min_fee <= pool.max_fee);
+        let mut data = vec![0; AmmPool::LEN];
+        pool.pack_into_slice(&mut data);
+        let view: &AmmPoolView = bytemuck::from_bytes(&data);
+        let direction = direction.map(|direction| Direction::try_from(direction).unwrap());
+        let fee = view.effective_fee(slot, reserve_a, reserve_b, direction).unwrap();
+        if pool.fee_mode == FeeMode::Static {
+            prop_assert_eq!(fee, pool.fee);
+        } else {
+            prop_assert!(pool.min_fee <= fee && fee <= pool.max_fee);
+        }
+    }
+
+    #[test]
+    fn test_order_round_trip(order in order()) {
+        let mut data = vec![0; Order::LEN];
+        order.pack_into_slice(&mut data);
+        prop_assert_eq!(Order::unpack_from_slice(&data).unwrap(), order);
+    }
+
+    #[test]
+    fn test_long_term_order_round_trip(order in long_term_order()) {
+        let mut data = vec![0; LongTermOrder::LEN];
+        order.pack_into_slice(&mut data);
+        prop_assert_eq!(LongTermOrder::unpack_from_slice(&data).unwrap(), order);
+    }
+
+    #[test]
+    fn test_twamm_round_trip(twamm in twamm()) {
+        let mut data = vec![0; Twamm::LEN];
+        twamm.pack_into_slice(&mut data);
+        prop_assert_eq!(Twamm::unpack_from_slice(&data).unwrap(), twamm);
+    }
+
+    #[test]
+    fn test_farm_round_trip(farm in farm()) {
+        let mut data = vec![0; Farm::LEN];
+        farm.pack_into_slice(&mut data);
+        prop_assert_eq!(Farm::unpack_from_slice(&data).unwrap(), farm);
+    }
+
+    #[test]
+    fn test_stake_round_trip(stake in stake()) {
+        let mut data = vec![0; Stake::LEN];
+        stake.pack_into_slice(&mut data);
+        prop_assert_eq!(Stake::unpack_from_slice(&data).unwrap(), stake);
+    }
+
+    #[test]
+    fn test_farm_pays_at_most_scheduled(
+        staked in 1..u64::MAX,
+        shares in any::<u64>(),
+        reward_rate in 0..u32::MAX as u64,
+        (start, slots, slot) in (0..u32::MAX as u64, 1..u32::MAX as u64, any::<u64>()),
+    ) {
+        let farm = Farm {
+            staked_shares: staked,
+            reward_rate,
+            end_slot: start + slots,
+            last_slot: start,
+            ..Farm::default()
+        };
+        let mut accrued = farm;
+        accrued.accrue(slot).unwrap();
+        let mut stake = Stake {
+            shares: shares.min(staked),
+            ..Stake::default()
+        };
+        accrued.settle(&mut stake).unwrap();
+        // a stake never earns more than the schedule paid out until the slot
+        let paid = reward_rate as u128 * (slot.clamp(start, start + slots) - start) as u128;
+        prop_assert!(stake.rewards as u128 <= paid);
+        prop_assert_eq!(accrued.last_slot, slot.max(start));
+    }
+
+    #[test]
+    fn test_farm_keeps_unstaked_rewards(
+        reward_rate in any::<u64>(),
+        (start, slots, slot) in (0..u32::MAX as u64, 1..u32::MAX as u64, 0..u32::MAX as u64 * 2),
+    ) {
+        let mut farm = Farm {
+            reward_rate,
+            end_slot: start + slots,
+            last_slot: start,
+            ..Farm::default()
+        };
+        farm.accrue(slot).unwrap();
+        // without stakes nothing is paid, the slots left wait for the next stake
+        prop_assert_eq!(farm.reward_per_share, 0);
+        prop_assert_eq!(farm.last_slot, slot.max(start));
+        prop_assert_eq!(farm.end_slot - farm.last_slot, slots);
+    }
+}
+
+#[test]
+fn test_farm_schedule() {
+    let mut farm = Farm::default();
+    farm.accrue(100).unwrap();
+    farm.schedule(100, 1_000, 10).unwrap();
+    assert_eq!((farm.reward_rate, farm.end_slot), (100, 110));
+    // nobody staked, the slots pay nobody and the schedule ends later
+    farm.accrue(105).unwrap();
+    assert_eq!(farm.reward_per_share, 0);
+    assert_eq!(farm.end_slot, 115);
+    // the 1_000 left and 1_000 more over 20 slots
+    farm.schedule(105, 1_000, 20).unwrap();
+    assert_eq!((farm.reward_rate, farm.end_slot), (100, 125));
+    // too little to pay anything per slot
+    assert_eq!(
+        Farm::default().schedule(0, 9, 10),
+        Err(AmmError::InvalidAmount)
+    );
+
+    farm.staked_shares = 3;
+    let mut stake = Stake {
+        shares: 1,
+        reward_per_share: farm.reward_per_share,
+        ..Stake::default()
+    };
+    // accrued until the end slot only
+    farm.accrue(200).unwrap();
+    farm.settle(&mut stake).unwrap();
+    assert_eq!(stake.rewards, 100 * 20 / 3);
+    assert_eq!(stake.reward_per_share, farm.reward_per_share);
+}
@@ -0,0 +1,314 @@
+mod common;
+
+use amm::{error::AmmError, instruction::Direction, math};
+use common::*;
+use solana_program::{
+    instruction::Instruction, program_option::COption, program_pack::Pack, pubkey::Pubkey,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{account::AccountSharedData, signature::Signer};
+use spl_token_2022::{
+    extension::{
+        default_account_state, interest_bearing_mint, transfer_fee, AccountType, ExtensionType,
+    },
+    state::{Account, AccountState, Mint},
+};
+
+/// type of the transfer hook extension in later token-2022 versions
+const TRANSFER_HOOK_EXTENSION: u16 = 14;
+
+const FEE_BPS_A: u16 = 100;
+const FEE_BPS_B: u16 = 50;
+
+/// token-2022 transfer fee without a maximum
+fn transfer_fee(amount: u64, basis_points: u16) -> u64 {
+    transfer_fee::TransferFee {
+        epoch: 0.into(),
+        maximum_fee: u64::MAX.into(),
+        transfer_fee_basis_points: basis_points.into(),
+    }
+    .calculate_fee(amount)
+    .unwrap()
+}
+
+fn transfer_fee_extension(setup: &PoolSetup, mint: &Pubkey, basis_points: u16) -> Instruction {
+    transfer_fee::instruction::initialize_transfer_fee_config(
+        &setup.token_program,
+        mint,
+        None,
+        None,
+        basis_points,
+        u64::MAX,
+    )
+    .unwrap()
+}
+
+/// Create token-2022 mints of 6 decimals, with the extensions of each mint.
+async fn create_mints(
+    context: &mut ProgramTestContext,
+    setup: &PoolSetup,
+    extensions_a: (&[ExtensionType], &[Instruction]),
+    extensions_b: (&[ExtensionType], &[Instruction]),
+) {
+    let authority = setup.mint_authority.pubkey();
+    for (mint, (types, instructions)) in
+        [(&setup.mint_a, extensions_a), (&setup.mint_b, extensions_b)]
+    {
+        create_mint_with_extensions(
+            context,
+            &setup.token_program,
+            mint,
+            &authority,
+            6,
+            types,
+            instructions,
+        )
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_token_2022_swap() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    create_mints(&mut context, &setup, (&[], &[]), (&[], &[])).await;
+    setup.fund_owner(&mut context).await;
+    setup.initialize(&mut context).await.unwrap();
+    let user = setup.create_user(&mut context).await;
+    setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await
+        .unwrap();
+    let vault_b = token_balance(&mut context.banks_client, &setup.vault_b).await;
+    assert_eq!(AMOUNT_B - vault_b, 181_818);
+    setup.lock_for_close(&mut context).await;
+    setup.terminate(&mut context, &setup.owner).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_transfer_fee() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    let fee_a = transfer_fee_extension(&setup, &setup.mint_a.pubkey(), FEE_BPS_A);
+    let fee_b = transfer_fee_extension(&setup, &setup.mint_b.pubkey(), FEE_BPS_B);
+    let types = [ExtensionType::TransferFeeConfig];
+    create_mints(&mut context, &setup, (&types, &[fee_a]), (&types, &[fee_b])).await;
+    setup.fund_owner(&mut context).await;
+    setup.initialize(&mut context).await.unwrap();
+
+    // the pool starts from what the vaults received
+    let reserve_a = AMOUNT_A - transfer_fee(AMOUNT_A, FEE_BPS_A);
+    let reserve_b = AMOUNT_B - transfer_fee(AMOUNT_B, FEE_BPS_B);
+    let banks_client = &mut context.banks_client;
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!((pool.ka, pool.kb), (reserve_a, reserve_b));
+    assert_eq!(token_balance(banks_client, &setup.vault_a).await, reserve_a);
+
+    // a2b prices the amount received after the fee of mint a
+    let user = setup.create_user(&mut context).await;
+    let amount = 100_000;
+    setup
+        .swap(&mut context, &user, amount, Direction::A2B)
+        .await
+        .unwrap();
+    let received = amount - transfer_fee(amount, FEE_BPS_A);
+    let amount_out = math::calculate_amount_a2b(reserve_a, reserve_b, received).unwrap();
+    let fee = math::calculate_fee(amount_out, FEE).unwrap();
+    let banks_client = &mut context.banks_client;
+    let reserve_a = reserve_a + received;
+    let reserve_b = reserve_b - amount_out;
+    assert_eq!(token_balance(banks_client, &setup.vault_a).await, reserve_a);
+    assert_eq!(token_balance(banks_client, &setup.vault_b).await, reserve_b);
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT + amount_out - transfer_fee(amount_out, FEE_BPS_B) - fee
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.fee_vault).await,
+        fee - transfer_fee(fee, FEE_BPS_B)
+    );
+
+    // b2a grosses up the payment so that vault b receives the full price
+    let amount = 50_000;
+    setup
+        .swap(&mut context, &user, amount, Direction::B2A)
+        .await
+        .unwrap();
+    let amount_in = math::calculate_amount_b2a(reserve_a, reserve_b, amount).unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        reserve_a - amount
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_b).await,
+        reserve_b + amount_in
+    );
+
+    // withheld fees are harvested so the vaults can close
+    setup.lock_for_close(&mut context).await;
+    setup.terminate(&mut context, &setup.owner).await.unwrap();
+    let banks_client = &mut context.banks_client;
+    assert!(get_account(banks_client, &setup.vault_a).await.is_none());
+    assert!(get_account(banks_client, &setup.vault_b).await.is_none());
+    assert!(get_account(banks_client, &setup.fee_vault).await.is_none());
+}
+
+#[tokio::test]
+async fn test_transfer_fee_long_term_order() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    let fee_b = transfer_fee_extension(&setup, &setup.mint_b.pubkey(), FEE_BPS_B);
+    let types = [ExtensionType::TransferFeeConfig];
+    create_mints(&mut context, &setup, (&[], &[]), (&types, &[fee_b])).await;
+    setup.fund_owner(&mut context).await;
+    setup.initialize(&mut context).await.unwrap();
+    // the escrows settle by balance, either mint charging a fee is rejected
+    let user = setup.create_wallet_user(&mut context).await;
+    let result = setup
+        .place_long_term_order(&mut context, &user, 100_000, Direction::A2B, 2_000)
+        .await;
+    assert_error(result.map(|_| ()), amm_error(AmmError::UnsupportedMint));
+}
+
+#[tokio::test]
+async fn test_interest_bearing() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    let interest = interest_bearing_mint::instruction::initialize(
+        &setup.token_program,
+        &setup.mint_a.pubkey(),
+        None,
+        500,
+    )
+    .unwrap();
+    create_mints(
+        &mut context,
+        &setup,
+        (&[ExtensionType::InterestBearingConfig], &[interest]),
+        (&[], &[]),
+    )
+    .await;
+    setup.fund_owner(&mut context).await;
+    setup.initialize(&mut context).await.unwrap();
+    let user = setup.create_user(&mut context).await;
+    // interest only changes the ui amount, the pool prices raw amounts
+    setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await
+        .unwrap();
+    let vault_b = token_balance(&mut context.banks_client, &setup.vault_b).await;
+    assert_eq!(AMOUNT_B - vault_b, 181_818);
+}
+
+#[tokio::test]
+async fn test_unsupported_mint() {
+    // non-transferable
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    let non_transferable = spl_token_2022::instruction::initialize_non_transferable_mint(
+        &setup.token_program,
+        &setup.mint_a.pubkey(),
+    )
+    .unwrap();
+    create_mints(
+        &mut context,
+        &setup,
+        (&[ExtensionType::NonTransferable], &[non_transferable]),
+        (&[], &[]),
+    )
+    .await;
+    setup.fund_owner(&mut context).await;
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::UnsupportedMint));
+
+    // permanent delegate
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    let permanent_delegate = spl_token_2022::instruction::initialize_permanent_delegate(
+        &setup.token_program,
+        &setup.mint_b.pubkey(),
+        &setup.mint_authority.pubkey(),
+    )
+    .unwrap();
+    create_mints(
+        &mut context,
+        &setup,
+        (&[], &[]),
+        (&[ExtensionType::PermanentDelegate], &[permanent_delegate]),
+    )
+    .await;
+    setup.fund_owner(&mut context).await;
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::UnsupportedMint));
+
+    // extensions not on the allow list
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    let close_authority = spl_token_2022::instruction::initialize_mint_close_authority(
+        &setup.token_program,
+        &setup.mint_a.pubkey(),
+        Some(&setup.mint_authority.pubkey()),
+    )
+    .unwrap();
+    let default_state = default_account_state::instruction::initialize_default_account_state(
+        &setup.token_program,
+        &setup.mint_b.pubkey(),
+        &AccountState::Initialized,
+    )
+    .unwrap();
+    create_mints(
+        &mut context,
+        &setup,
+        (&[ExtensionType::MintCloseAuthority], &[close_authority]),
+        (&[ExtensionType::DefaultAccountState], &[default_state]),
+    )
+    .await;
+    setup.fund_owner(&mut context).await;
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::UnsupportedMint));
+
+    // a transfer hook, which this token-2022 version can't read
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    create_mints(&mut context, &setup, (&[], &[]), (&[], &[])).await;
+    setup.fund_owner(&mut context).await;
+    let mut data = vec![0; Account::LEN + 1 + 4 + 64];
+    Mint {
+        mint_authority: COption::Some(setup.mint_authority.pubkey()),
+        supply: USER_AMOUNT,
+        decimals: 6,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    }
+    .pack_into_slice(&mut data[..Mint::LEN]);
+    data[Account::LEN] = AccountType::Mint as u8;
+    data[Account::LEN + 1..Account::LEN + 3]
+        .copy_from_slice(&TRANSFER_HOOK_EXTENSION.to_le_bytes());
+    data[Account::LEN + 3..Account::LEN + 5].copy_from_slice(&64u16.to_le_bytes());
+    let mut account = AccountSharedData::new(1_000_000_000, data.len(), &setup.token_program);
+    account.set_data(data);
+    context.set_account(&setup.mint_b.pubkey(), &account);
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::UnsupportedMint));
+}
+
+#[tokio::test]
+async fn test_mixed_token_programs() {
+    let mut context = program_test().start_with_context().await;
+    let setup = PoolSetup::with_token_program(spl_token_2022::id());
+    let authority = setup.mint_authority.pubkey();
+    create_mint(&mut context, &setup.mint_a, &authority, 6).await;
+    create_mint_with_extensions(
+        &mut context,
+        &setup.token_program,
+        &setup.mint_b,
+        &authority,
+        6,
+        &[],
+        &[],
+    )
+    .await
+    .unwrap();
+    setup.fund_owner(&mut context).await;
+    let result = setup.initialize(&mut context).await;
+    assert_error(result, amm_error(AmmError::InvalidTokenProgramId));
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use amm::{error::AmmError, instruction::Direction};
+use common::*;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction, pubkey::Pubkey,
+};
+use solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::{AccountSharedData, WritableAccount},
+    program_pack::Pack,
+    signature::Signer,
+};
+
+fn malicious_program_id() -> Pubkey {
+    Pubkey::new_from_array([9; 32])
+}
+
+/// Pretends to be a token program, every transfer succeeds without moving a token.
+fn malicious_process(_: &Pubkey, _: &[AccountInfo], _: &[u8]) -> ProgramResult {
+    Ok(())
+}
+
+fn malicious_program_test() -> ProgramTest {
+    let mut program_test = program_test();
+    program_test.add_program(
+        "malicious",
+        malicious_program_id(),
+        processor!(malicious_process),
+    );
+    program_test
+}
+
+/// Start a test validator with the malicious program and one initialized pool.
+async fn setup_malicious() -> (ProgramTestContext, PoolSetup) {
+    let mut context = malicious_program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    setup.initialize(&mut context).await.unwrap();
+    (context, setup)
+}
+
+/// Replace the token program of the instruction.
+fn with_token_program(mut instruction: Instruction, token_program: Pubkey) -> Instruction {
+    for account in instruction.accounts.iter_mut() {
+        if account.pubkey == spl_token::id() {
+            account.pubkey = token_program;
+        }
+    }
+    instruction
+}
+
+async fn assert_untouched(context: &mut ProgramTestContext, setup: &PoolSetup) {
+    let banks_client = &mut context.banks_client;
+    assert_eq!(token_balance(banks_client, &setup.vault_a).await, AMOUNT_A);
+    assert_eq!(token_balance(banks_client, &setup.vault_b).await, AMOUNT_B);
+}
+
+#[tokio::test]
+async fn test_initialize_malicious_program() {
+    let mut context = malicious_program_test().start_with_context().await;
+    let setup = PoolSetup::new();
+    setup.create_accounts(&mut context, (6, 6)).await;
+    // the pool would record reserves it never received
+    let instruction =
+        with_token_program(setup.initialize_instruction(FEE, 0), malicious_program_id());
+    let result = process(
+        &mut context,
+        &[instruction],
+        &[&setup.owner.wallet, &setup.pool],
+    )
+    .await;
+    assert_error(result, amm_error(AmmError::InvalidTokenProgramId));
+}
+
+#[tokio::test]
+async fn test_swap_malicious_program() {
+    let (mut context, setup) = setup_malicious().await;
+    let user = setup.create_user(&mut context).await;
+    for token_program in [malicious_program_id(), spl_token_2022::id()] {
+        let instruction = with_token_program(
+            setup.swap_instruction(&user, 100_000, Direction::A2B),
+            token_program,
+        );
+        let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+        assert_error(result, amm_error(AmmError::InvalidTokenProgramId));
+    }
+    assert_untouched(&mut context, &setup).await;
+}
+
+#[tokio::test]
+async fn test_swap_wrong_fee_vault() {
+    let (mut context, setup) = setup_malicious().await;
+    let user = setup.create_user(&mut context).await;
+    // the user takes the fee back
+    let mut instruction = setup.swap_instruction(&user, 100_000, Direction::A2B);
+    instruction.accounts[3].pubkey = user.token_b.pubkey();
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidVault));
+
+    // pda of another pool
+    let mut instruction = setup.swap_instruction(&user, 100_000, Direction::A2B);
+    instruction.accounts[4].pubkey = user.wallet.pubkey();
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidPDA));
+    assert_untouched(&mut context, &setup).await;
+}
+
+#[tokio::test]
+async fn test_swap_fee_vault_not_held_by_pda() {
+    let (mut context, setup) = setup_malicious().await;
+    let user = setup.create_user(&mut context).await;
+    let fee_vault = get_account(&mut context.banks_client, &setup.fee_vault)
+        .await
+        .unwrap();
+    // the fee vault handed over to the user
+    let mut vault = spl_token::state::Account::unpack(&fee_vault.data).unwrap();
+    vault.owner = user.wallet.pubkey();
+    let mut data = fee_vault.data.clone();
+    vault.pack_into_slice(&mut data);
+    let mut account = AccountSharedData::from(fee_vault.clone());
+    account.set_data(data);
+    context.set_account(&setup.fee_vault, &account);
+    let instruction = setup.swap_instruction(&user, 100_000, Direction::A2B);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+
+    // the fee vault under another program
+    let mut account = AccountSharedData::from(fee_vault);
+    account.set_owner(malicious_program_id());
+    context.set_account(&setup.fee_vault, &account);
+    let instruction = setup.swap_instruction(&user, 100_000, Direction::A2B);
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidTokenProgramId));
+    assert_untouched(&mut context, &setup).await;
+}
+
+#[tokio::test]
+async fn test_withdrawal_fee_malicious_program() {
+    let (mut context, setup) = setup_malicious().await;
+    let user = setup.create_user(&mut context).await;
+    setup
+        .swap(&mut context, &user, 100_000, Direction::A2B)
+        .await
+        .unwrap();
+    let owner = &setup.owner;
+    let instruction = with_token_program(
+        setup.withdrawal_fee_instruction(&owner.wallet.pubkey(), &owner.token_b.pubkey()),
+        malicious_program_id(),
+    );
+    let result = process(&mut context, &[instruction], &[&owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidTokenProgramId));
+    let fee = token_balance(&mut context.banks_client, &setup.fee_vault).await;
+    assert!(fee > 0);
+}
+
+#[tokio::test]
+async fn test_terminate_malicious_program() {
+    let (mut context, setup) = setup_malicious().await;
+    // the pool would close while the vaults keep the tokens
+    let instruction = with_token_program(
+        setup.terminate_instruction(&setup.owner),
+        malicious_program_id(),
+    );
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidTokenProgramId));
+    assert!(get_account(&mut context.banks_client, &setup.pool.pubkey())
+        .await
+        .is_some());
+    assert_untouched(&mut context, &setup).await;
+}
@@ -0,0 +1,536 @@
+mod common;
+
+use amm::{
+    error::AmmError,
+    instruction::{self, Direction},
+    math,
+    state::{LongTermOrder, OrderStatus, MAX_EXPIRIES, MIN_LONG_TERM_DEPOSIT_BPS, TWAMM_INTERVAL},
+};
+use common::*;
+use solana_program::{clock::Clock, instruction::InstructionError, pubkey::Pubkey};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::signature::{Keypair, Signer};
+
+const ORDER_AMOUNT: u64 = 100_000;
+const ORDER_SLOTS: u64 = 2 * TWAMM_INTERVAL;
+
+async fn current_slot(context: &mut ProgramTestContext) -> u64 {
+    context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .slot
+}
+
+fn escrows(setup: &PoolSetup) -> (Pubkey, Pubkey) {
+    let pool = setup.pool.pubkey();
+    let escrow = |seed| instruction::find_vault_address(&program_id(), &pool, seed).0;
+    (
+        escrow(instruction::TWAMM_ESCROW_A_SEED),
+        escrow(instruction::TWAMM_ESCROW_B_SEED),
+    )
+}
+
+#[tokio::test]
+async fn test_place_long_term_order() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let slot = current_slot(&mut context).await;
+    let order = setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::A2B,
+            ORDER_SLOTS,
+        )
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let state = get_long_term_order(banks_client, &order.pubkey()).await;
+    // the order ends on the interval, the rate leaves the rest of the deposit unsold
+    let end_slot = slot + ORDER_SLOTS + TWAMM_INTERVAL - 1;
+    let end_slot = end_slot - end_slot % TWAMM_INTERVAL;
+    assert_eq!(
+        state,
+        LongTermOrder {
+            status: OrderStatus::Open,
+            direction: Direction::A2B,
+            pool: setup.pool.pubkey(),
+            owner: user.wallet.pubkey(),
+            token_in: user.token_a.pubkey(),
+            token_out: user.token_b.pubkey(),
+            deposit: ORDER_AMOUNT,
+            sell_rate: ORDER_AMOUNT / (end_slot - slot),
+            start_slot: slot,
+            end_slot,
+            proceeds_per_rate: 0,
+        }
+    );
+    let (escrow_a, escrow_b) = escrows(&setup);
+    assert_eq!(token_balance(banks_client, &escrow_a).await, ORDER_AMOUNT);
+    assert_eq!(token_balance(banks_client, &escrow_b).await, 0);
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT - ORDER_AMOUNT
+    );
+    let twamm = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(twamm.sell_rate_a, state.sell_rate);
+    assert_eq!(twamm.expiries[0].slot, end_slot);
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.long_term_orders, 1);
+
+    // a second order shares the accounts and the expiry
+    setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::B2A,
+            ORDER_SLOTS,
+        )
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let twamm = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    assert!(twamm.sell_rate_b > 0);
+    assert_eq!(twamm.expiries[0].orders, 2);
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.long_term_orders, 2);
+}
+
+#[tokio::test]
+async fn test_place_long_term_order_errors() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    for (amount, slots) in [(0, ORDER_SLOTS), (ORDER_AMOUNT, 0), (1, ORDER_SLOTS)] {
+        let result = setup
+            .place_long_term_order(&mut context, &user, amount, Direction::A2B, slots)
+            .await;
+        assert_error(result.map(|_| ()), amm_error(AmmError::InvalidAmount));
+    }
+    // sells something every slot, but less than the least deposit
+    let min_deposit = AMOUNT_B * MIN_LONG_TERM_DEPOSIT_BPS / math::BPS_MUL;
+    let result = setup
+        .place_long_term_order(&mut context, &user, min_deposit - 1, Direction::B2A, 1)
+        .await;
+    assert_error(result.map(|_| ()), amm_error(AmmError::InvalidAmount));
+    setup
+        .place_long_term_order(&mut context, &user, min_deposit, Direction::B2A, 1)
+        .await
+        .unwrap();
+
+    // order must sign
+    let order = Keypair::new();
+    let mut instruction = setup.place_long_term_order_instruction(
+        &order.pubkey(),
+        &user,
+        ORDER_AMOUNT,
+        Direction::A2B,
+        ORDER_SLOTS,
+    );
+    instruction.accounts[1].is_signer = false;
+    let result = process(&mut context, &[instruction], &[&user.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    // virtual orders of another pool
+    let mut instruction = setup.place_long_term_order_instruction(
+        &order.pubkey(),
+        &user,
+        ORDER_AMOUNT,
+        Direction::A2B,
+        ORDER_SLOTS,
+    );
+    instruction.accounts[3].pubkey =
+        instruction::find_twamm_address(&program_id(), &Pubkey::new_unique()).0;
+    let result = process(&mut context, &[instruction], &[&user.wallet, &order]).await;
+    assert_error(result, amm_error(AmmError::InvalidPDA));
+}
+
+#[tokio::test]
+async fn test_execute_virtual_orders() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::A2B,
+            ORDER_SLOTS,
+        )
+        .await
+        .unwrap();
+    warp_slots(&mut context, TWAMM_INTERVAL / 2).await;
+    let banks_client = &mut context.banks_client;
+    let mut expected = get_twamm(banks_client, &setup.pool.pubkey()).await;
+
+    let instruction = setup.execute_virtual_orders_instruction();
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let slot = current_slot(&mut context).await;
+    let trade = expected.execute(AMOUNT_A, AMOUNT_B, slot, FEE).unwrap();
+    assert!(trade.amount_a_in > 0 && trade.fee > 0);
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        get_twamm(banks_client, &setup.pool.pubkey()).await,
+        expected
+    );
+    let (escrow_a, escrow_b) = escrows(&setup);
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        AMOUNT_A + trade.amount_a_in
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_b).await,
+        AMOUNT_B - trade.amount_b_out
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.fee_vault).await,
+        trade.fee
+    );
+    assert_eq!(
+        token_balance(banks_client, &escrow_a).await,
+        ORDER_AMOUNT - trade.amount_a_in
+    );
+    assert_eq!(
+        token_balance(banks_client, &escrow_b).await,
+        trade.proceeds_b
+    );
+
+    // nothing to run in the same slot
+    let instruction = setup.execute_virtual_orders_instruction();
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        AMOUNT_A + trade.amount_a_in
+    );
+}
+
+#[tokio::test]
+async fn test_swap_runs_virtual_orders() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::A2B,
+            ORDER_SLOTS,
+        )
+        .await
+        .unwrap();
+    warp_slots(&mut context, TWAMM_INTERVAL / 2).await;
+
+    // a pool with long-term orders needs them run first
+    let result = setup
+        .swap(&mut context, &user, 10_000, Direction::B2A)
+        .await;
+    assert_error(result, InstructionError::NotEnoughAccountKeys);
+
+    let banks_client = &mut context.banks_client;
+    let mut expected = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    let instruction = setup.twamm_swap_instruction(&user, 10_000, Direction::B2A);
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let slot = current_slot(&mut context).await;
+    let trade = expected.execute(AMOUNT_A, AMOUNT_B, slot, FEE).unwrap();
+    let banks_client = &mut context.banks_client;
+    let twamm = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(twamm.last_slot, slot);
+    // the swap bought a out of what the virtual orders left
+    assert_eq!(
+        token_balance(banks_client, &setup.vault_a).await,
+        AMOUNT_A + trade.amount_a_in - 10_000
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_long_term_order() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let order = setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::A2B,
+            ORDER_SLOTS,
+        )
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let state = get_long_term_order(banks_client, &order.pubkey()).await;
+    warp_slots(&mut context, TWAMM_INTERVAL / 2).await;
+
+    // only the owner cancels a running pool
+    let instruction =
+        setup.cancel_long_term_order_instruction(&order.pubkey(), &user, Direction::A2B, false);
+    let result = process(&mut context, &[instruction], &[]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    let banks_client = &mut context.banks_client;
+    let mut expected = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    let lamports = get_account(banks_client, &user.wallet.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+    let order_lamports = get_account(banks_client, &order.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+    let instruction =
+        setup.cancel_long_term_order_instruction(&order.pubkey(), &user, Direction::A2B, true);
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let slot = current_slot(&mut context).await;
+    expected.execute(AMOUNT_A, AMOUNT_B, slot, FEE).unwrap();
+    let (unsold, proceeds) = expected.remove_order(&state).unwrap();
+    assert_eq!(
+        unsold,
+        ORDER_AMOUNT - state.sell_rate * (slot - state.start_slot)
+    );
+    assert!(proceeds > 0);
+
+    let banks_client = &mut context.banks_client;
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT - ORDER_AMOUNT + unsold
+    );
+    assert_eq!(
+        token_balance(banks_client, &user.token_b.pubkey()).await,
+        USER_AMOUNT + proceeds
+    );
+    // the order is closed, its rent back to the owner
+    assert!(get_account(banks_client, &order.pubkey()).await.is_none());
+    assert_eq!(
+        get_account(banks_client, &user.wallet.pubkey())
+            .await
+            .unwrap()
+            .lamports,
+        lamports + order_lamports
+    );
+    let twamm = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(twamm, expected);
+    assert_eq!(twamm.sell_rate_a, 0);
+    let pool = get_pool(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(pool.long_term_orders, 0);
+
+    // swaps don't need the virtual orders anymore
+    setup
+        .swap(&mut context, &user, 10_000, Direction::A2B)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_withdraw_ended_order() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let seller_b = setup.create_wallet_user(&mut context).await;
+    let order = setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::A2B,
+            ORDER_SLOTS,
+        )
+        .await
+        .unwrap();
+    setup
+        .place_long_term_order(
+            &mut context,
+            &seller_b,
+            ORDER_AMOUNT,
+            Direction::B2A,
+            ORDER_SLOTS,
+        )
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    let state = get_long_term_order(banks_client, &order.pubkey()).await;
+    warp_slots(&mut context, ORDER_SLOTS + TWAMM_INTERVAL).await;
+
+    let instruction =
+        setup.cancel_long_term_order_instruction(&order.pubkey(), &user, Direction::A2B, true);
+    process(&mut context, &[instruction], &[&user.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    // the order sold its rate to the end, only the rounding is refunded
+    let sold = state.sell_rate * (state.end_slot - state.start_slot);
+    assert_eq!(
+        token_balance(banks_client, &user.token_a.pubkey()).await,
+        USER_AMOUNT - sold
+    );
+    let proceeds = token_balance(banks_client, &user.token_b.pubkey()).await - USER_AMOUNT;
+    // matched with the other side at about the price of 2 b per a
+    assert!(proceeds > sold && proceeds < 2 * sold);
+    let twamm = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    assert_eq!(twamm.sell_rate_a, 0);
+    assert_eq!(twamm.sell_rate_b, 0);
+    assert_eq!(twamm.expiries[0].orders, 1);
+}
+
+#[tokio::test]
+async fn test_expiries_recover() {
+    let (mut context, setup) = setup_pool().await;
+    let spammer = setup.create_wallet_user(&mut context).await;
+    let user = setup.create_wallet_user(&mut context).await;
+    // small orders ending in every interval take all expiries
+    let mut orders = vec![];
+    for i in 1..=MAX_EXPIRIES as u64 {
+        let order = setup
+            .place_long_term_order(
+                &mut context,
+                &spammer,
+                ORDER_AMOUNT / 2,
+                Direction::A2B,
+                i * TWAMM_INTERVAL,
+            )
+            .await
+            .unwrap();
+        orders.push(order);
+    }
+    let result = setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::B2A,
+            (MAX_EXPIRIES as u64 + 1) * TWAMM_INTERVAL,
+        )
+        .await;
+    assert_error(result.map(|_| ()), amm_error(AmmError::TooManyExpiries));
+
+    // an order still selling is the owner's to cancel
+    let instruction = setup.cancel_long_term_order_instruction(
+        &orders[0].pubkey(),
+        &spammer,
+        Direction::A2B,
+        false,
+    );
+    let result = process(&mut context, &[instruction], &[]).await;
+    assert_error(result, amm_error(AmmError::InvalidSignAccount));
+
+    // once the virtual orders ran past its end anyone withdraws it to its owner, which
+    // frees the expiry
+    warp_slots(&mut context, ORDER_SLOTS).await;
+    let banks_client = &mut context.banks_client;
+    let state = get_long_term_order(banks_client, &orders[0].pubkey()).await;
+    let instruction = setup.cancel_long_term_order_instruction(
+        &orders[0].pubkey(),
+        &spammer,
+        Direction::A2B,
+        false,
+    );
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let banks_client = &mut context.banks_client;
+    assert!(get_account(banks_client, &orders[0].pubkey())
+        .await
+        .is_none());
+    assert!(token_balance(banks_client, &spammer.token_b.pubkey()).await > USER_AMOUNT);
+    let twamm = get_twamm(banks_client, &setup.pool.pubkey()).await;
+    assert!(twamm.expiries.iter().all(|e| e.slot != state.end_slot));
+    setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::B2A,
+            (MAX_EXPIRIES as u64 + 1) * TWAMM_INTERVAL,
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_terminate_with_long_term_orders() {
+    let (mut context, setup) = setup_pool().await;
+    let user = setup.create_wallet_user(&mut context).await;
+    let order = setup
+        .place_long_term_order(
+            &mut context,
+            &user,
+            ORDER_AMOUNT,
+            Direction::B2A,
+            ORDER_SLOTS,
+        )
+        .await
+        .unwrap();
+    // the order sells for a while, its proceeds are rounded down
+    warp_slots(&mut context, TWAMM_INTERVAL / 2).await;
+    let instruction = setup.execute_virtual_orders_instruction();
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    setup.lock_for_close(&mut context).await;
+    let instruction = setup.terminate_twamm_instruction(&setup.owner, &user.wallet.pubkey());
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::OpenOrders));
+
+    // anyone closes the order once the grace period passed, a locked pool sells nothing
+    let instruction =
+        setup.cancel_long_term_order_instruction(&order.pubkey(), &user, Direction::B2A, false);
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let banks_client = &mut context.banks_client;
+    assert!(token_balance(banks_client, &user.token_b.pubkey()).await < USER_AMOUNT);
+    assert!(token_balance(banks_client, &user.token_a.pubkey()).await > USER_AMOUNT);
+
+    let (escrow_a, escrow_b) = escrows(&setup);
+    let twamm = instruction::find_twamm_address(&program_id(), &setup.pool.pubkey()).0;
+    let mut rent = 0;
+    for key in [twamm, escrow_a, escrow_b] {
+        rent += get_account(banks_client, &key).await.unwrap().lamports;
+    }
+    let dust_a = token_balance(banks_client, &escrow_a).await;
+    let dust_b = token_balance(banks_client, &escrow_b).await;
+    assert!(dust_a + dust_b > 0);
+    let vault_a = token_balance(banks_client, &setup.vault_a).await;
+    let vault_b = token_balance(banks_client, &setup.vault_b).await;
+    let fee = token_balance(banks_client, &setup.fee_vault).await;
+    let owner_a = token_balance(banks_client, &setup.owner.token_a.pubkey()).await;
+    let owner_b = token_balance(banks_client, &setup.owner.token_b.pubkey()).await;
+    let user_lamports = get_account(banks_client, &user.wallet.pubkey())
+        .await
+        .unwrap()
+        .lamports;
+
+    // the virtual orders must close with the pool
+    let instruction = setup.terminate_instruction(&setup.owner);
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, InstructionError::NotEnoughAccountKeys);
+    // their rent goes back to the wallet that paid it
+    let instruction = setup.terminate_twamm_instruction(&setup.owner, &setup.owner.wallet.pubkey());
+    let result = process(&mut context, &[instruction], &[&setup.owner.wallet]).await;
+    assert_error(result, amm_error(AmmError::InvalidOwner));
+
+    let instruction = setup.terminate_twamm_instruction(&setup.owner, &user.wallet.pubkey());
+    process(&mut context, &[instruction], &[&setup.owner.wallet])
+        .await
+        .unwrap();
+    let banks_client = &mut context.banks_client;
+    // the dust of the escrows goes to the owner, the virtual orders close with the pool
+    assert_eq!(
+        token_balance(banks_client, &setup.owner.token_a.pubkey()).await,
+        owner_a + vault_a + dust_a
+    );
+    assert_eq!(
+        token_balance(banks_client, &setup.owner.token_b.pubkey()).await,
+        owner_b + vault_b + fee + dust_b
+    );
+    for key in [setup.pool.pubkey(), twamm, escrow_a, escrow_b] {
+        assert!(get_account(banks_client, &key).await.is_none());
+    }
+    let owner = get_account(banks_client, &setup.owner.wallet.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(owner.lamports, OWNER_LAMPORTS);
+    let user_wallet = get_account(banks_client, &user.wallet.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(user_wallet.lamports, user_lamports + rent);
+}